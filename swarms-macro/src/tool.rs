@@ -12,6 +12,9 @@ use syn::{FnArg, ItemFn, PatType, ReturnType, Type, parse_macro_input};
 struct ToolAttribute {
     name: Option<String>,
     description: Option<String>,
+    capabilities: Vec<Ident>,
+    /// Emit a strict mode definition, see `ToolDefinition::strict`.
+    strict: bool,
     args: Vec<ArgMeta>,
 }
 
@@ -40,6 +43,7 @@ impl Parse for ToolAttribute {
                     match (ident.to_string().as_str(), lit_result) {
                         ("name", Ok(lit)) => attr.name = Some(lit.value()),
                         ("description", Ok(lit)) => attr.description = Some(lit.value()),
+                        ("capabilities", Ok(lit)) => attr.capabilities = parse_capabilities(&lit)?,
                         (_, Err(e)) => {
                             return Err(Error::new_spanned(
                                 value,
@@ -55,6 +59,8 @@ impl Parse for ToolAttribute {
                     }
                 }
 
+                Meta::Path(path) if path.is_ident("strict") => attr.strict = true,
+
                 Meta::List(list) if list.path.is_ident("arg") => {
                     let args =
                         list.parse_args_with(Punctuated::<ArgMeta, Token![,]>::parse_terminated)?;
@@ -64,7 +70,7 @@ impl Parse for ToolAttribute {
                 meta => {
                     return Err(Error::new_spanned(
                         meta,
-                        "Unsupported attribute format, expected `key = value` or `arg(...)`",
+                        "Unsupported attribute format, expected `key = value`, `strict` or `arg(...)`",
                     ));
                 }
             }
@@ -74,6 +80,31 @@ impl Parse for ToolAttribute {
     }
 }
 
+/// Parse `"network, filesystem"` into the variants of `swarms_rs::tool::ToolCapability`.
+fn parse_capabilities(lit: &LitStr) -> Result<Vec<Ident>> {
+    lit.value()
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let variant = match name {
+                "network" => "Network",
+                "filesystem" => "Filesystem",
+                "code_exec" => "CodeExec",
+                _ => {
+                    return Err(Error::new(
+                        lit.span(),
+                        format!(
+                            "Unknown capability: {name}, expected `network`, `filesystem` or `code_exec`"
+                        ),
+                    ));
+                }
+            };
+            Ok(Ident::new(variant, lit.span()))
+        })
+        .collect()
+}
+
 impl Parse for ArgMeta {
     fn parse(input: ParseStream) -> Result<Self> {
         let mut arg = ArgMeta {
@@ -353,12 +384,18 @@ pub fn tool_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
             }
         }
     };
+    let capabilities = tool_attr.capabilities;
     // Modify the definition implementation to use the description
     let description = match tool_attr.description {
         Some(desc) => quote! { #desc.to_string() },
         None => quote! { format!("Function to {}", Self::NAME) },
     };
 
+    let strict = match tool_attr.strict {
+        true => quote! { .strict() },
+        false => quote! {},
+    };
+
     let definition_impl = if !is_struct_args {
         quote! {
             fn definition(&self) -> swarms_rs::llm::request::ToolDefinition {
@@ -376,7 +413,9 @@ pub fn tool_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
                             ),*
                         },
                     }),
+                    strict: false,
                 }
+                #strict
             }
         }
     } else {
@@ -386,7 +425,9 @@ pub fn tool_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
                     name: Self::NAME.to_string(),
                     description: #description,
                     parameters: schemars::schema_for!(#args_struct_name).as_value().to_owned(),
+                    strict: false,
                 }
+                #strict
             }
         }
     };
@@ -404,6 +445,8 @@ pub fn tool_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
 
         impl swarms_rs::tool::Tool for #struct_name {
             const NAME: &'static str = #tool_name;
+            const CAPABILITIES: &'static [swarms_rs::tool::ToolCapability] =
+                &[#(swarms_rs::tool::ToolCapability::#capabilities),*];
 
             type Error = #error_type;
             type Args = #args_struct_name;
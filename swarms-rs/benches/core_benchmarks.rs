@@ -0,0 +1,278 @@
+use criterion::{BatchSize, Criterion, black_box, criterion_group, criterion_main};
+use futures::future::{self, BoxFuture};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
+
+use swarms_rs::{
+    MemoryPersistence,
+    agent::{Agent, AgentError, swarms_agent::SwarmsAgentBuilder},
+    concurrent_workflow::ConcurrentWorkflow,
+    conversation::{AgentConversation, Participant},
+    llm::{
+        self, CompletionError,
+        completion::{AssistantContent, ToolCall, ToolFunction},
+        request::{CompletionRequest, CompletionResponse, ToolDefinition},
+    },
+    sequential_workflow::SequentialWorkflow,
+    tool::Tool,
+};
+
+/// Messages in the long conversations.
+const HISTORY_LEN: usize = 1000;
+const WORKFLOW_AGENTS: usize = 5;
+
+fn long_conversation() -> AgentConversation {
+    let mut conversation = AgentConversation::new("bench".to_owned());
+    for i in 0..HISTORY_LEN {
+        let participant = if i % 2 == 0 {
+            Participant::human("User")
+        } else {
+            Participant::agent("bench")
+        };
+        conversation.add(
+            participant,
+            format!("message {i}: {}", "lorem ipsum ".repeat(20)),
+        );
+    }
+    conversation
+}
+
+struct MockAgent {
+    name: String,
+}
+
+impl Agent for MockAgent {
+    fn run(&self, task: String) -> BoxFuture<'static, Result<String, AgentError>> {
+        Box::pin(future::ready(Ok(format!("{}: {task}", self.name))))
+    }
+
+    fn run_multiple_tasks(
+        &mut self,
+        tasks: Vec<String>,
+    ) -> BoxFuture<'static, Result<Vec<String>, AgentError>> {
+        let responses = tasks.iter().map(|task| format!("{}: {task}", self.name));
+        Box::pin(future::ready(Ok(responses.collect())))
+    }
+
+    fn plan(&self, _task: String) -> BoxFuture<'static, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn query_long_term_memory(&self, _task: String) -> BoxFuture<'static, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn save_task_state(&self, _task: String) -> BoxFuture<'static, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn is_response_complete(&self, _response: String) -> bool {
+        true
+    }
+
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn description(&self) -> String {
+        format!("Mock agent: {}", self.name)
+    }
+
+    fn clone_box(&self) -> Box<dyn Agent> {
+        Box::new(MockAgent {
+            name: self.name.clone(),
+        })
+    }
+}
+
+fn mock_agents() -> Vec<Box<dyn Agent>> {
+    (0..WORKFLOW_AGENTS)
+        .map(|i| {
+            Box::new(MockAgent {
+                name: format!("agent{i}"),
+            }) as Box<dyn Agent>
+        })
+        .collect()
+}
+
+/// Always asks to call the `search` tool.
+#[derive(Clone)]
+struct ToolCallModel;
+
+impl llm::Model for ToolCallModel {
+    type RawCompletionResponse = ();
+
+    fn name(&self) -> String {
+        "tool-caller".to_owned()
+    }
+
+    fn completion(
+        &self,
+        _request: CompletionRequest,
+    ) -> BoxFuture<'_, Result<CompletionResponse<()>, CompletionError>> {
+        let tool_call = ToolCall {
+            id: "call-1".to_owned(),
+            function: ToolFunction {
+                name: SearchTool::NAME.to_owned(),
+                arguments: serde_json::json!({
+                    "query": "rust agent frameworks",
+                    "tags": ["performance", "async", "orchestration"],
+                    "limit": 20,
+                }),
+            },
+        };
+        Box::pin(future::ready(Ok(CompletionResponse {
+            choice: vec![AssistantContent::ToolCall(tool_call)],
+            raw_response: (),
+        })))
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchArgs {
+    query: String,
+    tags: Vec<String>,
+    limit: usize,
+}
+
+#[derive(Serialize)]
+struct SearchHit {
+    title: String,
+    tags: Vec<String>,
+    score: f64,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("search failed")]
+struct SearchError;
+
+struct SearchTool;
+
+impl Tool for SearchTool {
+    type Error = SearchError;
+    type Args = SearchArgs;
+    type Output = Vec<SearchHit>;
+
+    const NAME: &'static str = "search";
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_owned(),
+            description: "Search the documents".to_owned(),
+            parameters: serde_json::json!({"type": "object"}),
+            strict: false,
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        Ok((0..args.limit)
+            .map(|i| SearchHit {
+                title: format!("{} #{i}", args.query),
+                tags: args.tags.clone(),
+                score: 1.0 / (i + 1) as f64,
+            })
+            .collect())
+    }
+}
+
+fn bench_conversation(c: &mut Criterion) {
+    c.bench_function("conversation_append_1000", |b| {
+        b.iter(|| black_box(long_conversation()))
+    });
+
+    let conversation = long_conversation();
+    c.bench_function("conversation_to_json_1000", |b| {
+        b.iter(|| black_box(conversation.to_json().unwrap()))
+    });
+    let json = serde_json::to_string(&conversation).unwrap();
+    c.bench_function("conversation_from_json_1000", |b| {
+        b.iter(|| black_box(serde_json::from_str::<AgentConversation>(&json).unwrap()))
+    });
+}
+
+fn bench_prompt_assembly(c: &mut Criterion) {
+    // Clones start without rendered messages
+    let conversation = long_conversation();
+    c.bench_function("prompt_assembly_cold_1000", |b| {
+        b.iter_batched(
+            || conversation.clone(),
+            |conversation| black_box(conversation.to_llm_messages()),
+            BatchSize::SmallInput,
+        )
+    });
+
+    // One new message per prompt, like the turns of an agent loop. The message is removed
+    // again so every iteration prompts with the same history length
+    let mut conversation = long_conversation();
+    conversation.to_llm_messages();
+    c.bench_function("prompt_assembly_append_1000", |b| {
+        b.iter(|| {
+            conversation.add(Participant::agent("bench"), "next turn".to_owned());
+            let messages = black_box(conversation.to_llm_messages());
+            conversation.delete(HISTORY_LEN);
+            messages
+        })
+    });
+}
+
+fn bench_workflow_scheduling(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+
+    let sequential = SequentialWorkflow::builder()
+        .agents(mock_agents())
+        .persistence(MemoryPersistence::default())
+        .build();
+    c.bench_function("sequential_workflow_5_agents", |b| {
+        b.iter(|| {
+            rt.block_on(sequential.run(black_box("input data")))
+                .unwrap()
+        })
+    });
+
+    // A new workflow per iteration, a workflow runs every task only once
+    c.bench_function("concurrent_workflow_5_agents", |b| {
+        b.iter_batched(
+            || {
+                ConcurrentWorkflow::builder()
+                    .agents(mock_agents())
+                    .persistence(MemoryPersistence::default())
+                    .build()
+            },
+            |concurrent| {
+                rt.block_on(concurrent.run(black_box("input data")))
+                    .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bench_tool_round_trip(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    // A new agent per iteration, the history of a reused agent would keep growing
+    c.bench_function("tool_call_round_trip", |b| {
+        b.iter_batched(
+            || {
+                SwarmsAgentBuilder::new_with_model(ToolCallModel)
+                    .agent_name("bench")
+                    .add_tool(SearchTool)
+                    .build()
+            },
+            |agent| rt.block_on(agent.run(black_box("find frameworks".to_owned()))),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_conversation,
+    bench_prompt_assembly,
+    bench_workflow_scheduling,
+    bench_tool_round_trip,
+);
+criterion_main!(benches);
@@ -170,6 +170,7 @@ fn bench_workflow_with_transform(c: &mut Criterion) {
                         Flow {
                             transform: Some(transform_fn.clone()),
                             condition: None,
+                            ..Default::default()
                         },
                     )
                     .unwrap();
@@ -181,6 +182,7 @@ fn bench_workflow_with_transform(c: &mut Criterion) {
                         Flow {
                             transform: Some(transform_fn),
                             condition: None,
+                            ..Default::default()
                         },
                     )
                     .unwrap();
@@ -214,6 +216,7 @@ fn bench_workflow_with_condition(c: &mut Criterion) {
                         Flow {
                             transform: None,
                             condition: Some(true_condition),
+                            ..Default::default()
                         },
                     )
                     .unwrap();
@@ -225,6 +228,7 @@ fn bench_workflow_with_condition(c: &mut Criterion) {
                         Flow {
                             transform: None,
                             condition: Some(false_condition),
+                            ..Default::default()
                         },
                     )
                     .unwrap();
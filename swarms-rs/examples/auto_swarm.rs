@@ -25,7 +25,7 @@ async fn main() -> Result<()> {
         .user_name("M4n5ter")
         .enable_autosave()
         .max_loops(1)
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .build();
 
     // MultiAgentOrchestrator will set the system_prompt for boss automatically.
@@ -0,0 +1,38 @@
+use std::env;
+
+use anyhow::Result;
+use swarms_rs::{agent::chat_session::ChatSession, llm::provider::openai::OpenAI};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_line_number(true)
+                .with_file(true),
+        )
+        .init();
+
+    let base_url = env::var("DEEPSEEK_BASE_URL").unwrap();
+    let api_key = env::var("DEEPSEEK_API_KEY").unwrap();
+    let client = OpenAI::from_url(base_url, api_key).set_model("deepseek-chat");
+    let agent = client
+        .agent_builder()
+        .system_prompt("You are a helpful assistant.")
+        .agent_name("ChatAgent")
+        .build();
+
+    let mut session = ChatSession::new(agent).user_name("M4n5ter");
+    let response = session.send("My favorite language is Rust.").await?;
+    println!("{response}");
+    let response = session.send("What is my favorite language?").await?;
+    println!("{response}");
+
+    session.save("./temp/chat_session.json").await?;
+    println!("{}", session.history());
+
+    Ok(())
+}
@@ -27,7 +27,7 @@ async fn main() -> Result<()> {
         .max_loops(1)
         .temperature(0.3)
         .enable_autosave()
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .add_stop_word("<DONE>")
         .build();
 
@@ -39,7 +39,7 @@ async fn main() -> Result<()> {
         .max_loops(1)
         .temperature(0.3)
         .enable_autosave()
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .add_stop_word("<DONE>")
         .build();
 
@@ -27,7 +27,7 @@ async fn main() -> Result<()> {
         .max_loops(1)
         .temperature(0.3)
         .enable_autosave()
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .build();
 
     let agent_2 = client
@@ -38,7 +38,7 @@ async fn main() -> Result<()> {
         .max_loops(1)
         .temperature(0.3)
         .enable_autosave()
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .build();
 
     let agents = vec![agent_1, agent_2]
@@ -0,0 +1,40 @@
+use std::env;
+
+use anyhow::Result;
+use swarms_rs::{agent::simulated_user::SimulatedUser, llm::provider::openai::OpenAI};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_line_number(true)
+                .with_file(true),
+        )
+        .init();
+
+    let base_url = env::var("DEEPSEEK_BASE_URL").unwrap();
+    let api_key = env::var("DEEPSEEK_API_KEY").unwrap();
+    let client = OpenAI::from_url(base_url, api_key).set_model("deepseek-chat");
+    let support = client
+        .agent_builder()
+        .system_prompt("You are the support agent of an online shop, refunds need an order id.")
+        .agent_name("Support")
+        .build();
+
+    let customer = SimulatedUser::new(
+        client,
+        "An impatient customer who only gives details when asked.",
+        "Get a refund for order 42, which arrived broken.",
+    )
+    .name("Customer");
+
+    let dialogue = customer.converse(&support, 5).await?;
+    println!("{}", dialogue.transcript());
+    println!("Goal achieved: {}", dialogue.goal_achieved);
+
+    Ok(())
+}
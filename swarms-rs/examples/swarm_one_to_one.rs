@@ -27,7 +27,7 @@ async fn main() -> Result<()> {
         .enable_autosave()
         .temperature(0.3)
         .max_loops(1)
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .build();
     let agent_2 = client
         .agent_builder()
@@ -37,7 +37,7 @@ async fn main() -> Result<()> {
         .enable_autosave()
         .temperature(0.3)
         .max_loops(1)
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .build();
 
     let result = one_to_one(
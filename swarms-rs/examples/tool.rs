@@ -29,7 +29,7 @@ async fn main() -> Result<()> {
         .user_name("M4n5ter")
         .enable_autosave()
         .max_loops(1)
-        .save_sate_dir("./temp")
+        .save_state_dir("./temp")
         .add_tool(SubTool)
         .add_tool(Add) // or AddTool, Add is a pub static variable of AddTool
         .add_tool(MultiplyTool)
@@ -96,9 +96,12 @@ fn mul(x: f64, y: f64) -> Result<f64, CalcError> {
 
 /// This shows how to use a struct as parameter.
 /// We can describe the field of the struct, just see the `ExecShell` struct.
-#[tool(description = "
+#[tool(
+    description = "
 Execute the shell command, can execute multiple commands at once.
-")]
+",
+    capabilities = "code_exec"
+)]
 fn exec(x: ExecShell) -> Result<String, CalcError> {
     tracing::info!("exec tool is called");
     let results = serde_json::to_string(&x).unwrap();
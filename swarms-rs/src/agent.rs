@@ -1,12 +1,57 @@
+use environment::EnvironmentContext;
 use futures::future::BoxFuture;
+use grounding::GroundingReport;
+use prompt_guard::InjectionDetection;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use state_manager::RetentionPolicy;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use tokio::sync::broadcast;
-
-use crate::{persistence, tool::ToolError};
-
+use wire_log::WireLogConfig;
+
+use crate::{
+    conversation::{AgentConversation, DedupStats, MessageDedup},
+    dry_run::DryRunStep,
+    error::{CategorizedError, ErrorCategory},
+    llm::{
+        request::{ResponseFormat, ToolDefinition},
+        tokenizer::count_tokens,
+    },
+    locale::Locale,
+    persistence,
+    tenant::TenantId,
+    tool::{ResourceLimits, ToolError, ToolPermissions},
+};
+
+pub mod boss_prompt;
+pub mod chat_session;
+pub mod definition;
+pub mod entity_memory;
+pub mod environment;
+pub mod episodic_memory;
+pub mod grounding;
+pub mod interactive;
+pub mod isolation;
+pub mod persona;
+pub mod prompt_guard;
+pub mod prompt_store;
+pub mod scratchpad;
+pub mod semantic_cache;
+pub mod simulated_user;
+pub mod state_manager;
 pub mod swarms_agent;
+pub mod tool_analytics;
+pub mod tool_examples;
+pub mod tool_selection;
+pub mod watermark;
+pub mod wire_log;
 
 #[derive(Debug, Error)]
 pub enum AgentError {
@@ -18,6 +63,8 @@ pub enum AgentError {
     BroadcastError(#[from] broadcast::error::SendError<Result<String, String>>),
     #[error("Persistence error: {0}")]
     PersistenceError(#[from] persistence::PersistenceError),
+    #[error("Conversation error: {0}")]
+    ConversationError(#[from] crate::conversation::ConversationError),
     #[error("Invalid save state path: {0}")]
     InvalidSaveStatePath(String),
     #[error("Completion error: {0}")]
@@ -28,83 +75,305 @@ pub enum AgentError {
     ToolNotFound(String),
     #[error("Tool error: {0}")]
     ToolError(#[from] ToolError),
+    #[error("Interactive channel closed")]
+    InteractionClosed,
+    #[error("Model {0} timed out")]
+    ModelTimeout(String),
+    #[error("Prompt store error: {0}")]
+    PromptStoreError(#[from] prompt_store::PromptStoreError),
     #[cfg(test)]
     #[error("Test error: {0}")]
     TestError(String),
 }
 
-#[derive(Clone)]
-pub struct AgentConfigBuilder {
-    config: AgentConfig,
+impl AgentError {
+    /// Whether the agent should retry after this error, provider errors are only retried
+    /// if they are transient.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AgentError::CompletionError(e) => e.is_retryable(),
+            AgentError::InteractionClosed
+            | AgentError::ToolError(ToolError::PermissionDenied { .. })
+            | AgentError::ToolError(ToolError::LimitExceeded { .. }) => false,
+            _ => true,
+        }
+    }
 }
 
-impl AgentConfigBuilder {
-    pub fn agent_name(mut self, name: impl Into<String>) -> Self {
-        self.config.name = name.into();
-        self
+impl CategorizedError for AgentError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            AgentError::IoError(_) | AgentError::SerdeError(_) => ErrorCategory::Persistence,
+            AgentError::BroadcastError(_) => ErrorCategory::Other,
+            AgentError::PersistenceError(e) => e.category(),
+            AgentError::ConversationError(e) => e.category(),
+            AgentError::InvalidSaveStatePath(_) => ErrorCategory::Validation,
+            AgentError::CompletionError(e) => e.category(),
+            AgentError::NoChoiceFound => ErrorCategory::Provider,
+            AgentError::ToolNotFound(_) => ErrorCategory::Tool,
+            AgentError::ToolError(e) => e.category(),
+            AgentError::InteractionClosed => ErrorCategory::Cancelled,
+            AgentError::ModelTimeout(_) => ErrorCategory::Timeout,
+            AgentError::PromptStoreError(e) => e.category(),
+            #[cfg(test)]
+            AgentError::TestError(_) => ErrorCategory::Other,
+        }
     }
+}
 
-    pub fn user_name(mut self, name: impl Into<String>) -> Self {
-        self.config.user_name = name.into();
-        self
-    }
+/// The configuration methods shared by [`AgentConfigBuilder`] and
+/// [`SwarmsAgentBuilder`](swarms_agent::SwarmsAgentBuilder), so both builders are configured
+/// the same way. Expands to methods of a builder with a `config: AgentConfig` field.
+macro_rules! agent_config_methods {
+    () => {
+        /// Use a stable id instead of a random one, so states and logs of the agent can be
+        /// correlated across restarts.
+        pub fn agent_id(mut self, id: impl Into<String>) -> Self {
+            self.config.id = id.into();
+            self
+        }
 
-    pub fn description(mut self, description: impl Into<String>) -> Self {
-        self.config.description = Some(description.into());
-        self
-    }
+        pub fn agent_name(mut self, name: impl Into<String>) -> Self {
+            self.config.name = name.into();
+            self
+        }
 
-    pub fn temperature(mut self, temperature: f64) -> Self {
-        self.config.temperature = temperature;
-        self
-    }
+        pub fn user_name(mut self, name: impl Into<String>) -> Self {
+            self.config.user_name = name.into();
+            self
+        }
 
-    pub fn max_loops(mut self, max_loops: u32) -> Self {
-        self.config.max_loops = max_loops;
-        self
-    }
+        pub fn model_name(mut self, name: impl Into<String>) -> Self {
+            self.config.model_name = name.into();
+            self
+        }
 
-    pub fn max_tokens(mut self, max_tokens: u64) -> Self {
-        self.config.max_tokens = max_tokens;
-        self
-    }
+        pub fn description(mut self, description: impl Into<String>) -> Self {
+            self.config.description = Some(description.into());
+            self
+        }
 
-    pub fn enable_plan(mut self, planning_prompt: impl Into<Option<String>>) -> Self {
-        self.config.plan_enabled = true;
-        self.config.planning_prompt = planning_prompt.into();
-        self
-    }
+        pub fn temperature(mut self, temperature: f64) -> Self {
+            self.config.temperature = temperature;
+            self
+        }
 
-    pub fn enable_autosave(mut self) -> Self {
-        self.config.autosave = true;
-        self
-    }
+        /// Ask the model to sample deterministically with this seed, if its provider
+        /// supports it.
+        pub fn seed(mut self, seed: u64) -> Self {
+            self.config.seed = Some(seed);
+            self
+        }
 
-    pub fn retry_attempts(mut self, retry_attempts: u32) -> Self {
-        self.config.retry_attempts = retry_attempts;
-        self
-    }
+        pub fn max_tokens(mut self, max_tokens: u64) -> Self {
+            self.config.max_tokens = max_tokens;
+            self
+        }
 
-    pub fn enable_rag_every_loop(mut self) -> Self {
-        self.config.rag_every_loop = true;
-        self
-    }
+        pub fn max_loops(mut self, max_loops: u32) -> Self {
+            self.config.max_loops = max_loops;
+            self
+        }
 
-    pub fn save_sate_path(mut self, path: impl Into<String>) -> Self {
-        self.config.save_state_dir = Some(path.into());
-        self
-    }
+        /// Plan before each run with the planning prompt, or the default planning prompt of
+        /// the agent's locale if `None`.
+        pub fn enable_plan(mut self, planning_prompt: impl Into<Option<String>>) -> Self {
+            self.config.plan_enabled = true;
+            self.config.planning_prompt = planning_prompt.into();
+            self
+        }
 
-    pub fn add_stop_word(mut self, stop_word: impl Into<String>) -> Self {
-        self.config.stop_words.insert(stop_word.into());
-        self
-    }
+        pub fn enable_autosave(mut self) -> Self {
+            self.config.autosave = true;
+            self
+        }
 
-    pub fn stop_words(self, stop_words: Vec<String>) -> Self {
-        stop_words
-            .into_iter()
-            .fold(self, |builder, stop_word| builder.add_stop_word(stop_word))
-    }
+        pub fn retry_attempts(mut self, retry_attempts: u32) -> Self {
+            self.config.retry_attempts = retry_attempts;
+            self
+        }
+
+        pub fn enable_rag_every_loop(mut self) -> Self {
+            self.config.rag_every_loop = true;
+            self
+        }
+
+        /// Save the task states in this directory.
+        pub fn save_state_dir(mut self, path: impl Into<String>) -> Self {
+            self.config.save_state_dir = Some(path.into());
+            self
+        }
+
+        #[deprecated(note = "use `save_state_dir` instead")]
+        pub fn save_sate_dir(self, path: impl Into<String>) -> Self {
+            self.save_state_dir(path)
+        }
+
+        #[deprecated(note = "use `save_state_dir` instead")]
+        pub fn save_sate_path(self, path: impl Into<String>) -> Self {
+            self.save_state_dir(path)
+        }
+
+        /// Limit the saved states, the state directory is pruned after every save.
+        pub fn state_retention(
+            mut self,
+            policy: $crate::agent::state_manager::RetentionPolicy,
+        ) -> Self {
+            self.config.state_retention = Some(policy);
+            self
+        }
+
+        /// Apply the global defaults, the directory of agent states, the seed and the max
+        /// concurrency.
+        pub fn swarms_config(mut self, config: &$crate::config::SwarmsConfig) -> Self {
+            if let Some(dir) = &config.save_state_dir {
+                self.config.save_state_dir = Some(dir.clone());
+            }
+            if let Some(seed) = config.seed {
+                self.config.seed = Some(seed);
+            }
+            if let Some(max_concurrency) = config.max_concurrency {
+                self = self.max_concurrency(max_concurrency);
+            }
+            self
+        }
+
+        /// Max number of tasks `run_multiple_tasks` runs at the same time.
+        pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+            self.config.max_concurrency = Some(max_concurrency.max(1));
+            self
+        }
+
+        /// Save the agent's states in the tenant's directory.
+        pub fn tenant(mut self, tenant_id: $crate::tenant::TenantId) -> Self {
+            self.config.tenant_id = Some(tenant_id);
+            self
+        }
+
+        /// Append every model request and response to a file, with secrets redacted.
+        pub fn enable_wire_log(mut self, config: $crate::agent::wire_log::WireLogConfig) -> Self {
+            self.config.wire_log = Some(config);
+            self
+        }
+
+        pub fn add_stop_word(mut self, stop_word: impl Into<String>) -> Self {
+            self.config.stop_words.insert(stop_word.into());
+            self
+        }
+
+        pub fn stop_words(self, stop_words: Vec<String>) -> Self {
+            stop_words
+                .into_iter()
+                .fold(self, |builder, stop_word| builder.add_stop_word(stop_word))
+        }
+
+        /// Strip stop words from the final response.
+        pub fn strip_stop_words(mut self) -> Self {
+            self.config.strip_stop_words = true;
+            self
+        }
+
+        pub fn stop_word_match(mut self, stop_word_match: $crate::agent::StopWordMatch) -> Self {
+            self.config.stop_word_match = stop_word_match;
+            self
+        }
+
+        pub fn stop_word_scope(mut self, stop_word_scope: $crate::agent::StopWordScope) -> Self {
+            self.config.stop_word_scope = stop_word_scope;
+            self
+        }
+
+        /// Compress older turns with a summary before the history exceeds the model's context
+        /// window.
+        pub fn enable_context_compression(mut self, context_window: u64) -> Self {
+            self.config.context_window = Some(context_window);
+            self
+        }
+
+        /// Give up on a model after `timeout` and try the next fallback model.
+        pub fn model_timeout(mut self, timeout: std::time::Duration) -> Self {
+            self.config.model_timeout = Some(timeout);
+            self
+        }
+
+        /// Stop the run once it used more than `token_budget` (estimated) tokens.
+        pub fn token_budget(mut self, token_budget: u64) -> Self {
+            self.config.token_budget = Some(token_budget);
+            self
+        }
+
+        /// Restrict the capabilities the agent's tools may use, calls of tools which need a
+        /// denied capability fail with [`ToolError::PermissionDenied`].
+        ///
+        /// [`ToolError::PermissionDenied`]: crate::tool::ToolError::PermissionDenied
+        pub fn tool_permissions(mut self, tool_permissions: $crate::tool::ToolPermissions) -> Self {
+            self.config.tool_permissions = tool_permissions;
+            self
+        }
+
+        /// Limit the resources the agent's code execution and file tools use per run, calls
+        /// which exceed a limit fail with [`ToolError::LimitExceeded`].
+        ///
+        /// [`ToolError::LimitExceeded`]: crate::tool::ToolError::LimitExceeded
+        pub fn resource_limits(mut self, resource_limits: $crate::tool::ResourceLimits) -> Self {
+            self.config.resource_limits = resource_limits;
+            self
+        }
+
+        /// Show the model an example call of each tool, generated from the tool's schema. Helps
+        /// weaker models to call tools with the right arguments.
+        pub fn tool_examples(mut self, tool_examples: bool) -> Self {
+            self.config.tool_examples = tool_examples;
+            self
+        }
+
+        /// Send every tool in strict mode, so providers which support it, like OpenAI, only
+        /// call tools with arguments which match their schema. Tools defined with
+        /// `#[tool(strict)]` are always sent in strict mode.
+        pub fn strict_tools(mut self, strict_tools: bool) -> Self {
+            self.config.strict_tools = strict_tools;
+            self
+        }
+
+        /// The language of the prompt scaffolding the agent adds, see [`locale`](crate::locale).
+        pub fn locale(mut self, locale: $crate::locale::Locale) -> Self {
+            self.config.locale = locale;
+            self
+        }
+
+        /// Tell the model the current date and time and facts of its environment on every run,
+        /// see [`environment`](crate::agent::environment).
+        pub fn environment(
+            mut self,
+            environment: $crate::agent::environment::EnvironmentContext,
+        ) -> Self {
+            self.config.environment = Some(environment);
+            self
+        }
+
+        /// Drop consecutive duplicate messages from the prompts, the number of dropped
+        /// messages is reported in [`AgentRunResult::dedup`](crate::agent::AgentRunResult::dedup).
+        pub fn dedup_messages(mut self, dedup: $crate::conversation::MessageDedup) -> Self {
+            self.config.message_dedup = Some(dedup);
+            self
+        }
+
+        /// Tag the agent, see [`AgentConfig::metadata`](crate::agent::AgentConfig::metadata).
+        pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+            self.config.metadata.insert(key.into(), value.into());
+            self
+        }
+    };
+}
+pub(crate) use agent_config_methods;
+
+#[derive(Clone)]
+pub struct AgentConfigBuilder {
+    config: AgentConfig,
+}
+
+impl AgentConfigBuilder {
+    agent_config_methods!();
 
     pub fn build(self) -> AgentConfig {
         self.config
@@ -130,6 +399,64 @@ pub struct AgentConfig {
     pub rag_every_loop: bool,
     pub save_state_dir: Option<String>,
     pub stop_words: HashSet<String>,
+    /// Strip stop words from the final response.
+    #[serde(default)]
+    pub strip_stop_words: bool,
+    #[serde(default)]
+    pub stop_word_match: StopWordMatch,
+    #[serde(default)]
+    pub stop_word_scope: StopWordScope,
+    /// The model's context window in tokens, enables automatic context compression.
+    pub context_window: Option<u64>,
+    /// Timeout of a single completion request, the next fallback model is tried on timeout.
+    pub model_timeout: Option<Duration>,
+    /// Max number of (estimated) tokens a single run may use.
+    pub token_budget: Option<u64>,
+    /// States are saved in the tenant's directory inside `save_state_dir`.
+    #[serde(default)]
+    pub tenant_id: Option<TenantId>,
+    /// Log every model request and response, see [`wire_log`].
+    #[serde(default)]
+    pub wire_log: Option<WireLogConfig>,
+    /// Capabilities the agent's tools may use, all capabilities are allowed by default.
+    #[serde(default)]
+    pub tool_permissions: ToolPermissions,
+    /// Per-run limits of the tools which execute code or access files, unlimited by default.
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+    /// List an example call of every tool sent to the model in the system prompt, see
+    /// [`tool_examples`].
+    #[serde(default)]
+    pub tool_examples: bool,
+    /// Send every tool in strict mode, see [`ToolDefinition::strict`].
+    ///
+    /// [`ToolDefinition::strict`]: crate::llm::request::ToolDefinition::strict
+    #[serde(default)]
+    pub strict_tools: bool,
+    /// Language of the prompt scaffolding the agent adds, e.g. the section headings of its
+    /// memories and the default planning prompt.
+    #[serde(default)]
+    pub locale: Locale,
+    /// Date, time and facts of the environment added to the system prompt of every run.
+    #[serde(default)]
+    pub environment: Option<EnvironmentContext>,
+    /// Business context of the agent, e.g. its team. Copied into the agent's outputs in the
+    /// swarm metadata, and `{{metadata.<key>}}` in the system prompt and the task is replaced
+    /// with the value.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Prune the state directory after every save, see [`state_manager`].
+    #[serde(default)]
+    pub state_retention: Option<RetentionPolicy>,
+    /// Drop consecutive duplicate messages from the prompts.
+    #[serde(default)]
+    pub message_dedup: Option<MessageDedup>,
+    /// Seed of the model's sampling, for reproducible runs with providers which support it.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Max number of tasks `run_multiple_tasks` runs at the same time, unlimited by default.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
 }
 
 impl AgentConfig {
@@ -138,6 +465,19 @@ impl AgentConfig {
             config: AgentConfig::default(),
         }
     }
+
+    /// Replace `{{metadata.<key>}}` in the text with the value of the key, placeholders of
+    /// unknown keys are kept.
+    pub fn render_metadata(&self, text: &str) -> String {
+        if !text.contains("{{metadata.") {
+            return text.to_owned();
+        }
+        self.metadata
+            .iter()
+            .fold(text.to_owned(), |text, (key, value)| {
+                text.replace(&format!("{{{{metadata.{key}}}}}"), value)
+            })
+    }
 }
 
 impl Default for AgentConfig {
@@ -159,14 +499,302 @@ impl Default for AgentConfig {
             rag_every_loop: false,
             save_state_dir: None,
             stop_words: HashSet::new(),
+            strip_stop_words: false,
+            stop_word_match: StopWordMatch::default(),
+            stop_word_scope: StopWordScope::default(),
+            context_window: None,
+            model_timeout: None,
+            token_budget: None,
+            tenant_id: None,
+            wire_log: None,
+            tool_permissions: ToolPermissions::default(),
+            resource_limits: ResourceLimits::default(),
+            tool_examples: false,
+            strict_tools: false,
+            locale: Locale::default(),
+            environment: None,
+            metadata: BTreeMap::new(),
+            state_retention: None,
+            message_dedup: None,
+            seed: None,
+            max_concurrency: None,
         }
     }
 }
 
+/// How a stop word is matched against a response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopWordMatch {
+    /// The response contains the stop word anywhere.
+    #[default]
+    Contains,
+    /// The response ends with the stop word, trailing whitespace is ignored.
+    Suffix,
+}
+
+impl StopWordMatch {
+    /// Find the stop word in `response`, returns the stop word if it matches.
+    pub fn find<'a>(
+        &self,
+        stop_words: impl IntoIterator<Item = &'a String>,
+        response: &str,
+    ) -> Option<&'a String> {
+        stop_words.into_iter().find(|word| match self {
+            StopWordMatch::Contains => response.contains(word.as_str()),
+            StopWordMatch::Suffix => response.trim_end().ends_with(word.as_str()),
+        })
+    }
+
+    /// Remove the stop words from `response`.
+    pub fn strip<'a>(
+        &self,
+        stop_words: impl IntoIterator<Item = &'a String>,
+        response: &str,
+    ) -> String {
+        stop_words
+            .into_iter()
+            .fold(response.to_owned(), |response, word| match self {
+                StopWordMatch::Contains => response.replace(word.as_str(), ""),
+                StopWordMatch::Suffix => match response.trim_end().strip_suffix(word.as_str()) {
+                    Some(stripped) => stripped.to_owned(),
+                    None => response,
+                },
+            })
+            .trim_end()
+            .to_owned()
+    }
+}
+
+/// Which output is checked for stop words.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StopWordScope {
+    /// Check the response of each loop on its own.
+    #[default]
+    PerLoop,
+    /// Check the whole response accumulated over all loops.
+    PerResponse,
+}
+
+/// One-off overrides for a single run, the agent's shared config is not changed.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Replace the agent's system prompt for this run.
+    pub system_prompt_override: Option<String>,
+    /// Extra context appended to the system prompt for this run.
+    pub extra_context: Option<String>,
+    /// Replace the agent's temperature for this run.
+    pub temperature_override: Option<f64>,
+    /// Cancel the run from another task.
+    pub cancellation: Option<CancellationToken>,
+    /// Further restrict the capabilities of the agent's tools for this run.
+    pub tool_permissions: Option<ToolPermissions>,
+    /// Constrain the answers of this run to JSON, see
+    /// [`structured_output::run_structured`](crate::structured_output::run_structured).
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// Why an agent run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// A stop word was found in the response.
+    StopWord,
+    /// The agent ran `max_loops` loops.
+    MaxLoops,
+    /// All retry attempts of a loop failed.
+    RetriesExhausted,
+    /// The run was cancelled with a [`CancellationToken`].
+    Cancelled,
+    /// The run used up its token budget.
+    BudgetExceeded,
+    /// The answer of a similar task was returned from the agent's semantic cache.
+    Cached,
+    /// The agent doesn't report why it stopped.
+    Unspecified,
+}
+
+impl StopReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopReason::StopWord => "stop_word",
+            StopReason::MaxLoops => "max_loops",
+            StopReason::RetriesExhausted => "retries_exhausted",
+            StopReason::Cancelled => "cancelled",
+            StopReason::BudgetExceeded => "budget_exceeded",
+            StopReason::Cached => "cached",
+            StopReason::Unspecified => "unspecified",
+        }
+    }
+
+    /// Whether the agent finished the task, `false` if it gave up or was stopped early.
+    pub fn is_completed(&self) -> bool {
+        matches!(
+            self,
+            StopReason::StopWord
+                | StopReason::MaxLoops
+                | StopReason::Cached
+                | StopReason::Unspecified
+        )
+    }
+}
+
+/// Cooperatively cancel a run, the agent stops before its next loop or retry attempt.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Estimated token usage of a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// A tool call made during a run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    /// Name of the agent which made the call.
+    #[serde(default)]
+    pub agent: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+    /// The tool's output, or the error message if the call failed.
+    pub output: Result<String, String>,
+    /// How long the tool ran, `0` if the call was denied.
+    #[serde(default)]
+    pub duration_ms: u64,
+}
+
+/// The saved state of an agent's task, see [`AgentConfig::autosave`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AgentState {
+    pub agent_id: String,
+    pub agent_name: String,
+    pub task: String,
+    pub conversation: AgentConversation,
+}
+
+impl AgentState {
+    /// Upgrades of saved states, see [`persistence::Migration`].
+    pub(crate) const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+}
+
+/// The detailed result of [`Agent::run_detailed`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentRunResult {
+    /// The final answer, the same as [`Agent::run`] returns.
+    pub answer: String,
+    /// The response of each loop.
+    pub responses: Vec<String>,
+    pub tool_calls: Vec<ToolCallRecord>,
+    pub usage: TokenUsage,
+    pub duration: Duration,
+    pub stop_reason: StopReason,
+    /// Prompt injections found in tool outputs and retrieved documents.
+    #[serde(default)]
+    pub injections: Vec<InjectionDetection>,
+    /// How well the retrieved context supports the answer, if the agent checks it.
+    #[serde(default)]
+    pub grounding: Option<GroundingReport>,
+    /// Duplicate messages dropped from the prompts, see [`AgentConfig::message_dedup`].
+    #[serde(default)]
+    pub dedup: DedupStats,
+    /// The version of the system prompt, if the agent takes it from a
+    /// [`PromptStore`](prompt_store::PromptStore).
+    #[serde(default)]
+    pub prompt: Option<prompt_store::PromptRef>,
+    /// Who produced the answer, if the agent tags its answers with a
+    /// [`Watermark`](watermark::Watermark).
+    #[serde(default)]
+    pub provenance: Option<watermark::Provenance>,
+}
+
 pub trait Agent: Send + Sync {
     /// Runs the autonomous agent loop to complete the given task.
     fn run(&self, task: String) -> BoxFuture<Result<String, AgentError>>;
 
+    /// Runs the autonomous agent loop with one-off [`RunOptions`].
+    ///
+    /// Agents that don't support overrides ignore the options and behave like [`Agent::run`].
+    fn run_with_options(
+        &self,
+        task: String,
+        options: RunOptions,
+    ) -> BoxFuture<'_, Result<String, AgentError>> {
+        let _ = options;
+        self.run(task)
+    }
+
+    /// Runs the autonomous agent loop and returns the answer with details about the run.
+    ///
+    /// Agents that don't track details only fill in the answer and the duration.
+    fn run_detailed(&self, task: String) -> BoxFuture<'_, Result<AgentRunResult, AgentError>> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let answer = self.run(task).await?;
+            Ok(AgentRunResult {
+                responses: vec![answer.clone()],
+                answer,
+                tool_calls: vec![],
+                usage: TokenUsage::default(),
+                duration: start.elapsed(),
+                stop_reason: StopReason::Unspecified,
+                injections: vec![],
+                grounding: None,
+                dedup: DedupStats::default(),
+                prompt: None,
+                provenance: None,
+            })
+        })
+    }
+
+    /// Runs the autonomous agent loop with one-off [`RunOptions`] and returns the details of
+    /// the run.
+    ///
+    /// Agents that don't support overrides ignore the options and behave like
+    /// [`Agent::run_detailed`].
+    fn run_detailed_with_options(
+        &self,
+        task: String,
+        options: RunOptions,
+    ) -> BoxFuture<'_, Result<AgentRunResult, AgentError>> {
+        let _ = options;
+        self.run_detailed(task)
+    }
+
+    /// Describe the call [`Agent::run`] would make with the task, without calling any LLM.
+    ///
+    /// Agents that don't know their model only estimate the prompt tokens of the task.
+    fn dry_run(&self, task: String) -> DryRunStep {
+        DryRunStep {
+            agent: self.name(),
+            model: None,
+            system_prompt: None,
+            estimated_prompt_tokens: count_tokens("", &task) as u64,
+            estimated_completion_tokens: 0,
+            prompt: task,
+        }
+    }
+
     /// Run multiple tasks concurrently
     fn run_multiple_tasks(
         &mut self,
@@ -179,7 +807,7 @@ pub trait Agent: Send + Sync {
     /// Query long term memory and add the results to short term memory
     fn query_long_term_memory(&self, task: String) -> BoxFuture<Result<(), AgentError>>;
 
-    /// Save the agent state to a file
+    /// Save the agent state to a file, fails if the agent has no conversation for the task
     fn save_task_state(&self, task: String) -> BoxFuture<Result<(), AgentError>>;
 
     /// Check a response to determine if it is complete
@@ -194,6 +822,21 @@ pub trait Agent: Send + Sync {
     /// Get agent description
     fn description(&self) -> String;
 
+    /// Key-value tags of the agent, see [`AgentConfig::metadata`].
+    fn metadata(&self) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+
+    /// Name of the model the agent runs on, if it is known.
+    fn model_name(&self) -> Option<String> {
+        None
+    }
+
+    /// Definitions of the tools the agent can call.
+    fn tools(&self) -> Vec<ToolDefinition> {
+        Vec::new()
+    }
+
     fn clone_box(&self) -> Box<dyn Agent>;
 }
 
@@ -0,0 +1,195 @@
+//! System prompts of boss agents, which route tasks to other agents.
+//!
+//! A boss only routes well if it knows what the agents can do and when it is asked. The
+//! [`BossPrompt`] lists every agent with its description, model and tools, and adds the
+//! current date and time and any context variables of the caller. The agents are captured
+//! once, the date is taken whenever the prompt is built, so bosses rebuild it on every run.
+
+use std::collections::BTreeMap;
+
+use super::{Agent, environment};
+use crate::locale::{self, Locale};
+
+#[derive(Debug, Clone)]
+pub struct BossPrompt {
+    instructions: String,
+    agents: Vec<AgentProfile>,
+    context: BTreeMap<String, String>,
+    current_time: bool,
+    locale: Locale,
+}
+
+// What the boss is told about an agent
+#[derive(Debug, Clone)]
+struct AgentProfile {
+    name: String,
+    description: String,
+    model: Option<String>,
+    tools: Vec<(String, String)>,
+}
+
+impl BossPrompt {
+    /// A prompt with the boss's `instructions` and the current date and time.
+    pub fn new(instructions: impl Into<String>) -> Self {
+        Self {
+            instructions: instructions.into(),
+            agents: Vec::new(),
+            context: BTreeMap::new(),
+            current_time: true,
+            locale: Locale::default(),
+        }
+    }
+
+    pub fn agent(mut self, agent: &dyn Agent) -> Self {
+        self.add_agent(agent);
+        self
+    }
+
+    pub fn agents<'a>(mut self, agents: impl IntoIterator<Item = &'a dyn Agent>) -> Self {
+        for agent in agents {
+            self.add_agent(agent);
+        }
+        self
+    }
+
+    /// Add an agent to the list, e.g. one which joined after the prompt was created.
+    pub fn add_agent(&mut self, agent: &dyn Agent) {
+        self.agents.push(AgentProfile {
+            name: agent.name(),
+            description: agent.description(),
+            model: agent.model_name(),
+            tools: agent
+                .tools()
+                .into_iter()
+                .map(|tool| (tool.name, tool.description))
+                .collect(),
+        });
+    }
+
+    /// A context variable, e.g. the user's locale. Setting a key again replaces the value.
+    pub fn context(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.context.insert(key.into(), value.into());
+        self
+    }
+
+    /// Whether to tell the boss the current date and time, `true` by default.
+    pub fn current_time(mut self, current_time: bool) -> Self {
+        self.current_time = current_time;
+        self
+    }
+
+    /// Replace the boss's instructions.
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    /// Language of the section headings, English by default. The instructions aren't
+    /// translated.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn build(&self) -> String {
+        let catalog = self.locale.catalog();
+        let mut prompt = self.instructions.trim().to_owned();
+
+        if self.current_time {
+            let now = environment::local_time();
+            let heading = locale::heading(catalog.current_date_and_time);
+            prompt.push_str(&format!("\n\n{heading}\n{now}"));
+        }
+
+        if !self.context.is_empty() {
+            prompt.push_str(&format!("\n\n{}", locale::heading(catalog.context)));
+            for (key, value) in &self.context {
+                prompt.push_str(&format!("\n- {key}: {value}"));
+            }
+        }
+
+        prompt.push_str(&format!(
+            "\n\n{}",
+            locale::heading(catalog.available_agents)
+        ));
+        if self.agents.is_empty() {
+            prompt.push_str(&format!("\n{}", catalog.no_agents));
+        }
+        for agent in &self.agents {
+            prompt.push_str(&format!("\n- {}: {}", agent.name, agent.description));
+            if let Some(model) = &agent.model {
+                prompt.push_str(&format!("\n  {}: {model}", catalog.model));
+            }
+            if agent.tools.is_empty() {
+                prompt.push_str(&format!("\n  {}: {}", catalog.tools, catalog.no_tools));
+            } else {
+                prompt.push_str(&format!("\n  {}:", catalog.tools));
+                for (name, description) in &agent.tools {
+                    prompt.push_str(&format!("\n    - {name}: {description}"));
+                }
+            }
+        }
+
+        prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use swarms_macro::tool;
+
+    use super::*;
+    use crate::{
+        self as swarms_rs, agent::swarms_agent::SwarmsAgentBuilder, test_support::FnModel,
+    };
+
+    fn echo_model() -> FnModel {
+        FnModel::reply("echo", "echo")
+    }
+
+    #[tool(description = "Search the web")]
+    fn search(query: String) -> Result<String, std::io::Error> {
+        Ok(query)
+    }
+
+    #[test]
+    fn test_build() {
+        let researcher = SwarmsAgentBuilder::new_with_model(echo_model())
+            .agent_name("researcher")
+            .description("Finds sources")
+            .add_tool(Search)
+            .build();
+        let writer = SwarmsAgentBuilder::new_with_model(echo_model())
+            .agent_name("writer")
+            .description("Writes the report")
+            .build();
+        let prompt = BossPrompt::new("Route the task.\n")
+            .agents([&researcher as &dyn Agent, &writer])
+            .context("locale", "de-DE")
+            .current_time(false);
+        assert_eq!(
+            prompt.build(),
+            "Route the task.\n\n### Context:\n- locale: de-DE\n\n### Available Agents:\n\
+             - researcher: Finds sources\n  Model: echo\n  Tools:\n    - search: Search the web\n\
+             - writer: Writes the report\n  Model: echo\n  Tools: none"
+        );
+
+        assert!(
+            prompt
+                .clone()
+                .current_time(true)
+                .build()
+                .contains("### Current Date and Time:\n")
+        );
+
+        let german = prompt
+            .instructions("Leite die Aufgabe weiter.")
+            .locale(Locale::German);
+        assert_eq!(
+            german.build(),
+            "Leite die Aufgabe weiter.\n\n### Kontext:\n- locale: de-DE\n\n### Verfügbare Agenten:\n\
+             - researcher: Finds sources\n  Modell: echo\n  Werkzeuge:\n    - search: Search the web\n\
+             - writer: Writes the report\n  Modell: echo\n  Werkzeuge: keine"
+        );
+    }
+}
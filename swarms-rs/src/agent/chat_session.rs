@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+
+pub use crate::conversation::ConversationFormat;
+use crate::{
+    conversation::{AgentConversation, Participant},
+    llm,
+};
+
+use super::{Agent, AgentError, swarms_agent::SwarmsAgent};
+
+/// A multi-turn chat with an agent, the conversation is kept across [`ChatSession::send`] calls.
+///
+/// Every message is a single turn of [`SwarmsAgent::chat`] with the conversation as its
+/// history, tool calls are executed like in a run. Unlike [`Agent::run`] it doesn't go through
+/// the run loop: there is no planning, no `max_loops` or stop words, no context compression
+/// and nothing is added to the agent's short-term memory or saved as its state.
+pub struct ChatSession<M>
+where
+    M: llm::Model + Clone + Send + Sync + 'static,
+    M::RawCompletionResponse: Clone + Send + Sync,
+{
+    agent: SwarmsAgent<M>,
+    user_name: String,
+    conversation: AgentConversation,
+}
+
+impl<M> ChatSession<M>
+where
+    M: llm::Model + Clone + Send + Sync + 'static,
+    M::RawCompletionResponse: Clone + Send + Sync,
+{
+    pub fn new(agent: SwarmsAgent<M>) -> Self {
+        let conversation = AgentConversation::new(agent.name());
+        Self {
+            agent,
+            user_name: "User".to_owned(),
+            conversation,
+        }
+    }
+
+    pub fn user_name(mut self, name: impl Into<String>) -> Self {
+        self.user_name = name.into();
+        self
+    }
+
+    /// Send a message to the agent and return its reply, both are added to the history.
+    ///
+    /// The agent answers with a single turn, see [`ChatSession`].
+    pub async fn send(&mut self, message: impl Into<String>) -> Result<String, AgentError> {
+        let message = message.into();
+        let history: Vec<llm::completion::Message> = (&self.conversation).into();
+        let response = self.agent.chat(message.clone(), history).await?;
+
+        self.conversation
+            .add(Participant::human(self.user_name.clone()), message);
+        self.conversation
+            .add(Participant::agent(self.agent.name()), response.clone());
+        Ok(response)
+    }
+
+    /// The conversation so far.
+    pub fn history(&self) -> &AgentConversation {
+        &self.conversation
+    }
+
+    pub fn agent(&self) -> &SwarmsAgent<M> {
+        &self.agent
+    }
+
+    /// Forget the conversation and start over.
+    pub fn reset(&mut self) {
+        self.conversation.clear();
+    }
+
+    /// Save the conversation to a JSON file.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), AgentError> {
+        AgentConversation::save_as_json(path.as_ref(), &self.conversation.history).await?;
+        Ok(())
+    }
+
+    /// Restore the conversation from a JSON file created by [`ChatSession::save`].
+    pub async fn load(&mut self, path: impl AsRef<Path>) -> Result<(), AgentError> {
+        self.conversation.history = self.conversation.load_from_json(path.as_ref()).await?;
+        Ok(())
+    }
+
+    /// Save the conversation in the given format, e.g. zstd-compressed.
+    pub async fn save_as(
+        &self,
+        path: impl AsRef<Path>,
+        format: ConversationFormat,
+    ) -> Result<(), AgentError> {
+        AgentConversation::save_with_format(path.as_ref(), &self.conversation.history, format)
+            .await?;
+        Ok(())
+    }
+
+    /// Restore the conversation from a file created with the same format.
+    pub async fn load_as(
+        &mut self,
+        path: impl AsRef<Path>,
+        format: ConversationFormat,
+    ) -> Result<(), AgentError> {
+        self.conversation.history =
+            AgentConversation::load_with_format(path.as_ref(), format).await?;
+        Ok(())
+    }
+
+    /// Save the conversation now and after every message. With an event log every change
+    /// is appended instead of rewriting the whole conversation.
+    pub async fn autosave(
+        &mut self,
+        path: impl Into<PathBuf>,
+        format: ConversationFormat,
+    ) -> Result<(), AgentError> {
+        self.conversation.autosave(path, format).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::{agent::swarms_agent::SwarmsAgentBuilder, test_support::FnModel};
+
+    #[tokio::test]
+    async fn test_send_is_a_single_turn() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let model = FnModel::new("model", {
+            let calls = calls.clone();
+            move |request| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                format!("seen {} messages", request.chat_history.len())
+            }
+        });
+        // The run loop would call the model three times per task
+        let agent = SwarmsAgentBuilder::new_with_model(model)
+            .agent_name("assistant")
+            .max_loops(3)
+            .build();
+        let mut session = ChatSession::new(agent);
+
+        assert_eq!(session.send("hi").await.unwrap(), "seen 0 messages");
+        assert_eq!(session.send("again").await.unwrap(), "seen 2 messages");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(session.history().history.len(), 4);
+    }
+}
@@ -0,0 +1,351 @@
+//! Portable agent definitions, to share agents between projects.
+//!
+//! An [`AgentDefinition`] describes an agent without code: its name, description, system
+//! prompt, the names of its tools and hints for its model. Definitions are exported as JSON
+//! and imported from JSON or TOML. The tools are code, so they are registered once in an
+//! [`AgentRegistry`] under their names, and the registry builds the agents of its
+//! definitions, e.g. for an [`AutoSwarm`](crate::auto_swarm::AutoSwarm).
+//!
+//! ```toml
+//! name = "researcher"
+//! description = "Finds and summarizes sources"
+//! system_prompt = "You are a meticulous researcher."
+//! tools = ["search"]
+//!
+//! [model]
+//! name = "gpt-4o"
+//! temperature = 0.2
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::swarms_agent::{SwarmsAgent, SwarmsAgentBuilder};
+use crate::{
+    error::{CategorizedError, ErrorCategory},
+    llm,
+    persistence::{self, PersistenceError},
+    tool::ToolDyn,
+};
+
+#[derive(Debug, Error)]
+pub enum AgentDefinitionError {
+    #[error("Agent name must be set")]
+    EmptyName,
+    #[error("Description of agent {0} must be set")]
+    EmptyDescription(String),
+    #[error("Temperature of agent {0} must be between 0 and 2, got {1}")]
+    InvalidTemperature(String, f64),
+    #[error("Agent {0} lists tool {1} more than once")]
+    DuplicateTool(String, String),
+    #[error("Tool {0} is not registered")]
+    UnknownTool(String),
+    #[error("Agent {0} is already registered")]
+    DuplicateAgent(String),
+    #[error("Agent {0} is not registered")]
+    UnknownAgent(String),
+    #[error("Persistence error: {0}")]
+    PersistenceError(#[from] PersistenceError),
+    #[error("Toml error: {0}")]
+    TomlError(#[from] toml::de::Error),
+}
+
+impl CategorizedError for AgentDefinitionError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            AgentDefinitionError::PersistenceError(e) => e.category(),
+            _ => ErrorCategory::Validation,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentDefinition {
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub system_prompt: String,
+    /// Names of the tools, registered in the [`AgentRegistry`] which builds the agent.
+    #[serde(default)]
+    pub tools: Vec<String>,
+    #[serde(default)]
+    pub model: ModelHints,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// What the agent was tuned for, the model itself is chosen when the agent is built.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelHints {
+    /// Name of the model the agent works best with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+}
+
+impl AgentDefinition {
+    /// Upgrades of exported definitions, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    pub fn new(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            system_prompt: String::new(),
+            tools: Vec::new(),
+            model: ModelHints::default(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    pub fn system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+        self.system_prompt = system_prompt.into();
+        self
+    }
+
+    pub fn tool(mut self, name: impl Into<String>) -> Self {
+        self.tools.push(name.into());
+        self
+    }
+
+    pub fn model(mut self, model: ModelHints) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Check the definition on its own, the tools are checked when it's registered.
+    pub fn validate(&self) -> Result<(), AgentDefinitionError> {
+        if self.name.trim().is_empty() {
+            return Err(AgentDefinitionError::EmptyName);
+        }
+        if self.description.trim().is_empty() {
+            return Err(AgentDefinitionError::EmptyDescription(self.name.clone()));
+        }
+        if let Some(temperature) = self.model.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            return Err(AgentDefinitionError::InvalidTemperature(
+                self.name.clone(),
+                temperature,
+            ));
+        }
+        let mut tools = HashSet::with_capacity(self.tools.len());
+        for tool in &self.tools {
+            if !tools.insert(tool) {
+                return Err(AgentDefinitionError::DuplicateTool(
+                    self.name.clone(),
+                    tool.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, AgentDefinitionError> {
+        Ok(persistence::to_versioned_json(self)?)
+    }
+
+    /// Parse and validate a definition exported by [`AgentDefinition::to_json`].
+    pub fn from_json(json: &str) -> Result<Self, AgentDefinitionError> {
+        let definition: Self = persistence::from_versioned_json(json.as_bytes(), Self::MIGRATIONS)?;
+        definition.validate()?;
+        Ok(definition)
+    }
+
+    /// Parse and validate a hand-written TOML definition.
+    pub fn from_toml(toml: &str) -> Result<Self, AgentDefinitionError> {
+        let definition: Self = toml::from_str(toml)?;
+        definition.validate()?;
+        Ok(definition)
+    }
+
+    /// Export the definition as JSON.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), AgentDefinitionError> {
+        Ok(persistence::save_to_file(self.to_json()?, path).await?)
+    }
+
+    /// Import a definition, files ending in `.toml` are read as TOML, all others as JSON.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, AgentDefinitionError> {
+        let path = path.as_ref();
+        let data = persistence::load_verified(path).await?;
+        let text = String::from_utf8_lossy(&data);
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            Self::from_toml(&text)
+        } else {
+            Self::from_json(&text)
+        }
+    }
+}
+
+/// Tools by name and the agents defined with them.
+#[derive(Clone, Default)]
+pub struct AgentRegistry {
+    tools: HashMap<String, Arc<dyn ToolDyn>>,
+    definitions: BTreeMap<String, AgentDefinition>,
+}
+
+impl AgentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a tool under its name, for the definitions which list it.
+    pub fn tool(mut self, tool: impl ToolDyn + 'static) -> Self {
+        self.tools.insert(tool.name(), Arc::new(tool));
+        self
+    }
+
+    /// Validate and add the definition, all its tools must be registered.
+    pub fn register(&mut self, definition: AgentDefinition) -> Result<(), AgentDefinitionError> {
+        definition.validate()?;
+        if let Some(tool) = definition
+            .tools
+            .iter()
+            .find(|tool| !self.tools.contains_key(*tool))
+        {
+            return Err(AgentDefinitionError::UnknownTool(tool.clone()));
+        }
+        if self.definitions.contains_key(&definition.name) {
+            return Err(AgentDefinitionError::DuplicateAgent(definition.name));
+        }
+        self.definitions.insert(definition.name.clone(), definition);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AgentDefinition> {
+        self.definitions.get(name)
+    }
+
+    /// The registered definitions, ordered by name.
+    pub fn definitions(&self) -> impl Iterator<Item = &AgentDefinition> {
+        self.definitions.values()
+    }
+
+    /// Build the registered agent on the model, with the hinted temperature and max tokens.
+    pub fn build<M>(&self, name: &str, model: M) -> Result<SwarmsAgent<M>, AgentDefinitionError>
+    where
+        M: llm::Model + Clone + Send + Sync,
+        M::RawCompletionResponse: Clone + Send + Sync,
+    {
+        let definition = self
+            .get(name)
+            .ok_or_else(|| AgentDefinitionError::UnknownAgent(name.to_owned()))?;
+
+        let mut builder = SwarmsAgentBuilder::new_with_model(model)
+            .agent_name(&definition.name)
+            .description(&definition.description);
+        if !definition.system_prompt.is_empty() {
+            builder = builder.system_prompt(&definition.system_prompt);
+        }
+        if let Some(temperature) = definition.model.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(max_tokens) = definition.model.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        for (key, value) in &definition.metadata {
+            builder = builder.metadata(key, value);
+        }
+        for tool in &definition.tools {
+            // Safety: the tools of registered definitions are registered
+            builder = builder.add_shared_tool(Arc::clone(&self.tools[tool]));
+        }
+        Ok(builder.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use swarms_macro::tool;
+
+    use super::*;
+    use crate::{self as swarms_rs, agent::Agent, test_support::FnModel};
+
+    fn echo_model() -> FnModel {
+        FnModel::reply("echo", "echo")
+    }
+
+    #[tool(description = "Search the web")]
+    fn search(query: String) -> Result<String, std::io::Error> {
+        Ok(query)
+    }
+
+    const RESEARCHER: &str = r#"
+        name = "researcher"
+        description = "Finds and summarizes sources"
+        system_prompt = "You are a meticulous researcher."
+        tools = ["search"]
+
+        [model]
+        name = "gpt-4o"
+        temperature = 0.2
+    "#;
+
+    #[test]
+    fn test_import_export_and_build() {
+        let definition = AgentDefinition::from_toml(RESEARCHER).unwrap();
+        assert_eq!(definition.model.name.as_deref(), Some("gpt-4o"));
+        let json = definition.to_json().unwrap();
+        assert_eq!(AgentDefinition::from_json(&json).unwrap(), definition);
+
+        let mut registry = AgentRegistry::new();
+        assert!(matches!(
+            registry.register(definition.clone()),
+            Err(AgentDefinitionError::UnknownTool(tool)) if tool == "search"
+        ));
+        let mut registry = registry.tool(Search);
+        registry.register(definition.clone()).unwrap();
+        assert!(matches!(
+            registry.register(definition),
+            Err(AgentDefinitionError::DuplicateAgent(_))
+        ));
+
+        let agent = registry.build("researcher", echo_model()).unwrap();
+        assert_eq!(agent.description(), "Finds and summarizes sources");
+        assert_eq!(agent.tools()[0].name, "search");
+        let exported = agent.definition();
+        assert_eq!(exported.system_prompt, "You are a meticulous researcher.");
+        assert_eq!(exported.tools, ["search"]);
+        assert_eq!(exported.model.temperature, Some(0.2));
+        assert!(matches!(
+            registry.build("writer", echo_model()),
+            Err(AgentDefinitionError::UnknownAgent(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = [
+            AgentDefinition::new(" ", "Writes"),
+            AgentDefinition::new("writer", ""),
+            AgentDefinition::new("writer", "Writes").model(ModelHints {
+                temperature: Some(3.0),
+                ..Default::default()
+            }),
+            AgentDefinition::new("writer", "Writes")
+                .tool("search")
+                .tool("search"),
+        ];
+        for definition in invalid {
+            assert!(definition.validate().is_err(), "{definition:?}");
+        }
+        assert!(AgentDefinition::from_toml("name = \"writer\"").is_err());
+    }
+}
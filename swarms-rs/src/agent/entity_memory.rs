@@ -0,0 +1,402 @@
+//! Memory of the people, projects and terms which come up across tasks.
+//!
+//! After every completed run an [`EntityMemory`] extracts the entities mentioned in the task
+//! and the answer, with a model or with patterns, and stores the facts about them. When a
+//! later task mentions a known entity, its facts are added to the agent's context. Unlike
+//! a [`Persona`](super::persona::Persona), which is what the agent knows about itself, the
+//! memory is about the world and is only shown when it's relevant.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{Mutex, OnceCell};
+
+use super::persona::parse_facts;
+use crate::{
+    error::{CategorizedError, ErrorCategory},
+    llm::{CompletionError, Model, completion::AssistantContent, request::CompletionRequest},
+    locale::{self, Locale},
+    persistence::{self, Persistence, PersistenceError},
+};
+
+/// Max number of facts an entity keeps by default.
+const DEFAULT_MAX_FACTS: usize = 20;
+
+const EXTRACTION_PROMPT: &str = "Extract the people, projects, organizations and terms the \
+text mentions, with the facts it states about them. Reply with one fact per line, formatted \
+as \"- name | kind | fact\", or with NONE.";
+
+#[derive(Debug, Error)]
+pub enum EntityMemoryError {
+    #[error("Completion error: {0}")]
+    CompletionError(#[from] CompletionError),
+    #[error("Persistence error: {0}")]
+    PersistenceError(#[from] PersistenceError),
+}
+
+impl CategorizedError for EntityMemoryError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            EntityMemoryError::CompletionError(e) => e.category(),
+            EntityMemoryError::PersistenceError(e) => e.category(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    pub name: String,
+    /// E.g. person, project or term.
+    pub kind: String,
+    /// Oldest first.
+    pub facts: Vec<String>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// An entity and a fact about it, as found by an [`EntityExtractor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    pub name: String,
+    pub kind: String,
+    pub fact: String,
+}
+
+type Complete = Arc<
+    dyn Fn(CompletionRequest) -> BoxFuture<'static, Result<String, CompletionError>> + Send + Sync,
+>;
+
+/// Finds the entities in a text.
+#[derive(Clone)]
+pub enum EntityExtractor {
+    Llm(Complete),
+    /// Kind and pattern, the first capture group or else the whole match is the name.
+    Patterns(Vec<(String, Regex)>),
+}
+
+impl EntityExtractor {
+    /// Ask the model for the entities and facts.
+    pub fn llm<M>(model: M) -> Self
+    where
+        M: Model + Clone + Send + Sync + 'static,
+        M::RawCompletionResponse: Send,
+    {
+        EntityExtractor::Llm(Arc::new(move |request| {
+            let model = model.clone();
+            Box::pin(async move {
+                let response = model.completion(request).await?;
+                match response.choice.into_iter().next() {
+                    Some(AssistantContent::Text(text)) => Ok(text.text),
+                    _ => Err(CompletionError::Other("Extractor returned no text".into())),
+                }
+            })
+        }))
+    }
+
+    /// Find entities by kind and pattern, e.g. `("ticket", r"\b([A-Z]+-\d+)\b")`. The facts
+    /// about an entity are the sentences which mention it.
+    pub fn patterns<'a>(
+        patterns: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, regex::Error> {
+        let patterns = patterns
+            .into_iter()
+            .map(|(kind, pattern)| Ok((kind.to_owned(), Regex::new(pattern)?)))
+            .collect::<Result<_, regex::Error>>()?;
+        Ok(EntityExtractor::Patterns(patterns))
+    }
+
+    pub async fn extract(&self, text: &str) -> Result<Vec<Mention>, CompletionError> {
+        match self {
+            EntityExtractor::Llm(complete) => {
+                let request = CompletionRequest {
+                    prompt: text.into(),
+                    system_prompt: Some(EXTRACTION_PROMPT.to_owned()),
+                    chat_history: vec![],
+                    tools: vec![],
+                    temperature: Some(0.0),
+                    max_tokens: None,
+                    seed: None,
+                    response_format: None,
+                };
+                let reply = complete(request).await?;
+                Ok(parse_facts(&reply)
+                    .iter()
+                    .filter_map(|line| {
+                        let mut parts = line.splitn(3, '|').map(str::trim);
+                        let (name, kind, fact) = (parts.next()?, parts.next()?, parts.next()?);
+                        (!name.is_empty() && !fact.is_empty()).then(|| Mention {
+                            name: name.to_owned(),
+                            kind: kind.to_lowercase(),
+                            fact: fact.to_owned(),
+                        })
+                    })
+                    .collect())
+            }
+            EntityExtractor::Patterns(patterns) => {
+                let mut mentions = Vec::new();
+                for sentence in sentences(text) {
+                    for (kind, regex) in patterns {
+                        for captures in regex.captures_iter(sentence) {
+                            // Safety: the whole match is always captured
+                            let name = captures.get(1).unwrap_or(captures.get(0).unwrap());
+                            mentions.push(Mention {
+                                name: name.as_str().to_owned(),
+                                kind: kind.clone(),
+                                fact: sentence.to_owned(),
+                            });
+                        }
+                    }
+                }
+                Ok(mentions)
+            }
+        }
+    }
+}
+
+fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    text.split_inclusive(['.', '!', '?', '\n'])
+        .map(str::trim)
+        .filter(|sentence| !sentence.is_empty())
+}
+
+/// Entity name in lowercase -> entity
+type Entities = BTreeMap<String, Entity>;
+
+/// Clones share the entities.
+#[derive(Clone)]
+pub struct EntityMemory {
+    extractor: EntityExtractor,
+    entities: Arc<RwLock<Entities>>,
+    max_facts: usize,
+    locale: Locale,
+    persistence: Option<(Arc<dyn Persistence>, PathBuf)>,
+    loaded: Arc<OnceCell<()>>,
+    // Serializes the saves, so the last save holds the latest entities
+    save_lock: Arc<Mutex<()>>,
+}
+
+impl EntityMemory {
+    /// Upgrades of saved entities, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    /// The entities are kept in memory only by default.
+    pub fn new(extractor: EntityExtractor) -> Self {
+        Self {
+            extractor,
+            entities: Arc::default(),
+            max_facts: DEFAULT_MAX_FACTS,
+            locale: Locale::default(),
+            persistence: None,
+            loaded: Arc::new(OnceCell::new()),
+            save_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// The oldest facts of an entity are forgotten once there are more, 20 by default.
+    pub fn max_facts(mut self, max_facts: usize) -> Self {
+        self.max_facts = max_facts.max(1);
+        self
+    }
+
+    /// Language of the heading of the prompt, agents set it to their own locale.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Save the entities to `path` of the backend after they changed, and load them before
+    /// the first use, so they outlive the process.
+    pub fn persistent(
+        mut self,
+        persistence: impl Persistence + 'static,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        self.persistence = Some((Arc::new(persistence), path.into()));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Entity> {
+        self.read().get(&name.to_lowercase()).cloned()
+    }
+
+    /// All entities, ordered by name.
+    pub fn entities(&self) -> Vec<Entity> {
+        self.read().values().cloned().collect()
+    }
+
+    /// The known entities the text mentions.
+    pub fn mentioned(&self, text: &str) -> Vec<Entity> {
+        let text = text.to_lowercase();
+        self.read()
+            .iter()
+            .filter(|(name, _)| mentions(&text, name))
+            .map(|(_, entity)| entity.clone())
+            .collect()
+    }
+
+    /// The facts about the entities the task mentions, as a section of the agent's context.
+    pub fn prompt(&self, task: &str) -> Option<String> {
+        let entities = self.mentioned(task);
+        if entities.is_empty() {
+            return None;
+        }
+        let mut prompt = locale::heading(self.locale.catalog().known_entities);
+        for entity in entities {
+            prompt.push_str(&format!("\n{} ({}):", entity.name, entity.kind));
+            for fact in &entity.facts {
+                prompt.push_str(&format!("\n- {fact}"));
+            }
+        }
+        Some(prompt)
+    }
+
+    /// Extract the entities of the text and store the new facts about them, returns the
+    /// number of new facts.
+    pub async fn observe(&self, text: &str) -> Result<usize, EntityMemoryError> {
+        self.load().await?;
+        let mentions = self.extractor.extract(text).await?;
+        let added = self.remember(mentions);
+        if added > 0 {
+            self.save().await?;
+        }
+        Ok(added)
+    }
+
+    /// Store the facts which are new, returns their number. Call [`load`](Self::load) first
+    /// if the memory is persistent.
+    pub fn remember(&self, mentions: impl IntoIterator<Item = Mention>) -> usize {
+        let now = Utc::now();
+        let mut entities = self.write();
+        let mut added = 0;
+        for mention in mentions {
+            let entity = entities
+                .entry(mention.name.to_lowercase())
+                .or_insert_with(|| Entity {
+                    name: mention.name.clone(),
+                    kind: mention.kind.clone(),
+                    facts: Vec::new(),
+                    last_seen: now,
+                });
+            entity.last_seen = now;
+            if entity
+                .facts
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(&mention.fact))
+            {
+                continue;
+            }
+            entity.facts.push(mention.fact);
+            let excess = entity.facts.len().saturating_sub(self.max_facts);
+            entity.facts.drain(..excess);
+            added += 1;
+        }
+        added
+    }
+
+    /// Replace the entities with the saved ones, once. A missing file keeps the entities.
+    pub async fn load(&self) -> Result<(), PersistenceError> {
+        let Some((persistence, path)) = &self.persistence else {
+            return Ok(());
+        };
+        self.loaded
+            .get_or_try_init(|| async {
+                match persistence.load(path).await {
+                    Ok(data) => {
+                        let entities = persistence::from_versioned_json(&data, Self::MIGRATIONS)?;
+                        *self.write() = entities;
+                        Ok(())
+                    }
+                    Err(PersistenceError::IoError(e))
+                        if e.kind() == std::io::ErrorKind::NotFound =>
+                    {
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await
+            .map(|_| ())
+    }
+
+    async fn save(&self) -> Result<(), PersistenceError> {
+        let Some((persistence, path)) = &self.persistence else {
+            return Ok(());
+        };
+        let _guard = self.save_lock.lock().await;
+        let json = persistence::to_versioned_json(&*self.read())?;
+        persistence.save(path, json.into_bytes()).await
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Entities> {
+        // Safety: the lock is never held across a panic
+        self.entities.read().unwrap()
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Entities> {
+        self.entities.write().unwrap()
+    }
+}
+
+// Whether the lowercase text mentions the name as a whole word
+fn mentions(text: &str, name: &str) -> bool {
+    let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    text.match_indices(name).any(|(start, _)| {
+        !is_word(text[..start].chars().next_back())
+            && !is_word(text[start + name.len()..].chars().next())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{persistence::MemoryPersistence, test_support::FnModel};
+
+    fn extractor_model() -> FnModel {
+        FnModel::reply(
+            "extractor",
+            "Entities:\n- Ada | Person | Leads project Falcon\n- Falcon | project | Ships in May\n\
+             - broken line",
+        )
+    }
+
+    #[tokio::test]
+    async fn test_llm_extraction_and_prompt() {
+        let storage = MemoryPersistence::new();
+        let memory = EntityMemory::new(EntityExtractor::llm(extractor_model()))
+            .persistent(storage.clone(), "entities.json");
+        assert_eq!(memory.observe("...").await.unwrap(), 2);
+        assert_eq!(memory.observe("...").await.unwrap(), 0);
+        assert_eq!(memory.get("ADA").unwrap().kind, "person");
+
+        let restarted = EntityMemory::new(EntityExtractor::patterns([]).unwrap())
+            .persistent(storage, "entities.json");
+        restarted.load().await.unwrap();
+        assert_eq!(
+            restarted.prompt("How is falcon doing?").as_deref(),
+            Some("### Known Entities:\nFalcon (project):\n- Ships in May")
+        );
+        // Names only match as whole words
+        assert!(restarted.prompt("Check the adapter").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pattern_extraction() {
+        let extractor = EntityExtractor::patterns([("ticket", r"\b([A-Z]+-\d+)\b")]).unwrap();
+        let memory = EntityMemory::new(extractor).max_facts(1);
+        memory
+            .observe("OPS-12 is blocked. Nothing else! OPS-12 needs a review.")
+            .await
+            .unwrap();
+        let ticket = memory.get("ops-12").unwrap();
+        assert_eq!(ticket.kind, "ticket");
+        assert_eq!(ticket.facts, ["OPS-12 needs a review."]);
+        assert!(EntityExtractor::patterns([("bad", "(")]).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Tell the model when and where it runs.
+//!
+//! Models don't know today's date, so tasks like "summarize this week's news" silently go
+//! wrong. An [`EnvironmentContext`] adds the current date and time, the timezone and facts of
+//! the caller, e.g. the user's country, to the system prompt of every run. The section is
+//! built when the run starts and appended after everything else in the system prompt, so the
+//! prompt's stable prefix stays cacheable by providers and the semantic cache never sees it.
+
+use std::collections::BTreeMap;
+
+use chrono::{FixedOffset, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::locale::{self, Locale};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S %:z (%A)";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentContext {
+    /// Add the current date and time, `true` by default.
+    #[serde(default = "default_current_time")]
+    pub current_time: bool,
+    /// Offset of the time from UTC in seconds, the local offset of the host if `None`.
+    #[serde(default)]
+    pub utc_offset: Option<i32>,
+    /// Name of the timezone, e.g. `Europe/Berlin`.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub facts: BTreeMap<String, String>,
+}
+
+fn default_current_time() -> bool {
+    true
+}
+
+impl Default for EnvironmentContext {
+    fn default() -> Self {
+        Self {
+            current_time: true,
+            utc_offset: None,
+            timezone: None,
+            facts: BTreeMap::new(),
+        }
+    }
+}
+
+impl EnvironmentContext {
+    /// The current date and time in the host's local time.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn current_time(mut self, current_time: bool) -> Self {
+        self.current_time = current_time;
+        self
+    }
+
+    /// Tell the time in the user's timezone instead of the host's, e.g. for a server in UTC
+    /// serving users in Berlin. chrono has no timezone database, so the offset is fixed.
+    pub fn timezone(mut self, name: impl Into<String>, utc_offset: FixedOffset) -> Self {
+        self.timezone = Some(name.into());
+        self.utc_offset = Some(utc_offset.local_minus_utc());
+        self
+    }
+
+    /// A fact about the environment, e.g. the user's country. Setting a key again replaces
+    /// the value.
+    pub fn fact(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.facts.insert(key.into(), value.into());
+        self
+    }
+
+    /// The section of the system prompt, `None` if there is nothing to tell.
+    pub fn prompt(&self, locale: Locale) -> Option<String> {
+        if !self.current_time && self.timezone.is_none() && self.facts.is_empty() {
+            return None;
+        }
+        let catalog = locale.catalog();
+        let mut prompt = locale::heading(catalog.environment);
+        if self.current_time {
+            let now = match self.utc_offset.and_then(FixedOffset::east_opt) {
+                Some(offset) => Utc::now().with_timezone(&offset).format(TIME_FORMAT),
+                None => Local::now().fixed_offset().format(TIME_FORMAT),
+            };
+            prompt.push_str(&format!("\n- {}: {now}", catalog.current_date_and_time));
+        }
+        if let Some(timezone) = &self.timezone {
+            prompt.push_str(&format!("\n- {}: {timezone}", catalog.timezone));
+        }
+        for (key, value) in &self.facts {
+            prompt.push_str(&format!("\n- {key}: {value}"));
+        }
+        Some(prompt)
+    }
+}
+
+/// The current date and time in the host's local time, as told to models.
+pub(crate) fn local_time() -> String {
+    Local::now().format(TIME_FORMAT).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt() {
+        let environment = EnvironmentContext::new()
+            .timezone(
+                "Asia/Kolkata",
+                FixedOffset::east_opt(5 * 3600 + 1800).unwrap(),
+            )
+            .fact("country", "India");
+        let prompt = environment.prompt(Locale::English).unwrap();
+        let lines = prompt.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "### Environment:");
+        assert!(lines[1].starts_with("- Current Date and Time: "));
+        assert!(lines[1].contains(" +05:30 ("));
+        assert_eq!(lines[2..], ["- Timezone: Asia/Kolkata", "- country: India"]);
+
+        let facts = environment.current_time(false).prompt(Locale::German);
+        assert_eq!(
+            facts.unwrap(),
+            "### Umgebung:\n- Zeitzone: Asia/Kolkata\n- country: India"
+        );
+        assert_eq!(
+            EnvironmentContext::new()
+                .current_time(false)
+                .prompt(Locale::English),
+            None
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! Memory of an agent's past tasks, recalled like the memory stream of generative agents.
+//!
+//! After every completed run a summary of the task and its answer is stored as an
+//! [`Episode`] with its embedding. Before a run the episodes with the highest score are
+//! added to the agent's context. The score adds up the episode's
+//!
+//! - recency, which decays exponentially with the hours since the episode was last recalled,
+//! - relevance, the cosine similarity of its embedding to the task's, and
+//! - importance, rated when the episode is stored,
+//!
+//! each normalized to `0..=1` over the episodes and weighted by the [`ScoreWeights`].
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::{
+    error::{CategorizedError, ErrorCategory},
+    llm::{CompletionError, EmbeddingModel, embedding::cosine_similarity},
+    locale::{self, Locale},
+    persistence::{self, Persistence, PersistenceError},
+};
+
+const DEFAULT_MAX_EPISODES: usize = 1000;
+const DEFAULT_TOP_K: usize = 3;
+/// Recency decay per hour, from the generative agents paper.
+const DEFAULT_DECAY: f64 = 0.995;
+/// Max length of the answer in the default summary, in characters.
+const SUMMARY_ANSWER_CHARS: usize = 500;
+
+#[derive(Debug, Error)]
+pub enum EpisodicMemoryError {
+    #[error("Completion error: {0}")]
+    CompletionError(#[from] CompletionError),
+    #[error("Persistence error: {0}")]
+    PersistenceError(#[from] PersistenceError),
+}
+
+impl CategorizedError for EpisodicMemoryError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            EpisodicMemoryError::CompletionError(e) => e.category(),
+            EpisodicMemoryError::PersistenceError(e) => e.category(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Episode {
+    pub task: String,
+    pub summary: String,
+    pub embedding: Vec<f32>,
+    /// Between 0 and 1.
+    pub importance: f32,
+    pub created_at: DateTime<Utc>,
+    pub last_recalled: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScoreWeights {
+    pub recency: f64,
+    pub relevance: f64,
+    pub importance: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            recency: 1.0,
+            relevance: 1.0,
+            importance: 1.0,
+        }
+    }
+}
+
+/// Summarizes a task and its answer for the memory.
+pub type Summarizer = Arc<dyn Fn(&str, &str) -> String + Send + Sync>;
+/// Rates the importance of a task and its answer between 0 and 1.
+pub type ImportanceRater = Arc<dyn Fn(&str, &str) -> f32 + Send + Sync>;
+
+/// Clones share the episodes.
+#[derive(Clone)]
+pub struct EpisodicMemory {
+    embedder: Arc<dyn EmbeddingModel + Send + Sync>,
+    episodes: Arc<RwLock<Vec<Episode>>>,
+    weights: ScoreWeights,
+    decay: f64,
+    top_k: usize,
+    max_episodes: usize,
+    locale: Locale,
+    summarizer: Summarizer,
+    importance: ImportanceRater,
+    persistence: Option<(Arc<dyn Persistence>, PathBuf)>,
+    loaded: Arc<OnceCell<()>>,
+    // Serializes the saves, so the last save holds the latest episodes
+    save_lock: Arc<Mutex<()>>,
+}
+
+impl EpisodicMemory {
+    /// Upgrades of saved episodes, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    /// By default the 3 best of at most 1000 episodes are recalled, all parts of the score
+    /// weigh the same, every episode is equally important and the episodes are kept in
+    /// memory only.
+    pub fn new(embedder: impl EmbeddingModel + Send + Sync + 'static) -> Self {
+        Self {
+            embedder: Arc::new(embedder),
+            episodes: Arc::default(),
+            weights: ScoreWeights::default(),
+            decay: DEFAULT_DECAY,
+            top_k: DEFAULT_TOP_K,
+            max_episodes: DEFAULT_MAX_EPISODES,
+            summarizer: Arc::new(summarize),
+            importance: Arc::new(|_, _| 0.5),
+            locale: Locale::default(),
+            persistence: None,
+            loaded: Arc::new(OnceCell::new()),
+            save_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn weights(mut self, weights: ScoreWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Factor the recency decays by per hour, 0.995 by default.
+    pub fn decay(mut self, decay: f64) -> Self {
+        self.decay = decay.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Number of episodes recalled per run.
+    pub fn top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Max number of episodes, the least recently recalled episode is forgotten first.
+    pub fn max_episodes(mut self, max_episodes: usize) -> Self {
+        self.max_episodes = max_episodes.max(1);
+        self
+    }
+
+    /// How a task and its answer are summarized, by default the task and the beginning of
+    /// the answer.
+    pub fn summarizer(
+        mut self,
+        summarizer: impl Fn(&str, &str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.summarizer = Arc::new(summarizer);
+        self
+    }
+
+    /// How important a task and its answer are, between 0 and 1.
+    pub fn importance(
+        mut self,
+        importance: impl Fn(&str, &str) -> f32 + Send + Sync + 'static,
+    ) -> Self {
+        self.importance = Arc::new(importance);
+        self
+    }
+
+    /// Language of the heading of the prompt, agents set it to their own locale.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Save the episodes to `path` of the backend after every change, and load them before
+    /// the first use, so they outlive the process.
+    pub fn persistent(
+        mut self,
+        persistence: impl Persistence + 'static,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        self.persistence = Some((Arc::new(persistence), path.into()));
+        self
+    }
+
+    /// All episodes, oldest first.
+    pub fn episodes(&self) -> Vec<Episode> {
+        self.read().clone()
+    }
+
+    /// Summarize, rate and embed the task and its answer and store them as an episode.
+    pub async fn record(&self, task: &str, answer: &str) -> Result<(), EpisodicMemoryError> {
+        self.load().await?;
+        let summary = (self.summarizer)(task, answer);
+        let embedding = self.embed(&summary).await?;
+        let now = Utc::now();
+        self.insert(Episode {
+            task: task.to_owned(),
+            summary,
+            embedding,
+            importance: (self.importance)(task, answer).clamp(0.0, 1.0),
+            created_at: now,
+            last_recalled: now,
+        });
+        Ok(self.save().await?)
+    }
+
+    /// Store the episode, e.g. one imported from another memory.
+    pub fn insert(&self, episode: Episode) {
+        let mut episodes = self.write();
+        episodes.push(episode);
+        while episodes.len() > self.max_episodes {
+            // Safety: there are more episodes than the max, which is at least 1
+            let stalest = episodes
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, episode)| episode.last_recalled)
+                .map(|(i, _)| i)
+                .unwrap();
+            episodes.remove(stalest);
+        }
+    }
+
+    /// The best scored episodes for the task, best first, with their scores. Recalling an
+    /// episode refreshes its recency.
+    pub async fn recall(&self, task: &str) -> Result<Vec<(Episode, f64)>, EpisodicMemoryError> {
+        self.load().await?;
+        if self.read().is_empty() || self.top_k == 0 {
+            return Ok(Vec::new());
+        }
+        let embedding = self.embed(task).await?;
+        let now = Utc::now();
+
+        let recalled = {
+            let mut episodes = self.write();
+            let mut scores = score(&episodes, &embedding, now, self.decay, self.weights);
+            scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+            scores.truncate(self.top_k);
+            scores
+                .into_iter()
+                .map(|(i, score)| {
+                    episodes[i].last_recalled = now;
+                    (episodes[i].clone(), score)
+                })
+                .collect::<Vec<_>>()
+        };
+        self.save().await?;
+        Ok(recalled)
+    }
+
+    /// The recalled episodes as a section of the agent's context.
+    pub async fn prompt(&self, task: &str) -> Result<Option<String>, EpisodicMemoryError> {
+        let recalled = self.recall(task).await?;
+        if recalled.is_empty() {
+            return Ok(None);
+        }
+        let mut prompt = locale::heading(self.locale.catalog().relevant_past_tasks);
+        for (episode, _) in recalled {
+            prompt.push_str(&format!(
+                "\n- ({}) {}",
+                episode.created_at.format("%Y-%m-%d"),
+                episode.summary.replace('\n', "\n  ")
+            ));
+        }
+        Ok(Some(prompt))
+    }
+
+    /// Replace the episodes with the saved ones, once. A missing file keeps the episodes.
+    pub async fn load(&self) -> Result<(), PersistenceError> {
+        let Some((persistence, path)) = &self.persistence else {
+            return Ok(());
+        };
+        self.loaded
+            .get_or_try_init(|| async {
+                match persistence.load(path).await {
+                    Ok(data) => {
+                        let episodes = persistence::from_versioned_json(&data, Self::MIGRATIONS)?;
+                        *self.write() = episodes;
+                        Ok(())
+                    }
+                    Err(PersistenceError::IoError(e))
+                        if e.kind() == std::io::ErrorKind::NotFound =>
+                    {
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await
+            .map(|_| ())
+    }
+
+    async fn save(&self) -> Result<(), PersistenceError> {
+        let Some((persistence, path)) = &self.persistence else {
+            return Ok(());
+        };
+        let _guard = self.save_lock.lock().await;
+        let json = persistence::to_versioned_json(&*self.read())?;
+        persistence.save(path, json.into_bytes()).await
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, CompletionError> {
+        self.embedder
+            .embed(vec![text.to_owned()])
+            .await?
+            .pop()
+            .ok_or_else(|| CompletionError::Other("Embedding model returned no embedding".into()))
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Vec<Episode>> {
+        // Safety: the lock is never held across a panic
+        self.episodes.read().unwrap()
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Vec<Episode>> {
+        self.episodes.write().unwrap()
+    }
+}
+
+fn summarize(task: &str, answer: &str) -> String {
+    let mut answer = answer.trim().to_owned();
+    if let Some((end, _)) = answer.char_indices().nth(SUMMARY_ANSWER_CHARS) {
+        answer.truncate(end);
+        answer.push_str("...");
+    }
+    format!("Task: {}\nOutcome: {answer}", task.trim())
+}
+
+// Index and score of each episode, the parts of the score are min-max normalized over the
+// episodes
+fn score(
+    episodes: &[Episode],
+    embedding: &[f32],
+    now: DateTime<Utc>,
+    decay: f64,
+    weights: ScoreWeights,
+) -> Vec<(usize, f64)> {
+    let recency = normalize(episodes.iter().map(|episode| {
+        let hours = (now - episode.last_recalled).num_seconds().max(0) as f64 / 3600.0;
+        decay.powf(hours)
+    }));
+    let relevance = normalize(
+        episodes
+            .iter()
+            .map(|episode| cosine_similarity(&episode.embedding, embedding) as f64),
+    );
+    let importance = normalize(episodes.iter().map(|episode| episode.importance as f64));
+    (0..episodes.len())
+        .map(|i| {
+            let score = weights.recency * recency[i]
+                + weights.relevance * relevance[i]
+                + weights.importance * importance[i];
+            (i, score)
+        })
+        .collect()
+}
+
+// Scale to 0..=1, equal values all become 1
+fn normalize(values: impl Iterator<Item = f64>) -> Vec<f64> {
+    let values = values.collect::<Vec<_>>();
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| {
+            if max - min > f64::EPSILON {
+                (value - min) / (max - min)
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{persistence::MemoryPersistence, test_support::KeywordEmbedder};
+
+    fn episode(task: &str, embedding: [f32; 3], importance: f32, age_hours: i64) -> Episode {
+        let at = Utc::now() - TimeDelta::hours(age_hours);
+        Episode {
+            task: task.to_owned(),
+            summary: format!("Task: {task}"),
+            embedding: embedding.to_vec(),
+            importance,
+            created_at: at,
+            last_recalled: at,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recall_scores() {
+        let memory = EpisodicMemory::new(KeywordEmbedder).top_k(2).decay(0.9);
+        memory.insert(episode("refund A", [1.0, 0.0, 0.0], 0.2, 48));
+        memory.insert(episode("shipping", [0.0, 1.0, 0.0], 0.5, 0));
+        memory.insert(episode("refund B", [1.0, 0.0, 0.0], 0.9, 24));
+
+        let recalled = memory.recall("Another refund").await.unwrap();
+        let tasks = recalled
+            .iter()
+            .map(|(episode, _)| episode.task.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(tasks, ["refund B", "shipping"]);
+        assert!(recalled[0].1 > recalled[1].1);
+
+        // Only relevance counts
+        let memory = memory.weights(ScoreWeights {
+            recency: 0.0,
+            relevance: 1.0,
+            importance: 0.0,
+        });
+        let prompt = memory.prompt("refund").await.unwrap().unwrap();
+        assert!(prompt.starts_with("### Relevant Past Tasks:\n- ("));
+        assert!(prompt.contains("Task: refund A") && prompt.contains("Task: refund B"));
+    }
+
+    #[tokio::test]
+    async fn test_record_persist_and_forget() {
+        let storage = MemoryPersistence::new();
+        let memory = EpisodicMemory::new(KeywordEmbedder)
+            .max_episodes(2)
+            .importance(|task, _| if task.contains("password") { 1.0 } else { 0.0 })
+            .persistent(storage.clone(), "episodes.json");
+        memory.record("Reset my password", "Done.").await.unwrap();
+        memory.record("Refund order 1", "Refunded.").await.unwrap();
+        memory.record("Refund order 2", "Refunded.").await.unwrap();
+
+        let restarted = EpisodicMemory::new(KeywordEmbedder).persistent(storage, "episodes.json");
+        restarted.load().await.unwrap();
+        let episodes = restarted.episodes();
+        assert_eq!(episodes.len(), 2);
+        assert_eq!(episodes[0].task, "Refund order 1");
+        assert_eq!(
+            episodes[1].summary,
+            "Task: Refund order 2\nOutcome: Refunded."
+        );
+        assert_eq!(episodes[1].importance, 0.0);
+    }
+}
@@ -0,0 +1,214 @@
+//! Opt-in check of whether the agent's final answer is supported by the retrieved context.
+//!
+//! The context of a run is its [`RunOptions::extra_context`](super::RunOptions) and the
+//! outputs of the tools it called. The answer is split into claims (sentences), and every
+//! claim which the context doesn't support is reported in a [`GroundingReport`] together
+//! with a groundedness score. Useful for RAG-heavy agents and swarms.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::llm::{
+    CompletionError, EmbeddingModel, Model, completion::AssistantContent,
+    embedding::cosine_similarity, request::CompletionRequest,
+};
+
+const VERIFIER_PROMPT: &str = "You check whether an answer is supported by the given context. \
+List every claim of the answer which the context does not support, and score the answer from \
+0.0 (nothing is supported) to 1.0 (everything is supported). Reply only with JSON: \
+{\"score\": <number>, \"unsupported_claims\": [<string>, ...]}";
+
+/// How the answer is compared against the context.
+#[derive(Clone)]
+pub enum GroundingCheck<M> {
+    /// A claim is supported if its embedding has a cosine similarity of at least `threshold`
+    /// to a sentence of the context.
+    Embedding {
+        embedder: Arc<dyn EmbeddingModel + Send + Sync>,
+        threshold: f32,
+    },
+    /// Ask a model to find the unsupported claims.
+    Llm(M),
+}
+
+/// The result of a [`GroundingCheck`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroundingReport {
+    /// How well the context supports the answer, from `0.0` to `1.0`.
+    pub score: f32,
+    pub unsupported_claims: Vec<String>,
+}
+
+impl<M> GroundingCheck<M>
+where
+    M: Model + Send + Sync,
+    M::RawCompletionResponse: Send,
+{
+    pub fn embedding(
+        embedder: impl EmbeddingModel + Send + Sync + 'static,
+        threshold: f32,
+    ) -> Self {
+        GroundingCheck::Embedding {
+            embedder: Arc::new(embedder),
+            threshold,
+        }
+    }
+
+    pub fn llm(model: M) -> Self {
+        GroundingCheck::Llm(model)
+    }
+
+    /// Compare the answer against the context, an answer without claims is fully grounded.
+    pub async fn check(
+        &self,
+        answer: &str,
+        context: &[String],
+    ) -> Result<GroundingReport, CompletionError> {
+        let claims = split_sentences(answer);
+        if claims.is_empty() {
+            return Ok(GroundingReport {
+                score: 1.0,
+                unsupported_claims: vec![],
+            });
+        }
+
+        match self {
+            GroundingCheck::Embedding {
+                embedder,
+                threshold,
+            } => {
+                let sentences = context
+                    .iter()
+                    .flat_map(|text| split_sentences(text))
+                    .collect::<Vec<_>>();
+                let claim_count = claims.len();
+                let texts = claims.iter().chain(&sentences).cloned().collect();
+                let embeddings = embedder.embed(texts).await?;
+                if embeddings.len() != claim_count + sentences.len() {
+                    return Err(CompletionError::Other(
+                        "Embedding model returned the wrong number of embeddings".into(),
+                    ));
+                }
+                let (claim_embeddings, context_embeddings) = embeddings.split_at(claim_count);
+                let unsupported_claims = claims
+                    .into_iter()
+                    .zip(claim_embeddings)
+                    .filter(|(_, claim)| {
+                        !context_embeddings
+                            .iter()
+                            .any(|sentence| cosine_similarity(claim, sentence) >= *threshold)
+                    })
+                    .map(|(claim, _)| claim)
+                    .collect::<Vec<_>>();
+                Ok(GroundingReport {
+                    score: 1.0 - unsupported_claims.len() as f32 / claim_count as f32,
+                    unsupported_claims,
+                })
+            }
+            GroundingCheck::Llm(model) => {
+                let request = CompletionRequest {
+                    prompt: format!("Context:\n{}\n\nAnswer:\n{answer}", context.join("\n\n"))
+                        .into(),
+                    system_prompt: Some(VERIFIER_PROMPT.to_owned()),
+                    chat_history: vec![],
+                    tools: vec![],
+                    temperature: Some(0.0),
+                    max_tokens: None,
+                    seed: None,
+                    response_format: None,
+                };
+                let response = model.completion(request).await?;
+                let Some(AssistantContent::Text(text)) = response.choice.first() else {
+                    return Err(CompletionError::Other(
+                        "Grounding verifier returned no text".into(),
+                    ));
+                };
+                parse_report(&text.text)
+            }
+        }
+    }
+}
+
+/// The report in the verifier's reply, which may wrap the JSON in other text.
+fn parse_report(reply: &str) -> Result<GroundingReport, CompletionError> {
+    let json = match (reply.find('{'), reply.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &reply[start..=end],
+        _ => reply,
+    };
+    let mut report = serde_json::from_str::<GroundingReport>(json)?;
+    report.score = report.score.clamp(0.0, 1.0);
+    Ok(report)
+}
+
+/// The sentences of the text, sentences without any letter or digit are skipped.
+fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut sentence = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        sentence.push(c);
+        let ends_sentence = c == '\n'
+            || (matches!(c, '.' | '!' | '?') && chars.peek().is_none_or(|c| c.is_whitespace()));
+        if ends_sentence || chars.peek().is_none() {
+            let trimmed = sentence.trim();
+            if trimmed.chars().any(char::is_alphanumeric) {
+                sentences.push(trimmed.to_owned());
+            }
+            sentence.clear();
+        }
+    }
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FnModel, KeywordEmbedder};
+
+    fn verifier(reply: &str) -> FnModel {
+        FnModel::reply("verifier", reply)
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            split_sentences("Refunds take 3.5 days! Shipping is free.\n- \nAsk us?"),
+            ["Refunds take 3.5 days!", "Shipping is free.", "Ask us?"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_embedding_check() {
+        let check = GroundingCheck::<FnModel>::embedding(KeywordEmbedder, 0.9);
+        let context = vec!["Refunds are paid within 5 days. Orders ship in 2 days.".to_owned()];
+        let report = check
+            .check(
+                "You get a refund in 5 days. Your password is 1234.",
+                &context,
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.unsupported_claims, ["Your password is 1234."]);
+        assert_eq!(report.score, 0.5);
+
+        let empty = check.check("", &context).await.unwrap();
+        assert_eq!(empty.score, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_llm_check() {
+        let check = GroundingCheck::llm(verifier(
+            "Sure: {\"score\": 1.5, \"unsupported_claims\": [\"The moon is cheese.\"]}",
+        ));
+        let report = check
+            .check("The moon is cheese.", &["Facts".to_owned()])
+            .await
+            .unwrap();
+        assert_eq!(report.score, 1.0);
+        assert_eq!(report.unsupported_claims, ["The moon is cheese."]);
+
+        let invalid = GroundingCheck::llm(verifier("looks fine"));
+        assert!(invalid.check("Answer.", &[]).await.is_err());
+    }
+}
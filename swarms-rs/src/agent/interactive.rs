@@ -0,0 +1,59 @@
+use tokio::sync::oneshot;
+
+const QUESTION_START: &str = "<QUESTION>";
+const QUESTION_END: &str = "</QUESTION>";
+
+/// A clarifying question from an agent, answer it to let the agent continue.
+#[derive(Debug)]
+pub struct ClarificationRequest {
+    pub agent_name: String,
+    pub task: String,
+    pub question: String,
+    answer_tx: oneshot::Sender<String>,
+}
+
+impl ClarificationRequest {
+    pub(crate) fn new(
+        agent_name: String,
+        task: String,
+        question: String,
+    ) -> (Self, oneshot::Receiver<String>) {
+        let (answer_tx, answer_rx) = oneshot::channel();
+        let request = Self {
+            agent_name,
+            task,
+            question,
+            answer_tx,
+        };
+        (request, answer_rx)
+    }
+
+    /// Send the answer back to the agent, returns the answer if the agent is gone.
+    pub fn answer(self, answer: impl Into<String>) -> Result<(), String> {
+        self.answer_tx.send(answer.into())
+    }
+}
+
+/// Extract the clarifying question from a response, if the model asked one.
+pub(crate) fn extract_question(response: &str) -> Option<&str> {
+    let start = response.find(QUESTION_START)? + QUESTION_START.len();
+    let end = start + response[start..].find(QUESTION_END)?;
+    let question = response[start..end].trim();
+    (!question.is_empty()).then_some(question)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_question() {
+        assert_eq!(
+            extract_question("<QUESTION> Which language? </QUESTION>"),
+            Some("Which language?")
+        );
+        assert_eq!(extract_question("No question here"), None);
+        assert_eq!(extract_question("<QUESTION></QUESTION>"), None);
+        assert_eq!(extract_question("<QUESTION>unterminated"), None);
+    }
+}
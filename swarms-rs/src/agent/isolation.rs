@@ -0,0 +1,83 @@
+//! Run an agent's tool calls away from the shared runtime, so a tool which blocks or hogs the
+//! CPU can not starve the other agents of a large swarm.
+//!
+//! With [`Isolation::Blocking`] every tool call runs on tokio's blocking thread pool, with
+//! [`Isolation::Runtime`] on a dedicated runtime, which agents can share by cloning it. A tool
+//! which panics on either fails its call instead of the agent's run.
+
+use std::{io, sync::Arc};
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::{
+    concurrency::TaskPanicked,
+    tool::{ToolDyn, ToolError},
+};
+
+/// Where an agent runs its tool calls.
+#[derive(Clone, Default)]
+pub enum Isolation {
+    /// On the task running the agent.
+    #[default]
+    Shared,
+    /// On the blocking thread pool of the current runtime, for tools doing CPU-heavy or
+    /// blocking work.
+    Blocking,
+    /// On a dedicated runtime.
+    Runtime(IsolatedRuntime),
+}
+
+impl Isolation {
+    pub(crate) async fn call(
+        &self,
+        tool: Arc<dyn ToolDyn>,
+        args: String,
+    ) -> Result<String, ToolError> {
+        let handle = match self {
+            Isolation::Shared => return tool.call(args).await,
+            Isolation::Blocking => {
+                let handle = Handle::current();
+                tokio::task::spawn_blocking(move || handle.block_on(tool.call(args)))
+            }
+            Isolation::Runtime(runtime) => {
+                runtime.handle().spawn(async move { tool.call(args).await })
+            }
+        };
+        handle
+            .await
+            .unwrap_or_else(|e| Err(ToolError::ToolCallError(Box::new(TaskPanicked::from(e)))))
+    }
+}
+
+/// A multi-threaded tokio runtime for the tool calls of one or more agents, clones share the
+/// runtime. It shuts down in the background when the last clone is dropped.
+#[derive(Clone)]
+pub struct IsolatedRuntime(Arc<RuntimeOwner>);
+
+impl IsolatedRuntime {
+    /// A runtime with this many worker threads, named `{name}-worker`.
+    pub fn new(name: &str, worker_threads: usize) -> io::Result<Self> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(worker_threads.max(1))
+            .thread_name(format!("{name}-worker"))
+            .enable_all()
+            .build()?;
+        Ok(Self(Arc::new(RuntimeOwner(Some(runtime)))))
+    }
+
+    pub fn handle(&self) -> &Handle {
+        // Safety: the runtime is only taken on drop
+        self.0.0.as_ref().unwrap().handle()
+    }
+}
+
+// Dropping a runtime blocks, which panics inside of another runtime
+struct RuntimeOwner(Option<Runtime>);
+
+impl Drop for RuntimeOwner {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
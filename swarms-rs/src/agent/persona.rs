@@ -0,0 +1,238 @@
+//! A long-lived character for agents which play the same role over many runs, e.g. in
+//! simulations.
+//!
+//! The [`Persona`] is added to the system prompt of every request. After every completed
+//! run the agent reflects on the exchange and adds the facts it learned to the persona. If
+//! the agent has a persona file the persona is saved after every reflection, and loaded from
+//! the file on the first run after a restart.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+
+use crate::persistence::{self, Persistence, PersistenceError};
+
+/// Max number of learned facts a persona keeps by default.
+const DEFAULT_MAX_FACTS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Persona {
+    pub name: String,
+    pub backstory: String,
+    pub goals: Vec<String>,
+    /// Facts learned in earlier runs, oldest first.
+    pub facts: Vec<String>,
+    /// The oldest facts are forgotten once there are more.
+    #[serde(default = "default_max_facts")]
+    pub max_facts: usize,
+}
+
+fn default_max_facts() -> usize {
+    DEFAULT_MAX_FACTS
+}
+
+impl Persona {
+    /// Upgrades of saved personas, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            backstory: String::new(),
+            goals: Vec::new(),
+            facts: Vec::new(),
+            max_facts: DEFAULT_MAX_FACTS,
+        }
+    }
+
+    pub fn backstory(mut self, backstory: impl Into<String>) -> Self {
+        self.backstory = backstory.into();
+        self
+    }
+
+    pub fn goal(mut self, goal: impl Into<String>) -> Self {
+        self.goals.push(goal.into());
+        self
+    }
+
+    pub fn fact(mut self, fact: impl Into<String>) -> Self {
+        self.learn([fact.into()]);
+        self
+    }
+
+    pub fn max_facts(mut self, max_facts: usize) -> Self {
+        self.max_facts = max_facts;
+        self.forget_oldest();
+        self
+    }
+
+    /// Add the facts which are new, returns the number of added facts.
+    pub fn learn(&mut self, facts: impl IntoIterator<Item = String>) -> usize {
+        let mut added = 0;
+        for fact in facts {
+            let fact = fact.trim();
+            if fact.is_empty()
+                || self
+                    .facts
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(fact))
+            {
+                continue;
+            }
+            self.facts.push(fact.to_owned());
+            added += 1;
+        }
+        self.forget_oldest();
+        added
+    }
+
+    fn forget_oldest(&mut self) {
+        let excess = self.facts.len().saturating_sub(self.max_facts);
+        self.facts.drain(..excess);
+    }
+
+    /// The profile as it is added to the system prompt.
+    pub fn prompt(&self) -> String {
+        let mut prompt = format!("You are {}.", self.name);
+        if !self.backstory.is_empty() {
+            prompt.push_str(&format!("\n\nBackstory:\n{}", self.backstory));
+        }
+        for (title, items) in [("Goals", &self.goals), ("What you know", &self.facts)] {
+            if !items.is_empty() {
+                prompt.push_str(&format!("\n\n{title}:\n"));
+                prompt.push_str(&list(items));
+            }
+        }
+        prompt
+    }
+
+    /// Load a persona saved by an agent.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, PersistenceError> {
+        let data = persistence::load_verified(path).await?;
+        persistence::from_versioned_json(&data, Self::MIGRATIONS)
+    }
+}
+
+fn list(items: &[String]) -> String {
+    items.iter().map(|item| format!("- {item}\n")).collect()
+}
+
+/// The facts in a reflection reply, empty if there are none.
+pub(crate) fn parse_facts(reply: &str) -> Vec<String> {
+    reply
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            line.strip_prefix("- ")
+                .or_else(|| line.strip_prefix("* "))
+                .map(str::to_owned)
+        })
+        .collect()
+}
+
+/// The persona of an agent, shared by the agent's clones.
+pub(crate) struct PersonaState {
+    persona: RwLock<Persona>,
+    path: Option<PathBuf>,
+    loaded: OnceCell<()>,
+}
+
+impl PersonaState {
+    pub(crate) fn new(persona: Persona, path: Option<PathBuf>) -> Self {
+        Self {
+            persona: RwLock::new(persona),
+            path,
+            loaded: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Persona {
+        self.persona.read().unwrap().clone() // Safety: the lock is never held across a panic
+    }
+
+    /// Replace the persona with the saved one, once. A missing file keeps the persona.
+    pub(crate) async fn load(&self, persistence: &dyn Persistence) -> Result<(), PersistenceError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        self.loaded
+            .get_or_try_init(|| async {
+                match persistence.load(path).await {
+                    Ok(data) => {
+                        let persona = persistence::from_versioned_json(&data, Persona::MIGRATIONS)?;
+                        *self.persona.write().unwrap() = persona;
+                        Ok(())
+                    }
+                    Err(PersistenceError::IoError(e))
+                        if e.kind() == std::io::ErrorKind::NotFound =>
+                    {
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await
+            .map(|_| ())
+    }
+
+    /// Add the learned facts and save the persona if any of them is new.
+    pub(crate) async fn learn(
+        &self,
+        facts: Vec<String>,
+        persistence: &dyn Persistence,
+    ) -> Result<usize, PersistenceError> {
+        let (added, persona) = {
+            let mut persona = self.persona.write().unwrap();
+            (persona.learn(facts), persona.clone())
+        };
+        if let Some(path) = &self.path
+            && added > 0
+        {
+            let json = persistence::to_versioned_json(&persona)?;
+            persistence.save(path, json.into_bytes()).await?;
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_learn_and_prompt() {
+        let mut persona = Persona::new("Mara")
+            .backstory("A baker in a small town.")
+            .goal("Open a second shop")
+            .fact("Tom buys rye bread")
+            .max_facts(2);
+        assert_eq!(
+            persona.learn(vec!["tom buys rye bread".to_owned(), " ".to_owned()]),
+            0
+        );
+        assert_eq!(
+            persona.learn(vec!["Flour got pricier".to_owned(), "It rained".to_owned()]),
+            2
+        );
+        assert_eq!(persona.facts, ["Flour got pricier", "It rained"]);
+
+        let prompt = persona.prompt();
+        assert!(prompt.starts_with("You are Mara."));
+        assert!(prompt.contains("Goals:\n- Open a second shop\n"));
+        assert!(prompt.contains("What you know:\n- Flour got pricier\n- It rained\n"));
+        assert_eq!(Persona::new("Mara").prompt(), "You are Mara.");
+    }
+
+    #[test]
+    fn test_parse_facts() {
+        assert!(parse_facts("NONE").is_empty());
+        assert_eq!(
+            parse_facts("New facts:\n- Tom is moving away\n  * The mill closed\n"),
+            ["Tom is moving away", "The mill closed"]
+        );
+    }
+}
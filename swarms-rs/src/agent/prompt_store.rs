@@ -0,0 +1,337 @@
+//! Named, versioned prompts, e.g. system prompts which are tuned over time.
+//!
+//! Every published text of a prompt becomes a new version. Runs use the stable version,
+//! unless the agent is pinned to a version or the run is one of the percentage of runs a new
+//! version is rolled out to. Agents which take their system prompt from a [`PromptStore`]
+//! record the version they used in [`AgentRunResult::prompt`](super::AgentRunResult::prompt),
+//! so outcomes can be compared between versions before the new one is promoted.
+//!
+//! The history of each prompt is one JSON file in the store's directory.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::{
+    error::{CategorizedError, ErrorCategory},
+    persistence::{self, FilePersistence, Persistence, PersistenceError},
+    rng::SwarmRng,
+};
+
+#[derive(Debug, Error)]
+pub enum PromptStoreError {
+    #[error("Prompt {0} not found")]
+    UnknownPrompt(String),
+    #[error("Prompt {0} has no version {1}")]
+    UnknownVersion(String, u32),
+    #[error("Rollout percentage must be at most 100, got {0}")]
+    InvalidPercentage(u8),
+    #[error("Persistence error: {0}")]
+    PersistenceError(#[from] PersistenceError),
+}
+
+impl CategorizedError for PromptStoreError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            PromptStoreError::UnknownPrompt(_)
+            | PromptStoreError::UnknownVersion(..)
+            | PromptStoreError::InvalidPercentage(_) => ErrorCategory::Validation,
+            PromptStoreError::PersistenceError(e) => e.category(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptVersion {
+    /// Versions are numbered from 1.
+    pub version: u32,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A version which a share of the runs use instead of the stable version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rollout {
+    pub version: u32,
+    /// Share of the runs in percent.
+    pub percentage: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptHistory {
+    pub name: String,
+    /// Oldest first.
+    pub versions: Vec<PromptVersion>,
+    pub stable: u32,
+    pub rollout: Option<Rollout>,
+    /// Agent name -> version the agent always uses.
+    #[serde(default)]
+    pub pins: BTreeMap<String, u32>,
+}
+
+impl PromptHistory {
+    /// Upgrades of saved histories, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    pub fn get(&self, version: u32) -> Option<&PromptVersion> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    fn check(&self, version: u32) -> Result<(), PromptStoreError> {
+        match self.get(version) {
+            Some(_) => Ok(()),
+            None => Err(PromptStoreError::UnknownVersion(self.name.clone(), version)),
+        }
+    }
+}
+
+/// The version of a prompt a run used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptRef {
+    pub name: String,
+    pub version: u32,
+}
+
+/// Clones share the backend and the random rollout decisions.
+#[derive(Clone)]
+pub struct PromptStore {
+    persistence: Arc<dyn Persistence>,
+    dir: PathBuf,
+    rng: Arc<SwarmRng>,
+    // Serializes the read-modify-write of the histories
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl PromptStore {
+    /// A store of files in the directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self::with_persistence(FilePersistence, dir)
+    }
+
+    pub fn with_persistence(
+        persistence: impl Persistence + 'static,
+        dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            persistence: Arc::new(persistence),
+            dir: dir.into(),
+            rng: Arc::new(SwarmRng::new()),
+            write_lock: Arc::default(),
+        }
+    }
+
+    /// Seed the rollout decisions, the same runs in the same order then get the same
+    /// versions.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Arc::new(SwarmRng::seeded(seed));
+        self
+    }
+
+    pub async fn history(&self, name: &str) -> Result<PromptHistory, PromptStoreError> {
+        match self.persistence.load(&self.path(name)).await {
+            Ok(data) => Ok(persistence::from_versioned_json(
+                &data,
+                PromptHistory::MIGRATIONS,
+            )?),
+            Err(PersistenceError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(PromptStoreError::UnknownPrompt(name.to_owned()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Add the text as the next version of the prompt and return its number. The first
+    /// version of a prompt is stable right away, later versions are rolled out or promoted.
+    pub async fn publish(
+        &self,
+        name: &str,
+        text: impl Into<String>,
+    ) -> Result<u32, PromptStoreError> {
+        let _guard = self.write_lock.lock().await;
+        let mut history = match self.history(name).await {
+            Ok(history) => history,
+            Err(PromptStoreError::UnknownPrompt(_)) => PromptHistory {
+                name: name.to_owned(),
+                versions: Vec::new(),
+                stable: 1,
+                rollout: None,
+                pins: BTreeMap::new(),
+            },
+            Err(e) => return Err(e),
+        };
+        let version = history.versions.last().map_or(1, |v| v.version + 1);
+        history.versions.push(PromptVersion {
+            version,
+            text: text.into(),
+            created_at: Utc::now(),
+        });
+        self.save(&history).await?;
+        Ok(version)
+    }
+
+    /// Use the version for `percentage` percent of the runs, replacing any other rollout.
+    pub async fn rollout(
+        &self,
+        name: &str,
+        version: u32,
+        percentage: u8,
+    ) -> Result<(), PromptStoreError> {
+        if percentage > 100 {
+            return Err(PromptStoreError::InvalidPercentage(percentage));
+        }
+        self.update(name, |history| {
+            history.check(version)?;
+            history.rollout = Some(Rollout {
+                version,
+                percentage,
+            });
+            Ok(())
+        })
+        .await
+    }
+
+    /// Make the version stable and end the rollout.
+    pub async fn promote(&self, name: &str, version: u32) -> Result<(), PromptStoreError> {
+        self.update(name, |history| {
+            history.check(version)?;
+            history.stable = version;
+            history.rollout = None;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Always use the version for the agent, regardless of the stable version and rollout.
+    pub async fn pin(
+        &self,
+        name: &str,
+        agent_name: &str,
+        version: u32,
+    ) -> Result<(), PromptStoreError> {
+        self.update(name, |history| {
+            history.check(version)?;
+            history.pins.insert(agent_name.to_owned(), version);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn unpin(&self, name: &str, agent_name: &str) -> Result<(), PromptStoreError> {
+        self.update(name, |history| {
+            history.pins.remove(agent_name);
+            Ok(())
+        })
+        .await
+    }
+
+    /// The version of the prompt for a run of the agent and its text.
+    pub async fn resolve(
+        &self,
+        name: &str,
+        agent_name: &str,
+    ) -> Result<(PromptRef, String), PromptStoreError> {
+        let history = self.history(name).await?;
+        let version = match (history.pins.get(agent_name), history.rollout) {
+            (Some(version), _) => *version,
+            (None, Some(rollout)) if self.rng.below(100) < rollout.percentage as u64 => {
+                rollout.version
+            }
+            _ => history.stable,
+        };
+        let text = history
+            .get(version)
+            .ok_or_else(|| PromptStoreError::UnknownVersion(name.to_owned(), version))?
+            .text
+            .clone();
+        let prompt = PromptRef {
+            name: name.to_owned(),
+            version,
+        };
+        Ok((prompt, text))
+    }
+
+    async fn update(
+        &self,
+        name: &str,
+        update: impl FnOnce(&mut PromptHistory) -> Result<(), PromptStoreError>,
+    ) -> Result<(), PromptStoreError> {
+        let _guard = self.write_lock.lock().await;
+        let mut history = self.history(name).await?;
+        update(&mut history)?;
+        self.save(&history).await
+    }
+
+    async fn save(&self, history: &PromptHistory) -> Result<(), PromptStoreError> {
+        let json = persistence::to_versioned_json(history)?;
+        self.persistence
+            .save(&self.path(&history.name), json.into_bytes())
+            .await?;
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        prompt_path(&self.dir, name)
+    }
+}
+
+// `<dir>/<name>.json`
+fn prompt_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.json", persistence::sanitize_file_name(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::MemoryPersistence;
+
+    #[tokio::test]
+    async fn test_versions_rollout_and_pins() {
+        let store = PromptStore::with_persistence(MemoryPersistence::default(), "prompts").seed(7);
+        assert!(matches!(
+            store.resolve("support", "agent").await,
+            Err(PromptStoreError::UnknownPrompt(_))
+        ));
+
+        assert_eq!(store.publish("support", "Be polite.").await.unwrap(), 1);
+        assert_eq!(store.publish("support", "Be brief.").await.unwrap(), 2);
+        let (prompt, text) = store.resolve("support", "agent").await.unwrap();
+        assert_eq!((prompt.version, text.as_str()), (1, "Be polite."));
+
+        store.rollout("support", 2, 30).await.unwrap();
+        let mut rolled_out = 0;
+        for _ in 0..1000 {
+            let (prompt, _) = store.resolve("support", "agent").await.unwrap();
+            rolled_out += (prompt.version == 2) as u32;
+        }
+        assert!((200..400).contains(&rolled_out), "{rolled_out}");
+
+        store.pin("support", "legacy", 1).await.unwrap();
+        store.promote("support", 2).await.unwrap();
+        assert_eq!(
+            store.resolve("support", "agent").await.unwrap().0.version,
+            2
+        );
+        assert_eq!(
+            store.resolve("support", "legacy").await.unwrap().0.version,
+            1
+        );
+
+        assert!(matches!(
+            store.rollout("support", 3, 10).await,
+            Err(PromptStoreError::UnknownVersion(_, 3))
+        ));
+        assert!(matches!(
+            store.rollout("support", 2, 101).await,
+            Err(PromptStoreError::InvalidPercentage(101))
+        ));
+        let history = store.history("support").await.unwrap();
+        assert_eq!((history.stable, history.rollout), (2, None));
+    }
+}
@@ -0,0 +1,338 @@
+//! A persistent key-value scratchpad, where an agent keeps notes across tasks.
+//!
+//! The agent reads and writes its scratchpad with the [`ScratchpadGet`] and [`ScratchpadSet`]
+//! tools, and the application through the [`Scratchpad`] API. The entries are saved after
+//! every write and loaded before the first access, so they survive restarts, which is
+//! lighter than a vector store for facts which are looked up by name.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use serde::Deserialize;
+use tokio::{
+    sync::{Mutex, OnceCell},
+    task::JoinHandle,
+};
+
+use crate::{
+    llm::request::ToolDefinition,
+    persistence::{self, Persistence, PersistenceError},
+    tool::Tool,
+};
+
+/// Clones share the entries.
+#[derive(Clone)]
+pub struct Scratchpad {
+    entries: Arc<RwLock<BTreeMap<String, String>>>,
+    persistence: Arc<dyn Persistence>,
+    path: PathBuf,
+    loaded: Arc<OnceCell<()>>,
+    // Serializes the writes, so the last save holds the latest entries and a failed save
+    // only rolls back its own change
+    save_lock: Arc<Mutex<()>>,
+}
+
+impl Scratchpad {
+    /// Upgrades of saved scratchpads, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    /// A scratchpad saved to `path` of the backend.
+    pub fn new(persistence: impl Persistence + 'static, path: impl Into<PathBuf>) -> Self {
+        Self::with_shared_persistence(Arc::new(persistence), path)
+    }
+
+    pub(crate) fn with_shared_persistence(
+        persistence: Arc<dyn Persistence>,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            entries: Arc::default(),
+            persistence,
+            path: path.into(),
+            loaded: Arc::new(OnceCell::new()),
+            save_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, PersistenceError> {
+        self.load().await?;
+        Ok(self.read().get(key).cloned())
+    }
+
+    /// Set the value of the key and save the scratchpad, returns the previous value. If the
+    /// save fails the key keeps its previous value.
+    pub async fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Option<String>, PersistenceError> {
+        self.load().await?;
+        let key = key.into();
+        let _guard = self.save_lock.lock().await;
+        let previous = self.write().insert(key.clone(), value.into());
+        if let Err(e) = self.save().await {
+            self.restore(key, previous.clone());
+            return Err(e);
+        }
+        Ok(previous)
+    }
+
+    /// Remove the key and save the scratchpad, returns the removed value. If the save fails
+    /// the key is kept.
+    pub async fn remove(&self, key: &str) -> Result<Option<String>, PersistenceError> {
+        self.load().await?;
+        let _guard = self.save_lock.lock().await;
+        let removed = self.write().remove(key);
+        if removed.is_some()
+            && let Err(e) = self.save().await
+        {
+            self.restore(key.to_owned(), removed);
+            return Err(e);
+        }
+        Ok(removed)
+    }
+
+    /// All entries, ordered by key.
+    pub async fn entries(&self) -> Result<BTreeMap<String, String>, PersistenceError> {
+        self.load().await?;
+        Ok(self.read().clone())
+    }
+
+    /// Load the saved entries, once. A missing file is an empty scratchpad.
+    pub async fn load(&self) -> Result<(), PersistenceError> {
+        self.loaded
+            .get_or_try_init(|| async {
+                match self.persistence.load(&self.path).await {
+                    Ok(data) => {
+                        let entries = persistence::from_versioned_json(&data, Self::MIGRATIONS)?;
+                        *self.write() = entries;
+                        Ok(())
+                    }
+                    Err(PersistenceError::IoError(e))
+                        if e.kind() == std::io::ErrorKind::NotFound =>
+                    {
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await
+            .map(|_| ())
+    }
+
+    // Callers hold the save lock
+    async fn save(&self) -> Result<(), PersistenceError> {
+        let json = persistence::to_versioned_json(&*self.read())?;
+        self.persistence.save(&self.path, json.into_bytes()).await
+    }
+
+    // Undo a write whose save failed
+    fn restore(&self, key: String, previous: Option<String>) {
+        match previous {
+            Some(value) => self.write().insert(key, value),
+            None => self.write().remove(&key),
+        };
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<String, String>> {
+        // Safety: the lock is never held across a panic
+        self.entries.read().unwrap()
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<String, String>> {
+        self.entries.write().unwrap()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScratchpadGetArgs {
+    pub key: String,
+}
+
+/// Reads a note from the agent's scratchpad.
+pub struct ScratchpadGet(pub Scratchpad);
+
+impl Tool for ScratchpadGet {
+    type Error = PersistenceError;
+    type Args = ScratchpadGetArgs;
+    type Output = String;
+
+    const NAME: &'static str = "scratchpad_get";
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_owned(),
+            description: "Read a note you saved in your scratchpad in this or an earlier task."
+                .to_owned(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "key": {"type": "string", "description": "Key of the note"}
+                },
+                "required": ["key"]
+            }),
+            strict: false,
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let scratchpad = self.0.clone();
+        join(tokio::spawn(async move {
+            if let Some(value) = scratchpad.get(&args.key).await? {
+                return Ok(value);
+            }
+            // Tell the agent which keys exist, so it doesn't have to guess
+            let keys = scratchpad.entries().await?.into_keys().collect::<Vec<_>>();
+            Ok(format!(
+                "No note with key {}. Keys: {}",
+                args.key,
+                if keys.is_empty() {
+                    "none".to_owned()
+                } else {
+                    keys.join(", ")
+                }
+            ))
+        }))
+        .await
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScratchpadSetArgs {
+    pub key: String,
+    pub value: String,
+}
+
+/// Writes a note to the agent's scratchpad.
+pub struct ScratchpadSet(pub Scratchpad);
+
+impl Tool for ScratchpadSet {
+    type Error = PersistenceError;
+    type Args = ScratchpadSetArgs;
+    type Output = String;
+
+    const NAME: &'static str = "scratchpad_set";
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_owned(),
+            description: "Save a note in your scratchpad, it's kept for later tasks. Replaces \
+                          the note with the same key."
+                .to_owned(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "key": {"type": "string", "description": "Key of the note"},
+                    "value": {"type": "string", "description": "The note"}
+                },
+                "required": ["key", "value"]
+            }),
+            strict: false,
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let scratchpad = self.0.clone();
+        join(tokio::spawn(async move {
+            scratchpad.set(&args.key, args.value).await?;
+            Ok(format!("Saved note {}", args.key))
+        }))
+        .await
+    }
+}
+
+// Tool futures must be `Sync` and the persistence futures aren't, so the tools run them on a
+// task and only await its handle
+async fn join(
+    task: JoinHandle<Result<String, PersistenceError>>,
+) -> Result<String, PersistenceError> {
+    task.await
+        .map_err(|e| PersistenceError::IoError(std::io::Error::other(e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::persistence::MemoryPersistence;
+
+    /// Loads from the storage, but every save fails.
+    struct ReadOnly(MemoryPersistence);
+
+    impl Persistence for ReadOnly {
+        fn save<'a>(
+            &'a self,
+            _path: &'a Path,
+            _data: Vec<u8>,
+        ) -> BoxFuture<'a, Result<(), PersistenceError>> {
+            Box::pin(async { Err(std::io::Error::other("read-only").into()) })
+        }
+
+        fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>, PersistenceError>> {
+            self.0.load(path)
+        }
+
+        fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<(), PersistenceError>> {
+            self.0.remove(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scratchpad_survives_restart() {
+        let storage = MemoryPersistence::new();
+        let scratchpad = Scratchpad::new(storage.clone(), "scratchpads/agent.json");
+        assert_eq!(scratchpad.get("city").await.unwrap(), None);
+        let set = ScratchpadSet(scratchpad.clone());
+        set.call(ScratchpadSetArgs {
+            key: "city".to_owned(),
+            value: "Oslo".to_owned(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            scratchpad.set("unit", "celsius").await.unwrap(),
+            None::<String>
+        );
+
+        let restarted = Scratchpad::new(storage, "scratchpads/agent.json");
+        let get = ScratchpadGet(restarted.clone());
+        let args = |key: &str| ScratchpadGetArgs {
+            key: key.to_owned(),
+        };
+        assert_eq!(get.call(args("city")).await.unwrap(), "Oslo");
+        assert_eq!(
+            get.call(args("country")).await.unwrap(),
+            "No note with key country. Keys: city, unit"
+        );
+        assert_eq!(
+            restarted.remove("unit").await.unwrap().as_deref(),
+            Some("celsius")
+        );
+        assert_eq!(restarted.entries().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_save_is_rolled_back() {
+        let storage = MemoryPersistence::new();
+        let path = "scratchpads/agent.json";
+        Scratchpad::new(storage.clone(), path)
+            .set("city", "Oslo")
+            .await
+            .unwrap();
+
+        let scratchpad = Scratchpad::new(ReadOnly(storage), path);
+        assert!(scratchpad.set("city", "Bergen").await.is_err());
+        assert!(scratchpad.set("unit", "celsius").await.is_err());
+        assert!(scratchpad.remove("city").await.is_err());
+        assert_eq!(
+            scratchpad.entries().await.unwrap(),
+            BTreeMap::from([("city".to_owned(), "Oslo".to_owned())])
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Opt-in cache of an agent's answers, keyed by the meaning of the task.
+//!
+//! Tasks are embedded with an [`EmbeddingModel`], a task whose embedding has a cosine
+//! similarity of at least the threshold to a cached task is answered from the cache without
+//! calling the model. Useful for FAQ-style agents which get many similar tasks.
+//!
+//! Answers are cached per scope, e.g. the agent's run options, an answer is only returned for
+//! a task of the same scope.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::llm::{CompletionError, EmbeddingModel, embedding::cosine_similarity};
+
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// Clones share the cached answers.
+#[derive(Clone)]
+pub struct SemanticCache {
+    embedder: Arc<dyn EmbeddingModel + Send + Sync>,
+    threshold: f32,
+    ttl: Option<Duration>,
+    max_entries: usize,
+    entries: Arc<Mutex<VecDeque<CacheEntry>>>,
+}
+
+struct CacheEntry {
+    scope: u64,
+    embedding: Vec<f32>,
+    answer: String,
+    created_at: Instant,
+}
+
+impl SemanticCache {
+    /// Answers never expire and at most 1024 answers are kept by default.
+    pub fn new(embedder: impl EmbeddingModel + Send + Sync + 'static, threshold: f32) -> Self {
+        Self {
+            embedder: Arc::new(embedder),
+            threshold,
+            ttl: None,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Expire answers `ttl` after they were cached.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Max number of cached answers, the oldest answer is evicted first.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    pub(crate) async fn embed(&self, task: &str) -> Result<Vec<f32>, CompletionError> {
+        let mut embeddings = self.embedder.embed(vec![task.to_owned()]).await?;
+        embeddings
+            .pop()
+            .ok_or_else(|| CompletionError::Other("Embedding model returned no embedding".into()))
+    }
+
+    /// The answer of the most similar cached task of the scope, if it's similar enough and not
+    /// expired.
+    pub(crate) fn get(&self, scope: u64, embedding: &[f32]) -> Option<String> {
+        let mut entries = self.lock();
+        self.evict_expired(&mut entries);
+        entries
+            .iter()
+            .filter(|entry| entry.scope == scope)
+            .map(|entry| (cosine_similarity(&entry.embedding, embedding), entry))
+            .filter(|(similarity, _)| *similarity >= self.threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, entry)| entry.answer.clone())
+    }
+
+    pub(crate) fn insert(&self, scope: u64, embedding: Vec<f32>, answer: String) {
+        let mut entries = self.lock();
+        self.evict_expired(&mut entries);
+        while entries.len() >= self.max_entries {
+            entries.pop_front();
+        }
+        entries.push_back(CacheEntry {
+            scope,
+            embedding,
+            answer,
+            created_at: Instant::now(),
+        });
+    }
+
+    /// Number of cached answers, including expired answers which were not evicted yet.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<CacheEntry>> {
+        // Safety: the lock is never held across a panic
+        self.entries.lock().unwrap()
+    }
+
+    fn evict_expired(&self, entries: &mut VecDeque<CacheEntry>) {
+        if let Some(ttl) = self.ttl {
+            // Entries are in insertion order, so the expired ones are at the front
+            while entries
+                .front()
+                .is_some_and(|entry| entry.created_at.elapsed() >= ttl)
+            {
+                entries.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::KeywordEmbedder;
+
+    #[tokio::test]
+    async fn test_get_similar_answer() {
+        let cache = SemanticCache::new(KeywordEmbedder, 0.9).max_entries(2);
+        let refund = cache.embed("How do I get a refund?").await.unwrap();
+        cache.insert(0, refund, "Use the refund form.".to_owned());
+
+        let similar = cache.embed("Refund policy please").await.unwrap();
+        assert_eq!(
+            cache.get(0, &similar).as_deref(),
+            Some("Use the refund form.")
+        );
+        let other = cache.embed("Reset my password").await.unwrap();
+        assert_eq!(cache.get(0, &other), None);
+        assert_eq!(
+            cache.get(1, &similar),
+            None,
+            "other scopes don't share answers"
+        );
+
+        cache.insert(0, other.clone(), "Click 'forgot password'.".to_owned());
+        cache.insert(0, vec![0.0, 1.0, 0.0], "2-3 days.".to_owned());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(0, &similar), None, "oldest answer is evicted");
+    }
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let cache = SemanticCache::new(KeywordEmbedder, 0.9).ttl(Duration::from_millis(20));
+        cache.insert(0, vec![1.0, 0.0, 0.0], "Use the refund form.".to_owned());
+        assert!(cache.get(0, &[1.0, 0.0, 0.0]).is_some());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get(0, &[1.0, 0.0, 0.0]), None);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+//! A user played by an LLM, for automated end-to-end dialog tests and self-play data.
+//!
+//! The [`SimulatedUser`] follows a persona and pursues a goal, it opens the conversation and
+//! answers every reply of the target agent until it reaches its goal or runs out of turns.
+//! The target can be any [`Agent`], it gets the conversation so far as its task every turn.
+
+use serde::{Deserialize, Serialize};
+
+use crate::llm::{self, completion::Message};
+
+use super::{Agent, AgentError, swarms_agent::SwarmsAgentBuilder};
+
+/// Token the simulated user ends its message with once its goal is achieved.
+pub const GOAL_ACHIEVED_TOKEN: &str = "<GOAL_ACHIEVED>";
+const OPENING_PROMPT: &str = "Start the conversation with your first message.";
+
+pub struct SimulatedUser<M>
+where
+    M: llm::Model + Clone + Send + Sync + 'static,
+    M::RawCompletionResponse: Clone + Send + Sync,
+{
+    model: M,
+    name: String,
+    persona: String,
+    goal: String,
+}
+
+impl<M> SimulatedUser<M>
+where
+    M: llm::Model + Clone + Send + Sync + 'static,
+    M::RawCompletionResponse: Clone + Send + Sync,
+{
+    pub fn new(model: M, persona: impl Into<String>, goal: impl Into<String>) -> Self {
+        Self {
+            model,
+            name: "Simulated User".to_owned(),
+            persona: persona.into(),
+            goal: goal.into(),
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    fn system_prompt(&self) -> String {
+        format!(
+            "You are role-playing a user talking to an AI assistant, never act as the assistant.\n\
+             Persona: {}\n\
+             Goal: {}\n\
+             Write only your next message to the assistant, stay in character and pursue your \
+             goal. Once the goal is achieved, end your message with {GOAL_ACHIEVED_TOKEN}.",
+            self.persona, self.goal
+        )
+    }
+
+    /// Converse with the target for at most `max_turns` turns, a turn is a message of the
+    /// simulated user and the reply of the target.
+    ///
+    /// Every call starts a new conversation, so the same user can be used for many dialogs.
+    pub async fn converse(
+        &self,
+        target: &dyn Agent,
+        max_turns: usize,
+    ) -> Result<SimulatedDialogue, AgentError> {
+        let user = SwarmsAgentBuilder::new_with_model(self.model.clone())
+            .agent_name(&self.name)
+            .system_prompt(self.system_prompt())
+            .build();
+        let mut dialogue = SimulatedDialogue {
+            user: self.name.clone(),
+            target: target.name(),
+            turns: Vec::new(),
+            goal_achieved: false,
+        };
+
+        // The simulated user's own view: target replies are user messages
+        let mut history = Vec::new();
+        let mut prompt = OPENING_PROMPT.to_owned();
+        for _ in 0..max_turns {
+            let response = user.chat(prompt.clone(), history.clone()).await?;
+            history.push(Message::user(prompt));
+            history.push(Message::assistant(response.clone()));
+
+            let message = response.replace(GOAL_ACHIEVED_TOKEN, "").trim().to_owned();
+            if !message.is_empty() {
+                dialogue.turns.push(DialogueTurn {
+                    speaker: Speaker::User,
+                    content: message,
+                });
+            }
+            if response.contains(GOAL_ACHIEVED_TOKEN) {
+                dialogue.goal_achieved = true;
+                break;
+            }
+
+            let reply = target.run(dialogue.target_task()).await?;
+            dialogue.turns.push(DialogueTurn {
+                speaker: Speaker::Target,
+                content: reply.clone(),
+            });
+            prompt = reply;
+        }
+
+        tracing::info!(
+            "| Simulated User: {} | Target: {} | Turns: {} | Goal achieved: {}",
+            dialogue.user,
+            dialogue.target,
+            dialogue.turns.len(),
+            dialogue.goal_achieved
+        );
+        Ok(dialogue)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Speaker {
+    User,
+    Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DialogueTurn {
+    pub speaker: Speaker,
+    pub content: String,
+}
+
+/// The messages of a simulated dialog, in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulatedDialogue {
+    /// Name of the simulated user.
+    pub user: String,
+    /// Name of the target agent.
+    pub target: String,
+    pub turns: Vec<DialogueTurn>,
+    /// Whether the simulated user reported that its goal was achieved.
+    pub goal_achieved: bool,
+}
+
+impl SimulatedDialogue {
+    /// The dialog as `name: message` lines.
+    pub fn transcript(&self) -> String {
+        self.turns
+            .iter()
+            .map(|turn| format!("{}: {}", self.speaker_name(turn.speaker), turn.content))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn speaker_name(&self, speaker: Speaker) -> &str {
+        match speaker {
+            Speaker::User => &self.user,
+            Speaker::Target => &self.target,
+        }
+    }
+
+    /// The task of the target, the first message as is, later the whole conversation.
+    fn target_task(&self) -> String {
+        match self.turns.as_slice() {
+            [first] => first.content.clone(),
+            _ => format!(
+                "Conversation so far:\n\n{}\n\nReply to the last message of {}.",
+                self.transcript(),
+                self.user
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::llm::{
+        CompletionError,
+        request::{CompletionRequest, CompletionResponse},
+    };
+
+    /// Answers with the scripted responses in order, and records the prompts.
+    #[derive(Clone, Default)]
+    struct ScriptedModel {
+        responses: Arc<Mutex<VecDeque<&'static str>>>,
+        prompts: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ScriptedModel {
+        fn new(responses: impl IntoIterator<Item = &'static str>) -> Self {
+            Self {
+                responses: Arc::new(Mutex::new(responses.into_iter().collect())),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl llm::Model for ScriptedModel {
+        type RawCompletionResponse = ();
+
+        fn name(&self) -> String {
+            "scripted".to_owned()
+        }
+
+        fn completion(
+            &self,
+            request: CompletionRequest,
+        ) -> BoxFuture<'_, Result<CompletionResponse<()>, CompletionError>> {
+            Box::pin(async move {
+                self.prompts
+                    .lock()
+                    .unwrap()
+                    .push(serde_json::to_string(&request.prompt).unwrap());
+                let response = self.responses.lock().unwrap().pop_front().unwrap_or("...");
+                Ok(CompletionResponse {
+                    choice: vec![response.to_owned().into()],
+                    raw_response: (),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_converse_until_goal_achieved() {
+        let user = SimulatedUser::new(
+            ScriptedModel::new(["I want a refund.", "Order 42.", "Thanks! <GOAL_ACHIEVED>"]),
+            "An impatient customer",
+            "Get a refund for order 42",
+        )
+        .name("Customer");
+        let target_model = ScriptedModel::new(["Which order?", "Refunded."]);
+        let target = SwarmsAgentBuilder::new_with_model(target_model.clone())
+            .agent_name("Support")
+            .build();
+
+        let dialogue = user.converse(&target, 10).await.unwrap();
+        assert!(dialogue.goal_achieved);
+        assert_eq!(dialogue.turns.len(), 5);
+        assert_eq!(dialogue.turns[4].content, "Thanks!");
+        assert_eq!(dialogue.turns[4].speaker, Speaker::User);
+        assert!(
+            dialogue
+                .transcript()
+                .starts_with("Customer: I want a refund.\n\nSupport: Which order?")
+        );
+
+        // The target gets the first message as is, then the conversation so far
+        let prompts = target_model.prompts.lock().unwrap();
+        assert!(prompts[0].contains("I want a refund.") && !prompts[0].contains("Customer:"));
+        assert!(prompts[1].contains("Support: Which order?"));
+    }
+
+    #[tokio::test]
+    async fn test_converse_stops_after_max_turns() {
+        let user = SimulatedUser::new(ScriptedModel::default(), "A curious user", "Chat");
+        let target = SwarmsAgentBuilder::new_with_model(ScriptedModel::default()).build();
+
+        let dialogue = user.converse(&target, 2).await.unwrap();
+        assert!(!dialogue.goal_achieved);
+        assert_eq!(dialogue.turns.len(), 4);
+    }
+}
@@ -0,0 +1,306 @@
+//! Retention of the task states an agent saves with autosave.
+//!
+//! Every task gets its own state file, so the state directory grows without bound. A
+//! [`StateManager`] lists the saved states of a directory and prunes them according to a
+//! [`RetentionPolicy`]: states past the max age or beyond the max number of files are
+//! deleted, old states are compressed with zstd, and the oldest states are deleted until the
+//! directory fits the max size. Compressed states keep the `.json.zst` extension.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::persistence::{self, PersistenceError};
+
+const STATE_EXTENSION: &str = ".json";
+const COMPRESSED_EXTENSION: &str = ".json.zst";
+
+/// Limits of a state directory, unset limits are not enforced.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Keep at most this many states, the newest are kept.
+    pub max_files: Option<usize>,
+    /// Delete states which weren't saved for this long.
+    pub max_age: Option<Duration>,
+    /// Delete the oldest states until all states use at most this many bytes.
+    pub max_total_bytes: Option<u64>,
+    /// Compress states which weren't saved for this long.
+    pub compress_after: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    pub fn compress_after(mut self, compress_after: Duration) -> Self {
+        self.compress_after = Some(compress_after);
+        self
+    }
+}
+
+/// A state file in the state directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedState {
+    pub path: PathBuf,
+    /// When the state was last saved.
+    pub modified: SystemTime,
+    /// Size of the file in bytes.
+    pub size: u64,
+    pub compressed: bool,
+}
+
+/// What [`StateManager::prune`] did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub deleted: Vec<PathBuf>,
+    /// Paths of the new compressed files.
+    pub compressed: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StateManager {
+    dir: PathBuf,
+    policy: RetentionPolicy,
+}
+
+impl StateManager {
+    /// Manage the states in `dir`, nothing is pruned until a policy is set.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            policy: RetentionPolicy::default(),
+        }
+    }
+
+    pub fn retention(mut self, policy: RetentionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The saved states, newest first. A missing directory has no states.
+    pub async fn list(&self) -> Result<Vec<SavedState>, PersistenceError> {
+        let mut entries = match fs::read_dir(persistence::normalize_path(&self.dir)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut states = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let compressed = name.ends_with(COMPRESSED_EXTENSION);
+            if !compressed && !name.ends_with(STATE_EXTENSION) {
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            states.push(SavedState {
+                path: entry.path(),
+                modified: metadata.modified()?,
+                size: metadata.len(),
+                compressed,
+            });
+        }
+        states.sort_by(|a, b| b.modified.cmp(&a.modified).then(a.path.cmp(&b.path)));
+        Ok(states)
+    }
+
+    /// Apply the retention policy to the saved states.
+    pub async fn prune(&self) -> Result<PruneReport, PersistenceError> {
+        let now = SystemTime::now();
+        let age = |state: &SavedState| now.duration_since(state.modified).unwrap_or_default();
+        let mut report = PruneReport::default();
+        let mut states = self.list().await?;
+
+        let mut kept = Vec::with_capacity(states.len());
+        for (index, state) in states.drain(..).enumerate() {
+            let expired = self
+                .policy
+                .max_age
+                .is_some_and(|max_age| age(&state) > max_age);
+            let excess = self.policy.max_files.is_some_and(|max| index >= max);
+            if expired || excess {
+                persistence::remove_file(&state.path).await?;
+                report.deleted.push(state.path);
+            } else {
+                kept.push(state);
+            }
+        }
+
+        if let Some(compress_after) = self.policy.compress_after {
+            for state in &mut kept {
+                if state.compressed || age(state) <= compress_after {
+                    continue;
+                }
+                *state = compress_state(state).await?;
+                report.compressed.push(state.path.clone());
+            }
+        }
+
+        if let Some(max_total_bytes) = self.policy.max_total_bytes {
+            let mut total = kept.iter().map(|state| state.size).sum::<u64>();
+            while total > max_total_bytes {
+                // Safety: the total is only positive if a state is left
+                let oldest = kept.pop().unwrap();
+                persistence::remove_file(&oldest.path).await?;
+                total -= oldest.size;
+                report.deleted.push(oldest.path);
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Read a state, compressed states are decompressed. A corrupted state is recovered from
+    /// its backup.
+    pub async fn read(path: impl AsRef<Path>) -> Result<Vec<u8>, PersistenceError> {
+        let path = path.as_ref();
+        decode_state(path, persistence::load_verified(path).await?)
+    }
+}
+
+/// Decompress the data of a state if its path is a compressed state's.
+pub(crate) fn decode_state(path: &Path, data: Vec<u8>) -> Result<Vec<u8>, PersistenceError> {
+    if path.to_string_lossy().ends_with(COMPRESSED_EXTENSION) {
+        return persistence::decompress(data);
+    }
+    Ok(data)
+}
+
+/// Replace the state with its compressed file, which keeps the state's modification time.
+async fn compress_state(state: &SavedState) -> Result<SavedState, PersistenceError> {
+    let data = persistence::compress(persistence::load_verified(&state.path).await?)?;
+    let mut path = state.path.clone().into_os_string();
+    path.push(".zst");
+    let path = PathBuf::from(path);
+    persistence::save_to_file(&data, &path).await?;
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .await?
+        .into_std()
+        .await
+        .set_modified(state.modified)?;
+    persistence::remove_file(&state.path).await?;
+    Ok(SavedState {
+        path,
+        modified: state.modified,
+        size: data.len() as u64,
+        compressed: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// Write a state which was saved `age` ago.
+    fn write_state(dir: &Path, name: &str, age: Duration, size: usize) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, "x".repeat(size)).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let dir = temp_dir();
+        let old = write_state(&dir, "a_1_old.json", 2 * HOUR, 10);
+        let new = write_state(&dir, "a_1_new.json", HOUR, 10);
+        write_state(&dir, "notes.txt", HOUR, 10);
+
+        let manager = StateManager::new(&*dir);
+        let states = manager.list().await.unwrap();
+        let paths = states.iter().map(|s| s.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths, [new, old]);
+        assert!(
+            StateManager::new(dir.join("missing"))
+                .list()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_by_age_and_count() {
+        let dir = temp_dir();
+        let newest = write_state(&dir, "1.json", Duration::ZERO, 10);
+        let second = write_state(&dir, "2.json", HOUR, 10);
+        let third = write_state(&dir, "3.json", 2 * HOUR, 10);
+        let expired = write_state(&dir, "4.json", 48 * HOUR, 10);
+
+        let report = StateManager::new(&*dir)
+            .retention(RetentionPolicy::default().max_age(24 * HOUR).max_files(2))
+            .prune()
+            .await
+            .unwrap();
+        assert_eq!(report.deleted, [third, expired]);
+        assert!(newest.exists() && second.exists());
+    }
+
+    #[tokio::test]
+    async fn test_compress_and_max_size() {
+        let dir = temp_dir();
+        let newest = write_state(&dir, "1.json", Duration::ZERO, 1000);
+        write_state(&dir, "2.json", 2 * HOUR, 1000);
+        let oldest = write_state(&dir, "3.json", 3 * HOUR, 1000);
+
+        // Room for the newest state and one compressed state
+        let compressed_size = persistence::compress("x".repeat(1000)).unwrap().len() as u64;
+        let report = StateManager::new(&*dir)
+            .retention(
+                RetentionPolicy::default()
+                    .compress_after(HOUR)
+                    .max_total_bytes(1000 + compressed_size),
+            )
+            .prune()
+            .await
+            .unwrap();
+        let compressed = dir.join("2.json.zst");
+        assert_eq!(
+            report.compressed,
+            [compressed.clone(), dir.join("3.json.zst")]
+        );
+        assert_eq!(report.deleted, [dir.join("3.json.zst")]);
+        assert!(newest.exists() && !oldest.exists());
+
+        let states = StateManager::new(&*dir).list().await.unwrap();
+        assert_eq!(states.len(), 2);
+        assert!(states[1].compressed);
+        assert!(
+            SystemTime::now()
+                .duration_since(states[1].modified)
+                .unwrap()
+                > HOUR,
+            "compression keeps the modification time"
+        );
+        let data = StateManager::read(&compressed).await.unwrap();
+        assert_eq!(data, "x".repeat(1000).into_bytes());
+    }
+}
@@ -0,0 +1,186 @@
+//! Usage statistics of an agent's tools, to prune unused tools and improve the descriptions
+//! of tools which fail often.
+//!
+//! Every [`SwarmsAgent`](super::swarms_agent::SwarmsAgent) counts the calls, failures and
+//! latency of its tools in a [`ToolAnalytics`], and warns once about the tools the model never
+//! called after a number of runs. Agents which are given the same analytics share the stats.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::ToolCallRecord;
+
+/// Number of runs after which unused tools are reported by default.
+const DEFAULT_WARN_UNUSED_AFTER: u64 = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolStats {
+    pub calls: u64,
+    /// Calls which returned an error or were denied.
+    pub failures: u64,
+    pub total_latency_ms: u64,
+}
+
+impl ToolStats {
+    /// The stats of each tool in the calls, e.g. the calls of a single run.
+    pub fn from_calls(calls: &[ToolCallRecord]) -> BTreeMap<String, ToolStats> {
+        let mut stats = BTreeMap::<_, ToolStats>::new();
+        for call in calls {
+            stats.entry(call.name.clone()).or_default().add(call);
+        }
+        stats
+    }
+
+    /// Share of the calls which failed, `0` without calls.
+    pub fn failure_rate(&self) -> f64 {
+        if self.calls == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / self.calls as f64
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        Duration::from_millis(
+            self.total_latency_ms
+                .checked_div(self.calls)
+                .unwrap_or_default(),
+        )
+    }
+
+    fn add(&mut self, call: &ToolCallRecord) {
+        self.calls += 1;
+        self.failures += call.output.is_err() as u64;
+        self.total_latency_ms += call.duration_ms;
+    }
+}
+
+/// Clones share the stats.
+#[derive(Clone)]
+pub struct ToolAnalytics {
+    stats: Arc<RwLock<BTreeMap<String, ToolStats>>>,
+    runs: Arc<AtomicU64>,
+    warn_unused_after: Option<u64>,
+}
+
+impl Default for ToolAnalytics {
+    fn default() -> Self {
+        Self {
+            stats: Arc::default(),
+            runs: Arc::default(),
+            warn_unused_after: Some(DEFAULT_WARN_UNUSED_AFTER),
+        }
+    }
+}
+
+impl ToolAnalytics {
+    /// Unused tools are reported after 100 runs by default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report the tools which weren't called in the first `runs` runs, `None` to never
+    /// report them.
+    pub fn warn_unused_after(mut self, runs: Option<u64>) -> Self {
+        self.warn_unused_after = runs;
+        self
+    }
+
+    pub fn record(&self, call: &ToolCallRecord) {
+        // Safety: the lock is never held across a panic
+        let mut stats = self.stats.write().unwrap();
+        stats.entry(call.name.clone()).or_default().add(call);
+    }
+
+    /// Count a finished run, returns the registered tools which were never called if this is
+    /// the run after which unused tools are reported.
+    pub fn record_run<'a>(&self, registered: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let runs = self.runs.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.warn_unused_after == Some(runs) {
+            self.unused(registered)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Number of recorded runs.
+    pub fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self, tool: &str) -> ToolStats {
+        self.all().get(tool).copied().unwrap_or_default()
+    }
+
+    /// The stats of every called tool, ordered by name.
+    pub fn all(&self) -> BTreeMap<String, ToolStats> {
+        self.stats.read().unwrap().clone()
+    }
+
+    /// The registered tools which were never called.
+    pub fn unused<'a>(&self, registered: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let stats = self.stats.read().unwrap();
+        registered
+            .into_iter()
+            .filter(|tool| !stats.contains_key(*tool))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    pub fn reset(&self) {
+        self.stats.write().unwrap().clear();
+        self.runs.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, ok: bool, duration_ms: u64) -> ToolCallRecord {
+        ToolCallRecord {
+            agent: "agent".to_owned(),
+            name: name.to_owned(),
+            arguments: serde_json::json!({}),
+            output: if ok {
+                Ok("done".to_owned())
+            } else {
+                Err("failed".to_owned())
+            },
+            duration_ms,
+        }
+    }
+
+    #[test]
+    fn test_stats_and_unused_tools() {
+        let analytics = ToolAnalytics::new().warn_unused_after(Some(2));
+        for record in [
+            call("search", true, 100),
+            call("search", false, 300),
+            call("fetch", true, 50),
+        ] {
+            analytics.record(&record);
+        }
+        let search = analytics.stats("search");
+        assert_eq!(search.calls, 2);
+        assert_eq!(search.failure_rate(), 0.5);
+        assert_eq!(search.mean_latency(), Duration::from_millis(200));
+        assert_eq!(analytics.stats("shell"), ToolStats::default());
+
+        let registered = ["search", "fetch", "shell"];
+        assert!(analytics.clone().record_run(registered).is_empty());
+        assert_eq!(analytics.record_run(registered), ["shell"]);
+        assert!(analytics.record_run(registered).is_empty(), "reported once");
+        assert_eq!(analytics.runs(), 3);
+
+        let run = ToolStats::from_calls(&[call("fetch", false, 10)]);
+        assert_eq!(run["fetch"].failures, 1);
+    }
+}
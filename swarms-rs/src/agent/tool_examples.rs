@@ -0,0 +1,230 @@
+//! Few-shot examples of tool calls, generated from the tools' JSON schemas.
+//!
+//! Weaker models often call tools with misnamed or mistyped arguments. With
+//! [`AgentConfig::tool_examples`](crate::agent::AgentConfig::tool_examples) the agent lists an
+//! example call of every tool it sends in the system prompt. [`example_arguments`] builds the
+//! arguments from the schema: examples, defaults, constants and the first enum value of the
+//! schema are used as they are, other values are placeholders of the right type and format.
+
+use serde_json::{Map, Value, json};
+
+use crate::{
+    llm::request::ToolDefinition,
+    locale::{self, Locale},
+};
+
+// Nested schemas below this depth get `null`, recursive types would never end otherwise
+const MAX_DEPTH: usize = 8;
+
+/// Example arguments matching the JSON schema of a tool's parameters.
+pub fn example_arguments(schema: &Value) -> Value {
+    example(schema, schema, None, 0)
+}
+
+/// The tool examples section of the system prompt, `None` if there are no tools.
+pub(crate) fn prompt(tools: &[ToolDefinition], locale: Locale) -> Option<String> {
+    if tools.is_empty() {
+        return None;
+    }
+    let mut prompt = locale::heading(locale.catalog().tool_examples);
+    for tool in tools {
+        let arguments = example_arguments(&tool.parameters);
+        prompt.push_str(&format!("\n- {}: {arguments}", tool.name));
+    }
+    Some(prompt)
+}
+
+// An example of `schema`, `name` is the name of the property it describes
+fn example(root: &Value, schema: &Value, name: Option<&str>, depth: usize) -> Value {
+    let Value::Object(schema) = schema else {
+        // `true` allows anything
+        return Value::Null;
+    };
+    if depth > MAX_DEPTH {
+        return Value::Null;
+    }
+    if let Some(Value::Array(examples)) = schema.get("examples")
+        && let Some(example) = examples.first()
+    {
+        return example.clone();
+    }
+    for key in ["default", "const"] {
+        if let Some(value) = schema.get(key) {
+            return value.clone();
+        }
+    }
+    if let Some(Value::Array(values)) = schema.get("enum")
+        && let Some(value) = values.first()
+    {
+        return value.clone();
+    }
+    if let Some(Value::String(reference)) = schema.get("$ref") {
+        return match resolve(root, reference) {
+            Some(target) => example(root, target, name, depth + 1),
+            None => Value::Null,
+        };
+    }
+    for key in ["anyOf", "oneOf", "allOf"] {
+        if let Some(Value::Array(variants)) = schema.get(key) {
+            // `Option<T>` is `T` or null, the example shows `T`
+            let variant = variants
+                .iter()
+                .find(|variant| variant.get("type") != Some(&json!("null")))
+                .or(variants.first());
+            if let Some(variant) = variant {
+                return example(root, variant, name, depth + 1);
+            }
+        }
+    }
+
+    let kind = match schema.get("type") {
+        Some(Value::String(kind)) => kind.as_str(),
+        Some(Value::Array(kinds)) => kinds
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|kind| *kind != "null")
+            .unwrap_or("null"),
+        _ if schema.contains_key("properties") => "object",
+        _ => "null",
+    };
+    match kind {
+        "object" => {
+            let properties = match schema.get("properties") {
+                Some(Value::Object(properties)) => properties,
+                _ => return Value::Object(Map::new()),
+            };
+            let required = match schema.get("required") {
+                Some(Value::Array(required)) => required.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            // Only the required properties, unless all are optional
+            Value::Object(
+                properties
+                    .iter()
+                    .filter(|(key, _)| required.is_empty() || required.contains(&key.as_str()))
+                    .map(|(key, property)| {
+                        (key.clone(), example(root, property, Some(key), depth + 1))
+                    })
+                    .collect(),
+            )
+        }
+        "array" => {
+            let count = schema.get("minItems").and_then(Value::as_u64).unwrap_or(1);
+            let item = schema
+                .get("items")
+                .map_or(Value::Null, |items| example(root, items, name, depth + 1));
+            Value::Array(vec![item; count.max(1) as usize])
+        }
+        "string" => Value::String(string_example(schema, name)),
+        "integer" => schema
+            .get("minimum")
+            .and_then(Value::as_i64)
+            .map_or(json!(1), |minimum| json!(minimum.max(1))),
+        "number" => schema
+            .get("minimum")
+            .and_then(Value::as_f64)
+            .map_or(json!(1.5), |minimum| json!(minimum.max(1.5))),
+        "boolean" => Value::Bool(true),
+        _ => Value::Null,
+    }
+}
+
+fn string_example(schema: &Map<String, Value>, name: Option<&str>) -> String {
+    match schema.get("format").and_then(Value::as_str) {
+        Some("date-time") => "2025-01-31T09:30:00Z".to_owned(),
+        Some("date") => "2025-01-31".to_owned(),
+        Some("time") => "09:30:00".to_owned(),
+        Some("email") => "user@example.com".to_owned(),
+        Some("uri" | "url") => "https://example.com".to_owned(),
+        Some("uuid") => "123e4567-e89b-12d3-a456-426614174000".to_owned(),
+        _ => match name {
+            Some(name) => format!("example {}", name.replace('_', " ")),
+            None => "example".to_owned(),
+        },
+    }
+}
+
+// The schema a local `$ref` like `#/$defs/Name` points to
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    root.pointer(reference.strip_prefix('#')?)
+}
+
+#[cfg(test)]
+mod tests {
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    enum Unit {
+        Celsius,
+        Fahrenheit,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct Location {
+        city: String,
+        country_code: Option<String>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct Forecast {
+        location: Location,
+        unit: Unit,
+        days: u8,
+        hourly: bool,
+        #[schemars(extend("examples" = [["Oslo", "Bergen"]]))]
+        compare_with: Vec<String>,
+        note: Option<String>,
+    }
+
+    #[test]
+    fn test_example_arguments() {
+        let schema = schemars::schema_for!(Forecast).as_value().to_owned();
+        assert_eq!(
+            example_arguments(&schema),
+            json!({
+                "location": { "city": "example city" },
+                "unit": "Celsius",
+                "days": 1,
+                "hourly": true,
+                "compare_with": ["Oslo", "Bergen"],
+            })
+        );
+
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "when": { "type": "string", "format": "date" },
+                "scores": { "type": "array", "items": { "type": "number", "minimum": 3 }, "minItems": 2 },
+            },
+        });
+        assert_eq!(
+            example_arguments(&schema),
+            json!({ "when": "2025-01-31", "scores": [3.0, 3.0] })
+        );
+    }
+
+    #[test]
+    fn test_prompt() {
+        let tool = ToolDefinition {
+            name: "search".to_owned(),
+            description: "Search the web".to_owned(),
+            parameters: json!({
+                "type": "object",
+                "properties": { "query": { "type": "string" } },
+                "required": ["query"],
+            }),
+            strict: false,
+        };
+        assert_eq!(
+            prompt(&[tool], Locale::English).unwrap(),
+            "### Tool Examples:\n- search: {\"query\":\"example query\"}"
+        );
+        assert_eq!(prompt(&[], Locale::English), None);
+    }
+}
@@ -0,0 +1,173 @@
+//! Send only the tools which are relevant to the request, for agents with many tools.
+//!
+//! A [`ToolSelector`] embeds the name and description of each tool once, and per request
+//! keeps the `top_k` tools whose embedding is the most similar to the prompt's, leaving out
+//! tools below the similarity threshold. Tools on the always-include list are always sent,
+//! and agents with at most `top_k` tools send all of them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::llm::{
+    CompletionError, EmbeddingModel, embedding::cosine_similarity, request::ToolDefinition,
+};
+
+const DEFAULT_TOP_K: usize = 5;
+
+/// Clones share the embeddings of the tools.
+#[derive(Clone)]
+pub struct ToolSelector {
+    embedder: Arc<dyn EmbeddingModel + Send + Sync>,
+    top_k: usize,
+    threshold: f32,
+    always_include: HashSet<String>,
+    // Text of the tool -> its embedding
+    embeddings: Arc<Mutex<HashMap<String, Vec<f32>>>>,
+}
+
+impl ToolSelector {
+    /// Keeps the 5 most relevant tools by default, regardless of their similarity.
+    pub fn new(embedder: impl EmbeddingModel + Send + Sync + 'static) -> Self {
+        Self {
+            embedder: Arc::new(embedder),
+            top_k: DEFAULT_TOP_K,
+            threshold: f32::MIN,
+            always_include: HashSet::new(),
+            embeddings: Arc::default(),
+        }
+    }
+
+    /// Max number of selected tools, not counting the tools which are always included.
+    pub fn top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Min cosine similarity of a selected tool to the prompt.
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Always send the tool, e.g. tools the agent needs for every task.
+    pub fn always_include(mut self, tool_name: impl Into<String>) -> Self {
+        self.always_include.insert(tool_name.into());
+        self
+    }
+
+    /// The tools relevant to the prompt, in their original order.
+    pub async fn select(
+        &self,
+        prompt: &str,
+        tools: &[ToolDefinition],
+    ) -> Result<Vec<ToolDefinition>, CompletionError> {
+        if tools.len() <= self.top_k {
+            return Ok(tools.to_vec());
+        }
+        let candidates = tools
+            .iter()
+            .filter(|tool| !self.always_include.contains(&tool.name))
+            .collect::<Vec<_>>();
+        let texts = candidates
+            .iter()
+            .map(|tool| format!("{}: {}", tool.name, tool.description))
+            .collect::<Vec<_>>();
+
+        let missing = {
+            let embeddings = self.lock();
+            texts
+                .iter()
+                .filter(|text| !embeddings.contains_key(*text))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let mut inputs = missing.clone();
+        inputs.push(prompt.to_owned());
+        let mut embedded = self.embedder.embed(inputs).await?;
+        if embedded.len() != missing.len() + 1 {
+            return Err(CompletionError::Other(
+                "Embedding model returned the wrong number of embeddings".into(),
+            ));
+        }
+        // Safety: there is one embedding more than missing tools
+        let prompt_embedding = embedded.pop().unwrap();
+
+        let mut embeddings = self.lock();
+        embeddings.extend(missing.into_iter().zip(embedded));
+        let mut scored = candidates
+            .iter()
+            .zip(&texts)
+            .map(|(tool, text)| {
+                (
+                    cosine_similarity(&embeddings[text], &prompt_embedding),
+                    &tool.name,
+                )
+            })
+            .filter(|(similarity, _)| *similarity >= self.threshold)
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        let selected = scored
+            .into_iter()
+            .take(self.top_k)
+            .map(|(_, name)| name)
+            .collect::<HashSet<_>>();
+
+        Ok(tools
+            .iter()
+            .filter(|tool| {
+                selected.contains(&tool.name) || self.always_include.contains(&tool.name)
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<f32>>> {
+        // Safety: the lock is never held across a panic
+        self.embeddings.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::KeywordEmbedder;
+
+    fn tool(name: &str, description: &str) -> ToolDefinition {
+        ToolDefinition {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            parameters: serde_json::json!({"type": "object"}),
+            strict: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_select_relevant_tools() {
+        let tools = [
+            tool("issue_refund", "Issue a refund for an order"),
+            tool("track", "Track the shipping of an order"),
+            tool("reset", "Reset the password of a user"),
+            tool("log", "Log a note"),
+        ];
+        let selector = ToolSelector::new(KeywordEmbedder)
+            .top_k(1)
+            .always_include("log");
+        let names = |tools: Vec<ToolDefinition>| {
+            tools.into_iter().map(|tool| tool.name).collect::<Vec<_>>()
+        };
+
+        let selected = selector.select("Where is my shipping?", &tools).await;
+        assert_eq!(names(selected.unwrap()), ["track", "log"]);
+        let selected = selector.select("I forgot my password", &tools).await;
+        assert_eq!(names(selected.unwrap()), ["reset", "log"]);
+        assert_eq!(selector.lock().len(), 3);
+
+        let strict = selector.clone().threshold(0.5);
+        let selected = strict.select("Hello", &tools).await;
+        assert_eq!(names(selected.unwrap()), ["log"]);
+        let few = ToolSelector::new(KeywordEmbedder).top_k(4);
+        assert_eq!(few.select("Hello", &tools).await.unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,286 @@
+//! Label AI-generated outputs with their provenance: the agent, run, model and time which
+//! produced them.
+//!
+//! A [`Watermark`] tags an output either with a visible footer or with an invisible tag of
+//! zero-width characters at its end. Tags can be signed with HMAC-SHA256 over the output and
+//! its provenance, [`Watermark::verify`] then detects changed outputs and forged tags.
+//! [`Watermark::strip`] returns the output without its tag.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    error::{CategorizedError, ErrorCategory},
+    webhook,
+};
+
+const DEFAULT_LABEL: &str = "AI-generated content";
+const FOOTER_SEPARATOR: &str = "\n\n---\n";
+const FOOTER_START: &str = "<!-- provenance: ";
+const FOOTER_END: &str = " -->";
+// The invisible tag is the bits of the tag's JSON between two word joiners
+const INVISIBLE_DELIMITER: char = '\u{2060}';
+const INVISIBLE_ZERO: char = '\u{200B}';
+const INVISIBLE_ONE: char = '\u{200C}';
+
+#[derive(Debug, Error)]
+pub enum WatermarkError {
+    #[error("Content has no provenance tag")]
+    NotTagged,
+    #[error("Malformed provenance tag: {0}")]
+    Malformed(String),
+    #[error("Provenance tag is not signed")]
+    Unsigned,
+    #[error("Signature of the provenance tag doesn't match the content")]
+    InvalidSignature,
+}
+
+impl CategorizedError for WatermarkError {
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Validation
+    }
+}
+
+/// Who produced an output, and when.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub agent: String,
+    pub run_id: Uuid,
+    pub model: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    /// Further labels, e.g. the organization.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Provenance {
+    /// The provenance of an output produced now.
+    pub fn new(agent: impl Into<String>, run_id: Uuid) -> Self {
+        Self {
+            agent: agent.into(),
+            run_id,
+            model: None,
+            timestamp: Utc::now(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// How the provenance is added to the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TagStyle {
+    /// A footer with the label, followed by the provenance in an HTML comment, which Markdown
+    /// renderers hide.
+    #[default]
+    Footer,
+    /// Zero-width characters at the end of the output, invisible when displayed.
+    Invisible,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Tag {
+    #[serde(flatten)]
+    provenance: Provenance,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    style: TagStyle,
+    label: String,
+    signing_key: Option<String>,
+}
+
+impl Default for Watermark {
+    fn default() -> Self {
+        Self {
+            style: TagStyle::default(),
+            label: DEFAULT_LABEL.to_owned(),
+            signing_key: None,
+        }
+    }
+}
+
+impl Watermark {
+    /// Unsigned footers labelled "AI-generated content" by default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn style(mut self, style: TagStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Text of the footer, before the provenance.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = label.into();
+        self
+    }
+
+    /// Sign the tags with this key, verification then requires a valid signature.
+    pub fn signing_key(mut self, key: impl Into<String>) -> Self {
+        self.signing_key = Some(key.into());
+        self
+    }
+
+    /// The content with its provenance tag.
+    pub fn tag(&self, content: &str, provenance: &Provenance) -> String {
+        let tag = Tag {
+            provenance: provenance.clone(),
+            signature: self
+                .signing_key
+                .as_ref()
+                .map(|key| sign(key, content, provenance)),
+        };
+        // Safety: the tag only contains strings and a map with string keys
+        let json = serde_json::to_string(&tag).unwrap();
+        match self.style {
+            TagStyle::Footer => format!(
+                "{content}{FOOTER_SEPARATOR}{}\n{FOOTER_START}{json}{FOOTER_END}",
+                self.label
+            ),
+            TagStyle::Invisible => {
+                let bits = json.bytes().flat_map(|byte| {
+                    (0..8).rev().map(move |bit| match (byte >> bit) & 1 {
+                        0 => INVISIBLE_ZERO,
+                        _ => INVISIBLE_ONE,
+                    })
+                });
+                let mut tagged = String::from(content);
+                tagged.push(INVISIBLE_DELIMITER);
+                tagged.extend(bits);
+                tagged.push(INVISIBLE_DELIMITER);
+                tagged
+            }
+        }
+    }
+
+    /// The provenance of the tagged content, checks the signature if the watermark has a
+    /// signing key. Tags of either style are recognized.
+    pub fn verify(&self, tagged: &str) -> Result<Provenance, WatermarkError> {
+        let (content, json) = split_tag(tagged)?;
+        let tag = serde_json::from_str::<Tag>(&json)
+            .map_err(|e| WatermarkError::Malformed(e.to_string()))?;
+        if let Some(key) = &self.signing_key {
+            let signature = tag.signature.ok_or(WatermarkError::Unsigned)?;
+            if signature != sign(key, content, &tag.provenance) {
+                return Err(WatermarkError::InvalidSignature);
+            }
+        }
+        Ok(tag.provenance)
+    }
+
+    /// The content without its provenance tag, untagged content is returned unchanged.
+    pub fn strip(tagged: &str) -> &str {
+        split_tag(tagged).map_or(tagged, |(content, _)| content)
+    }
+}
+
+// Signature of the content together with its provenance
+fn sign(key: &str, content: &str, provenance: &Provenance) -> String {
+    // Safety: the provenance only contains strings and a map with string keys
+    let provenance = serde_json::to_string(provenance).unwrap();
+    webhook::sign(key, format!("{content}\n{provenance}").as_bytes())
+}
+
+// Split tagged content into the content and the JSON of its tag
+fn split_tag(tagged: &str) -> Result<(&str, String), WatermarkError> {
+    if let Some(rest) = tagged.strip_suffix(INVISIBLE_DELIMITER) {
+        let start = rest
+            .rfind(INVISIBLE_DELIMITER)
+            .ok_or_else(|| WatermarkError::Malformed("unterminated invisible tag".to_owned()))?;
+        let bits = rest[start + INVISIBLE_DELIMITER.len_utf8()..]
+            .chars()
+            .map(|c| match c {
+                INVISIBLE_ZERO => Ok(0u8),
+                INVISIBLE_ONE => Ok(1u8),
+                _ => Err(WatermarkError::Malformed(format!("unexpected {c:?}"))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let bytes = bits
+            .chunks(8)
+            .map(|chunk| chunk.iter().fold(0, |byte, bit| (byte << 1) | bit))
+            .collect();
+        let json =
+            String::from_utf8(bytes).map_err(|e| WatermarkError::Malformed(e.to_string()))?;
+        return Ok((&rest[..start], json));
+    }
+
+    let footer = tagged
+        .strip_suffix(FOOTER_END)
+        .and_then(|rest| rest.rfind(FOOTER_START).map(|start| (rest, start)));
+    let Some((rest, start)) = footer else {
+        return Err(WatermarkError::NotTagged);
+    };
+    let content_end = tagged[..start]
+        .rfind(FOOTER_SEPARATOR)
+        .ok_or(WatermarkError::NotTagged)?;
+    Ok((
+        &tagged[..content_end],
+        rest[start + FOOTER_START.len()..].to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_verify_and_strip() {
+        let provenance = Provenance::new("writer", Uuid::new_v4())
+            .model("gpt-4o")
+            .metadata("org", "acme");
+        let content = "The answer.\n\n---\nWith a rule of its own.";
+
+        for style in [TagStyle::Footer, TagStyle::Invisible] {
+            let watermark = Watermark::new().style(style).signing_key("secret");
+            let tagged = watermark.tag(content, &provenance);
+            assert_eq!(watermark.verify(&tagged).unwrap(), provenance);
+            assert_eq!(Watermark::strip(&tagged), content);
+
+            let changed = tagged.replacen("answer", "answers", 1);
+            assert!(matches!(
+                watermark.verify(&changed),
+                Err(WatermarkError::InvalidSignature)
+            ));
+            let unsigned = Watermark::new().style(style).tag(content, &provenance);
+            assert!(matches!(
+                watermark.verify(&unsigned),
+                Err(WatermarkError::Unsigned)
+            ));
+            assert!(Watermark::new().verify(&unsigned).is_ok());
+        }
+
+        let footer = Watermark::new()
+            .label("Written by AI")
+            .tag("Hi", &provenance);
+        assert!(footer.starts_with("Hi\n\n---\nWritten by AI\n<!-- provenance: {"));
+        let invisible = Watermark::new()
+            .style(TagStyle::Invisible)
+            .tag("Hi", &provenance);
+        assert!(invisible.starts_with("Hi\u{2060}"));
+
+        assert!(matches!(
+            Watermark::new().verify(content),
+            Err(WatermarkError::NotTagged)
+        ));
+        assert_eq!(Watermark::strip(content), content);
+    }
+}
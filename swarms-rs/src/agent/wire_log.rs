@@ -0,0 +1,159 @@
+//! Opt-in log of the requests an agent sends to its models and the responses it gets back.
+//!
+//! Every exchange is appended as one JSON line to the configured file:
+//! `{"timestamp", "agent", "model", "duration_ms", "request", "response" | "error"}`.
+//! Values of redacted fields are replaced with `[REDACTED]` at any depth, and strings which
+//! look like API keys or bearer tokens are redacted everywhere.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+const REDACTED: &str = "[REDACTED]";
+/// Prefixes of API keys of common providers.
+const KEY_PREFIXES: [&str; 4] = ["sk-", "sk_", "xai-", "AIza"];
+const MIN_KEY_LEN: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireLogConfig {
+    /// File the exchanges are appended to.
+    pub path: PathBuf,
+    /// Names of fields whose values are redacted, matched case-insensitively.
+    pub redact_fields: HashSet<String>,
+}
+
+impl WireLogConfig {
+    /// Log to `path`, redacting `api_key`, `authorization`, `password` and `secret` fields.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            redact_fields: ["api_key", "authorization", "password", "secret"]
+                .map(str::to_owned)
+                .into(),
+        }
+    }
+
+    pub fn redact_field(mut self, field: impl Into<String>) -> Self {
+        self.redact_fields.insert(field.into().to_lowercase());
+        self
+    }
+
+    /// The JSON line of an exchange, with secrets redacted.
+    pub(crate) fn entry(
+        &self,
+        agent: &str,
+        model: &str,
+        duration_ms: u64,
+        request: Value,
+        response: Result<Value, String>,
+    ) -> String {
+        let mut entry = json!({
+            "timestamp": Local::now().to_rfc3339(),
+            "agent": agent,
+            "model": model,
+            "duration_ms": duration_ms,
+            "request": request,
+        });
+        match response {
+            Ok(response) => entry["response"] = response,
+            Err(error) => entry["error"] = Value::String(error),
+        }
+        self.redact(&mut entry);
+        let mut line = entry.to_string();
+        line.push('\n');
+        line
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.redact_fields.contains(&key.to_lowercase()) {
+                        *value = Value::String(REDACTED.to_owned());
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact(value)),
+            Value::String(text) => *text = redact_secrets(text),
+            _ => {}
+        }
+    }
+}
+
+/// Redact words which look like API keys and the tokens following `Bearer`, the whitespace
+/// between the words is kept as it is.
+fn redact_secrets(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut after_bearer = false;
+    for piece in text.split_inclusive(char::is_whitespace) {
+        let word = piece.trim_end_matches(char::is_whitespace);
+        let separator = &piece[word.len()..];
+        if word.is_empty() {
+            redacted.push_str(separator);
+            continue;
+        }
+        let token = word.trim_matches(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_');
+        let is_key = token.len() >= MIN_KEY_LEN
+            && KEY_PREFIXES.iter().any(|prefix| token.starts_with(prefix));
+        if (after_bearer && !token.is_empty()) || is_key {
+            redacted.push_str(&word.replace(token, REDACTED));
+        } else {
+            redacted.push_str(word);
+        }
+        redacted.push_str(separator);
+        after_bearer = token.eq_ignore_ascii_case("bearer");
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secrets() {
+        assert_eq!(
+            redact_secrets("use key sk-abcdefghijklmnopqrstuvwx, thanks"),
+            "use key [REDACTED], thanks"
+        );
+        assert_eq!(
+            redact_secrets("Authorization: Bearer abc.def"),
+            "Authorization: Bearer [REDACTED]"
+        );
+        assert_eq!(redact_secrets("ask the sk- team"), "ask the sk- team");
+    }
+
+    #[test]
+    fn test_redact_secrets_across_lines_and_tabs() {
+        assert_eq!(
+            redact_secrets("key:\nsk-abcdefghijklmnopqrstuvwx\tend"),
+            "key:\n[REDACTED]\tend"
+        );
+        assert_eq!(
+            redact_secrets("Authorization: Bearer\n\tabc.def\n"),
+            "Authorization: Bearer\n\t[REDACTED]\n"
+        );
+    }
+
+    #[test]
+    fn test_entry_redacts_fields() {
+        let config = WireLogConfig::new("wire.log").redact_field("Email");
+        let line = config.entry(
+            "agent",
+            "gpt-4o",
+            12,
+            json!({"prompt": "hi", "metadata": {"email": "a@b.c", "API_KEY": "x"}}),
+            Err("failed".to_owned()),
+        );
+        let entry: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["request"]["metadata"]["email"], REDACTED);
+        assert_eq!(entry["request"]["metadata"]["API_KEY"], REDACTED);
+        assert_eq!(entry["request"]["prompt"], "hi");
+        assert_eq!(entry["error"], "failed");
+        assert!(line.ends_with('\n'));
+    }
+}
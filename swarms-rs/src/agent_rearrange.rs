@@ -0,0 +1,243 @@
+//! Agents arranged by a flow pattern.
+//!
+//! The flow `"researcher -> writer, critic -> editor"` runs the researcher on the task, then
+//! the writer and the critic concurrently on the researcher's answer, then the editor on both
+//! of their answers. Steps are separated by `->`, the agents of a step by `,`. Without a flow
+//! the agents run one after another, in the order they were added.
+
+use futures::future::{self, BoxFuture};
+use thiserror::Error;
+
+use crate::{
+    agent::{Agent, AgentError},
+    conversation::{AgentConversation, Participant},
+    error::{CategorizedError, ErrorCategory},
+    swarm::{Swarm, SwarmError, SwarmOutput},
+    utils::is_empty_task,
+};
+
+#[derive(Debug, Error)]
+pub enum AgentRearrangeError {
+    #[error("Tasks or Agents are empty")]
+    EmptyTasksOrAgents,
+    #[error("Invalid flow: {0}")]
+    InvalidFlow(String),
+    #[error("Flow names an unknown agent: {0}")]
+    UnknownAgent(String),
+    #[error("Agent Error: {0}")]
+    AgentError(#[from] AgentError),
+}
+
+impl CategorizedError for AgentRearrangeError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            AgentRearrangeError::EmptyTasksOrAgents
+            | AgentRearrangeError::InvalidFlow(_)
+            | AgentRearrangeError::UnknownAgent(_) => ErrorCategory::Validation,
+            AgentRearrangeError::AgentError(e) => e.category(),
+        }
+    }
+}
+
+pub struct AgentRearrangeBuilder {
+    name: String,
+    description: String,
+    agents: Vec<Box<dyn Agent>>,
+    flow: Option<String>,
+}
+
+impl AgentRearrangeBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn add_agent(mut self, agent: Box<dyn Agent>) -> Self {
+        self.agents.push(agent);
+        self
+    }
+
+    pub fn agents(mut self, agents: Vec<Box<dyn Agent>>) -> Self {
+        self.agents = agents;
+        self
+    }
+
+    /// Arrange the agents by this flow, e.g. `"a -> b, c -> d"`.
+    pub fn flow(mut self, flow: impl Into<String>) -> Self {
+        self.flow = Some(flow.into());
+        self
+    }
+
+    pub fn build(self) -> AgentRearrange {
+        AgentRearrange {
+            name: self.name,
+            description: self.description,
+            agents: self.agents,
+            flow: self.flow,
+        }
+    }
+}
+
+pub struct AgentRearrange {
+    name: String,
+    description: String,
+    agents: Vec<Box<dyn Agent>>,
+    flow: Option<String>,
+}
+
+impl AgentRearrange {
+    pub fn builder() -> AgentRearrangeBuilder {
+        AgentRearrangeBuilder {
+            name: "AgentRearrange".to_owned(),
+            description: "Agents arranged by a flow pattern.".to_owned(),
+            agents: Vec::new(),
+            flow: None,
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The steps of the flow, each with the indices of the agents running in it.
+    pub fn steps(&self) -> Result<Vec<Vec<usize>>, AgentRearrangeError> {
+        let Some(flow) = &self.flow else {
+            return Ok((0..self.agents.len()).map(|index| vec![index]).collect());
+        };
+        flow.split("->")
+            .map(|step| {
+                step.split(',')
+                    .map(str::trim)
+                    .map(|name| {
+                        if name.is_empty() {
+                            return Err(AgentRearrangeError::InvalidFlow(flow.clone()));
+                        }
+                        self.agents
+                            .iter()
+                            .position(|agent| agent.name() == name)
+                            .ok_or_else(|| AgentRearrangeError::UnknownAgent(name.to_owned()))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Run the flow on the task, returns the conversation of all answers.
+    pub async fn run(
+        &self,
+        task: impl Into<String>,
+    ) -> Result<AgentConversation, AgentRearrangeError> {
+        let task = task.into();
+        if self.agents.is_empty() || is_empty_task(&task) {
+            return Err(AgentRearrangeError::EmptyTasksOrAgents);
+        }
+        let steps = self.steps()?;
+
+        let mut conversation = AgentConversation::new(self.name.clone());
+        conversation.add(Participant::human("User"), task.clone());
+        let mut input = task;
+        for step in steps {
+            let agents = step.iter().map(|&index| &self.agents[index]);
+            let answers =
+                future::try_join_all(agents.clone().map(|agent| agent.run(input.clone()))).await?;
+            let mut outputs = Vec::with_capacity(answers.len());
+            for (agent, answer) in agents.zip(answers) {
+                conversation.add(Participant::agent(agent.name()), answer.clone());
+                outputs.push((agent.name(), answer));
+            }
+            // A single answer is passed on as it is, concurrent answers are labeled
+            input = match <[_; 1]>::try_from(outputs) {
+                Ok([(_, answer)]) => answer,
+                Err(outputs) => outputs
+                    .into_iter()
+                    .map(|(name, answer)| format!("{name}: {answer}"))
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+            };
+        }
+        Ok(conversation)
+    }
+}
+
+impl Swarm for AgentRearrange {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, task: String) -> BoxFuture<'_, Result<SwarmOutput, SwarmError>> {
+        Box::pin(async move {
+            self.run(task)
+                .await
+                .map(|output| Box::new(output) as _)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FnAgent;
+
+    fn agent(name: &'static str) -> Box<dyn Agent> {
+        Box::new(FnAgent::new(name, move |task| format!("{name}({task})")))
+    }
+
+    fn answers(conversation: &AgentConversation) -> Vec<String> {
+        conversation.history[1..]
+            .iter()
+            .map(|message| message.body().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_flow() {
+        let swarm = AgentRearrange::builder()
+            .agents(vec![agent("a"), agent("b"), agent("c")])
+            .flow("a -> b, c -> a")
+            .build();
+        let conversation = swarm.run("task").await.unwrap();
+        assert_eq!(
+            answers(&conversation),
+            [
+                "a(task)",
+                "b(a(task))",
+                "c(a(task))",
+                "a(b: b(a(task))\n\nc: c(a(task)))"
+            ]
+        );
+
+        let sequential = AgentRearrange::builder()
+            .agents(vec![agent("a"), agent("b")])
+            .build();
+        let conversation = sequential.run("task").await.unwrap();
+        assert_eq!(answers(&conversation), ["a(task)", "b(a(task))"]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_flow() {
+        let swarm = |flow: &str| {
+            AgentRearrange::builder()
+                .agents(vec![agent("a"), agent("b")])
+                .flow(flow)
+                .build()
+        };
+        assert!(matches!(
+            swarm("a -> x").run("task").await,
+            Err(AgentRearrangeError::UnknownAgent(name)) if name == "x"
+        ));
+        assert!(matches!(
+            swarm("a -> , b").run("task").await,
+            Err(AgentRearrangeError::InvalidFlow(_))
+        ));
+        assert!(matches!(
+            AgentRearrange::builder().build().run("task").await,
+            Err(AgentRearrangeError::EmptyTasksOrAgents)
+        ));
+    }
+}
@@ -9,13 +9,19 @@ use thiserror::Error;
 use crate::{
     self as swarms_rs,
     agent::{
-        Agent, AgentError,
+        Agent, AgentError, RunOptions,
+        boss_prompt::BossPrompt,
+        definition::AgentRegistry,
         swarms_agent::{SwarmsAgent, SwarmsAgentBuilder},
     },
+    error::{CategorizedError, ErrorCategory},
     llm,
-    swarm_router::{SwarmRouter, SwarmRouterError, SwarmType},
+    swarm_router::{SwarmRouter, SwarmRouterError},
+    utils::is_empty_task,
 };
 
+pub use crate::swarm_router::SwarmType;
+
 pub struct AutoSwarm<M>
 where
     M: llm::Model + Clone + Send + Sync + 'static,
@@ -26,7 +32,8 @@ where
     boss: SwarmsAgent<M>,
     agents_model: M,
     existing_agents: DashMap<String, Box<dyn Agent>>,
-    existing_agents_info: Vec<AgentInfo>,
+    boss_prompt: BossPrompt,
+    swarm_type: SwarmType,
 }
 
 impl<M> AutoSwarm<M>
@@ -40,10 +47,7 @@ where
         boss: SwarmsAgent<M>,
         agents_model: M,
     ) -> Self {
-        let boss = boss
-            .system_prompt(BOSS_PROMPT)
-            .tool(SelectAgents)
-            .tool(CreateAgents);
+        let boss = boss.tool(SelectAgents).tool(CreateAgents);
 
         Self {
             name: swarm_name.into(),
@@ -51,34 +55,74 @@ where
             boss,
             agents_model,
             existing_agents: DashMap::new(),
-            existing_agents_info: Vec::new(),
+            boss_prompt: BossPrompt::new(BOSS_PROMPT),
+            swarm_type: SwarmType::Auto,
         }
     }
 
+    /// The kind of swarm the selected or created agents run the task in.
+    pub fn swarm_type(mut self, swarm_type: SwarmType) -> Self {
+        self.swarm_type = swarm_type;
+        self
+    }
+
+    /// An existing agent the boss can select instead of creating new ones.
+    pub fn agent(mut self, agent: Box<dyn Agent>) -> Self {
+        self.boss_prompt.add_agent(&*agent);
+        self.existing_agents.insert(agent.name(), agent);
+        self
+    }
+
+    /// Add every agent of the registry as an existing agent, built on the agents' model.
+    pub fn registry_agents(mut self, registry: &AgentRegistry) -> Self {
+        for definition in registry.definitions() {
+            // Safety: the definition is registered
+            let agent = registry
+                .build(&definition.name, self.agents_model.clone())
+                .unwrap();
+            self = self.agent(Box::new(agent));
+        }
+        self
+    }
+
+    /// A context variable the boss is told about, e.g. the user's locale.
+    pub fn context(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.boss_prompt = self.boss_prompt.context(key, value);
+        self
+    }
+
+    /// Whether to tell the boss the current date and time, `true` by default.
+    pub fn current_time(mut self, current_time: bool) -> Self {
+        self.boss_prompt = self.boss_prompt.current_time(current_time);
+        self
+    }
+
     pub async fn run(
         &self,
         task: impl Into<String>,
     ) -> Result<Box<dyn erased_serde::Serialize>, AutoSwarmError> {
         let task: String = task.into();
 
-        if task.is_empty() {
-            return Err(AutoSwarmError::EmptyTask);
+        if is_empty_task(&task) {
+            return Err(AutoSwarmError::EmptyTasksOrAgents);
         }
 
-        let existing_agents = self
-            .existing_agents_info
-            .iter()
-            .fold(String::new(), |s, agent_info| format!("{s}\n{agent_info}"));
-
-        let prompt = format!("### Existing Agents:\n{existing_agents}\n\n### Task:\n{task}");
-
-        let boss_resp = self.boss.run(prompt).await?;
+        // Rebuilt on every run, so the boss always knows the current time
+        let options = RunOptions {
+            system_prompt_override: Some(self.boss_prompt.build()),
+            ..Default::default()
+        };
+        let prompt = format!("### Task:\n{task}");
+        let boss_resp = self.boss.run_with_options(prompt, options).await?;
         if let Ok(SelectAgentsRequest { agents }) = serde_json::from_str(&boss_resp) {
             let agents = agents
                 .into_iter()
                 .filter(|agent| self.existing_agents.contains_key(agent))
                 .map(|agent| self.existing_agents.get(&agent).unwrap().clone()) // Safety: We have already checked the agent exists.
                 .collect::<Vec<_>>();
+            if agents.is_empty() {
+                return Err(AutoSwarmError::EmptyTasksOrAgents);
+            }
             return self.swarm_router(task, agents).await;
         }
 
@@ -126,7 +170,7 @@ where
         task: String,
         agents: Vec<Box<dyn Agent>>,
     ) -> Result<Box<dyn erased_serde::Serialize>, AutoSwarmError> {
-        let result = SwarmRouter::new(&self.name, &self.description, SwarmType::Auto, agents)
+        let result = SwarmRouter::new(&self.name, &self.description, self.swarm_type, agents)
             .run(task)
             .await?;
 
@@ -136,8 +180,8 @@ where
 
 #[derive(Debug, Error)]
 pub enum AutoSwarmError {
-    #[error("Empty task")]
-    EmptyTask,
+    #[error("Tasks or Agents are empty")]
+    EmptyTasksOrAgents,
     #[error("JSON parsing error: {0}")]
     JsonParseError(#[from] serde_json::Error),
     #[error("Boss agent error: {0}")]
@@ -148,6 +192,20 @@ pub enum AutoSwarmError {
     UnknownBossBehavior(String),
 }
 
+impl CategorizedError for AutoSwarmError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            AutoSwarmError::EmptyTasksOrAgents => ErrorCategory::Validation,
+            // the boss agent's reply is unusable
+            AutoSwarmError::JsonParseError(_) | AutoSwarmError::UnknownBossBehavior(_) => {
+                ErrorCategory::Provider
+            }
+            AutoSwarmError::BossAgentError(e) => e.category(),
+            AutoSwarmError::SwarmRouterError(e) => e.category(),
+        }
+    }
+}
+
 #[tool(description = "
     Select a group of agents to solve the task.
     All agents will cooperate to solve the task.")]
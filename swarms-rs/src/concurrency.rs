@@ -0,0 +1,152 @@
+//! Bounded concurrency with panic containment, for the batch runs of agents and workflows.
+//!
+//! A panic in one task of a batch is caught and returned as [`TaskPanicked`] for that task,
+//! the other tasks of the batch keep running.
+
+use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+
+use futures::{FutureExt, StreamExt, stream::FuturesUnordered};
+use thiserror::Error;
+use tokio::task::{JoinError, JoinSet};
+
+#[derive(Debug, Error)]
+#[error("Task panicked: {0}")]
+pub(crate) struct TaskPanicked(pub String);
+
+impl From<Box<dyn Any + Send>> for TaskPanicked {
+    fn from(payload: Box<dyn Any + Send>) -> Self {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_owned());
+        TaskPanicked(message)
+    }
+}
+
+impl From<JoinError> for TaskPanicked {
+    fn from(e: JoinError) -> Self {
+        match e.try_into_panic() {
+            Ok(payload) => payload.into(),
+            Err(e) => TaskPanicked(e.to_string()),
+        }
+    }
+}
+
+/// Run `f` on the items on spawned tasks, at most `limit` at a time, unlimited if `None`.
+/// Returns the index of each item with its result, in the order the tasks finished.
+pub(crate) async fn spawn_bounded<I, T, F, Fut>(
+    items: impl IntoIterator<Item = I>,
+    limit: Option<usize>,
+    mut f: F,
+) -> Vec<(usize, Result<T, TaskPanicked>)>
+where
+    F: FnMut(I) -> Fut,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let limit = limit.unwrap_or(usize::MAX).max(1);
+    let mut items = items.into_iter().enumerate();
+    let mut running = JoinSet::new();
+    let mut results = Vec::new();
+    loop {
+        while running.len() < limit {
+            let Some((index, item)) = items.next() else {
+                break;
+            };
+            let task = f(item);
+            running.spawn(async move { (index, AssertUnwindSafe(task).catch_unwind().await) });
+        }
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        // The task catches its own panic, so it only fails if the runtime cancelled it
+        match joined {
+            Ok((index, result)) => results.push((index, result.map_err(TaskPanicked::from))),
+            Err(e) => tracing::error!("| concurrency | Task was cancelled: {e}"),
+        }
+    }
+    results
+}
+
+/// Like [`spawn_bounded`], for futures which borrow from the caller and so can't be spawned,
+/// they run concurrently on the caller's task.
+pub(crate) async fn run_bounded<I, T, F, Fut>(
+    items: impl IntoIterator<Item = I>,
+    limit: Option<usize>,
+    mut f: F,
+) -> Vec<(usize, Result<T, TaskPanicked>)>
+where
+    F: FnMut(I) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let limit = limit.unwrap_or(usize::MAX).max(1);
+    let mut items = items.into_iter().enumerate();
+    let mut running = FuturesUnordered::new();
+    let mut results = Vec::new();
+    loop {
+        while running.len() < limit {
+            let Some((index, item)) = items.next() else {
+                break;
+            };
+            let task = AssertUnwindSafe(f(item)).catch_unwind();
+            running.push(async move { (index, task.await) });
+        }
+        let Some((index, result)) = running.next().await else {
+            break;
+        };
+        results.push((index, result.map_err(TaskPanicked::from)));
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panics_are_contained_and_concurrency_bounded() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let task = |n: u64| {
+            let running = Arc::clone(&running);
+            let max_running = Arc::clone(&max_running);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                if n == 2 {
+                    panic!("agent {n} crashed");
+                }
+                n * 10
+            }
+        };
+
+        let mut results = spawn_bounded(0..5, Some(2), task).await;
+        results.sort_by_key(|(index, _)| *index);
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[1].1.as_ref().unwrap(), &10);
+        assert_eq!(
+            results[2].1.as_ref().unwrap_err().to_string(),
+            "Task panicked: agent 2 crashed"
+        );
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+
+        max_running.store(0, Ordering::SeqCst);
+        let results = run_bounded(0..5, None, task).await;
+        assert_eq!(
+            results.iter().filter(|(_, result)| result.is_ok()).count(),
+            4
+        );
+        assert_eq!(max_running.load(Ordering::SeqCst), 5);
+    }
+}
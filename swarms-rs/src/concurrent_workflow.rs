@@ -1,23 +1,37 @@
 use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
     hash::{Hash, Hasher},
-    path::Path,
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
 };
 
 use chrono::Local;
 use dashmap::{DashMap, DashSet};
-use futures::{StreamExt, future::BoxFuture, stream};
+use futures::{StreamExt, future::BoxFuture, stream::FuturesUnordered};
 use serde::Serialize;
 use thiserror::Error;
-use tokio::sync::mpsc;
+use tokio::sync::RwLock;
 use twox_hash::XxHash3_64;
 use uuid::Uuid;
 
 use crate::{
-    agent::{Agent, AgentError},
-    conversation::{AgentConversation, AgentShortMemory, Role},
-    persistence::{self, PersistenceError},
-    swarm::{MetadataSchema, Swarm, SwarmError},
-    utils::run_agent_with_output_schema,
+    agent::{Agent, AgentError, RunOptions, ToolCallRecord},
+    concurrency,
+    config::SwarmsConfig,
+    conversation::{AgentConversation, AgentShortMemory, Participant},
+    dry_run::DryRunReport,
+    error::{CategorizedError, ErrorCategory},
+    events::{self, Phase},
+    llm::{EmbeddingModel, embedding::cluster_by_similarity},
+    meeting_notes::MeetingNotesSummarizer,
+    persistence::{FilePersistence, Persistence, PersistenceError},
+    swarm::{MetadataSchema, Swarm, SwarmError, SwarmOutput},
+    tenant::{self, TenantId},
+    tool::ToolPermissions,
+    utils::{has_empty_tasks, is_empty_task, run_agent_with_output_schema},
+    webhook::{WebhookConfig, WebhookEvent, WebhookNotifier},
 };
 
 #[derive(Debug, Error)]
@@ -30,16 +44,60 @@ pub enum ConcurrentWorkflowError {
     EmptyTasksOrAgents,
     #[error("Task already exists")]
     TaskAlreadyExists,
+    #[error("Agent not found: {0}")]
+    AgentNotFound(String),
+    #[error("Workflow is running, agents can not be changed")]
+    WorkflowRunning,
+    #[error("Duplicate task id: {0}")]
+    DuplicateTaskId(String),
+    #[error("Unknown dependency: {0}")]
+    UnknownDependency(String),
+    #[error("Dependency cycle detected between tasks")]
+    DependencyCycle,
+    #[error("Skipped, dependency {0} failed")]
+    DependencyFailed(String),
     #[error("Json error: {0}")]
     JsonError(#[from] serde_json::Error),
 }
 
+impl CategorizedError for ConcurrentWorkflowError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            ConcurrentWorkflowError::AgentError(e) => e.category(),
+            ConcurrentWorkflowError::FilePersistenceError(e) => e.category(),
+            ConcurrentWorkflowError::JsonError(_) => ErrorCategory::Persistence,
+            ConcurrentWorkflowError::EmptyTasksOrAgents
+            | ConcurrentWorkflowError::TaskAlreadyExists
+            | ConcurrentWorkflowError::AgentNotFound(_)
+            | ConcurrentWorkflowError::WorkflowRunning
+            | ConcurrentWorkflowError::DuplicateTaskId(_)
+            | ConcurrentWorkflowError::UnknownDependency(_)
+            | ConcurrentWorkflowError::DependencyCycle => ErrorCategory::Validation,
+            ConcurrentWorkflowError::DependencyFailed(_) => ErrorCategory::Cancelled,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct ConcurrentWorkflowBuilder {
     name: String,
     description: String,
     metadata_output_dir: String,
     agents: Vec<Box<dyn Agent>>,
+    semantic_dedup: Option<SemanticDedup>,
+    webhook: Option<WebhookConfig>,
+    max_concurrency: Option<usize>,
+    tenant_id: Option<TenantId>,
+    tool_permissions: Option<ToolPermissions>,
+    meeting_notes: Option<MeetingNotesSummarizer>,
+    persistence: Option<Arc<dyn Persistence>>,
+}
+
+/// Run only one representative of each group of near-duplicate tasks in a batch.
+#[derive(Clone)]
+struct SemanticDedup {
+    embedder: Arc<dyn EmbeddingModel + Send + Sync>,
+    threshold: f32,
 }
 
 impl ConcurrentWorkflowBuilder {
@@ -69,12 +127,97 @@ impl ConcurrentWorkflowBuilder {
             .fold(self, |builder, agent| builder.add_agent(agent))
     }
 
+    /// Deduplicate batch tasks before running them.
+    ///
+    /// Tasks whose embeddings have a cosine similarity of at least `threshold` are grouped,
+    /// only one task of each group is run and its result is shared with the others.
+    pub fn semantic_dedup(
+        mut self,
+        embedder: impl EmbeddingModel + Send + Sync + 'static,
+        threshold: f32,
+    ) -> Self {
+        self.semantic_dedup = Some(SemanticDedup {
+            embedder: Arc::new(embedder),
+            threshold,
+        });
+        self
+    }
+
+    /// Post the result of each run to a webhook when the run completes or fails.
+    pub fn webhook(mut self, config: WebhookConfig) -> Self {
+        self.webhook = Some(config);
+        self
+    }
+
+    /// Max number of agents running a task at the same time, and of tasks [`ConcurrentWorkflow::run_batch`]
+    /// runs at the same time, unlimited by default.
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = Some(max_concurrency.max(1));
+        self
+    }
+
+    /// Write metadata and completed results in the tenant's directory, and record the tenant
+    /// in them.
+    pub fn tenant(mut self, tenant_id: TenantId) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+
+    /// Restrict the capabilities of the tools of all agents in the workflow, in addition to
+    /// the agents' own tool permissions.
+    pub fn tool_permissions(mut self, tool_permissions: ToolPermissions) -> Self {
+        self.tool_permissions = Some(tool_permissions);
+        self
+    }
+
+    /// Apply the global defaults, the metadata directory and the concurrency limit.
+    pub fn swarms_config(mut self, config: &SwarmsConfig) -> Self {
+        if let Some(dir) = &config.metadata_dir {
+            self.metadata_output_dir = dir.clone();
+        }
+        if let Some(max_concurrency) = config.max_concurrency {
+            self = self.max_concurrency(max_concurrency);
+        }
+        self
+    }
+
+    /// Write meeting notes of every run next to its metadata, as `<task hash>.notes.json`.
+    pub fn meeting_notes(mut self, summarizer: MeetingNotesSummarizer) -> Self {
+        self.meeting_notes = Some(summarizer);
+        self
+    }
+
+    /// Store the metadata, meeting notes and completed results in this backend instead of
+    /// files, e.g. [`MemoryPersistence`](crate::MemoryPersistence) for tests.
+    pub fn persistence(mut self, persistence: impl Persistence + 'static) -> Self {
+        self.persistence = Some(Arc::new(persistence));
+        self
+    }
+
+    /// Build the workflow, fails with [`ConcurrentWorkflowError::EmptyTasksOrAgents`] if no
+    /// agent was added.
+    ///
+    /// Use [`build`](Self::build) to add the agents later with [`ConcurrentWorkflow::add_agent`].
+    pub fn try_build(self) -> Result<ConcurrentWorkflow, ConcurrentWorkflowError> {
+        if self.agents.is_empty() {
+            return Err(ConcurrentWorkflowError::EmptyTasksOrAgents);
+        }
+        Ok(self.build())
+    }
+
     pub fn build(self) -> ConcurrentWorkflow {
         ConcurrentWorkflow {
             name: self.name,
             metadata_output_dir: self.metadata_output_dir,
             description: self.description,
-            agents: self.agents,
+            agents: RwLock::new(self.agents),
+            semantic_dedup: self.semantic_dedup,
+            webhook: self.webhook.map(WebhookNotifier::new),
+            max_concurrency: self.max_concurrency,
+            tenant_id: self.tenant_id,
+            tool_permissions: self.tool_permissions,
+            meeting_notes: self.meeting_notes,
+            persistence: self.persistence,
             ..Default::default()
         }
     }
@@ -87,8 +230,15 @@ pub struct ConcurrentWorkflow {
     metadata_map: MetadataSchemaMap,
     metadata_output_dir: String,
     tasks: DashSet<String>,
-    agents: Vec<Box<dyn Agent>>,
+    agents: RwLock<Vec<Box<dyn Agent>>>,
     conversation: AgentShortMemory,
+    semantic_dedup: Option<SemanticDedup>,
+    webhook: Option<WebhookNotifier>,
+    max_concurrency: Option<usize>,
+    tenant_id: Option<TenantId>,
+    tool_permissions: Option<ToolPermissions>,
+    meeting_notes: Option<MeetingNotesSummarizer>,
+    persistence: Option<Arc<dyn Persistence>>,
 }
 
 impl ConcurrentWorkflow {
@@ -96,57 +246,236 @@ impl ConcurrentWorkflow {
         ConcurrentWorkflowBuilder::default()
     }
 
+    /// Add an agent to the workflow, it will take part in the next run.
+    ///
+    /// Returns [`ConcurrentWorkflowError::WorkflowRunning`] if the workflow is running.
+    pub fn add_agent(&self, agent: Box<dyn Agent>) -> Result<(), ConcurrentWorkflowError> {
+        let mut agents = self
+            .agents
+            .try_write()
+            .map_err(|_| ConcurrentWorkflowError::WorkflowRunning)?;
+        agents.push(agent);
+        Ok(())
+    }
+
+    /// Remove an agent from the workflow by name and return it.
+    ///
+    /// Returns [`ConcurrentWorkflowError::WorkflowRunning`] if the workflow is running.
+    pub fn remove_agent(&self, name: &str) -> Result<Box<dyn Agent>, ConcurrentWorkflowError> {
+        let mut agents = self
+            .agents
+            .try_write()
+            .map_err(|_| ConcurrentWorkflowError::WorkflowRunning)?;
+        let index = agents
+            .iter()
+            .position(|agent| agent.name() == name)
+            .ok_or_else(|| ConcurrentWorkflowError::AgentNotFound(name.to_owned()))?;
+        Ok(agents.remove(index))
+    }
+
+    fn persistence(&self) -> &dyn Persistence {
+        self.persistence.as_deref().unwrap_or(&FilePersistence)
+    }
+
+    /// The metadata output dir, scoped to the tenant.
+    fn metadata_dir(&self) -> PathBuf {
+        tenant::scoped_dir(&self.metadata_output_dir, self.tenant_id.as_ref())
+    }
+
+    /// Snapshot of the conversations of all tasks run so far, keyed by task.
+    ///
+    /// Safe to call while the workflow is running, no lock is held after it returns.
+    pub fn conversations(&self) -> Vec<(String, AgentConversation)> {
+        self.conversation.snapshot()
+    }
+
+    /// The tool calls of all agents in the latest run of the task, `None` if the task has not
+    /// completed.
+    pub fn tool_calls(&self, task: &str) -> Option<Vec<ToolCallRecord>> {
+        self.metadata_map
+            .0
+            .get(task)
+            .map(|metadata| metadata.tool_calls().cloned().collect())
+    }
+
+    /// The metadata of the latest run of the task, `None` if the task has not completed.
+    ///
+    /// Pass it to a [`TraceExport`](crate::trace_export::TraceExport) to send the run to an
+    /// observability platform.
+    pub fn metadata(&self, task: &str) -> Option<MetadataSchema> {
+        self.metadata_map
+            .0
+            .get(task)
+            .map(|metadata| metadata.clone())
+    }
+
+    /// A [`RunBundle`](crate::run_bundle::RunBundle) of the latest run of the task with its
+    /// conversation, `None` if the task has not completed. Add the run's artifacts to it.
+    pub fn run_bundle(&self, task: &str) -> Option<crate::run_bundle::RunBundle> {
+        let bundle = crate::run_bundle::RunBundle::new(self.metadata(task)?).name(&self.name);
+        Some(match self.conversation.get_owned(task) {
+            Some(conversation) => bundle.conversation(conversation),
+            None => bundle,
+        })
+    }
+
+    /// Get the names of the agents currently in the workflow.
+    pub async fn agent_names(&self) -> Vec<String> {
+        self.agents
+            .read()
+            .await
+            .iter()
+            .map(|agent| agent.name())
+            .collect()
+    }
+
     pub async fn run(
         &self,
         task: impl Into<String>,
     ) -> Result<AgentConversation, ConcurrentWorkflowError> {
         let task = task.into();
+        // Hold the read lock for the whole run, so agents can not be changed mid-run.
+        let agents = self.agents.read().await;
+        let (conversation, _) = self.run_keyed(task.clone(), task, &agents).await?;
+        Ok(conversation)
+    }
+
+    // Run the task, its conversation and metadata are recorded under `key`. Returns the
+    // conversation and the names of the agents which failed.
+    async fn run_keyed(
+        &self,
+        key: String,
+        task: String,
+        agents: &[Box<dyn Agent>],
+    ) -> Result<(AgentConversation, Vec<String>), ConcurrentWorkflowError> {
+        let task_id = Uuid::new_v4();
+        let start = Instant::now();
+        tracing::info!(
+            target: events::TARGET,
+            event = events::WORKFLOW_RUN,
+            phase = Phase::Started.as_str(),
+            %task_id,
+            workflow = %self.name,
+        );
+
+        let result = self.run_inner(&key, task.clone(), agents).await;
 
-        if task.is_empty() || self.agents.is_empty() {
+        let duration_ms = start.elapsed().as_millis() as u64;
+        match &result {
+            Ok(_) => tracing::info!(
+                target: events::TARGET,
+                event = events::WORKFLOW_RUN,
+                phase = Phase::Completed.as_str(),
+                %task_id,
+                workflow = %self.name,
+                duration_ms,
+            ),
+            Err(e) => tracing::error!(
+                target: events::TARGET,
+                event = events::WORKFLOW_RUN,
+                phase = Phase::Failed.as_str(),
+                %task_id,
+                workflow = %self.name,
+                duration_ms,
+                error = %e,
+            ),
+        }
+
+        if let Some(webhook) = &self.webhook {
+            let event = match &result {
+                Ok(_) => WebhookEvent::completed(
+                    &self.name,
+                    &task,
+                    self.metadata_map.0.get(&key).map(|m| m.clone()),
+                ),
+                Err(e) => WebhookEvent::failed(&self.name, &task, e),
+            };
+            webhook.notify_in_background(event);
+        }
+
+        result
+    }
+
+    /// Report the agents the workflow would run for the task, without calling any LLM.
+    pub async fn dry_run(
+        &self,
+        task: impl Into<String>,
+    ) -> Result<DryRunReport, ConcurrentWorkflowError> {
+        let task = task.into();
+        let agents = self.agents.read().await;
+        if is_empty_task(&task) || agents.is_empty() {
             return Err(ConcurrentWorkflowError::EmptyTasksOrAgents);
         }
-        if !self.tasks.insert(task.clone()) {
+
+        Ok(DryRunReport {
+            workflow: self.name.clone(),
+            steps: agents
+                .iter()
+                .map(|agent| agent.dry_run(task.clone()))
+                .collect(),
+        })
+    }
+
+    async fn run_inner(
+        &self,
+        key: &str,
+        task: String,
+        agents: &[Box<dyn Agent>],
+    ) -> Result<(AgentConversation, Vec<String>), ConcurrentWorkflowError> {
+        if is_empty_task(&task) || agents.is_empty() {
+            return Err(ConcurrentWorkflowError::EmptyTasksOrAgents);
+        }
+        if !self.tasks.insert(key.to_owned()) {
             return Err(ConcurrentWorkflowError::TaskAlreadyExists);
         };
 
         self.conversation
-            .add(&task, &self.name, Role::User("User".to_owned()), &task);
-
-        let (tx, mut rx) = mpsc::channel(self.agents.len());
-        let agents = &self.agents;
-        stream::iter(agents)
-            .for_each_concurrent(None, |agent| {
-                let tx = tx.clone();
-                let task = task.clone();
-                async move {
-                    let output =
-                        match run_agent_with_output_schema(agent.as_ref(), task.clone()).await {
-                            Ok(output) => output,
-                            Err(e) => {
-                                tracing::error!(
-                                    "| concurrent workflow | Agent: {} | Task: {} | Error: {}",
-                                    agent.name(),
-                                    task,
-                                    e
-                                );
-                                return;
-                            }
-                        };
-                    tx.send(output).await.unwrap();
+            .add(key, &self.name, Participant::human("User"), &task);
+
+        let outcomes = concurrency::run_bounded(agents.iter(), self.max_concurrency, |agent| {
+            let options = RunOptions {
+                tool_permissions: self.tool_permissions.clone(),
+                ..Default::default()
+            };
+            run_agent_with_output_schema(agent.as_ref(), task.clone(), options)
+        })
+        .await;
+
+        let mut agents_output_schema = Vec::with_capacity(agents.len());
+        let mut failed = Vec::new();
+        for (index, outcome) in outcomes {
+            let error = match outcome {
+                Ok(Ok(output_schema)) => {
+                    self.conversation.add(
+                        key,
+                        &self.name,
+                        Participant::agent(output_schema.agent_name.clone()),
+                        &output_schema.output,
+                    );
+                    for tool_call in &output_schema.tool_calls {
+                        self.conversation.add_tool_call(key, tool_call.clone());
+                    }
+                    agents_output_schema.push(output_schema);
+                    continue;
                 }
-            })
-            .await;
-        drop(tx);
-
-        let mut agents_output_schema = Vec::with_capacity(self.agents.len());
-        while let Some(output_schema) = rx.recv().await {
-            self.conversation.add(
-                &task,
-                &self.name,
-                Role::Assistant(output_schema.agent_name.clone()),
-                &output_schema.output,
+                Ok(Err(e)) => e.to_string(),
+                // A panicking agent only fails its own output
+                Err(e) => e.to_string(),
+            };
+            let agent = &agents[index];
+            tracing::error!(
+                target: events::TARGET,
+                event = events::WORKFLOW_AGENT,
+                phase = Phase::Failed.as_str(),
+                workflow = %self.name,
+                agent = %agent.name(),
+                error = %error,
+                "| concurrent workflow | Agent: {} | Task: {} | Error: {}",
+                agent.name(),
+                task,
+                error
             );
-            agents_output_schema.push(output_schema);
+            failed.push(agent.name());
         }
 
         let metadata = MetadataSchema {
@@ -155,22 +484,36 @@ impl ConcurrentWorkflow {
             description: self.description.clone(),
             agents_output_schema,
             timestamp: Local::now(),
+            tenant_id: self.tenant_id.clone(),
         };
 
-        self.metadata_map.add(&task, metadata.clone());
+        self.metadata_map.add(key, metadata.clone());
 
         let mut hasher = XxHash3_64::default();
-        task.hash(&mut hasher);
+        key.hash(&mut hasher);
         let task_hash = hasher.finish();
-        let metadata_path_dir = Path::new(&self.metadata_output_dir);
+        let metadata_path_dir = self.metadata_dir();
         let metadata_output_dir = metadata_path_dir
             .join(format!("{:x}", task_hash & 0xFFFFFFFF)) // Lower 32 bits of the hash
             .with_extension("json");
-        let metadata_data = serde_json::to_string_pretty(&metadata)?;
-        persistence::save_to_file(metadata_data, &metadata_output_dir).await?;
+        metadata
+            .save(self.persistence(), &metadata_output_dir)
+            .await?;
+
+        if let Some(summarizer) = &self.meeting_notes {
+            summarizer
+                .write_notes(
+                    &metadata,
+                    self.persistence(),
+                    &metadata_output_dir.with_extension("notes.json"),
+                )
+                .await;
+        }
 
         // Safety: we know that the task exists
-        Ok(self.conversation.0.get(&task).unwrap().clone())
+        let mut conversation = self.conversation.get_owned(key).unwrap();
+        conversation.set_tenant_id(self.tenant_id.clone());
+        Ok((conversation, failed))
     }
 
     /// Runs the workflow for a batch of tasks, executes agents concurrently for each task.
@@ -178,28 +521,37 @@ impl ConcurrentWorkflow {
         &self,
         tasks: Vec<String>,
     ) -> Result<DashMap<String, AgentConversation>, ConcurrentWorkflowError> {
-        if tasks.is_empty() || self.agents.is_empty() {
+        // Hold the read lock for the whole batch, so agents can not be changed mid-run.
+        let agents = self.agents.read().await;
+        if has_empty_tasks(&tasks) || agents.is_empty() {
             return Err(ConcurrentWorkflowError::EmptyTasksOrAgents);
         }
 
+        // representative task -> duplicated tasks which share its result
+        let duplicates = self.find_duplicates(&tasks).await;
+        let tasks = tasks
+            .into_iter()
+            .filter(|task| !duplicates.values().any(|dups| dups.contains(task)))
+            .collect::<Vec<_>>();
+
         let results = DashMap::with_capacity(tasks.len());
-        let (tx, mut rx) = mpsc::channel(tasks.len());
-        stream::iter(tasks)
-            .for_each_concurrent(None, |task| {
-                let tx = tx.clone();
-                let workflow = self;
-                async move {
-                    let result = workflow.run(&task).await;
-                    tx.send((task, result)).await.unwrap(); // Safety: we know rx is not dropped
+        let outcomes = concurrency::run_bounded(&tasks, self.max_concurrency, |task| {
+            self.run_keyed(task.clone(), task.clone(), &agents)
+        })
+        .await;
+        for (index, outcome) in outcomes {
+            let task = &tasks[index];
+            match outcome {
+                Ok(Ok((conversation, _))) => {
+                    if let Some(dups) = duplicates.get(task) {
+                        for dup in dups {
+                            results.insert(dup.clone(), conversation.clone());
+                        }
+                    }
+                    results.insert(task.clone(), conversation);
                 }
-            })
-            .await;
-        drop(tx);
-
-        while let Some((task, result)) = rx.recv().await {
-            match result {
-                Ok(conversation) => {
-                    results.insert(task, conversation);
+                Ok(Err(e)) => {
+                    tracing::error!("| concurrent workflow | Task: {} | Error: {}", task, e);
                 }
                 Err(e) => {
                     tracing::error!("| concurrent workflow | Task: {} | Error: {}", task, e);
@@ -211,6 +563,305 @@ impl ConcurrentWorkflow {
     }
 }
 
+impl ConcurrentWorkflow {
+    /// Runs a batch of tasks with priorities and dependencies, at most `max_concurrency` tasks
+    /// run at the same time.
+    ///
+    /// A task starts once all its dependencies have succeeded, when several tasks are ready
+    /// the one with the highest priority starts first. Tasks whose dependencies failed are
+    /// skipped with [`ConcurrentWorkflowError::DependencyFailed`]. Returns the result of every
+    /// task, in the order of the tasks. The conversations and metadata of the runs are recorded
+    /// under the task ids, so tasks with the same text don't clash.
+    pub async fn run_batch_tasks(
+        &self,
+        tasks: Vec<BatchTask>,
+        max_concurrency: usize,
+    ) -> Result<Vec<Result<AgentConversation, ConcurrentWorkflowError>>, ConcurrentWorkflowError>
+    {
+        // Hold the read lock for the whole batch, so agents can not be changed mid-run.
+        let agents = self.agents.read().await;
+        if tasks.is_empty()
+            || tasks.iter().any(|task| is_empty_task(&task.task))
+            || agents.is_empty()
+        {
+            return Err(ConcurrentWorkflowError::EmptyTasksOrAgents);
+        }
+
+        let mut index_of = HashMap::with_capacity(tasks.len());
+        for (index, task) in tasks.iter().enumerate() {
+            if index_of.insert(task.id.as_str(), index).is_some() {
+                return Err(ConcurrentWorkflowError::DuplicateTaskId(task.id.clone()));
+            }
+        }
+
+        let mut pending = vec![0; tasks.len()];
+        let mut dependents = vec![Vec::new(); tasks.len()];
+        for (index, task) in tasks.iter().enumerate() {
+            for dependency in &task.depends_on {
+                let &dependency_index = index_of.get(dependency.as_str()).ok_or_else(|| {
+                    ConcurrentWorkflowError::UnknownDependency(dependency.clone())
+                })?;
+                dependents[dependency_index].push(index);
+                pending[index] += 1;
+            }
+        }
+        if has_dependency_cycle(&pending, &dependents) {
+            return Err(ConcurrentWorkflowError::DependencyCycle);
+        }
+
+        let mut ready = pending
+            .iter()
+            .enumerate()
+            .filter(|(_, pending)| **pending == 0)
+            .map(|(index, _)| (tasks[index].priority, Reverse(index)))
+            .collect::<BinaryHeap<_>>();
+
+        // Conversations of the completed tasks by id, for the references of their dependents
+        let completed = DashMap::with_capacity(tasks.len());
+        let mut results = tasks.iter().map(|_| None).collect::<Vec<_>>();
+        let mut running = FuturesUnordered::new();
+        loop {
+            while running.len() < max_concurrency.max(1) {
+                let Some((_, Reverse(index))) = ready.pop() else {
+                    break;
+                };
+                let batch_task = &tasks[index];
+                let task = batch_task.render(&completed);
+                let agents = &agents;
+                running.push(async move {
+                    (index, self.run_idempotent(batch_task, task, agents).await)
+                });
+            }
+
+            let Some((index, result)) = running.next().await else {
+                break;
+            };
+            match &result {
+                Ok(conversation) => {
+                    completed.insert(tasks[index].id.clone(), conversation.clone());
+                    for &dependent in &dependents[index] {
+                        pending[dependent] -= 1;
+                        if pending[dependent] == 0 {
+                            ready.push((tasks[dependent].priority, Reverse(dependent)));
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "| concurrent workflow | Task: {} | Error: {}, dependents are skipped",
+                        tasks[index].id,
+                        e
+                    );
+                }
+            }
+            results[index] = Some(result);
+        }
+
+        // Tasks which never became ready depend on a failed task
+        Ok(results
+            .into_iter()
+            .zip(&tasks)
+            .map(|(result, batch_task)| {
+                result.unwrap_or_else(|| {
+                    let failed = batch_task
+                        .depends_on
+                        .iter()
+                        .find(|dependency| !completed.contains_key(*dependency))
+                        .cloned()
+                        .unwrap_or_default();
+                    Err(ConcurrentWorkflowError::DependencyFailed(failed))
+                })
+            })
+            .collect())
+    }
+
+    /// Run the task, or load its result if a run with the same idempotency key completed
+    /// before. Results are persisted under the metadata output dir once all agents succeeded,
+    /// a run with a failed agent runs again on the next resume.
+    async fn run_idempotent(
+        &self,
+        batch_task: &BatchTask,
+        task: String,
+        agents: &[Box<dyn Agent>],
+    ) -> Result<AgentConversation, ConcurrentWorkflowError> {
+        let Some(key) = &batch_task.idempotency_key else {
+            let (conversation, _) = self.run_keyed(batch_task.id.clone(), task, agents).await?;
+            return Ok(conversation);
+        };
+
+        let mut hasher = XxHash3_64::default();
+        key.hash(&mut hasher);
+        let path = self
+            .metadata_dir()
+            .join("completed")
+            .join(format!("{:x}", hasher.finish()))
+            .with_extension("json");
+
+        if !batch_task.force_recompute
+            && let Ok(data) = self.persistence().load(&path).await
+        {
+            match serde_json::from_slice(&data) {
+                Ok(conversation) => {
+                    tracing::info!(
+                        "| concurrent workflow | Task: {} | Skipped, completed with key: {}",
+                        batch_task.id,
+                        key
+                    );
+                    return Ok(conversation);
+                }
+                Err(e) => tracing::warn!(
+                    "| concurrent workflow | Task: {} | Invalid stored result, recompute: {}",
+                    batch_task.id,
+                    e
+                ),
+            }
+        }
+
+        let (conversation, failed) = self.run_keyed(batch_task.id.clone(), task, agents).await?;
+        if !failed.is_empty() {
+            tracing::warn!(
+                "| concurrent workflow | Task: {} | Not stored as completed, failed agents: {:?}",
+                batch_task.id,
+                failed
+            );
+            return Ok(conversation);
+        }
+        self.persistence()
+            .save(&path, serde_json::to_vec(&conversation)?)
+            .await?;
+        Ok(conversation)
+    }
+
+    /// Group near-duplicate tasks if semantic dedup is enabled.
+    ///
+    /// Returns a map from each representative task to its duplicates, falls back to
+    /// running all tasks if the embedding fails.
+    async fn find_duplicates(&self, tasks: &[String]) -> HashMap<String, Vec<String>> {
+        let mut duplicates = HashMap::new();
+        let Some(dedup) = &self.semantic_dedup else {
+            return duplicates;
+        };
+        if tasks.len() < 2 {
+            return duplicates;
+        }
+
+        let embeddings = match dedup.embedder.embed(tasks.to_vec()).await {
+            Ok(embeddings) if embeddings.len() == tasks.len() => embeddings,
+            Ok(_) => {
+                tracing::warn!("| concurrent workflow | Embedding count mismatch, skip dedup");
+                return duplicates;
+            }
+            Err(e) => {
+                tracing::warn!("| concurrent workflow | Failed to embed tasks, skip dedup: {e}");
+                return duplicates;
+            }
+        };
+
+        for cluster in cluster_by_similarity(&embeddings, dedup.threshold) {
+            let representative = &tasks[cluster[0]];
+            let dups = cluster[1..]
+                .iter()
+                .map(|&index| tasks[index].clone())
+                .filter(|task| task != representative)
+                .collect::<Vec<_>>();
+            if !dups.is_empty() {
+                tracing::info!(
+                    "| concurrent workflow | Task: {} | Deduplicated: {:?}",
+                    representative,
+                    dups
+                );
+                duplicates.insert(representative.clone(), dups);
+            }
+        }
+        duplicates
+    }
+}
+
+/// A task of a batch with an optional priority and dependencies on other tasks of the batch.
+#[derive(Clone, Debug)]
+pub struct BatchTask {
+    id: String,
+    task: String,
+    priority: i32,
+    depends_on: Vec<String>,
+    idempotency_key: Option<String>,
+    force_recompute: bool,
+}
+
+impl BatchTask {
+    pub fn new(id: impl Into<String>, task: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            task: task.into(),
+            priority: 0,
+            depends_on: Vec::new(),
+            idempotency_key: None,
+            force_recompute: false,
+        }
+    }
+
+    /// Tasks with a higher priority start first, defaults to 0.
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Run this task after the task `id` succeeded.
+    ///
+    /// The result of the dependency can be referenced with `{{id}}` in the task.
+    pub fn depends_on(mut self, id: impl Into<String>) -> Self {
+        self.depends_on.push(id.into());
+        self
+    }
+
+    /// Persist the result under `key` once the task completes, re-running a batch skips
+    /// tasks whose key already has a result.
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Run the task even if a result for its idempotency key exists, the stored result
+    /// is replaced.
+    pub fn force_recompute(mut self) -> Self {
+        self.force_recompute = true;
+        self
+    }
+
+    /// Replace the references to dependencies with their results.
+    fn render(&self, results: &DashMap<String, AgentConversation>) -> String {
+        self.depends_on
+            .iter()
+            .fold(self.task.clone(), |task, dependency| {
+                match results.get(dependency) {
+                    Some(result) => {
+                        task.replace(&format!("{{{{{dependency}}}}}"), &result.to_string())
+                    }
+                    None => task,
+                }
+            })
+    }
+}
+
+/// Kahn's algorithm, `pending` is the number of dependencies of each task.
+fn has_dependency_cycle(pending: &[usize], dependents: &[Vec<usize>]) -> bool {
+    let mut pending = pending.to_vec();
+    let mut ready = (0..pending.len())
+        .filter(|&index| pending[index] == 0)
+        .collect::<Vec<_>>();
+    let mut visited = 0;
+    while let Some(index) = ready.pop() {
+        visited += 1;
+        for &dependent in &dependents[index] {
+            pending[dependent] -= 1;
+            if pending[dependent] == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+    visited != pending.len()
+}
+
 #[derive(Clone, Default, Serialize)]
 struct MetadataSchemaMap(DashMap<String, MetadataSchema>);
 
@@ -225,7 +876,7 @@ impl Swarm for ConcurrentWorkflow {
         &self.name
     }
 
-    fn run(&self, task: String) -> BoxFuture<Result<Box<dyn erased_serde::Serialize>, SwarmError>> {
+    fn run(&self, task: String) -> BoxFuture<'_, Result<SwarmOutput, SwarmError>> {
         Box::pin(async move {
             self.run(task)
                 .await
@@ -234,3 +885,301 @@ impl Swarm for ConcurrentWorkflow {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        persistence::MemoryPersistence,
+        test_support::{FnAgent, TestAgent, temp_dir},
+    };
+
+    #[test]
+    fn test_has_dependency_cycle() {
+        // 0 -> 1 -> 2
+        assert!(!has_dependency_cycle(
+            &[0, 1, 1],
+            &[vec![1], vec![2], vec![]]
+        ));
+        // 0 -> 1 -> 0
+        assert!(has_dependency_cycle(&[1, 1], &[vec![1], vec![0]]));
+    }
+
+    #[test]
+    fn test_batch_task_render() {
+        let results = DashMap::new();
+        let mut conversation = AgentConversation::new("test".to_owned());
+        conversation.add(Participant::agent("Agent"), "result".to_owned());
+        results.insert("a".to_owned(), conversation);
+
+        let task = BatchTask::new("b", "Use {{a}} and {{c}}")
+            .depends_on("a")
+            .depends_on("c");
+        let rendered = task.render(&results);
+        assert!(rendered.contains("result"));
+        assert!(!rendered.contains("{{a}}"));
+        assert!(rendered.contains("{{c}}"));
+    }
+
+    #[tokio::test]
+    async fn test_resume_batch_with_idempotency_key() {
+        let dir = temp_dir();
+        let workflow = |fail| {
+            ConcurrentWorkflow::builder()
+                .metadata_output_dir(dir.to_string_lossy())
+                .add_agent(Box::new(TestAgent { fail }))
+                .build()
+        };
+        let tasks = || vec![BatchTask::new("a", "task a").idempotency_key("key-a")];
+
+        let results = workflow(false).run_batch_tasks(tasks(), 1).await.unwrap();
+        assert!(
+            results[0]
+                .as_ref()
+                .unwrap()
+                .to_string()
+                .contains("done: task a")
+        );
+
+        // The agent would fail now, but the stored result is used
+        let results = workflow(true).run_batch_tasks(tasks(), 1).await.unwrap();
+        assert!(
+            results[0]
+                .as_ref()
+                .unwrap()
+                .to_string()
+                .contains("done: task a")
+        );
+
+        // Forced tasks run again, and the agent fails
+        let forced = vec![
+            BatchTask::new("a", "task a")
+                .idempotency_key("key-a")
+                .force_recompute(),
+        ];
+        let results = workflow(true).run_batch_tasks(forced, 1).await.unwrap();
+        assert!(
+            !results[0]
+                .as_ref()
+                .unwrap()
+                .to_string()
+                .contains("done: task a")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_is_not_stored() {
+        let storage = MemoryPersistence::new();
+        let workflow = |fail| {
+            ConcurrentWorkflow::builder()
+                .add_agent(Box::new(TestAgent { fail }))
+                .persistence(storage.clone())
+                .build()
+        };
+        let tasks = || vec![BatchTask::new("a", "task a").idempotency_key("key-a")];
+
+        // Only the metadata is stored, the run isn't completed
+        let results = workflow(true).run_batch_tasks(tasks(), 1).await.unwrap();
+        assert!(!results[0].as_ref().unwrap().to_string().contains("done"));
+        assert_eq!(storage.paths().len(), 1);
+
+        // The resumed run is recomputed
+        let results = workflow(false).run_batch_tasks(tasks(), 1).await.unwrap();
+        assert!(
+            results[0]
+                .as_ref()
+                .unwrap()
+                .to_string()
+                .contains("done: task a")
+        );
+        assert_eq!(storage.paths().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_change_agents() {
+        let dir = temp_dir();
+        let workflow = ConcurrentWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .build();
+
+        // Changes are rejected while a run holds the agents, instead of waiting for it
+        let running = workflow.agents.read().await;
+        assert!(matches!(
+            workflow.add_agent(Box::new(FnAgent::reply("late", "late answer"))),
+            Err(ConcurrentWorkflowError::WorkflowRunning)
+        ));
+        assert!(matches!(
+            workflow.remove_agent("test"),
+            Err(ConcurrentWorkflowError::WorkflowRunning)
+        ));
+        drop(running);
+
+        workflow
+            .add_agent(Box::new(FnAgent::reply("late", "late answer")))
+            .unwrap();
+        assert_eq!(workflow.agent_names().await, ["test", "late"]);
+        let results = workflow
+            .run_batch(vec!["a".to_owned(), "b".to_owned()])
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            workflow.metadata("a").unwrap().agents_output_schema.len(),
+            2
+        );
+
+        assert!(workflow.remove_agent("late").is_ok());
+        assert!(matches!(
+            workflow.remove_agent("late"),
+            Err(ConcurrentWorkflowError::AgentNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_batch_task_results() {
+        let dir = temp_dir();
+        let workflow = ConcurrentWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .build();
+
+        // Runs are keyed by task id, the same text doesn't clash
+        let results = workflow
+            .run_batch_tasks(
+                vec![BatchTask::new("a", "same"), BatchTask::new("b", "same")],
+                2,
+            )
+            .await
+            .unwrap();
+        assert!(results.iter().all(Result::is_ok));
+        assert!(workflow.metadata("a").is_some());
+        assert!(workflow.metadata("b").is_some());
+
+        // `a` ran before, so it fails and its dependent is skipped
+        let results = workflow
+            .run_batch_tasks(
+                vec![
+                    BatchTask::new("a", "again"),
+                    BatchTask::new("c", "after {{a}}").depends_on("a"),
+                    BatchTask::new("d", "other"),
+                ],
+                2,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            results[0],
+            Err(ConcurrentWorkflowError::TaskAlreadyExists)
+        ));
+        assert!(matches!(
+            &results[1],
+            Err(ConcurrentWorkflowError::DependencyFailed(id)) if id == "a"
+        ));
+        assert!(results[2].is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_scoped_metadata() {
+        let dir = temp_dir();
+        let tenant_id = TenantId::new("acme").unwrap();
+        let workflow = ConcurrentWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .tenant(tenant_id.clone())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .build();
+
+        let conversation = workflow.run("task").await.unwrap();
+        assert_eq!(conversation.tenant_id(), Some(&tenant_id));
+
+        let tenant_dir = dir.join("tenants").join("acme");
+        let metadata_path = std::fs::read_dir(&tenant_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .unwrap();
+        let metadata = std::fs::read_to_string(metadata_path).unwrap();
+        assert!(metadata.contains("\"tenant_id\": \"acme\""));
+        // Nothing is written outside of the tenant's directory
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_empty_tasks_or_agents() {
+        assert!(matches!(
+            ConcurrentWorkflow::builder().try_build(),
+            Err(ConcurrentWorkflowError::EmptyTasksOrAgents)
+        ));
+
+        let workflow = ConcurrentWorkflow::builder()
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .try_build()
+            .unwrap();
+        assert!(matches!(
+            workflow.run(" ").await,
+            Err(ConcurrentWorkflowError::EmptyTasksOrAgents)
+        ));
+        assert!(matches!(
+            workflow
+                .run_batch(vec!["task".to_owned(), String::new()])
+                .await,
+            Err(ConcurrentWorkflowError::EmptyTasksOrAgents)
+        ));
+        assert!(matches!(
+            workflow
+                .run_batch_tasks(vec![BatchTask::new("a", "")], 1)
+                .await,
+            Err(ConcurrentWorkflowError::EmptyTasksOrAgents)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_audit() {
+        let dir = temp_dir();
+        let workflow = ConcurrentWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .build();
+        assert!(workflow.tool_calls("task").is_none());
+
+        let conversation = workflow.run("task").await.unwrap();
+        assert_eq!(conversation.tool_calls_of("lookup").count(), 1);
+        let tool_calls = workflow.tool_calls("task").unwrap();
+        assert_eq!(tool_calls, conversation.tool_calls());
+        assert_eq!(tool_calls[0].agent, "test");
+        assert_eq!(
+            workflow.metadata("task").unwrap().tool_calls().count(),
+            tool_calls.len()
+        );
+        let bundle = workflow.run_bundle("task").unwrap();
+        assert_eq!(bundle.conversations[0].tool_calls(), tool_calls);
+        assert!(workflow.run_bundle("unknown").is_none());
+
+        let metadata_path = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .unwrap();
+        let metadata = std::fs::read_to_string(metadata_path).unwrap();
+        assert!(metadata.contains("\"tool_calls\"") && metadata.contains("lookup"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_persistence() {
+        let dir = temp_dir();
+        let storage = MemoryPersistence::new();
+        let workflow = ConcurrentWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .persistence(storage.clone())
+            .build();
+        workflow.run("task").await.unwrap();
+
+        let paths = storage.paths();
+        assert_eq!(paths.len(), 1);
+        assert!(paths[0].starts_with(&dir));
+        let metadata = String::from_utf8(storage.get(&paths[0]).unwrap()).unwrap();
+        assert!(metadata.contains("done: task"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
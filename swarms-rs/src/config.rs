@@ -0,0 +1,285 @@
+//! Global defaults loaded from a TOML file and environment variables.
+//!
+//! ```toml
+//! default_model = "deepseek-chat"
+//! max_concurrency = 8
+//! metadata_dir = "./temp/metadata"
+//! save_state_dir = "./temp/state"
+//! seed = 42
+//!
+//! [providers.deepseek]
+//! api_key = "sk-..."
+//! base_url = "https://api.deepseek.com/v1"
+//!
+//! [logging]
+//! level = "info"
+//! json = true
+//! ```
+//!
+//! Environment variables override the file:
+//!
+//! | variable                            | setting                        |
+//! |-------------------------------------|--------------------------------|
+//! | `SWARMS_DEFAULT_MODEL`              | `default_model`                |
+//! | `SWARMS_MAX_CONCURRENCY`            | `max_concurrency`              |
+//! | `SWARMS_METADATA_DIR`               | `metadata_dir`                 |
+//! | `SWARMS_SAVE_STATE_DIR`             | `save_state_dir`               |
+//! | `SWARMS_SEED`                       | `seed`                         |
+//! | `SWARMS_LOG_LEVEL`                  | `logging.level`                |
+//! | `SWARMS_JSON_LOGS`                  | `logging.json`                 |
+//! | `<PROVIDER>_API_KEY`                | `providers.<provider>.api_key` |
+//! | `<PROVIDER>_BASE_URL`               | `providers.<provider>.base_url`|
+//!
+//! Provider variables are read for the providers in the file and for `openai` and `deepseek`,
+//! `OPENAI_API_BASE` is accepted as well for compatibility with [`OpenAI::from_env`].
+//!
+//! [`OpenAI::from_env`]: crate::llm::provider::openai::OpenAI::from_env
+
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::error::{CategorizedError, ErrorCategory};
+
+/// Environment variable with the path of the config file.
+pub const CONFIG_PATH_ENV: &str = "SWARMS_CONFIG";
+/// Config file read by [`SwarmsConfig::load`] if [`CONFIG_PATH_ENV`] is not set.
+pub const DEFAULT_CONFIG_PATH: &str = "swarms.toml";
+
+const ENV_PROVIDERS: [&str; 2] = ["openai", "deepseek"];
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Toml error: {0}")]
+    TomlError(#[from] toml::de::Error),
+    #[error("Invalid value of {name}: {value}")]
+    InvalidEnv { name: String, value: String },
+    #[error("No API key configured for provider: {0}")]
+    MissingApiKey(String),
+}
+
+impl CategorizedError for ConfigError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            ConfigError::IoError(_) => ErrorCategory::Persistence,
+            ConfigError::TomlError(_)
+            | ConfigError::InvalidEnv { .. }
+            | ConfigError::MissingApiKey(_) => ErrorCategory::Validation,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwarmsConfig {
+    /// Provider settings by provider name, e.g. `openai`.
+    pub providers: HashMap<String, ProviderConfig>,
+    pub default_model: Option<String>,
+    /// Max number of agents or tasks a workflow runs at the same time.
+    pub max_concurrency: Option<usize>,
+    /// Directory of workflow metadata.
+    pub metadata_dir: Option<String>,
+    /// Directory of agent states.
+    pub save_state_dir: Option<String>,
+    /// Seed of the agents' sampling and the swarms' random decisions, for reproducible runs.
+    pub seed: Option<u64>,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderConfig {
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Log filter, e.g. `info` or `swarms_rs=debug`, `RUST_LOG` is used if unset.
+    pub level: Option<String>,
+    /// Write logs as JSON lines, requires the `json-logs` feature.
+    pub json: bool,
+}
+
+impl SwarmsConfig {
+    /// Load the file at `$SWARMS_CONFIG`, or `swarms.toml` if it exists, then apply the
+    /// environment variables.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match std::env::var(CONFIG_PATH_ENV) {
+            Ok(path) => Self::from_file(path)?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            Err(_) => Self::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Read a TOML file, without applying the environment variables.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)?;
+        Self::from_toml_str(&content)
+    }
+
+    pub fn from_toml_str(content: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Override the settings with the environment variables which are set.
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        self.apply_env_with(|name| std::env::var(name).ok())
+    }
+
+    fn apply_env_with(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(model) = var("SWARMS_DEFAULT_MODEL") {
+            self.default_model = Some(model);
+        }
+        if let Some(value) = var("SWARMS_MAX_CONCURRENCY") {
+            let max_concurrency = value.parse().map_err(|_| ConfigError::InvalidEnv {
+                name: "SWARMS_MAX_CONCURRENCY".to_owned(),
+                value,
+            })?;
+            self.max_concurrency = Some(max_concurrency);
+        }
+        if let Some(dir) = var("SWARMS_METADATA_DIR") {
+            self.metadata_dir = Some(dir);
+        }
+        if let Some(dir) = var("SWARMS_SAVE_STATE_DIR") {
+            self.save_state_dir = Some(dir);
+        }
+        if let Some(value) = var("SWARMS_SEED") {
+            let seed = value.parse().map_err(|_| ConfigError::InvalidEnv {
+                name: "SWARMS_SEED".to_owned(),
+                value,
+            })?;
+            self.seed = Some(seed);
+        }
+        if let Some(level) = var("SWARMS_LOG_LEVEL") {
+            self.logging.level = Some(level);
+        }
+        if let Some(value) = var("SWARMS_JSON_LOGS") {
+            self.logging.json = match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" => true,
+                "0" | "false" | "no" => false,
+                _ => {
+                    return Err(ConfigError::InvalidEnv {
+                        name: "SWARMS_JSON_LOGS".to_owned(),
+                        value,
+                    });
+                }
+            };
+        }
+
+        let mut providers = self.providers.keys().cloned().collect::<Vec<_>>();
+        providers.extend(ENV_PROVIDERS.map(str::to_owned));
+        for provider in providers {
+            let prefix = provider.to_uppercase().replace('-', "_");
+            let mut base_url = var(&format!("{prefix}_BASE_URL"));
+            if provider == "openai" {
+                base_url = base_url.or_else(|| var("OPENAI_API_BASE"));
+            }
+            let api_key = var(&format!("{prefix}_API_KEY"));
+            if api_key.is_none() && base_url.is_none() {
+                continue;
+            }
+
+            let config = self.providers.entry(provider).or_default();
+            if api_key.is_some() {
+                config.api_key = api_key;
+            }
+            if base_url.is_some() {
+                config.base_url = base_url;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn provider(&self, name: &str) -> Option<&ProviderConfig> {
+        self.providers.get(name)
+    }
+
+    /// Install a global subscriber with the configured level and format.
+    ///
+    /// Fails if a global subscriber is already set.
+    #[cfg(feature = "json-logs")]
+    pub fn init_logging(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let filter = match &self.logging.level {
+            Some(level) => tracing_subscriber::EnvFilter::try_new(level)?,
+            None => tracing_subscriber::EnvFilter::from_default_env(),
+        };
+        let builder = tracing_subscriber::fmt().with_env_filter(filter);
+        if self.logging.json {
+            builder.json().flatten_event(true).try_init()
+        } else {
+            builder.try_init()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        default_model = "deepseek-chat"
+        max_concurrency = 4
+
+        [providers.deepseek]
+        api_key = "file-key"
+        base_url = "https://api.deepseek.com/v1"
+
+        [logging]
+        level = "info"
+    "#;
+
+    #[test]
+    fn test_from_toml_str() {
+        let config = SwarmsConfig::from_toml_str(CONFIG).unwrap();
+        assert_eq!(config.default_model.as_deref(), Some("deepseek-chat"));
+        assert_eq!(config.max_concurrency, Some(4));
+        assert_eq!(
+            config.provider("deepseek").unwrap().api_key.as_deref(),
+            Some("file-key")
+        );
+        assert!(!config.logging.json);
+
+        assert!(SwarmsConfig::from_toml_str("unknown = 1").is_err());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let env = HashMap::from([
+            ("SWARMS_MAX_CONCURRENCY", "16"),
+            ("SWARMS_JSON_LOGS", "true"),
+            ("SWARMS_SEED", "42"),
+            ("DEEPSEEK_API_KEY", "env-key"),
+            ("OPENAI_API_BASE", "http://localhost:8000/v1"),
+        ]);
+        let mut config = SwarmsConfig::from_toml_str(CONFIG).unwrap();
+        config
+            .apply_env_with(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(config.max_concurrency, Some(16));
+        assert_eq!(config.seed, Some(42));
+        assert!(config.logging.json);
+        let deepseek = config.provider("deepseek").unwrap();
+        assert_eq!(deepseek.api_key.as_deref(), Some("env-key"));
+        assert_eq!(
+            deepseek.base_url.as_deref(),
+            Some("https://api.deepseek.com/v1")
+        );
+        let openai = config.provider("openai").unwrap();
+        assert_eq!(openai.api_key, None);
+        assert_eq!(openai.base_url.as_deref(), Some("http://localhost:8000/v1"));
+
+        let result = config
+            .apply_env_with(|name| (name == "SWARMS_MAX_CONCURRENCY").then(|| "many".to_owned()));
+        assert!(matches!(result, Err(ConfigError::InvalidEnv { .. })));
+    }
+}
@@ -1,15 +1,28 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     fmt::Display,
+    ops::{AddAssign, Range},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use chrono::Local;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
 
-use crate::persistence::{self, PersistenceError};
+use crate::{
+    agent::ToolCallRecord,
+    error::{CategorizedError, ErrorCategory},
+    feedback::{Feedback, FeedbackRecord},
+    llm::tokenizer::{TokenizerFamily, count_tokens},
+    persistence::{self, PersistenceError},
+    swarm::MetadataSchema,
+    tenant::TenantId,
+};
 
 #[derive(Debug, Error)]
 pub enum ConversationError {
@@ -17,6 +30,22 @@ pub enum ConversationError {
     JsonError(#[from] serde_json::Error),
     #[error("FilePersistence error: {0}")]
     FilePersistenceError(#[from] PersistenceError),
+    #[error("Message index {0} out of range")]
+    IndexOutOfRange(usize),
+    #[error("No conversation for task: {0}")]
+    UnknownTask(String),
+}
+
+impl CategorizedError for ConversationError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            ConversationError::JsonError(_) => ErrorCategory::Persistence,
+            ConversationError::FilePersistenceError(e) => e.category(),
+            ConversationError::IndexOutOfRange(_) | ConversationError::UnknownTask(_) => {
+                ErrorCategory::Validation
+            }
+        }
+    }
 }
 
 #[derive(Clone, Serialize)]
@@ -32,14 +61,74 @@ impl AgentShortMemory {
         &self,
         task: impl Into<String>,
         conversation_owner: impl Into<String>,
-        role: Role,
+        participant: impl Into<Participant>,
         message: impl Into<String>,
     ) {
         let mut conversation = self
             .0
             .entry(task.into())
             .or_insert(AgentConversation::new(conversation_owner.into()));
-        conversation.add(role, message.into())
+        conversation.add(participant, message.into())
+    }
+
+    /// Record a tool call in the task's conversation, ignored if there is no conversation
+    /// for the task.
+    pub fn add_tool_call(&self, task: &str, tool_call: ToolCallRecord) {
+        if let Some(mut conversation) = self.0.get_mut(task) {
+            conversation.add_tool_call(tool_call);
+        }
+    }
+
+    /// Attach human feedback to the task's conversation, see [`AgentConversation::annotate`].
+    pub fn annotate(&self, task: &str, feedback: Feedback) -> Result<(), ConversationError> {
+        match self.0.get_mut(task) {
+            Some(mut conversation) => conversation.annotate(feedback),
+            None => Err(ConversationError::UnknownTask(task.to_owned())),
+        }
+    }
+
+    /// A clone of the task's conversation.
+    ///
+    /// Prefer this over holding a `dashmap::Ref` from `self.0`, a guard kept across an await
+    /// point blocks every writer of the same shard and can deadlock.
+    pub fn get_owned(&self, task: &str) -> Option<AgentConversation> {
+        self.0.get(task).map(|conversation| conversation.clone())
+    }
+
+    /// The task's conversation converted to LLM messages, see
+    /// [`AgentConversation::to_llm_messages`].
+    pub fn to_messages(&self, task: &str) -> Option<Vec<crate::llm::completion::Message>> {
+        self.0
+            .get(task)
+            .map(|conversation| conversation.to_llm_messages())
+    }
+
+    /// The task's conversation converted to LLM messages, without the duplicates `dedup`
+    /// drops.
+    pub fn to_messages_deduplicated(
+        &self,
+        task: &str,
+        dedup: &MessageDedup,
+        model: &str,
+    ) -> Option<(Vec<crate::llm::completion::Message>, DedupStats)> {
+        let conversation = self.0.get(task)?;
+        let (kept, stats) = dedup.kept_indices(&conversation.history, model);
+        let messages = conversation
+            .prompt_cache
+            .render(&conversation.history, |rendered| {
+                kept.into_iter()
+                    .map(|index| rendered[index].rendered.clone())
+                    .collect()
+            });
+        Some((messages, stats))
+    }
+
+    /// Clones of all task conversations, the map is not locked while the snapshot is used.
+    pub fn snapshot(&self) -> Vec<(String, AgentConversation)> {
+        self.0
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
     }
 }
 
@@ -49,49 +138,538 @@ impl Default for AgentShortMemory {
     }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AgentConversation {
+    id: Uuid,
     agent_name: String,
     save_filepath: Option<PathBuf>,
+    #[serde(default)]
+    save_format: ConversationFormat,
+    /// Events appended to the event log since it was last compacted.
+    #[serde(skip)]
+    logged_events: usize,
+    #[serde(skip)]
+    autosave_writer: Option<AutosaveWriter>,
+    lineage: Option<Lineage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant_id: Option<TenantId>,
     pub history: Vec<Message>,
+    /// Audit trail of the tool calls made while producing the conversation, in call order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCallRecord>,
+    /// Human feedback on the conversation, in the order it was given.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    feedback: Vec<Feedback>,
+    #[serde(skip)]
+    prompt_cache: PromptCache,
+}
+
+/// The history rendered as LLM messages, kept between the prompts of a task so only the
+/// added and changed messages are rendered again.
+///
+/// A rendered message is reused as long as the message at its index still has the same
+/// participant and shares its content, so changes made through the public `history` are
+/// noticed as well. Everything after the first changed message is rendered again.
+#[derive(Default)]
+struct PromptCache(Mutex<Vec<RenderedMessage>>);
+
+struct RenderedMessage {
+    participant: Participant,
+    content: Arc<str>,
+    rendered: crate::llm::completion::Message,
+}
+
+impl PromptCache {
+    /// Bring the rendered messages up to date with `history` and pass them to `f`.
+    fn render<T>(&self, history: &[Message], f: impl FnOnce(&[RenderedMessage]) -> T) -> T {
+        let mut cache = self.0.lock().unwrap(); // Safety: the lock is never held across a panic
+        let valid = cache
+            .iter()
+            .zip(history)
+            .take_while(|(cached, message)| cached.renders(message))
+            .count();
+        cache.truncate(valid);
+        cache.extend(history[valid..].iter().map(RenderedMessage::new));
+        f(&cache)
+    }
+}
+
+impl Clone for PromptCache {
+    /// Clones start empty, most are snapshots which are never prompted with.
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl RenderedMessage {
+    fn new(message: &Message) -> Self {
+        let Content::Text(content) = &message.content;
+        Self {
+            participant: message.participant.clone(),
+            content: Arc::clone(content),
+            rendered: message.to_llm_message(),
+        }
+    }
+
+    fn renders(&self, message: &Message) -> bool {
+        let Content::Text(content) = &message.content;
+        Arc::ptr_eq(&self.content, content) && self.participant == message.participant
+    }
+}
+
+/// How a conversation history is written to its file.
+///
+/// The default is a pretty JSON array which autosave rewrites on every change. A chunked
+/// history is an event log of JSON lines, one [`ConversationEvent`] per change, and autosave
+/// only appends the new event. Compaction replaces the events with a single snapshot of the
+/// history. Compressed histories use zstd, a chunked history compresses every event into its
+/// own zstd frame so it can still be appended to. Compressed files should use the `.json.zst`
+/// extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationFormat {
+    pub compressed: bool,
+    pub chunked: bool,
+    /// Compact the event log after this many appended events.
+    pub compact_after: Option<usize>,
+}
+
+impl ConversationFormat {
+    pub fn compressed(mut self) -> Self {
+        self.compressed = true;
+        self
+    }
+
+    pub fn chunked(mut self) -> Self {
+        self.chunked = true;
+        self
+    }
+
+    /// Write a chunked history which autosave compacts after `events` appended events.
+    pub fn compact_after(mut self, events: usize) -> Self {
+        self.chunked = true;
+        self.compact_after = Some(events);
+        self
+    }
+
+    /// Encode the whole history, an event log starts with its snapshot.
+    fn encode(&self, messages: &[Message]) -> Result<Vec<u8>, ConversationError> {
+        if self.chunked {
+            return self.encode_event(&ConversationEvent::Snapshot {
+                history: messages.to_vec(),
+            });
+        }
+        let data = serde_json::to_vec_pretty(messages)?;
+        if self.compressed {
+            return Ok(persistence::compress(data)?);
+        }
+        Ok(data)
+    }
+
+    /// Encode an event, which can be appended to the event log.
+    fn encode_event(&self, event: &ConversationEvent) -> Result<Vec<u8>, ConversationError> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        if self.compressed {
+            return Ok(persistence::compress(line)?);
+        }
+        Ok(line)
+    }
+
+    fn decode(&self, data: Vec<u8>) -> Result<Vec<Message>, ConversationError> {
+        // zstd decodes concatenated frames as one stream, so appended events need no framing
+        let data = if self.compressed {
+            persistence::decompress(data)?
+        } else {
+            data
+        };
+        if !self.chunked {
+            return Ok(serde_json::from_slice(&data)?);
+        }
+        let mut history = Vec::new();
+        for line in data.split(|byte| *byte == b'\n') {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            serde_json::from_slice::<ConversationEvent>(line)?.apply(&mut history)?;
+        }
+        Ok(history)
+    }
+}
+
+// Writes the autosaves of a conversation in the background, in the order they were made, so
+// changing the conversation never waits for IO
+#[derive(Clone)]
+struct AutosaveWriter(mpsc::UnboundedSender<AutosaveWrite>);
+
+enum AutosaveWrite {
+    /// Replace the file with the whole history.
+    Snapshot(Vec<Message>),
+    /// Append an event to a chunked history.
+    Event(ConversationEvent),
+    /// Report once the previous writes are done.
+    Flush(oneshot::Sender<()>),
+}
+
+impl AutosaveWriter {
+    // The task ends once every clone of the writer is dropped and the writes are done
+    fn spawn(agent_name: String, filepath: PathBuf, format: ConversationFormat) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(write) = receiver.recv().await {
+                let result = match write {
+                    AutosaveWrite::Snapshot(history) => {
+                        AgentConversation::save_with_format(&filepath, &history, format).await
+                    }
+                    AutosaveWrite::Event(event) => match format.encode_event(&event) {
+                        Ok(line) => persistence::append_to_file(line, &filepath)
+                            .await
+                            .map_err(Into::into),
+                        Err(e) => Err(e),
+                    },
+                    AutosaveWrite::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                if let Err(e) = result {
+                    tracing::warn!(
+                        "| Agent: {} | Failed to autosave to {}: {}",
+                        agent_name,
+                        filepath.display(),
+                        e
+                    );
+                }
+            }
+        });
+        Self(sender)
+    }
+
+    fn write(&self, write: AutosaveWrite) {
+        // The receiver lives as long as the writer, sending can't fail
+        let _ = self.0.send(write);
+    }
+
+    async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        self.write(AutosaveWrite::Flush(done));
+        let _ = written.await;
+    }
+}
+
+/// A change of a conversation history, the lines of an event log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ConversationEvent {
+    /// The whole history, starts the log and replaces it on compaction.
+    Snapshot {
+        history: Vec<Message>,
+    },
+    Add {
+        message: Message,
+    },
+    Delete {
+        index: usize,
+    },
+    Update {
+        index: usize,
+        message: Message,
+    },
+    Clear,
+    Compress {
+        start: usize,
+        end: usize,
+        summary: Message,
+    },
+}
+
+impl ConversationEvent {
+    fn apply(self, history: &mut Vec<Message>) -> Result<(), ConversationError> {
+        match self {
+            ConversationEvent::Snapshot { history: snapshot } => *history = snapshot,
+            ConversationEvent::Add { message } => history.push(message),
+            ConversationEvent::Delete { index } => {
+                if index >= history.len() {
+                    return Err(ConversationError::IndexOutOfRange(index));
+                }
+                history.remove(index);
+            }
+            ConversationEvent::Update { index, message } => {
+                *history
+                    .get_mut(index)
+                    .ok_or(ConversationError::IndexOutOfRange(index))? = message;
+            }
+            ConversationEvent::Clear => history.clear(),
+            ConversationEvent::Compress {
+                start,
+                end,
+                summary,
+            } => {
+                if start > end || end > history.len() {
+                    return Err(ConversationError::IndexOutOfRange(end));
+                }
+                history.splice(start..end, [summary]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a forked conversation branched off from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lineage {
+    pub parent_id: Uuid,
+    /// Number of parent messages the branch was created with.
+    pub fork_index: usize,
 }
 
 impl AgentConversation {
     pub fn new(agent_name: String) -> Self {
         Self {
+            id: Uuid::new_v4(),
             agent_name,
             save_filepath: None,
+            save_format: ConversationFormat::default(),
+            logged_events: 0,
+            autosave_writer: None,
+            lineage: None,
+            tenant_id: None,
             history: Vec::new(),
+            tool_calls: Vec::new(),
+            feedback: Vec::new(),
+            prompt_cache: PromptCache::default(),
         }
     }
 
+    /// The tenant of the agent or workflow which produced the conversation.
+    pub fn tenant_id(&self) -> Option<&TenantId> {
+        self.tenant_id.as_ref()
+    }
+
+    pub(crate) fn set_tenant_id(&mut self, tenant_id: Option<TenantId>) {
+        self.tenant_id = tenant_id;
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The parent of this conversation, `None` if it's not a fork.
+    pub fn lineage(&self) -> Option<&Lineage> {
+        self.lineage.as_ref()
+    }
+
+    /// Fork the conversation into a new branch which keeps the first `index` messages.
+    ///
+    /// The branch gets a new id and records this conversation as its parent,
+    /// it is not auto saved and starts without tool calls and feedback.
+    pub fn fork(&self, index: usize) -> Result<AgentConversation, ConversationError> {
+        if index > self.history.len() {
+            return Err(ConversationError::IndexOutOfRange(index));
+        }
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            agent_name: self.agent_name.clone(),
+            save_filepath: None,
+            save_format: ConversationFormat::default(),
+            logged_events: 0,
+            autosave_writer: None,
+            lineage: Some(Lineage {
+                parent_id: self.id,
+                fork_index: index,
+            }),
+            tenant_id: self.tenant_id.clone(),
+            history: self.history[..index].to_vec(),
+            tool_calls: Vec::new(),
+            feedback: Vec::new(),
+            prompt_cache: PromptCache::default(),
+        })
+    }
+
+    /// Whether `other` is a branch forked directly from this conversation.
+    pub fn is_parent_of(&self, other: &AgentConversation) -> bool {
+        other
+            .lineage
+            .as_ref()
+            .is_some_and(|lineage| lineage.parent_id == self.id)
+    }
+
+    /// Compare with another branch, returns the messages of each side after the common prefix.
+    pub fn diverge<'a>(&'a self, other: &'a AgentConversation) -> (&'a [Message], &'a [Message]) {
+        let common = self
+            .history
+            .iter()
+            .zip(&other.history)
+            .take_while(|(a, b)| a == b)
+            .count();
+        (&self.history[common..], &other.history[common..])
+    }
+
     /// Add a message to the conversation history.
-    pub fn add(&mut self, role: Role, message: String) {
+    pub fn add(&mut self, participant: impl Into<Participant>, message: String) {
         let timestamp = Local::now().timestamp();
         let message = Message {
-            role,
-            content: Content::Text(format!("Time: {timestamp} \n{message}")),
+            participant: participant.into(),
+            content: Content::Text(format!("Time: {timestamp} \n{message}").into()),
         };
-        self.history.push(message);
-
-        if let Some(filepath) = &self.save_filepath {
-            let filepath = filepath.clone();
-            let history = self.history.clone();
-            tokio::spawn(async move {
-                let history = history;
-                let _ = Self::save_as_json(&filepath, &history).await;
-            });
+        self.history.push(message.clone());
+        self.autosave_event(ConversationEvent::Add { message });
+    }
+
+    /// Save the history to `filepath` now and after every change.
+    ///
+    /// The file is replaced, an event log is appended to from then on. The changes are written
+    /// in the background in the order they were made, failed writes are logged.
+    pub async fn autosave(
+        &mut self,
+        filepath: impl Into<PathBuf>,
+        format: ConversationFormat,
+    ) -> Result<(), ConversationError> {
+        // Pending writes of the previous autosave must not land after the new save
+        self.flush_autosave().await;
+        let filepath = filepath.into();
+        Self::save_with_format(&filepath, &self.history, format).await?;
+        self.save_filepath = Some(filepath);
+        self.save_format = format;
+        self.logged_events = 0;
+        self.autosave_writer = None;
+        Ok(())
+    }
+
+    /// Wait until the changes made so far are autosaved.
+    pub async fn flush_autosave(&self) {
+        if let Some(writer) = &self.autosave_writer {
+            writer.flush().await;
+        }
+    }
+
+    /// Stop saving the history after every change.
+    pub fn disable_autosave(&mut self) {
+        self.save_filepath = None;
+        self.autosave_writer = None;
+    }
+
+    /// Replace the events of the autosaved event log with a snapshot of the history.
+    ///
+    /// Does nothing if the conversation isn't autosaved chunked.
+    pub fn compact_event_log(&mut self) {
+        if self.save_format.chunked {
+            self.write_autosave(AutosaveWrite::Snapshot(self.history.clone()));
+            self.logged_events = 0;
+        }
+    }
+
+    /// Autosave a change, which already was applied to the history.
+    fn autosave_event(&mut self, event: ConversationEvent) {
+        if self.save_filepath.is_none() {
+            return;
+        }
+        if !self.save_format.chunked {
+            self.write_autosave(AutosaveWrite::Snapshot(self.history.clone()));
+            return;
         }
+
+        self.logged_events += 1;
+        let compact = self
+            .save_format
+            .compact_after
+            .is_some_and(|max| self.logged_events > max);
+        if compact {
+            self.compact_event_log();
+        } else {
+            self.write_autosave(AutosaveWrite::Event(event));
+        }
+    }
+
+    fn write_autosave(&mut self, write: AutosaveWrite) {
+        let Some(filepath) = &self.save_filepath else {
+            return;
+        };
+        self.autosave_writer
+            .get_or_insert_with(|| {
+                AutosaveWriter::spawn(self.agent_name.clone(), filepath.clone(), self.save_format)
+            })
+            .write(write);
+    }
+
+    pub fn add_tool_call(&mut self, tool_call: ToolCallRecord) {
+        self.tool_calls.push(tool_call);
+    }
+
+    /// The tool calls recorded in the conversation, in call order.
+    pub fn tool_calls(&self) -> &[ToolCallRecord] {
+        &self.tool_calls
+    }
+
+    /// The recorded calls of the tool.
+    pub fn tool_calls_of<'a>(&'a self, tool: &'a str) -> impl Iterator<Item = &'a ToolCallRecord> {
+        self.tool_calls.iter().filter(move |call| call.name == tool)
+    }
+
+    /// Attach human feedback to a message or, if it has no message, to the whole run.
+    pub fn annotate(&mut self, feedback: Feedback) -> Result<(), ConversationError> {
+        if let Some(index) = feedback.message
+            && index >= self.history.len()
+        {
+            return Err(ConversationError::IndexOutOfRange(index));
+        }
+        self.feedback.push(feedback);
+        Ok(())
+    }
+
+    /// The feedback on the conversation, in the order it was given.
+    pub fn feedback(&self) -> &[Feedback] {
+        &self.feedback
+    }
+
+    /// The feedback on the message at `index`.
+    pub fn feedback_on(&self, index: usize) -> impl Iterator<Item = &Feedback> {
+        self.feedback
+            .iter()
+            .filter(move |feedback| feedback.message == Some(index))
+    }
+
+    /// The feedback joined with the messages it rates, see [`crate::feedback`].
+    pub fn feedback_records(&self) -> Vec<FeedbackRecord> {
+        self.feedback
+            .iter()
+            // Feedback on a message which was deleted since is dropped
+            .filter(|feedback| {
+                feedback
+                    .message
+                    .is_none_or(|index| index < self.history.len())
+            })
+            .map(|feedback| {
+                let (context, response) = match feedback.message {
+                    Some(index) => (&self.history[..index], Some(self.history[index].clone())),
+                    None => (&self.history[..], None),
+                };
+                FeedbackRecord {
+                    conversation_id: self.id,
+                    agent_name: self.agent_name.clone(),
+                    tenant_id: self.tenant_id.clone(),
+                    context: context.to_vec(),
+                    response,
+                    feedback: feedback.clone(),
+                }
+            })
+            .collect()
     }
 
     /// Delete a message from the conversation history.
     pub fn delete(&mut self, index: usize) {
         self.history.remove(index);
+        self.autosave_event(ConversationEvent::Delete { index });
     }
 
     /// Update a message in the conversation history.
-    pub fn update(&mut self, index: usize, role: Role, content: Content) {
-        self.history[index] = Message { role, content };
+    pub fn update(&mut self, index: usize, participant: impl Into<Participant>, content: Content) {
+        let message = Message {
+            participant: participant.into(),
+            content,
+        };
+        self.history[index] = message.clone();
+        self.autosave_event(ConversationEvent::Update { index, message });
     }
 
     /// Query a message in the conversation history.
@@ -99,6 +677,22 @@ impl AgentConversation {
         &self.history[index]
     }
 
+    /// The messages written by participants of the kind.
+    pub fn messages_from(&self, kind: ParticipantKind) -> impl Iterator<Item = &Message> {
+        self.history
+            .iter()
+            .filter(move |message| message.participant.kind == kind)
+    }
+
+    /// The participants who wrote messages in the conversation.
+    pub fn participants(&self) -> ParticipantRegistry {
+        let mut registry = ParticipantRegistry::default();
+        for message in &self.history {
+            registry.register(message.participant.clone());
+        }
+        registry
+    }
+
     /// Search for a message in the conversation history.
     pub fn search(&self, keyword: &str) -> Vec<&Message> {
         self.history
@@ -110,6 +704,39 @@ impl AgentConversation {
     // Clear the conversation history.
     pub fn clear(&mut self) {
         self.history.clear();
+        self.autosave_event(ConversationEvent::Clear);
+    }
+
+    /// Replace the messages in `range` with a single summary message.
+    pub fn compress(
+        &mut self,
+        range: Range<usize>,
+        participant: impl Into<Participant>,
+        summary: String,
+    ) {
+        let summary = Message {
+            participant: participant.into(),
+            content: Content::Text(summary.into()),
+        };
+        self.history.splice(range.clone(), [summary.clone()]);
+        self.autosave_event(ConversationEvent::Compress {
+            start: range.start,
+            end: range.end,
+            summary,
+        });
+    }
+
+    /// The history converted to LLM messages.
+    ///
+    /// The conversion is cached, repeated calls only convert the messages added or changed
+    /// since the previous call.
+    pub fn to_llm_messages(&self) -> Vec<crate::llm::completion::Message> {
+        self.prompt_cache.render(&self.history, |rendered| {
+            rendered
+                .iter()
+                .map(|message| message.rendered.clone())
+                .collect()
+        })
     }
 
     pub fn to_json(&self) -> Result<String, ConversationError> {
@@ -117,19 +744,49 @@ impl AgentConversation {
     }
 
     /// Save the conversation history to a JSON file.
-    async fn save_as_json(filepath: &Path, data: &[Message]) -> Result<(), ConversationError> {
+    pub(crate) async fn save_as_json(
+        filepath: &Path,
+        data: &[Message],
+    ) -> Result<(), ConversationError> {
         let json_data = serde_json::to_string_pretty(data)?;
         persistence::save_to_file(json_data.as_bytes(), filepath).await?;
         Ok(())
     }
 
     /// Load the conversation history from a JSON file.
-    async fn load_from_json(&self, filepath: &Path) -> Result<Vec<Message>, ConversationError> {
-        let data = persistence::load_from_file(filepath).await?;
+    pub(crate) async fn load_from_json(
+        &self,
+        filepath: &Path,
+    ) -> Result<Vec<Message>, ConversationError> {
+        let data = persistence::load_verified(filepath).await?;
         let history = serde_json::from_slice(&data)?;
         Ok(history)
     }
 
+    /// Save the conversation history to a file in the given format.
+    pub(crate) async fn save_with_format(
+        filepath: &Path,
+        data: &[Message],
+        format: ConversationFormat,
+    ) -> Result<(), ConversationError> {
+        persistence::save_to_file(format.encode(data)?, filepath).await?;
+        Ok(())
+    }
+
+    /// Load a conversation history saved in the given format.
+    pub(crate) async fn load_with_format(
+        filepath: &Path,
+        format: ConversationFormat,
+    ) -> Result<Vec<Message>, ConversationError> {
+        // An event log is appended to, only its first snapshot has a checksum
+        let data = if format.chunked {
+            persistence::load_from_file(filepath).await?
+        } else {
+            persistence::load_verified(filepath).await?
+        };
+        format.decode(data)
+    }
+
     /// Export the conversation history to a file
     pub async fn export_to_file(&self, filepath: &Path) -> Result<(), ConversationError> {
         let data = self.to_string();
@@ -145,15 +802,10 @@ impl AgentConversation {
             .map(|line| {
                 let line = String::from_utf8_lossy(line);
                 // M4n5ter(User): hello
-                let (role, content) = line.split_once(": ").unwrap();
-                if role.contains("(User)") {
-                    let role = Role::User(role.replace("(User)", "").to_string());
-                    let content = Content::Text(content.to_string());
-                    Message { role, content }
-                } else {
-                    let role = Role::Assistant(role.replace("(Assistant)", "").to_string());
-                    let content = Content::Text(content.to_string());
-                    Message { role, content }
+                let (participant, content) = line.split_once(": ").unwrap();
+                Message {
+                    participant: Participant::parse(participant),
+                    content: Content::Text(content.into()),
                 }
             })
             .collect();
@@ -161,11 +813,11 @@ impl AgentConversation {
         Ok(())
     }
 
-    /// Count the number of messages by role
+    /// Count the number of messages by participant
     pub fn count_messages_by_role(&self) -> HashMap<String, usize> {
         let mut count = HashMap::new();
         for message in &self.history {
-            *count.entry(message.role.to_string()).or_insert(0) += 1;
+            *count.entry(message.participant.to_string()).or_insert(0) += 1;
         }
         count
     }
@@ -174,29 +826,191 @@ impl AgentConversation {
 impl Display for AgentConversation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for message in &self.history {
-            writeln!(f, "{}: {}", message.role, message.content)?;
+            writeln!(f, "{message}")?;
         }
         Ok(())
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Message {
-    pub role: Role,
+    /// Who wrote the message, saved as `role` before participants were typed.
+    #[serde(alias = "role")]
+    pub participant: Participant,
     pub content: Content,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Free-form roles which encode the participant's kind in its name, e.g.
+/// `Role::User("[RAG] Database")`.
+#[deprecated(note = "use `Participant`, which has a typed kind")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Role {
     User(String),
     Assistant(String),
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantKind {
+    Human,
+    Agent,
+    Tool,
+    /// A retriever adding documents to the conversation, e.g. a RAG database.
+    Retrieval,
+    /// Messages of the framework itself, e.g. summaries of compressed history.
+    System,
+}
+
+impl ParticipantKind {
+    const ALL: [ParticipantKind; 5] = [
+        ParticipantKind::Human,
+        ParticipantKind::Agent,
+        ParticipantKind::Tool,
+        ParticipantKind::Retrieval,
+        ParticipantKind::System,
+    ];
+
+    /// The label in the text export, humans and agents keep the labels of the old roles.
+    fn label(self) -> &'static str {
+        match self {
+            ParticipantKind::Human => "User",
+            ParticipantKind::Agent => "Assistant",
+            ParticipantKind::Tool => "Tool",
+            ParticipantKind::Retrieval => "Retrieval",
+            ParticipantKind::System => "System",
+        }
+    }
+}
+
+/// The author of a message, its kind and display name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "ParticipantRepr")]
+pub struct Participant {
+    pub kind: ParticipantKind,
+    pub name: String,
+}
+
+impl Participant {
+    pub fn new(kind: ParticipantKind, name: impl Into<String>) -> Self {
+        Self {
+            kind,
+            name: name.into(),
+        }
+    }
+
+    pub fn human(name: impl Into<String>) -> Self {
+        Self::new(ParticipantKind::Human, name)
+    }
+
+    pub fn agent(name: impl Into<String>) -> Self {
+        Self::new(ParticipantKind::Agent, name)
+    }
+
+    pub fn tool(name: impl Into<String>) -> Self {
+        Self::new(ParticipantKind::Tool, name)
+    }
+
+    pub fn retrieval(name: impl Into<String>) -> Self {
+        Self::new(ParticipantKind::Retrieval, name)
+    }
+
+    pub fn system(name: impl Into<String>) -> Self {
+        Self::new(ParticipantKind::System, name)
+    }
+
+    /// Parse the `name(Kind)` form of the text export, unlabeled names are agents.
+    fn parse(text: &str) -> Self {
+        ParticipantKind::ALL
+            .into_iter()
+            .find_map(|kind| {
+                let name = text.strip_suffix(&format!("({})", kind.label()))?;
+                Some(Self::new(kind, name))
+            })
+            .unwrap_or_else(|| Self::agent(text))
+    }
+}
+
+#[allow(deprecated)]
+impl From<Role> for Participant {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::User(name) => Participant::human(name),
+            Role::Assistant(name) => Participant::agent(name),
+        }
+    }
+}
+
+/// Participants as they are saved now, or as the roles saved before.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParticipantRepr {
+    Typed {
+        kind: ParticipantKind,
+        name: String,
+    },
+    #[allow(deprecated)]
+    Legacy(Role),
+}
+
+impl From<ParticipantRepr> for Participant {
+    fn from(repr: ParticipantRepr) -> Self {
+        match repr {
+            ParticipantRepr::Typed { kind, name } => Participant::new(kind, name),
+            ParticipantRepr::Legacy(role) => role.into(),
+        }
+    }
+}
+
+/// The participants of a conversation by display name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParticipantRegistry(BTreeMap<String, ParticipantKind>);
+
+impl ParticipantRegistry {
+    /// Add the participant, replaces the kind of a participant with the same name.
+    pub fn register(&mut self, participant: Participant) {
+        self.0.insert(participant.name, participant.kind);
+    }
+
+    pub fn kind_of(&self, name: &str) -> Option<ParticipantKind> {
+        self.0.get(name).copied()
+    }
+
+    /// The names of the participants of the kind, in name order.
+    pub fn names_of(&self, kind: ParticipantKind) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(move |(_, other)| **other == kind)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Participant> + '_ {
+        self.0
+            .iter()
+            .map(|(name, kind)| Participant::new(*kind, name.clone()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Content {
-    Text(String),
+    /// Shared, clones of messages and histories don't copy the text.
+    Text(Arc<str>),
 }
 
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.participant, self.content)
+    }
+}
+
+#[allow(deprecated)]
 impl Display for Role {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -206,6 +1020,12 @@ impl Display for Role {
     }
 }
 
+impl Display for Participant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.name, self.kind.label())
+    }
+}
+
 impl Display for Content {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -214,17 +1034,95 @@ impl Display for Content {
     }
 }
 
+impl Content {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Content::Text(text) => text,
+        }
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename = "history")]
 pub struct SwarmConversation {
     pub logs: VecDeque<AgentLog>,
+    /// The agent-task pairs which failed, in the order they failed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<AgentFailure>,
+    /// Whether the swarm stopped before its max number of rounds because the agents agreed.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub converged: bool,
+    /// The round new logs belong to.
+    #[serde(skip)]
+    round: usize,
 }
 
 impl SwarmConversation {
     pub fn new() -> Self {
         Self {
             logs: VecDeque::new(),
+            failures: Vec::new(),
+            converged: false,
+            round: 0,
+        }
+    }
+
+    /// Start the next round, the following logs belong to it.
+    pub fn next_round(&mut self) {
+        self.round += 1;
+    }
+
+    /// Add a log with the time the agent took to respond.
+    pub fn add_timed_log(
+        &mut self,
+        agent_name: String,
+        task: String,
+        response: String,
+        latency: Duration,
+    ) {
+        self.add_log(agent_name, task, response);
+        if let Some(log) = self.logs.back_mut() {
+            log.latency_ms = Some(latency.as_millis() as u64);
+        }
+    }
+
+    /// Statistics of the run, e.g. to compare swarm topologies.
+    pub fn stats(&self) -> SwarmStats {
+        let mut stats = SwarmStats {
+            messages: self.logs.len(),
+            failures: self.failures.len(),
+            rounds: self.logs.iter().map(|log| log.round + 1).max().unwrap_or(0),
+            ..Default::default()
+        };
+        let mut recipients = HashMap::<(usize, &str), usize>::new();
+        let mut latencies = Vec::new();
+        for log in &self.logs {
+            *stats
+                .messages_per_agent
+                .entry(log.agent_name.clone())
+                .or_default() += 1;
+            *recipients.entry((log.round, &log.task)).or_default() += 1;
+            latencies.extend(log.latency_ms);
+            stats.total_tokens += (TokenizerFamily::Generic.count_tokens(&log.task)
+                + TokenizerFamily::Generic.count_tokens(&log.response))
+                as u64;
         }
+        stats.fan_out = recipients.into_values().max().unwrap_or(0);
+        if !latencies.is_empty() {
+            stats.average_latency_ms =
+                Some(latencies.iter().sum::<u64>() as f64 / latencies.len() as f64);
+        }
+        stats.rounds_to_convergence = self.converged.then_some(stats.rounds);
+        stats
+    }
+
+    pub fn add_failure(&mut self, agent_name: String, task: String, error: impl Display) {
+        tracing::error!("Agent: {agent_name} | Task: {task} | Error: {error}");
+        self.failures.push(AgentFailure {
+            agent_name,
+            task,
+            error: error.to_string(),
+        });
     }
 
     pub fn add_log(&mut self, agent_name: String, task: String, response: String) {
@@ -233,11 +1131,33 @@ impl SwarmConversation {
             agent_name,
             task,
             response,
+            latency_ms: None,
+            round: self.round,
         };
         self.logs.push_back(log);
     }
 }
 
+/// The outputs of a workflow run, one log per agent.
+impl From<&MetadataSchema> for SwarmConversation {
+    fn from(metadata: &MetadataSchema) -> Self {
+        Self {
+            logs: metadata
+                .agents_output_schema
+                .iter()
+                .map(|output| AgentLog {
+                    agent_name: output.agent_name.clone(),
+                    task: output.task.clone(),
+                    response: output.output.clone(),
+                    latency_ms: Some((output.end - output.start).num_milliseconds().max(0) as u64),
+                    round: 0,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
 impl Default for SwarmConversation {
     fn default() -> Self {
         Self::new()
@@ -249,20 +1169,494 @@ pub struct AgentLog {
     pub agent_name: String,
     pub task: String,
     pub response: String,
+    /// How long the agent took to respond, if it was measured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// The round of the swarm the response belongs to, starting at `0`.
+    pub round: usize,
+}
+
+/// Statistics of a swarm run, see [`SwarmConversation::stats`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SwarmStats {
+    /// Number of responses.
+    pub messages: usize,
+    pub messages_per_agent: BTreeMap<String, usize>,
+    pub failures: usize,
+    /// Max number of agents which responded to the same message in the same round.
+    pub fan_out: usize,
+    /// Average time an agent took to respond, `None` if no response was timed.
+    pub average_latency_ms: Option<f64>,
+    pub rounds: usize,
+    /// The number of rounds until the agents agreed, `None` if they didn't.
+    pub rounds_to_convergence: Option<usize>,
+    /// Estimated tokens of all tasks and responses.
+    pub total_tokens: u64,
+}
+
+/// An agent which failed a task of a swarm, the other agents carried on.
+#[derive(Clone, Debug, Serialize)]
+pub struct AgentFailure {
+    pub agent_name: String,
+    pub task: String,
+    pub error: String,
 }
 
 impl From<&AgentConversation> for Vec<crate::llm::completion::Message> {
     fn from(conv: &AgentConversation) -> Self {
-        conv.history
-            .iter()
-            .map(|msg| match &msg.role {
-                Role::User(name) => {
-                    crate::llm::completion::Message::user(format!("{}: {}", name, msg.content))
-                }
-                Role::Assistant(name) => {
-                    crate::llm::completion::Message::assistant(format!("{}: {}", name, msg.content))
-                }
+        conv.to_llm_messages()
+    }
+}
+
+impl Message {
+    fn to_llm_message(&self) -> crate::llm::completion::Message {
+        let Content::Text(content) = &self.content;
+        let name = &self.participant.name;
+        let mut text = String::with_capacity(name.len() + 2 + content.len());
+        text.push_str(name);
+        text.push_str(": ");
+        text.push_str(content);
+        // Only agents answer as the assistant, the model sees everything else as input
+        match self.participant.kind {
+            ParticipantKind::Agent => crate::llm::completion::Message::assistant(text),
+            ParticipantKind::Human
+            | ParticipantKind::Tool
+            | ParticipantKind::Retrieval
+            | ParticipantKind::System => crate::llm::completion::Message::user(text),
+        }
+    }
+
+    /// The text without the timestamp [`AgentConversation::add`] puts in front.
+    pub(crate) fn body(&self) -> &str {
+        let text = self.content.as_str();
+        text.strip_prefix("Time: ")
+            .and_then(|rest| rest.split_once(" \n"))
+            .filter(|(timestamp, _)| timestamp.parse::<i64>().is_ok())
+            .map_or(text, |(_, body)| body)
+    }
+}
+
+/// Drop consecutive messages with the same or nearly the same text from the prompt, e.g.
+/// when several agents broadcast similar results. The conversation itself is not changed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MessageDedup {
+    /// Min share of words two messages have in common (Jaccard similarity) to be near
+    /// duplicates, `1.0` only drops messages with the same words, ignoring case,
+    /// whitespace and punctuation.
+    pub similarity: f64,
+}
+
+impl Default for MessageDedup {
+    fn default() -> Self {
+        Self { similarity: 1.0 }
+    }
+}
+
+impl MessageDedup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also drop near duplicates, `similarity` is clamped to `0.0..=1.0`.
+    pub fn similarity(mut self, similarity: f64) -> Self {
+        self.similarity = similarity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The messages without the consecutive duplicates, the first of each run of duplicates
+    /// is kept. Saved tokens are estimated with the tokenizer of `model`.
+    pub fn apply<'a>(
+        &self,
+        messages: &'a [Message],
+        model: &str,
+    ) -> (Vec<&'a Message>, DedupStats) {
+        let (kept, stats) = self.kept_indices(messages, model);
+        (
+            kept.into_iter().map(|index| &messages[index]).collect(),
+            stats,
+        )
+    }
+
+    fn kept_indices(&self, messages: &[Message], model: &str) -> (Vec<usize>, DedupStats) {
+        let mut stats = DedupStats::default();
+        let mut kept: Vec<usize> = Vec::with_capacity(messages.len());
+        for (index, message) in messages.iter().enumerate() {
+            if let Some(&previous) = kept.last()
+                && self.is_duplicate(messages[previous].body(), message.body())
+            {
+                stats.removed_messages += 1;
+                stats.saved_tokens += count_tokens(model, message.content.as_str()) as u64;
+                continue;
+            }
+            kept.push(index);
+        }
+        (kept, stats)
+    }
+
+    fn is_duplicate(&self, a: &str, b: &str) -> bool {
+        let words = |text: &str| {
+            text.split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>()
+        };
+        let (a, b) = (words(a), words(b));
+        if a == b {
+            return true;
+        }
+        if self.similarity >= 1.0 || a.is_empty() || b.is_empty() {
+            return false;
+        }
+        let a = a.into_iter().collect::<HashSet<_>>();
+        let b = b.into_iter().collect::<HashSet<_>>();
+        let common = a.intersection(&b).count();
+        common as f64 / (a.len() + b.len() - common) as f64 >= self.similarity
+    }
+}
+
+/// What [`MessageDedup`] dropped, summed over all prompts of a run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DedupStats {
+    pub removed_messages: u64,
+    /// Estimated prompt tokens the dropped messages would have used.
+    pub saved_tokens: u64,
+}
+
+impl DedupStats {
+    pub fn is_empty(&self) -> bool {
+        self.removed_messages == 0
+    }
+}
+
+impl AddAssign for DedupStats {
+    fn add_assign(&mut self, other: Self) {
+        self.removed_messages += other.removed_messages;
+        self.saved_tokens += other.saved_tokens;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn create_conversation(messages: &[&str]) -> AgentConversation {
+        let mut conversation = AgentConversation::new("test".to_owned());
+        for message in messages {
+            conversation.add(Participant::human("User"), message.to_string());
+        }
+        conversation
+    }
+
+    #[test]
+    fn test_fork_keeps_prefix_and_lineage() {
+        let conversation = create_conversation(&["a", "b", "c"]);
+        let branch = conversation.fork(2).unwrap();
+
+        assert_eq!(branch.history.len(), 2);
+        assert_ne!(branch.id(), conversation.id());
+        assert_eq!(
+            branch.lineage(),
+            Some(&Lineage {
+                parent_id: conversation.id(),
+                fork_index: 2,
             })
-            .collect()
+        );
+        assert!(conversation.is_parent_of(&branch));
+        assert!(!branch.is_parent_of(&conversation));
+    }
+
+    #[test]
+    fn test_fork_index_out_of_range() {
+        let conversation = create_conversation(&["a"]);
+        assert!(matches!(
+            conversation.fork(2),
+            Err(ConversationError::IndexOutOfRange(2))
+        ));
+    }
+
+    #[test]
+    fn test_diverge() {
+        let conversation = create_conversation(&["a", "b"]);
+        let mut branch = conversation.fork(1).unwrap();
+        branch.add(Participant::agent("Agent"), "other".to_owned());
+
+        let (ours, theirs) = conversation.diverge(&branch);
+        assert_eq!(ours.len(), 1);
+        assert_eq!(theirs.len(), 1);
+        assert_eq!(theirs[0].participant, Participant::agent("Agent"));
+    }
+
+    #[test]
+    fn test_compress_replaces_range_with_summary() {
+        let mut conversation = create_conversation(&["task", "a", "b", "latest"]);
+        conversation.compress(1..3, Participant::agent("Summarizer"), "summary".to_owned());
+
+        assert_eq!(conversation.history.len(), 3);
+        assert_eq!(conversation.history[1].content.to_string(), "summary");
+        assert!(
+            conversation.history[2]
+                .content
+                .to_string()
+                .ends_with("latest")
+        );
+    }
+
+    #[test]
+    fn test_prompt_cache() {
+        let rendered = |conversation: &AgentConversation| {
+            conversation
+                .history
+                .iter()
+                .map(Message::to_llm_message)
+                .collect::<Vec<_>>()
+        };
+        let mut conversation = create_conversation(&["task", "a"]);
+        assert_eq!(conversation.to_llm_messages(), rendered(&conversation));
+
+        conversation.add(Participant::agent("test"), "b".to_owned());
+        assert_eq!(conversation.to_llm_messages(), rendered(&conversation));
+        assert_eq!(conversation.prompt_cache.0.lock().unwrap().len(), 3);
+
+        // Changes made through the public history are noticed
+        conversation.history[1].content = Content::Text("changed".into());
+        conversation.history[2].participant = Participant::human("User");
+        assert_eq!(conversation.to_llm_messages(), rendered(&conversation));
+        conversation.delete(0);
+        assert_eq!(conversation.to_llm_messages(), rendered(&conversation));
+        conversation.compress(0..2, Participant::agent("test"), "summary".to_owned());
+        assert_eq!(conversation.to_llm_messages(), rendered(&conversation));
+
+        let memory = AgentShortMemory::new();
+        for message in ["task", "same", "same"] {
+            memory.add("task", "test", Participant::human("User"), message);
+        }
+        let (messages, stats) = memory
+            .to_messages_deduplicated("task", &MessageDedup::new(), "gpt-4o")
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(stats.removed_messages, 1);
+    }
+
+    #[test]
+    fn test_short_memory_snapshot() {
+        let memory = AgentShortMemory::new();
+        memory.add("a", "agent", Participant::human("User"), "hello");
+        memory.add("b", "agent", Participant::human("User"), "world");
+
+        let conversation = memory.get_owned("a").unwrap();
+        // No guard is held, writing to the same task doesn't block
+        memory.add("a", "agent", Participant::human("User"), "again");
+        assert_eq!(conversation.history.len(), 1);
+        assert_eq!(memory.to_messages("a").unwrap().len(), 2);
+        assert!(memory.get_owned("c").is_none());
+
+        let mut tasks = memory
+            .snapshot()
+            .into_iter()
+            .map(|(task, _)| task)
+            .collect::<Vec<_>>();
+        tasks.sort();
+        assert_eq!(tasks, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_autosave_appends() {
+        for format in [
+            ConversationFormat::default().chunked(),
+            ConversationFormat::default().chunked().compressed(),
+        ] {
+            let dir = temp_dir();
+            let path = dir.join("chat.json.zst");
+            let mut conversation = create_conversation(&["a"]);
+            conversation.autosave(&path, format).await.unwrap();
+            let saved_size = std::fs::metadata(&path).unwrap().len();
+            conversation.add(Participant::agent("test"), "b".to_owned());
+            conversation.add(Participant::human("User"), "c".to_owned());
+            conversation.flush_autosave().await;
+
+            assert!(std::fs::metadata(&path).unwrap().len() > saved_size);
+            let history = AgentConversation::load_with_format(&path, format)
+                .await
+                .unwrap();
+            assert_eq!(history, conversation.history);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_autosave_keeps_order() {
+        let dir = temp_dir();
+        let path = dir.join("chat.json");
+        let format = ConversationFormat::default();
+        let mut conversation = create_conversation(&["a"]);
+        conversation.autosave(&path, format).await.unwrap();
+        for index in 0..20 {
+            conversation.add(Participant::human("User"), index.to_string());
+        }
+        conversation.flush_autosave().await;
+
+        let history = AgentConversation::load_with_format(&path, format)
+            .await
+            .unwrap();
+        assert_eq!(history, conversation.history);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_save() {
+        let dir = temp_dir();
+        let path = dir.join("chat.json.zst");
+        let conversation = create_conversation(&["a", "b"]);
+        let format = ConversationFormat::default().compressed();
+        AgentConversation::save_with_format(&path, &conversation.history, format)
+            .await
+            .unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert!(serde_json::from_slice::<Vec<Message>>(&data).is_err());
+        let history = AgentConversation::load_with_format(&path, format)
+            .await
+            .unwrap();
+        assert_eq!(history, conversation.history);
+    }
+
+    #[tokio::test]
+    async fn test_event_log_replays_changes_and_compacts() {
+        let dir = temp_dir();
+        let path = dir.join("chat.jsonl");
+        let format = ConversationFormat::default().compact_after(5);
+        let mut conversation = create_conversation(&["a", "b", "c", "d"]);
+        conversation.autosave(&path, format).await.unwrap();
+        conversation.delete(0);
+        conversation.update(0, Participant::human("User"), Content::Text("B".into()));
+        conversation.compress(1..3, Participant::agent("test"), "summary".to_owned());
+        conversation.add(Participant::human("User"), "e".to_owned());
+        conversation.flush_autosave().await;
+
+        // The snapshot and 4 events
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.lines().count(), 5);
+        let history = AgentConversation::load_with_format(&path, format)
+            .await
+            .unwrap();
+        assert_eq!(history, conversation.history);
+
+        conversation.add(Participant::human("User"), "f".to_owned());
+        conversation.add(Participant::human("User"), "g".to_owned());
+        conversation.flush_autosave().await;
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(log.lines().count(), 1, "the log was compacted");
+        let history = AgentConversation::load_with_format(&path, format)
+            .await
+            .unwrap();
+        assert_eq!(history, conversation.history);
+
+        conversation.clear();
+        conversation.compact_event_log();
+        conversation.flush_autosave().await;
+        let history = AgentConversation::load_with_format(&path, format)
+            .await
+            .unwrap();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn test_participants() {
+        let mut conversation = AgentConversation::new("test".to_owned());
+        conversation.add(Participant::human("User"), "question".to_owned());
+        conversation.add(Participant::retrieval("Database"), "document".to_owned());
+        conversation.add(Participant::agent("test"), "answer".to_owned());
+
+        let participants = conversation.participants();
+        assert_eq!(participants.len(), 3);
+        assert_eq!(
+            participants.kind_of("Database"),
+            Some(ParticipantKind::Retrieval)
+        );
+        assert_eq!(
+            participants
+                .names_of(ParticipantKind::Human)
+                .collect::<Vec<_>>(),
+            ["User"]
+        );
+        let retrieved = conversation
+            .messages_from(ParticipantKind::Retrieval)
+            .collect::<Vec<_>>();
+        assert_eq!(retrieved.len(), 1);
+        assert!(retrieved[0].content.to_string().ends_with("document"));
+
+        let messages = Vec::<crate::llm::completion::Message>::from(&conversation);
+        assert!(matches!(
+            messages[1],
+            crate::llm::completion::Message::User { .. }
+        ));
+        assert!(matches!(
+            messages[2],
+            crate::llm::completion::Message::Assistant { .. }
+        ));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_legacy_roles() {
+        let legacy = r#"[{"role":{"User":"User"},"content":{"Text":"hi"}}]"#;
+        let history = serde_json::from_str::<Vec<Message>>(legacy).unwrap();
+        assert_eq!(history[0].participant, Participant::human("User"));
+        assert_eq!(
+            Participant::from(Role::Assistant("test".to_owned())),
+            Participant::agent("test")
+        );
+
+        let message = Message {
+            participant: Participant::tool("search"),
+            content: Content::Text("result".into()),
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(serde_json::from_str::<Message>(&json).unwrap(), message);
+        assert_eq!(message.to_string(), "search(Tool): result");
+    }
+
+    #[test]
+    fn test_parse_participant() {
+        assert_eq!(
+            Participant::parse("M4n5ter(User)"),
+            Participant::human("M4n5ter")
+        );
+        assert_eq!(
+            Participant::parse("Context Summarizer(System)"),
+            Participant::system("Context Summarizer")
+        );
+        assert_eq!(
+            Participant::parse("unlabeled"),
+            Participant::agent("unlabeled")
+        );
+    }
+
+    #[test]
+    fn test_message_dedup() {
+        let mut conversation = AgentConversation::new("test".to_owned());
+        conversation.add(Participant::human("User"), "task".to_owned());
+        conversation.add(Participant::agent("a"), "The answer is 42.".to_owned());
+        conversation.add(Participant::agent("b"), "the answer  is 42.".to_owned());
+        conversation.add(
+            Participant::agent("c"),
+            "The answer is 42 indeed.".to_owned(),
+        );
+        conversation.add(Participant::human("User"), "task".to_owned());
+
+        let (kept, stats) = MessageDedup::new().apply(&conversation.history, "gpt-4o");
+        assert_eq!(kept.len(), 4);
+        assert_eq!(stats.removed_messages, 1);
+        assert!(stats.saved_tokens > 0);
+        // Only consecutive messages are compared
+        assert_eq!(kept[3].participant, Participant::human("User"));
+
+        let (kept, stats) = MessageDedup::new()
+            .similarity(0.7)
+            .apply(&conversation.history, "gpt-4o");
+        assert_eq!(
+            kept.iter().map(|m| m.body()).collect::<Vec<_>>(),
+            ["task", "The answer is 42.", "task"]
+        );
+        assert_eq!(stats.removed_messages, 2);
     }
 }
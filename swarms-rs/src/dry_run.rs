@@ -0,0 +1,139 @@
+//! Dry runs of workflows, which report what a run would do without calling any LLM.
+//!
+//! Outputs of agents are not known in a dry run, the input of a downstream agent uses the
+//! placeholder from [`output_placeholder`] instead. Token counts are estimates, see
+//! [`tokenizer`](crate::llm::tokenizer), and completion tokens are an upper bound.
+
+use std::{collections::HashMap, fmt::Display};
+
+/// One agent call a workflow would make.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DryRunStep {
+    pub agent: String,
+    /// Name of the model, `None` if the agent doesn't report it.
+    pub model: Option<String>,
+    pub system_prompt: Option<String>,
+    /// The input the agent would receive.
+    pub prompt: String,
+    pub estimated_prompt_tokens: u64,
+    pub estimated_completion_tokens: u64,
+}
+
+/// Price of a model in USD per million tokens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModelPricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPricing {
+    pub fn new(input_per_million: f64, output_per_million: f64) -> Self {
+        Self {
+            input_per_million,
+            output_per_million,
+        }
+    }
+}
+
+/// Steps a workflow would run, in execution order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DryRunReport {
+    pub workflow: String,
+    pub steps: Vec<DryRunStep>,
+}
+
+impl DryRunReport {
+    pub fn estimated_prompt_tokens(&self) -> u64 {
+        self.steps
+            .iter()
+            .map(|step| step.estimated_prompt_tokens)
+            .sum()
+    }
+
+    pub fn estimated_completion_tokens(&self) -> u64 {
+        self.steps
+            .iter()
+            .map(|step| step.estimated_completion_tokens)
+            .sum()
+    }
+
+    pub fn estimated_total_tokens(&self) -> u64 {
+        self.estimated_prompt_tokens() + self.estimated_completion_tokens()
+    }
+
+    /// Estimated cost in USD with the given prices by model name, steps with an unknown model
+    /// are not counted.
+    pub fn estimated_cost(&self, pricing: &HashMap<String, ModelPricing>) -> f64 {
+        self.steps
+            .iter()
+            .filter_map(|step| {
+                let price = pricing.get(step.model.as_ref()?)?;
+                Some(
+                    (step.estimated_prompt_tokens as f64 * price.input_per_million
+                        + step.estimated_completion_tokens as f64 * price.output_per_million)
+                        / 1_000_000.0,
+                )
+            })
+            .sum()
+    }
+}
+
+impl Display for DryRunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Dry run of {}:", self.workflow)?;
+        for (index, step) in self.steps.iter().enumerate() {
+            writeln!(
+                f,
+                "{}. {} ({}) | prompt tokens: ~{} | completion tokens: <= {}",
+                index + 1,
+                step.agent,
+                step.model.as_deref().unwrap_or("unknown model"),
+                step.estimated_prompt_tokens,
+                step.estimated_completion_tokens
+            )?;
+        }
+        write!(
+            f,
+            "Total: ~{} prompt tokens, <= {} completion tokens",
+            self.estimated_prompt_tokens(),
+            self.estimated_completion_tokens()
+        )
+    }
+}
+
+/// Placeholder for the output of an agent, which is not known in a dry run.
+pub fn output_placeholder(agent: &str) -> String {
+    format!("<output of {agent}>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(model: &str, prompt_tokens: u64, completion_tokens: u64) -> DryRunStep {
+        DryRunStep {
+            agent: "agent".to_owned(),
+            model: Some(model.to_owned()),
+            system_prompt: None,
+            prompt: "task".to_owned(),
+            estimated_prompt_tokens: prompt_tokens,
+            estimated_completion_tokens: completion_tokens,
+        }
+    }
+
+    #[test]
+    fn test_estimated_cost() {
+        let report = DryRunReport {
+            workflow: "test".to_owned(),
+            steps: vec![
+                step("gpt-4o", 1_000_000, 500_000),
+                step("unknown", 1_000, 1_000),
+            ],
+        };
+        let pricing = HashMap::from([("gpt-4o".to_owned(), ModelPricing::new(2.5, 10.0))]);
+
+        assert_eq!(report.estimated_total_tokens(), 1_502_000);
+        assert_eq!(report.estimated_cost(&pricing), 7.5);
+        assert!(report.to_string().contains("1. agent (gpt-4o)"));
+    }
+}
@@ -0,0 +1,67 @@
+//! Categories shared by the error types of all modules.
+//!
+//! Every error type implements [`CategorizedError`], wrapper variants (e.g. an [`AgentError`]
+//! inside a workflow error) report the category of the wrapped error, and keep it as their
+//! [`source`](std::error::Error::source), so callers can handle errors by category without
+//! matching on each module's enum.
+//!
+//! [`AgentError`]: crate::agent::AgentError
+
+use std::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The LLM provider failed or returned an unusable response.
+    Provider,
+    /// A tool was not found or failed.
+    Tool,
+    /// Reading or writing state, metadata or files failed.
+    Persistence,
+    /// The input or configuration is invalid.
+    Validation,
+    /// The operation was cancelled.
+    Cancelled,
+    /// The operation timed out.
+    Timeout,
+    /// Everything else.
+    Other,
+}
+
+pub trait CategorizedError: Error {
+    fn category(&self) -> ErrorCategory;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        agent::AgentError, concurrent_workflow::ConcurrentWorkflowError,
+        graph_workflow::GraphWorkflowError, tool::ToolError,
+    };
+
+    #[test]
+    fn test_wrapped_category_and_source() {
+        let err = ConcurrentWorkflowError::AgentError(AgentError::ToolError(
+            ToolError::ToolCallError("boom".into()),
+        ));
+        assert_eq!(err.category(), ErrorCategory::Tool);
+
+        let source = err.source().unwrap();
+        assert!(source.to_string().contains("boom"));
+        assert!(source.source().is_some());
+    }
+
+    #[test]
+    fn test_graph_workflow_keeps_agent_error() {
+        let err =
+            GraphWorkflowError::AgentError(Arc::new(AgentError::ToolNotFound("search".to_owned())));
+        assert_eq!(err.category(), ErrorCategory::Tool);
+        assert!(err.source().unwrap().to_string().contains("search"));
+        assert_eq!(
+            GraphWorkflowError::Canceled.category(),
+            ErrorCategory::Cancelled
+        );
+    }
+}
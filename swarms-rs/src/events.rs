@@ -0,0 +1,69 @@
+//! Structured lifecycle events of agents and workflows.
+//!
+//! Every lifecycle event is emitted with the [`TARGET`] target and a stable set of fields,
+//! so log pipelines can parse them without depending on the message text:
+//!
+//! | field         | description                                                  |
+//! |---------------|--------------------------------------------------------------|
+//! | `event`       | One of the event names below.                                |
+//! | `phase`       | `started`, `completed` or `failed`, see [`Phase`].           |
+//! | `task_id`     | Unique id of the run, shared by all phases of the same run.  |
+//! | `agent`       | Name of the agent, if the event belongs to an agent.         |
+//! | `workflow`    | Name of the workflow, if the event belongs to a workflow.    |
+//! | `tool`        | Name of the tool, only for [`AGENT_TOOL_CALL`].              |
+//! | `experiment`  | Name of the experiment, only for [`EXPERIMENT_RUN`].         |
+//! | `variant`     | Id of the variant, only for [`EXPERIMENT_RUN`].              |
+//! | `duration_ms` | Duration of the run, only for `completed` and `failed`.      |
+//! | `error`       | The error message, only for `failed`.                        |
+//! | `stop_reason` | Why the agent stopped, only for a `completed` [`AGENT_RUN`]. |
+//!
+//! | event            | phases                           | emitted by                       |
+//! |------------------|----------------------------------|----------------------------------|
+//! | `agent.run`      | `started`, `completed`, `failed` | every agent run                  |
+//! | `agent.tool_call`| `started`, `failed`              | every tool call of an agent, `failed` if the call is denied by the agent's tool permissions |
+//! | `workflow.run`   | `started`, `completed`, `failed` | sequential and concurrent runs   |
+//! | `workflow.agent` | `failed`                         | an agent failing in a concurrent workflow |
+//! | `experiment.run` | `completed`, `failed`            | every run of an experiment       |
+//!
+//! Event names and fields are only ever added, never renamed or removed.
+//!
+//! With the `json-logs` feature, [`init_json_logging`] installs a global subscriber which
+//! writes all tracing events, including these, as one JSON object per line.
+
+/// Target of all lifecycle events.
+pub const TARGET: &str = "swarms::events";
+
+pub const AGENT_RUN: &str = "agent.run";
+pub const AGENT_TOOL_CALL: &str = "agent.tool_call";
+pub const WORKFLOW_RUN: &str = "workflow.run";
+pub const WORKFLOW_AGENT: &str = "workflow.agent";
+pub const EXPERIMENT_RUN: &str = "experiment.run";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Started,
+    Completed,
+    Failed,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Started => "started",
+            Phase::Completed => "completed",
+            Phase::Failed => "failed",
+        }
+    }
+}
+
+/// Install a global subscriber which writes all tracing events as JSON lines to stdout.
+///
+/// The log level is read from `RUST_LOG`, fails if a global subscriber is already set.
+#[cfg(feature = "json-logs")]
+pub fn init_json_logging() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .try_init()
+}
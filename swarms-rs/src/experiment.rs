@@ -0,0 +1,558 @@
+//! A/B experiments between agent variants, e.g. different prompts, models or configs.
+//!
+//! Each task is assigned to one variant, the output is tagged with the variant id, and
+//! [`Experiment::summary`] compares the variants by success rate, latency and score.
+//!
+//! [`Experiment::compare`] runs a task on every variant instead, scoring the outputs of a
+//! comparison gives the [`PreferencePair`]s to train on, see [`Experiment::preference_pairs`].
+
+use std::{
+    fmt::Display,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use futures::future;
+use serde::Serialize;
+use thiserror::Error;
+use twox_hash::XxHash3_64;
+
+use crate::{
+    agent::{Agent, AgentError},
+    error::{CategorizedError, ErrorCategory},
+    events::{self, Phase},
+    feedback::{ChatMessage, PreferencePair},
+    rng::SwarmRng,
+};
+
+#[derive(Debug, Error)]
+pub enum ExperimentError {
+    #[error("Experiment has no variants")]
+    NoVariants,
+    #[error("Duplicate variant id: {0}")]
+    DuplicateVariant(String),
+    #[error("Task was not run in this experiment: {0}")]
+    UnknownTask(String),
+    #[error("Unknown variant: {0}")]
+    UnknownVariant(String),
+    #[error("Agent error: {0}")]
+    AgentError(#[from] AgentError),
+}
+
+impl CategorizedError for ExperimentError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            ExperimentError::NoVariants
+            | ExperimentError::DuplicateVariant(_)
+            | ExperimentError::UnknownTask(_)
+            | ExperimentError::UnknownVariant(_) => ErrorCategory::Validation,
+            ExperimentError::AgentError(e) => e.category(),
+        }
+    }
+}
+
+/// How tasks are assigned to variants.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Assignment {
+    /// The same task is always assigned to the same variant.
+    #[default]
+    Hash,
+    /// Every run picks a variant at random, see [`ExperimentBuilder::seed`].
+    Random,
+}
+
+pub struct Variant {
+    id: String,
+    agent: Box<dyn Agent>,
+    weight: u32,
+}
+
+impl Variant {
+    pub fn new(id: impl Into<String>, agent: Box<dyn Agent>) -> Self {
+        Self {
+            id: id.into(),
+            agent,
+            weight: 1,
+        }
+    }
+
+    /// Relative share of the tasks assigned to this variant, defaults to 1.
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct ExperimentBuilder {
+    name: String,
+    variants: Vec<Variant>,
+    assignment: Assignment,
+    seed: Option<u64>,
+}
+
+impl ExperimentBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variants.push(variant);
+        self
+    }
+
+    pub fn assignment(mut self, assignment: Assignment) -> Self {
+        self.assignment = assignment;
+        self
+    }
+
+    /// Seed the random assignment, the same tasks in the same order are then assigned to the
+    /// same variants.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn build(self) -> Result<Experiment, ExperimentError> {
+        if self.variants.iter().all(|variant| variant.weight == 0) {
+            return Err(ExperimentError::NoVariants);
+        }
+        for (i, variant) in self.variants.iter().enumerate() {
+            if self.variants[..i].iter().any(|v| v.id == variant.id) {
+                return Err(ExperimentError::DuplicateVariant(variant.id.clone()));
+            }
+        }
+
+        Ok(Experiment {
+            name: self.name,
+            variants: self.variants,
+            assignment: self.assignment,
+            rng: SwarmRng::from_seed(self.seed),
+            results: DashMap::new(),
+            comparisons: DashMap::new(),
+        })
+    }
+}
+
+/// The output of a task, tagged with the variant which produced it.
+#[derive(Clone, Debug, Serialize)]
+pub struct ExperimentOutput {
+    pub variant_id: String,
+    pub task: String,
+    pub output: Result<String, String>,
+    pub duration: Duration,
+    pub score: Option<f64>,
+}
+
+pub struct Experiment {
+    name: String,
+    variants: Vec<Variant>,
+    assignment: Assignment,
+    rng: SwarmRng,
+    /// Task -> latest output
+    results: DashMap<String, ExperimentOutput>,
+    /// Task -> latest output of every variant
+    comparisons: DashMap<String, Vec<ExperimentOutput>>,
+}
+
+impl Experiment {
+    pub fn builder() -> ExperimentBuilder {
+        ExperimentBuilder::default()
+    }
+
+    /// The id of the variant the task is assigned to.
+    pub fn assign(&self, task: &str) -> &str {
+        let total_weight = self.variants.iter().map(|v| v.weight as u64).sum::<u64>();
+        let point = match self.assignment {
+            Assignment::Hash => {
+                let mut hasher = XxHash3_64::default();
+                task.hash(&mut hasher);
+                hasher.finish() % total_weight
+            }
+            Assignment::Random => self.rng.below(total_weight),
+        };
+
+        let mut upper = 0;
+        for variant in &self.variants {
+            upper += variant.weight as u64;
+            if point < upper {
+                return &variant.id;
+            }
+        }
+        unreachable!("point is always below the total weight")
+    }
+
+    /// Run the task on its assigned variant, failed runs are recorded too.
+    pub async fn run(&self, task: impl Into<String>) -> Result<ExperimentOutput, ExperimentError> {
+        let task = task.into();
+        let variant_id = self.assign(&task);
+        // Safety: assign always returns the id of an existing variant
+        let variant = self.variants.iter().find(|v| v.id == variant_id).unwrap();
+        let (output, result) = self.run_variant(variant, task.clone()).await;
+        self.results.insert(task, output.clone());
+        result?;
+        Ok(output)
+    }
+
+    /// Run the task on every variant, e.g. to compare their outputs side by side. The
+    /// outputs are kept apart from those of [`Experiment::run`] and don't count in the
+    /// summary.
+    pub async fn compare(&self, task: impl Into<String>) -> Vec<ExperimentOutput> {
+        let task = task.into();
+        let outputs = future::join_all(
+            self.variants
+                .iter()
+                .map(|variant| self.run_variant(variant, task.clone())),
+        )
+        .await
+        .into_iter()
+        .map(|(output, _)| output)
+        .collect::<Vec<_>>();
+        self.comparisons.insert(task, outputs.clone());
+        outputs
+    }
+
+    async fn run_variant(
+        &self,
+        variant: &Variant,
+        task: String,
+    ) -> (ExperimentOutput, Result<String, AgentError>) {
+        let start = Instant::now();
+        let result = variant.agent.run(task.clone()).await;
+        let duration = start.elapsed();
+
+        match &result {
+            Ok(_) => tracing::info!(
+                target: events::TARGET,
+                event = events::EXPERIMENT_RUN,
+                phase = Phase::Completed.as_str(),
+                experiment = %self.name,
+                variant = %variant.id,
+                agent = %variant.agent.name(),
+                duration_ms = duration.as_millis() as u64,
+            ),
+            Err(e) => tracing::error!(
+                target: events::TARGET,
+                event = events::EXPERIMENT_RUN,
+                phase = Phase::Failed.as_str(),
+                experiment = %self.name,
+                variant = %variant.id,
+                agent = %variant.agent.name(),
+                duration_ms = duration.as_millis() as u64,
+                error = %e,
+            ),
+        }
+        #[cfg(feature = "metrics")]
+        {
+            let labels = [
+                ("experiment", self.name.as_str()),
+                ("variant", variant.id.as_str()),
+                (
+                    "status",
+                    if result.is_ok() {
+                        "completed"
+                    } else {
+                        "failed"
+                    },
+                ),
+            ];
+            crate::metrics::inc_counter(crate::metrics::EXPERIMENT_RUNS, &labels, 1);
+            crate::metrics::observe(
+                crate::metrics::EXPERIMENT_RUN_DURATION,
+                &labels[..2],
+                duration.as_secs_f64(),
+            );
+        }
+
+        let output = ExperimentOutput {
+            variant_id: variant.id.clone(),
+            task,
+            output: result
+                .as_ref()
+                .map(Clone::clone)
+                .map_err(ToString::to_string),
+            duration,
+            score: None,
+        };
+        (output, result)
+    }
+
+    /// Score the output of a task, e.g. from an evaluator agent or user feedback.
+    pub fn record_score(&self, task: &str, score: f64) -> Result<(), ExperimentError> {
+        let mut output = self
+            .results
+            .get_mut(task)
+            .ok_or_else(|| ExperimentError::UnknownTask(task.to_owned()))?;
+        output.score = Some(score);
+        Ok(())
+    }
+
+    /// Score the output of a variant in the comparison of a task.
+    pub fn record_comparison_score(
+        &self,
+        task: &str,
+        variant_id: &str,
+        score: f64,
+    ) -> Result<(), ExperimentError> {
+        let mut outputs = self
+            .comparisons
+            .get_mut(task)
+            .ok_or_else(|| ExperimentError::UnknownTask(task.to_owned()))?;
+        let output = outputs
+            .iter_mut()
+            .find(|output| output.variant_id == variant_id)
+            .ok_or_else(|| ExperimentError::UnknownVariant(variant_id.to_owned()))?;
+        output.score = Some(score);
+        Ok(())
+    }
+
+    /// The outputs of the comparisons, grouped by task.
+    pub fn comparisons(&self) -> Vec<Vec<ExperimentOutput>> {
+        self.comparisons.iter().map(|c| c.value().clone()).collect()
+    }
+
+    /// Preference pairs of the compared outputs, every output is chosen over each
+    /// lower scored output of the same task. Failed and unscored outputs are skipped.
+    pub fn preference_pairs(&self) -> Vec<PreferencePair> {
+        let mut pairs = Vec::new();
+        for comparison in self.comparisons() {
+            let mut scored = comparison
+                .iter()
+                .filter_map(|output| Some((output.score?, output.output.as_ref().ok()?)))
+                .collect::<Vec<_>>();
+            scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+            for (i, (score, chosen)) in scored.iter().enumerate() {
+                for (_, rejected) in scored[i + 1..].iter().filter(|(s, _)| s < score) {
+                    pairs.push(PreferencePair {
+                        prompt: vec![ChatMessage::new("user", &comparison[0].task)],
+                        chosen: (*chosen).clone(),
+                        rejected: (*rejected).clone(),
+                    });
+                }
+            }
+        }
+        pairs
+    }
+
+    pub fn outputs(&self) -> Vec<ExperimentOutput> {
+        self.results.iter().map(|r| r.value().clone()).collect()
+    }
+
+    /// Compare the variants over all recorded outputs.
+    pub fn summary(&self) -> ExperimentSummary {
+        let outputs = self.outputs();
+        let variants = self
+            .variants
+            .iter()
+            .map(|variant| {
+                let outputs = outputs
+                    .iter()
+                    .filter(|output| output.variant_id == variant.id)
+                    .collect::<Vec<_>>();
+                let runs = outputs.len();
+                let successes = outputs.iter().filter(|o| o.output.is_ok()).count();
+                let scores = outputs.iter().filter_map(|o| o.score).collect::<Vec<_>>();
+                VariantSummary {
+                    variant_id: variant.id.clone(),
+                    runs,
+                    success_rate: ratio(successes as f64, runs),
+                    avg_duration: outputs
+                        .iter()
+                        .map(|o| o.duration)
+                        .sum::<Duration>()
+                        .checked_div(runs as u32)
+                        .unwrap_or_default(),
+                    avg_score: ratio(scores.iter().sum(), scores.len()),
+                }
+            })
+            .collect();
+
+        ExperimentSummary {
+            experiment: self.name.clone(),
+            variants,
+        }
+    }
+}
+
+fn ratio(sum: f64, count: usize) -> Option<f64> {
+    (count > 0).then(|| sum / count as f64)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct VariantSummary {
+    pub variant_id: String,
+    pub runs: usize,
+    pub success_rate: Option<f64>,
+    pub avg_duration: Duration,
+    pub avg_score: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ExperimentSummary {
+    pub experiment: String,
+    pub variants: Vec<VariantSummary>,
+}
+
+impl ExperimentSummary {
+    /// The variant with the highest average score, if any output was scored.
+    pub fn best_variant(&self) -> Option<&VariantSummary> {
+        self.variants
+            .iter()
+            .filter(|v| v.avg_score.is_some())
+            .max_by(|a, b| {
+                a.avg_score
+                    .unwrap_or_default()
+                    .total_cmp(&b.avg_score.unwrap_or_default())
+            })
+    }
+}
+
+impl Display for ExperimentSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Experiment: {}", self.experiment)?;
+        writeln!(
+            f,
+            "{:<20} {:>6} {:>8} {:>12} {:>8}",
+            "variant", "runs", "success", "avg latency", "score"
+        )?;
+        let or_dash = |value: Option<f64>| value.map_or("-".to_owned(), |v| format!("{v:.2}"));
+        for variant in &self.variants {
+            writeln!(
+                f,
+                "{:<20} {:>6} {:>8} {:>11.2}s {:>8}",
+                variant.variant_id,
+                variant.runs,
+                or_dash(variant.success_rate),
+                variant.avg_duration.as_secs_f64(),
+                or_dash(variant.avg_score)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FnAgent;
+
+    // Answers with its prefix followed by the task
+    fn echo_agent(prefix: &'static str) -> Box<dyn Agent> {
+        Box::new(FnAgent::new("echo", move |task| format!("{prefix}{task}")))
+    }
+
+    fn experiment(assignment: Assignment) -> Experiment {
+        Experiment::builder()
+            .name("prompt-test")
+            .variant(Variant::new("a", echo_agent("")))
+            .variant(Variant::new("b", echo_agent("")).weight(3))
+            .assignment(assignment)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_build_validation() {
+        let no_variants = Experiment::builder().build();
+        assert!(matches!(no_variants, Err(ExperimentError::NoVariants)));
+
+        let duplicate = Experiment::builder()
+            .variant(Variant::new("a", echo_agent("")))
+            .variant(Variant::new("a", echo_agent("")))
+            .build();
+        assert!(matches!(duplicate, Err(ExperimentError::DuplicateVariant(id)) if id == "a"));
+    }
+
+    #[test]
+    fn test_assignment() {
+        let hashed = experiment(Assignment::Hash);
+        let first = hashed.assign("task").to_owned();
+        assert!((0..10).all(|_| hashed.assign("task") == first));
+
+        let random = experiment(Assignment::Random);
+        let b_count = (0..1000).filter(|_| random.assign("task") == "b").count();
+        // b has 3/4 of the weight
+        assert!((600..900).contains(&b_count), "{b_count}");
+
+        let seeded = || {
+            let experiment = Experiment::builder()
+                .variant(Variant::new("a", echo_agent("")))
+                .variant(Variant::new("b", echo_agent("")))
+                .assignment(Assignment::Random)
+                .seed(7)
+                .build()
+                .unwrap();
+            (0..20)
+                .map(|_| experiment.assign("task").to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(seeded(), seeded());
+    }
+
+    #[tokio::test]
+    async fn test_summary() {
+        let experiment = experiment(Assignment::Hash);
+        for i in 0..20 {
+            experiment.run(format!("task {i}")).await.unwrap();
+        }
+        let output = experiment.run("scored").await.unwrap();
+        assert_eq!(output.output.as_deref(), Ok("scored"));
+        experiment.record_score("scored", 0.9).unwrap();
+        assert!(matches!(
+            experiment.record_score("unknown", 1.0),
+            Err(ExperimentError::UnknownTask(_))
+        ));
+
+        let summary = experiment.summary();
+        assert_eq!(summary.variants.iter().map(|v| v.runs).sum::<usize>(), 21);
+        assert!(
+            summary
+                .variants
+                .iter()
+                .all(|v| v.runs == 0 || v.success_rate == Some(1.0))
+        );
+        assert_eq!(
+            summary.best_variant().unwrap().variant_id,
+            output.variant_id
+        );
+        assert!(summary.to_string().contains("prompt-test"));
+    }
+
+    #[tokio::test]
+    async fn test_preference_pairs() {
+        let experiment = Experiment::builder()
+            .variant(Variant::new("short", echo_agent("Short: ")))
+            .variant(Variant::new("long", echo_agent("Long: ")))
+            .variant(Variant::new("terse", echo_agent("Terse: ")))
+            .build()
+            .unwrap();
+        let outputs = experiment.compare("Explain DPO").await;
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[1].output.as_deref(), Ok("Long: Explain DPO"));
+        assert!(experiment.summary().variants.iter().all(|v| v.runs == 0));
+
+        experiment
+            .record_comparison_score("Explain DPO", "long", 0.9)
+            .unwrap();
+        experiment
+            .record_comparison_score("Explain DPO", "short", 0.4)
+            .unwrap();
+        assert!(matches!(
+            experiment.record_comparison_score("Explain DPO", "unknown", 1.0),
+            Err(ExperimentError::UnknownVariant(_))
+        ));
+
+        // The unscored output is skipped
+        let pairs = experiment.preference_pairs();
+        assert_eq!(
+            pairs,
+            [PreferencePair {
+                prompt: vec![ChatMessage::new("user", "Explain DPO")],
+                chosen: "Long: Explain DPO".to_owned(),
+                rejected: "Short: Explain DPO".to_owned(),
+            }]
+        );
+    }
+}
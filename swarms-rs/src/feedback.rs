@@ -0,0 +1,423 @@
+//! Human feedback on agent conversations, for fine-tuning and evaluation datasets.
+//!
+//! [`Feedback`] rates a message of a conversation, or the whole run if it has no message,
+//! with a thumbs up or down, a corrected answer and tags. It's stored in the
+//! [`AgentConversation`] it annotates, see [`AgentConversation::annotate`], and so saved with
+//! the agent's state. [`FeedbackRecord`]s join each feedback with the messages it rates, one
+//! record per line of a JSONL dataset, see [`export_jsonl`].
+//!
+//! For DPO and reward model training, [`preference_pairs`] turns the feedback into
+//! [`PreferencePair`]s of a prompt with a chosen and a rejected answer: a corrected answer is
+//! preferred over the answer it corrects, and a thumbs up answer over a thumbs down answer to
+//! the same prompt. [`Experiment::preference_pairs`] pairs the scored outputs of variants
+//! instead. [`export_preferences_jsonl`] saves the pairs in the formats of the common
+//! training libraries, e.g. TRL's `DPOTrainer`.
+//!
+//! [`Experiment::preference_pairs`]: crate::experiment::Experiment::preference_pairs
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::{
+    conversation::{AgentConversation, ConversationError, Message, ParticipantKind},
+    persistence,
+    tenant::TenantId,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rating {
+    ThumbsUp,
+    ThumbsDown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Feedback {
+    /// Index of the rated message in the history, the whole run is rated if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<usize>,
+    /// The run which produced the rated messages, e.g. the run id of its provenance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<Rating>,
+    /// What the message should have said.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correction: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// Who gave the feedback, e.g. the reviewer's user name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Default for Feedback {
+    fn default() -> Self {
+        Self {
+            message: None,
+            run_id: None,
+            rating: None,
+            correction: None,
+            tags: BTreeSet::new(),
+            author: None,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+impl Feedback {
+    /// Feedback on the whole run, given now.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn thumbs_up() -> Self {
+        Self::new().rating(Rating::ThumbsUp)
+    }
+
+    pub fn thumbs_down() -> Self {
+        Self::new().rating(Rating::ThumbsDown)
+    }
+
+    /// Rate the message at `index` of the history instead of the whole run.
+    pub fn message(mut self, index: usize) -> Self {
+        self.message = Some(index);
+        self
+    }
+
+    pub fn run_id(mut self, run_id: Uuid) -> Self {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    pub fn rating(mut self, rating: Rating) -> Self {
+        self.rating = Some(rating);
+        self
+    }
+
+    pub fn correction(mut self, correction: impl Into<String>) -> Self {
+        self.correction = Some(correction.into());
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.insert(tag.into());
+        self
+    }
+
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+}
+
+/// A feedback joined with the conversation it rates, a line of a feedback dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedbackRecord {
+    pub conversation_id: Uuid,
+    pub agent_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<TenantId>,
+    /// The messages before the rated message, the whole history if the run is rated.
+    pub context: Vec<Message>,
+    /// The rated message, `None` if the run is rated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Message>,
+    #[serde(flatten)]
+    pub feedback: Feedback,
+}
+
+/// The feedback records of the conversations, in the order of the conversations and their
+/// feedback.
+pub fn records<'a>(
+    conversations: impl IntoIterator<Item = &'a AgentConversation>,
+) -> Vec<FeedbackRecord> {
+    conversations
+        .into_iter()
+        .flat_map(AgentConversation::feedback_records)
+        .collect()
+}
+
+/// Save the feedback records of the conversations to `path` as JSONL, returns the number of
+/// records.
+pub async fn export_jsonl<'a>(
+    conversations: impl IntoIterator<Item = &'a AgentConversation>,
+    path: impl AsRef<Path>,
+) -> Result<usize, ConversationError> {
+    let records = records(conversations);
+    let mut data = Vec::new();
+    for record in &records {
+        serde_json::to_writer(&mut data, record)?;
+        data.push(b'\n');
+    }
+    persistence::save_to_file(data, path).await?;
+    Ok(records.len())
+}
+
+/// A message of the prompt of a [`PreferencePair`], with a role chat templates know.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// `system`, `user` or `assistant`.
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+        }
+    }
+}
+
+impl From<&Message> for ChatMessage {
+    /// Messages of tools and retrievers are input of the model, like those of humans.
+    fn from(message: &Message) -> Self {
+        let role = match message.participant.kind {
+            ParticipantKind::Agent => "assistant",
+            ParticipantKind::System => "system",
+            ParticipantKind::Human | ParticipantKind::Tool | ParticipantKind::Retrieval => "user",
+        };
+        Self::new(role, message.body())
+    }
+}
+
+/// A prompt with a preferred and a rejected answer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreferencePair {
+    pub prompt: Vec<ChatMessage>,
+    pub chosen: String,
+    pub rejected: String,
+}
+
+/// Line format of a preference dataset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreferenceFormat {
+    /// `{"prompt": [..], "chosen": [..], "rejected": [..]}` with chat messages, the answers are
+    /// a single assistant message. Chat templates of the model format it.
+    #[default]
+    Conversational,
+    /// `{"prompt": "..", "chosen": "..", "rejected": ".."}` with plain text, the contents of the
+    /// prompt's messages are joined by blank lines.
+    Standard,
+}
+
+impl PreferencePair {
+    pub fn to_json(&self, format: PreferenceFormat) -> Value {
+        match format {
+            PreferenceFormat::Conversational => json!({
+                "prompt": self.prompt,
+                "chosen": [ChatMessage::new("assistant", &self.chosen)],
+                "rejected": [ChatMessage::new("assistant", &self.rejected)],
+            }),
+            PreferenceFormat::Standard => json!({
+                "prompt": self
+                    .prompt
+                    .iter()
+                    .map(|message| message.content.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n"),
+                "chosen": self.chosen,
+                "rejected": self.rejected,
+            }),
+        }
+    }
+}
+
+/// Preference pairs of the feedback on messages: a correction is chosen over the message it
+/// corrects, and every thumbs up message over every different thumbs down message with the
+/// same prompt. Feedback on whole runs has no answer to pair and is skipped.
+pub fn preference_pairs<'a>(
+    records: impl IntoIterator<Item = &'a FeedbackRecord>,
+) -> Vec<PreferencePair> {
+    let mut pairs = Vec::new();
+    // Prompt -> (thumbs up, thumbs down) answers, grouped by content since the messages of
+    // different runs differ in their timestamps
+    let mut rated = BTreeMap::<Vec<(String, String)>, (Vec<String>, Vec<String>)>::new();
+    for record in records {
+        let Some(response) = &record.response else {
+            continue;
+        };
+        let prompt = record
+            .context
+            .iter()
+            .map(ChatMessage::from)
+            .collect::<Vec<_>>();
+        let answer = response.body().to_owned();
+        if let Some(correction) = &record.feedback.correction
+            && *correction != answer
+        {
+            pairs.push(PreferencePair {
+                prompt: prompt.clone(),
+                chosen: correction.clone(),
+                rejected: answer.clone(),
+            });
+        }
+        let key = prompt
+            .into_iter()
+            .map(|message| (message.role, message.content))
+            .collect();
+        let (up, down) = rated.entry(key).or_default();
+        match record.feedback.rating {
+            Some(Rating::ThumbsUp) => up.push(answer),
+            Some(Rating::ThumbsDown) => down.push(answer),
+            None => {}
+        }
+    }
+    for (prompt, (up, down)) in rated {
+        let prompt = prompt
+            .into_iter()
+            .map(|(role, content)| ChatMessage { role, content })
+            .collect::<Vec<_>>();
+        for chosen in &up {
+            for rejected in down.iter().filter(|rejected| *rejected != chosen) {
+                pairs.push(PreferencePair {
+                    prompt: prompt.clone(),
+                    chosen: chosen.clone(),
+                    rejected: rejected.clone(),
+                });
+            }
+        }
+    }
+    pairs
+}
+
+/// Save the preference pairs to `path` as JSONL in the format, returns the number of pairs.
+pub async fn export_preferences_jsonl(
+    pairs: &[PreferencePair],
+    format: PreferenceFormat,
+    path: impl AsRef<Path>,
+) -> Result<usize, ConversationError> {
+    let mut data = Vec::new();
+    for pair in pairs {
+        serde_json::to_writer(&mut data, &pair.to_json(format))?;
+        data.push(b'\n');
+    }
+    persistence::save_to_file(data, path).await?;
+    Ok(pairs.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{conversation::Participant, test_support::temp_dir};
+
+    #[tokio::test]
+    async fn test_annotate_and_export() {
+        let mut conversation = AgentConversation::new("writer".to_owned());
+        conversation.add(Participant::human("user"), "Write a haiku".to_owned());
+        conversation.add(Participant::agent("writer"), "Roses are red".to_owned());
+        conversation
+            .annotate(
+                Feedback::thumbs_down()
+                    .message(1)
+                    .correction("An old silent pond")
+                    .tag("not-a-haiku")
+                    .author("reviewer"),
+            )
+            .unwrap();
+        conversation.annotate(Feedback::thumbs_up()).unwrap();
+        assert!(matches!(
+            conversation.annotate(Feedback::thumbs_up().message(2)),
+            Err(ConversationError::IndexOutOfRange(2))
+        ));
+        assert_eq!(conversation.feedback().len(), 2);
+
+        // Saved with the conversation
+        let json = serde_json::to_string(&conversation).unwrap();
+        let conversation = serde_json::from_str::<AgentConversation>(&json).unwrap();
+        assert_eq!(conversation.feedback_on(1).count(), 1);
+
+        let records = records([&conversation]);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].agent_name, "writer");
+        assert_eq!(records[0].context, conversation.history[..1]);
+        assert_eq!(records[0].response.as_ref(), Some(&conversation.history[1]));
+        assert_eq!(records[0].feedback.rating, Some(Rating::ThumbsDown));
+        assert_eq!(records[1].context.len(), 2);
+        assert_eq!(records[1].response, None);
+
+        let dir = temp_dir();
+        let path = dir.join("feedback.jsonl");
+        assert_eq!(export_jsonl([&conversation], &path).await.unwrap(), 2);
+        let data = tokio::fs::read_to_string(&path).await.unwrap();
+        let lines = data.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let record = serde_json::from_str::<FeedbackRecord>(lines[0]).unwrap();
+        assert_eq!(record, records[0]);
+        assert!(lines[0].contains(r#""rating":"thumbs_down""#));
+    }
+
+    #[tokio::test]
+    async fn test_preference_pairs() {
+        // Two runs of the same task, each answer rated
+        let runs = ["Roses are red", "An old silent pond"].map(|answer| {
+            let mut conversation = AgentConversation::new("writer".to_owned());
+            conversation.add(Participant::human("user"), "Write a haiku".to_owned());
+            conversation.add(Participant::agent("writer"), answer.to_owned());
+            conversation
+        });
+        let [mut bad, mut good] = runs;
+        bad.annotate(
+            Feedback::thumbs_down()
+                .message(1)
+                .correction("A frog jumps in"),
+        )
+        .unwrap();
+        good.annotate(Feedback::thumbs_up().message(1)).unwrap();
+        good.annotate(Feedback::thumbs_up()).unwrap();
+
+        let pairs = preference_pairs(&records([&bad, &good]));
+        let prompt = vec![ChatMessage::new("user", "Write a haiku")];
+        assert_eq!(
+            pairs,
+            [
+                PreferencePair {
+                    prompt: prompt.clone(),
+                    chosen: "A frog jumps in".to_owned(),
+                    rejected: "Roses are red".to_owned(),
+                },
+                PreferencePair {
+                    prompt,
+                    chosen: "An old silent pond".to_owned(),
+                    rejected: "Roses are red".to_owned(),
+                },
+            ]
+        );
+
+        assert_eq!(
+            pairs[1].to_json(PreferenceFormat::Standard),
+            json!({
+                "prompt": "Write a haiku",
+                "chosen": "An old silent pond",
+                "rejected": "Roses are red",
+            })
+        );
+        let dir = temp_dir();
+        let path = dir.join("dpo.jsonl");
+        let exported = export_preferences_jsonl(&pairs, PreferenceFormat::Conversational, &path)
+            .await
+            .unwrap();
+        assert_eq!(exported, 2);
+        let data = tokio::fs::read_to_string(&path).await.unwrap();
+        let line = serde_json::from_str::<Value>(data.lines().next().unwrap()).unwrap();
+        assert_eq!(
+            line,
+            json!({
+                "prompt": [{"role": "user", "content": "Write a haiku"}],
+                "chosen": [{"role": "assistant", "content": "A frog jumps in"}],
+                "rejected": [{"role": "assistant", "content": "Roses are red"}],
+            })
+        );
+    }
+}
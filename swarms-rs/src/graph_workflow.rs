@@ -1,20 +1,44 @@
 use std::{
-    collections::{HashMap, hash_map},
-    sync::Arc,
+    collections::{HashMap, HashSet, hash_map},
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    pin::pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
     time::Duration,
 };
 
-use dashmap::DashMap;
+use chrono::{DateTime, Utc};
+use dashmap::{DashMap, DashSet};
+use futures::{
+    StreamExt,
+    future::{self, BoxFuture, Either},
+    stream::FuturesUnordered,
+};
 use petgraph::{
     Direction,
     graph::{EdgeIndex, NodeIndex},
     prelude::StableGraph,
-    visit::EdgeRef,
+    stable_graph::EdgeReference,
+    visit::{EdgeRef, IntoEdgeReferences},
 };
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
+use twox_hash::XxHash3_64;
+
+use crate::{
+    agent::{Agent, AgentError},
+    dry_run::{self, DryRunReport},
+    error::{CategorizedError, ErrorCategory},
+    persistence::{self, Persistence, PersistenceError},
+    utils::is_empty_task,
+};
 
-use crate::agent::Agent;
+/// Max delay between two retries of a node, unless the node's initial backoff is longer.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60);
 
 // The main orchestration structure
 pub struct DAGWorkflow {
@@ -26,6 +50,18 @@ pub struct DAGWorkflow {
     workflow: StableGraph<AgentNode, Flow>,
     // Map from agent name to node index for quick lookup
     name_to_node: HashMap<String, NodeIndex>,
+    // Retry and failure policies by agent name
+    policies: HashMap<String, NodePolicy>,
+    // Aggregation nodes by name, they have no agent
+    aggregators: HashMap<String, Aggregator>,
+    // Branch predictors by agent name, for speculative execution
+    predictors: HashMap<String, BranchPredictor>,
+    // Output caches by agent name
+    node_caches: HashMap<String, NodeCache>,
+    // Cached outputs by agent name and input hash, shared by all runs
+    memo: DashMap<(String, u64), CachedOutput>,
+    // Live state of each node during execution
+    node_states: NodeStateTracker,
 }
 
 impl DAGWorkflow {
@@ -36,6 +72,12 @@ impl DAGWorkflow {
             agents: DashMap::new(),
             workflow: StableGraph::new(),
             name_to_node: HashMap::new(),
+            policies: HashMap::new(),
+            aggregators: HashMap::new(),
+            predictors: HashMap::new(),
+            node_caches: HashMap::new(),
+            memo: DashMap::new(),
+            node_states: NodeStateTracker::new(),
         }
     }
 
@@ -54,6 +96,37 @@ impl DAGWorkflow {
         }
     }
 
+    /// Add an aggregation node, which joins the outputs of its incoming branches.
+    ///
+    /// The node is connected like an agent, it runs once its quorum of incoming branches
+    /// has finished, and passes the combined output downstream.
+    pub fn add_aggregator(
+        &mut self,
+        name: impl Into<String>,
+        aggregator: Aggregator,
+    ) -> Result<(), GraphWorkflowError> {
+        let name = name.into();
+        if self.name_to_node.contains_key(&name) {
+            return Err(GraphWorkflowError::NodeAlreadyExists(name));
+        }
+        if let AggregationStrategy::Agent(agent) = &aggregator.strategy
+            && !self.agents.contains_key(agent)
+        {
+            return Err(GraphWorkflowError::AgentNotFound(format!(
+                "Aggregator agent '{}' not found",
+                agent
+            )));
+        }
+
+        let node_idx = self.workflow.add_node(AgentNode {
+            name: name.clone(),
+            last_result: Mutex::new(None),
+        });
+        self.name_to_node.insert(name.clone(), node_idx);
+        self.aggregators.insert(name, aggregator);
+        Ok(())
+    }
+
     // Add a flow connection between two agents
     pub fn connect_agents(
         &mut self,
@@ -62,13 +135,13 @@ impl DAGWorkflow {
         flow: Flow,
     ) -> Result<EdgeIndex, GraphWorkflowError> {
         // Ensure both agents exist
-        if !self.agents.contains_key(from) {
+        if !self.agents.contains_key(from) && !self.aggregators.contains_key(from) {
             return Err(GraphWorkflowError::AgentNotFound(format!(
                 "Source agent '{}' not found",
                 from
             )));
         }
-        if !self.agents.contains_key(to) {
+        if !self.agents.contains_key(to) && !self.aggregators.contains_key(to) {
             return Err(GraphWorkflowError::AgentNotFound(format!(
                 "Target agent '{}' not found",
                 to
@@ -92,54 +165,13 @@ impl DAGWorkflow {
             })
         });
 
-        // Add the edge
-        let edge_idx = self.workflow.add_edge(from_idx, to_idx, flow);
-
-        // Check for cycles
-        if self.has_cycle() {
-            // Remove the edge we just added
-            self.workflow.remove_edge(edge_idx);
+        // The edge closes a cycle if its target already reaches its source, only the
+        // subgraph downstream of the target is searched
+        if petgraph::algo::has_path_connecting(&self.workflow, to_idx, from_idx, None) {
             return Err(GraphWorkflowError::CycleDetected);
         }
 
-        Ok(edge_idx)
-    }
-
-    // Check if the workflow has a cycle
-    fn has_cycle(&self) -> bool {
-        // Implementation using DFS to detect cycles
-        let mut visited = vec![false; self.workflow.node_count()];
-        let mut rec_stack = vec![false; self.workflow.node_count()];
-
-        for node in self.workflow.node_indices() {
-            if !visited[node.index()] && self.is_cyclic_util(node, &mut visited, &mut rec_stack) {
-                return true;
-            }
-        }
-        false
-    }
-
-    fn is_cyclic_util(
-        &self,
-        node: NodeIndex,
-        visited: &mut [bool],
-        rec_stack: &mut [bool],
-    ) -> bool {
-        visited[node.index()] = true;
-        rec_stack[node.index()] = true;
-
-        for neighbor in self.workflow.neighbors_directed(node, Direction::Outgoing) {
-            if !visited[neighbor.index()] {
-                if self.is_cyclic_util(neighbor, visited, rec_stack) {
-                    return true;
-                }
-            } else if rec_stack[neighbor.index()] {
-                return true;
-            }
-        }
-
-        rec_stack[node.index()] = false;
-        false
+        Ok(self.workflow.add_edge(from_idx, to_idx, flow))
     }
 
     // Remove an agent connection
@@ -168,6 +200,10 @@ impl DAGWorkflow {
         if let Some(node_idx) = self.name_to_node.remove(name) {
             self.workflow.remove_node(node_idx);
             self.agents.remove(name);
+            self.aggregators.remove(name);
+            self.predictors.remove(name);
+            self.node_caches.remove(name);
+            self.memo.retain(|(node, _), _| node != name);
             Ok(())
         } else {
             Err(GraphWorkflowError::AgentNotFound(format!(
@@ -187,7 +223,7 @@ impl DAGWorkflow {
             agent
                 .run(input)
                 .await
-                .map_err(|e| GraphWorkflowError::AgentError(e.to_string()))
+                .map_err(|e| GraphWorkflowError::AgentError(Arc::new(e)))
         } else {
             Err(GraphWorkflowError::AgentNotFound(format!(
                 "Agent '{}' not found",
@@ -196,13 +232,108 @@ impl DAGWorkflow {
         }
     }
 
-    // Execute the entire workflow starting from a specific agent
+    /// Set the retry and failure policy of an agent's node.
+    ///
+    /// Nodes without a policy are tried once and skip their subtree on failure.
+    pub fn set_node_policy(
+        &mut self,
+        name: &str,
+        policy: NodePolicy,
+    ) -> Result<(), GraphWorkflowError> {
+        if !self.name_to_node.contains_key(name) {
+            return Err(GraphWorkflowError::AgentNotFound(format!(
+                "Agent '{}' not found",
+                name
+            )));
+        }
+        if let FailureAction::ErrorHandler(handler) = &policy.on_failure
+            && !self.agents.contains_key(handler)
+        {
+            return Err(GraphWorkflowError::AgentNotFound(format!(
+                "Error handler agent '{}' not found",
+                handler
+            )));
+        }
+        self.policies.insert(name.to_owned(), policy);
+        Ok(())
+    }
+
+    /// Reuse the agent's output when it gets the same input again, in the same or a later run,
+    /// instead of calling it again. Only successful outputs are cached.
+    pub fn set_node_cache(
+        &mut self,
+        name: &str,
+        cache: NodeCache,
+    ) -> Result<(), GraphWorkflowError> {
+        if !self.agents.contains_key(name) {
+            return Err(GraphWorkflowError::AgentNotFound(format!(
+                "Agent '{}' not found",
+                name
+            )));
+        }
+        self.memo.retain(|(node, _), _| node != name);
+        self.node_caches.insert(name.to_owned(), cache);
+        Ok(())
+    }
+
+    /// Speculatively execute the branch the predictor expects the agent's output to take.
+    ///
+    /// When the agent is done, the predictor gets its output and names the target agent of
+    /// the likely flow. The target starts right away, while the conditions of the agent's
+    /// flows are evaluated, which pays off for slow [`Flow::async_condition`]s. If the flow is
+    /// taken the target's result is kept, otherwise its run is cancelled. Only targets which
+    /// are agents with this single incoming flow are executed speculatively.
+    pub fn set_branch_predictor(
+        &mut self,
+        name: &str,
+        predictor: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+    ) -> Result<(), GraphWorkflowError> {
+        if !self.name_to_node.contains_key(name) {
+            return Err(GraphWorkflowError::AgentNotFound(format!(
+                "Agent '{}' not found",
+                name
+            )));
+        }
+        self.predictors.insert(name.to_owned(), Arc::new(predictor));
+        Ok(())
+    }
+
+    /// Execute the entire workflow starting from a specific agent.
+    ///
+    /// Returns `GraphWorkflowError::Halted` if a node with `FailureAction::Halt` failed, and
+    /// the start agent's error if it failed and no error handler replaced its output. Failures
+    /// of other nodes are in the result map, use `execute_workflow_with_report` to get the
+    /// results of the other nodes in every case.
     pub async fn execute_workflow(
         &mut self,
         start_agent: &str,
         input: impl Into<String>,
     ) -> Result<DashMap<String, Result<String, GraphWorkflowError>>, GraphWorkflowError> {
+        let report = self
+            .execute_workflow_with_report(start_agent, input)
+            .await?;
+        if let Some(failure) = report
+            .failures
+            .into_iter()
+            .find(|failure| failure.action == FailureAction::Halt)
+        {
+            return Err(GraphWorkflowError::Halted(Box::new(failure)));
+        }
+        if let Some(Err(e)) = report.results.get(start_agent).as_deref() {
+            return Err(e.clone());
+        }
+        Ok(report.results)
+    }
+
+    /// Execute the entire workflow starting from a specific agent, and report every node
+    /// which failed after its retries, even if the workflow was halted.
+    pub async fn execute_workflow_with_report(
+        &mut self,
+        start_agent: &str,
+        input: impl Into<String>,
+    ) -> Result<WorkflowReport, GraphWorkflowError> {
         let input = input.into();
+        self.validate_input(&input)?;
 
         let start_idx = self.name_to_node.get(start_agent).ok_or_else(|| {
             GraphWorkflowError::AgentNotFound(format!("Start agent '{}' not found", start_agent))
@@ -214,35 +345,44 @@ impl DAGWorkflow {
             if let Some(node_weight) = self.workflow.node_weight_mut(idx) {
                 let mut last_result = node_weight.last_result.lock().await;
                 *last_result = None;
+                self.node_states.set(&node_weight.name, NodeState::Pending);
             }
         }
 
-        // Create a shared results map for all agents to write to
-        let results = Arc::new(DashMap::new());
         // Create a shared tracking state for the entire workflow
-        let edge_tracker = Arc::new(DashMap::new());
-        let processed_nodes = Arc::new(DashMap::new());
-        // Execute the workflow
-        self.execute_node(
-            *start_idx,
-            input,
-            Arc::clone(&results),
-            edge_tracker,
-            processed_nodes,
-        )
-        .await?;
-        Ok(Arc::into_inner(results).expect("Results should not be poisoned"))
+        let state = ExecutionState::default();
+        // Execute the workflow, a failure of the start agent is part of the report
+        let _ = self.execute_node(*start_idx, input, &state).await;
+
+        let mut failures = state
+            .failures
+            .into_iter()
+            .map(|(_, failure)| failure)
+            .collect::<Vec<_>>();
+        failures.sort_by(|a, b| a.agent.cmp(&b.agent));
+        Ok(WorkflowReport {
+            results: state.results,
+            failures,
+            halted: state.halted.load(Ordering::SeqCst),
+            cached: {
+                let mut cached = state.cached.into_iter().collect::<Vec<_>>();
+                cached.sort();
+                cached
+            },
+            speculation: SpeculationStats {
+                hits: state.speculation_hits.load(Ordering::SeqCst),
+                misses: state.speculation_misses.load(Ordering::SeqCst),
+            },
+        })
     }
 
+    // Execute a node and everything downstream of it, returns the node's result
     async fn execute_node(
         &self,
         node_idx: NodeIndex,
         input: String,
-        results: Arc<DashMap<String, Result<String, GraphWorkflowError>>>,
-        edge_tracker: Arc<DashMap<(NodeIndex, NodeIndex), bool>>,
-        processed_nodes: Arc<DashMap<NodeIndex, Vec<(NodeIndex, String)>>>,
+        state: &ExecutionState,
     ) -> Result<String, GraphWorkflowError> {
-        // Get the agent name from the node
         let agent_name = &self
             .workflow
             .node_weight(node_idx)
@@ -252,20 +392,118 @@ impl DAGWorkflow {
             .name;
 
         // Check if we already have a result for this node (avoid duplicate work)
-        if let Some(entry) = results.get(agent_name) {
+        if let Some(entry) = state.results.get(agent_name) {
             return entry.value().clone();
         }
 
-        // Execute the agent with timeout protection
-        let result = tokio::time::timeout(
-            Duration::from_secs(300), // 5-minute timeout
-            self.execute_agent(agent_name, input),
-        )
-        .await
-        .map_err(|_| GraphWorkflowError::Timeout(agent_name.clone()))?;
+        // Kahn-style scheduling without recursion: a node becomes ready once all its
+        // incoming branches (or the quorum of an aggregation node) delivered their outputs,
+        // the ready nodes run concurrently
+        state.triggered.insert(node_idx);
+        let mut running = FuturesUnordered::new();
+        running.push(self.run_node(node_idx, input, state));
+        while let Some((source_idx, branches)) = running.next().await {
+            for (target_idx, next_input) in branches {
+                if let Some(input) = self.deliver(source_idx, target_idx, next_input, state) {
+                    running.push(self.run_node(target_idx, input, state));
+                }
+            }
+        }
+
+        state
+            .results
+            .get(agent_name)
+            .map_or(Err(GraphWorkflowError::Canceled), |entry| {
+                entry.value().clone()
+            })
+    }
+
+    // Run a single node, returns the targets of its taken flows with their inputs
+    async fn run_node(
+        &self,
+        node_idx: NodeIndex,
+        input: String,
+        state: &ExecutionState,
+    ) -> (NodeIndex, Vec<(NodeIndex, String)>) {
+        let Some(node) = self.workflow.node_weight(node_idx) else {
+            return (node_idx, vec![]);
+        };
+        let agent_name = &node.name;
+        if state.halted.load(Ordering::SeqCst) {
+            return (node_idx, vec![]);
+        }
+
+        self.node_states.set(agent_name, NodeState::Running);
+        let policy = self.policies.get(agent_name).cloned().unwrap_or_default();
+        let (mut result, attempts) = match self.aggregators.get(agent_name) {
+            Some(aggregator) => {
+                let inputs = state
+                    .processed_nodes
+                    .get(&node_idx)
+                    .map(|inputs| inputs.value().clone())
+                    .unwrap_or_default();
+                self.aggregate(aggregator, inputs, &policy).await
+            }
+            None => match state.speculated.remove(&node_idx) {
+                Some((_, result)) => result,
+                None => {
+                    self.execute_agent_node(agent_name, &input, &policy, state)
+                        .await
+                }
+            },
+        };
+
+        if let Err(e) = &result {
+            tracing::error!(
+                "Agent '{}' execution failed after {} attempts: {:?}",
+                agent_name,
+                attempts,
+                e
+            );
+            let mut failure = NodeFailure {
+                agent: agent_name.clone(),
+                attempts,
+                error: e.clone(),
+                action: policy.on_failure.clone(),
+                handled: false,
+                skipped: vec![],
+            };
+            match &policy.on_failure {
+                FailureAction::Halt => state.halted.store(true, Ordering::SeqCst),
+                FailureAction::SkipSubtree => {}
+                FailureAction::ErrorHandler(handler) => {
+                    let handler_input = format!(
+                        "Agent '{}' failed after {} attempts with error: {}\n\nIts input was:\n{}",
+                        agent_name, attempts, e, input
+                    );
+                    match self.execute_agent(handler, handler_input).await {
+                        Ok(output) => {
+                            failure.handled = true;
+                            result = Ok(output);
+                        }
+                        Err(e) => {
+                            tracing::error!("Error handler '{}' failed: {:?}", handler, e);
+                        }
+                    }
+                }
+            }
+            if !failure.handled {
+                failure.skipped = self.descendants(node_idx);
+            }
+            state.failures.insert(agent_name.clone(), failure);
+        }
+
+        let node_state = match &result {
+            Ok(_) => NodeState::Done,
+            Err(_) => NodeState::Failed,
+        };
+        self.node_states.set(agent_name, node_state);
 
         // Store the result
-        results.entry(agent_name.clone()).or_insert(result.clone());
+        state
+            .results
+            .entry(agent_name.clone())
+            .or_insert(result.clone());
 
         // Update the node's last result
         if let Some(node_weight) = self.workflow.node_weight(node_idx) {
@@ -273,101 +511,495 @@ impl DAGWorkflow {
             *last_result = Some(result.clone());
         }
 
-        // If successful, propagate to connected agents
-        match &result {
-            Ok(output) => {
-                // Find all outgoing edges that pass the condition (if any)
-                let valid_edges = self
-                    .workflow
-                    .edges_directed(node_idx, Direction::Outgoing)
-                    .filter(|edge| {
-                        edge.weight()
-                            .condition
-                            .as_ref()
-                            .map(|cond| cond(output))
-                            .unwrap_or(true) // if no condition, always execute
-                    })
-                    .collect::<Vec<_>>();
+        // If successful, find the flows to the connected agents which are taken
+        let Ok(output) = &result else {
+            return (node_idx, vec![]);
+        };
+        let edges = self
+            .workflow
+            .edges_directed(node_idx, Direction::Outgoing)
+            .collect::<Vec<_>>();
+        let conditions = future::join_all(edges.iter().map(|edge| edge.weight().is_taken(output)));
+        let taken = match self.predict_branch(agent_name, output, &edges) {
+            Some(index) => {
+                let input =
+                    self.node_input(&[(node_idx, edges[index].weight().next_input(output))]);
+                let target = edges[index].target();
+                let target_name = &self.workflow[target].name;
+                tracing::debug!("Speculatively executing agent '{}'", target_name);
+                let policy = self.policies.get(target_name).cloned().unwrap_or_default();
+                let run = self.execute_agent_node(target_name, &input, &policy, state);
+                let is_hit = |taken: &Vec<bool>| {
+                    edges
+                        .iter()
+                        .zip(taken)
+                        .any(|(edge, taken)| *taken && edge.target() == target)
+                };
+                let (taken, result) = race_speculation(conditions, run, is_hit).await;
+                match result {
+                    Some(result) => {
+                        state.speculated.insert(target, result);
+                        state.speculation_hits.fetch_add(1, Ordering::SeqCst);
+                    }
+                    None => {
+                        tracing::debug!("Cancelled speculative run of agent '{}'", target_name);
+                        state.speculation_misses.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+                taken
+            }
+            None => conditions.await,
+        };
+        let branches = edges
+            .into_iter()
+            .zip(taken)
+            .filter(|(_, taken)| *taken)
+            .map(|(edge, _)| (edge.target(), edge.weight().next_input(output)))
+            .collect();
+        (node_idx, branches)
+    }
+
+    // Record the output a flow delivered to its target, returns the target's input once
+    // enough of its incoming flows delivered, only the first time
+    fn deliver(
+        &self,
+        source_idx: NodeIndex,
+        target_idx: NodeIndex,
+        input: String,
+        state: &ExecutionState,
+    ) -> Option<String> {
+        state.edge_tracker.insert((source_idx, target_idx), true);
+        state
+            .processed_nodes
+            .entry(target_idx)
+            .or_default()
+            .push((source_idx, input));
+
+        let incoming_edges = self
+            .workflow
+            .edges_directed(target_idx, Direction::Incoming)
+            .map(|e| (e.source(), target_idx))
+            .collect::<Vec<_>>();
+        let processed = incoming_edges
+            .iter()
+            .filter(|edge| state.edge_tracker.contains_key(edge))
+            .count();
+        let required = self
+            .workflow
+            .node_weight(target_idx)
+            .and_then(|node| self.aggregators.get(&node.name))
+            .and_then(|aggregator| aggregator.quorum)
+            .map_or(incoming_edges.len(), |quorum| {
+                quorum.min(incoming_edges.len())
+            });
+        if processed < required || !state.triggered.insert(target_idx) {
+            return None;
+        }
+        state
+            .processed_nodes
+            .get(&target_idx)
+            .map(|inputs| self.node_input(inputs.value()))
+    }
+
+    /// The input of a node, the outputs of its incoming branches as `[From <agent>] <output>`
+    /// lines.
+    fn node_input(&self, inputs: &[(NodeIndex, String)]) -> String {
+        inputs
+            .iter()
+            .map(|(source_idx, input)| {
+                format!("[From {}] {}\n", self.workflow[*source_idx].name, input)
+            })
+            .collect()
+    }
+
+    /// The index of the edge the node's branch predictor expects to be taken, if its target
+    /// can be executed speculatively.
+    fn predict_branch(
+        &self,
+        name: &str,
+        output: &str,
+        edges: &[EdgeReference<'_, Flow>],
+    ) -> Option<usize> {
+        let target_name = self.predictors.get(name)?(output)?;
+        let index = edges
+            .iter()
+            .position(|edge| self.workflow[edge.target()].name == target_name)?;
+        let edge = &edges[index];
+        let single_input = self
+            .workflow
+            .edges_directed(edge.target(), Direction::Incoming)
+            .count()
+            == 1;
+        let passes_condition = edge
+            .weight()
+            .condition
+            .as_ref()
+            .is_none_or(|condition| condition(output));
+        (single_input && passes_condition && self.agents.contains_key(&target_name))
+            .then_some(index)
+    }
+
+    /// Run the agent of a node, or reuse its cached output for the same input.
+    async fn execute_agent_node(
+        &self,
+        name: &str,
+        input: &str,
+        policy: &NodePolicy,
+        state: &ExecutionState,
+    ) -> (Result<String, GraphWorkflowError>, u32) {
+        let Some(cache) = self.node_caches.get(name) else {
+            return self.execute_agent_with_retries(name, input, policy).await;
+        };
+        let mut hasher = XxHash3_64::default();
+        input.hash(&mut hasher);
+        let key = (name.to_owned(), hasher.finish());
+
+        if let Some(output) = self.cached_output(cache, &key).await {
+            tracing::debug!("Reusing the cached output of agent '{}'", name);
+            state.cached.insert(name.to_owned());
+            return (Ok(output), 0);
+        }
+        let (result, attempts) = self.execute_agent_with_retries(name, input, policy).await;
+        if let Ok(output) = &result {
+            let cached = CachedOutput {
+                output: output.clone(),
+                created_at: Utc::now(),
+            };
+            if let Some((persistence, dir)) = &cache.persistence {
+                let saved = match persistence::to_versioned_json(&cached) {
+                    Ok(json) => {
+                        persistence
+                            .save(&cache_path(dir, &key), json.into_bytes())
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = saved {
+                    tracing::warn!("Failed to save the output of agent '{}': {}", name, e);
+                }
+            }
+            self.memo.insert(key, cached);
+        }
+        (result, attempts)
+    }
+
+    /// The cached output of the key if it has not expired, from memory or else from the
+    /// cache's persistence.
+    async fn cached_output(&self, cache: &NodeCache, key: &(String, u64)) -> Option<String> {
+        let fresh = |cached: &CachedOutput| {
+            cache.ttl.is_none_or(|ttl| {
+                (Utc::now() - cached.created_at)
+                    .to_std()
+                    .is_ok_and(|age| age < ttl)
+            })
+        };
+        if let Some(cached) = self.memo.get(key)
+            && fresh(&cached)
+        {
+            return Some(cached.output.clone());
+        }
+        self.memo.remove(key);
 
-                let mut futures = Vec::new();
+        let (persistence, dir) = cache.persistence.as_ref()?;
+        let cached = match persistence.load(&cache_path(dir, key)).await {
+            Ok(data) => {
+                persistence::from_versioned_json::<CachedOutput>(&data, CachedOutput::MIGRATIONS)
+            }
+            Err(e) => Err(e),
+        };
+        match cached {
+            Ok(cached) if fresh(&cached) => {
+                let output = cached.output.clone();
+                self.memo.insert(key.clone(), cached);
+                Some(output)
+            }
+            Ok(_) => None,
+            Err(PersistenceError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to load the cached output of agent '{}': {}",
+                    key.0,
+                    e
+                );
+                None
+            }
+        }
+    }
 
-                for edge in valid_edges {
-                    let source_node = node_idx;
-                    let target_node = edge.target();
-                    let flow = edge.weight().clone();
-                    let results_clone = Arc::clone(&results);
-                    let processed_nodes_clone = Arc::clone(&processed_nodes);
-                    let edge_tracker_clone = Arc::clone(&edge_tracker);
+    /// Run the agent until it succeeds or the policy's retries are used up,
+    /// returns the last result and the number of attempts.
+    async fn execute_agent_with_retries(
+        &self,
+        name: &str,
+        input: &str,
+        policy: &NodePolicy,
+    ) -> (Result<String, GraphWorkflowError>, u32) {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            // Execute the agent with timeout protection
+            let result = tokio::time::timeout(
+                Duration::from_secs(300), // 5-minute timeout
+                self.execute_agent(name, input.to_owned()),
+            )
+            .await
+            .unwrap_or_else(|_| Err(GraphWorkflowError::Timeout(name.to_owned())));
+
+            let retryable = match &result {
+                Ok(_) => return (result, attempt),
+                Err(GraphWorkflowError::AgentError(e)) => e.is_retryable(),
+                Err(GraphWorkflowError::Timeout(_)) => true,
+                Err(_) => false,
+            };
+            if !retryable || attempt > policy.max_retries {
+                return (result, attempt);
+            }
+
+            let backoff = policy.retry_backoff(attempt);
+            tracing::warn!(
+                "Agent '{}' failed, retry {}/{} in {:?}",
+                name,
+                attempt,
+                policy.max_retries,
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
 
-                    let future = async move {
-                        // Apply transformation if any
-                        let next_input = flow
+    /// Combine the outputs of the incoming branches of an aggregation node,
+    /// returns the result and the number of attempts.
+    async fn aggregate(
+        &self,
+        aggregator: &Aggregator,
+        mut inputs: Vec<(NodeIndex, String)>,
+        policy: &NodePolicy,
+    ) -> (Result<String, GraphWorkflowError>, u32) {
+        // Only the branches which made the quorum are joined
+        if let Some(quorum) = aggregator.quorum {
+            inputs.truncate(quorum);
+        }
+        let mut outputs = inputs
+            .into_iter()
+            .filter_map(|(source_idx, output)| {
+                let source_name = self.workflow.node_weight(source_idx)?.name.clone();
+                Some((source_name, output))
+            })
+            .collect::<Vec<_>>();
+        // Arrival order is not deterministic
+        outputs.sort_by(|a, b| a.0.cmp(&b.0));
+        let concatenated = outputs
+            .iter()
+            .map(|(source_name, output)| format!("[From {}] {}\n", source_name, output))
+            .collect::<String>();
+
+        match &aggregator.strategy {
+            AggregationStrategy::Concat => (Ok(concatenated), 1),
+            AggregationStrategy::Template(template) => {
+                let mut joined = template.replace("{inputs}", &concatenated);
+                for (source_name, output) in &outputs {
+                    joined = joined.replace(&format!("{{{}}}", source_name), output);
+                }
+                (Ok(joined), 1)
+            }
+            AggregationStrategy::Agent(agent) => {
+                self.execute_agent_with_retries(agent, &concatenated, policy)
+                    .await
+            }
+        }
+    }
+
+    // Names of all nodes reachable from the node, excluding itself
+    fn descendants(&self, node_idx: NodeIndex) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut dfs = petgraph::visit::Dfs::new(&self.workflow, node_idx);
+        while let Some(idx) = dfs.next(&self.workflow) {
+            if idx != node_idx
+                && let Some(node) = self.workflow.node_weight(idx)
+            {
+                names.push(node.name.clone());
+            }
+        }
+        names.sort();
+        names
+    }
+
+    fn validate_input(&self, input: &str) -> Result<(), GraphWorkflowError> {
+        if self.name_to_node.is_empty() || is_empty_task(input) {
+            return Err(GraphWorkflowError::EmptyTasksOrAgents);
+        }
+        Ok(())
+    }
+
+    /// Report the agents the workflow would run from the start agent, without calling any LLM.
+    ///
+    /// Conditions of flows can't be evaluated without outputs, every flow is assumed to be
+    /// taken, transformations are applied to the output placeholders.
+    pub fn dry_run(
+        &self,
+        start_agent: &str,
+        input: impl Into<String>,
+    ) -> Result<DryRunReport, GraphWorkflowError> {
+        let input = input.into();
+        self.validate_input(&input)?;
+        let start_idx = *self.name_to_node.get(start_agent).ok_or_else(|| {
+            GraphWorkflowError::AgentNotFound(format!("Start agent '{}' not found", start_agent))
+        })?;
+
+        let mut reachable = HashSet::new();
+        let mut dfs = petgraph::visit::Dfs::new(&self.workflow, start_idx);
+        while let Some(idx) = dfs.next(&self.workflow) {
+            reachable.insert(idx);
+        }
+        let order = petgraph::algo::toposort(&self.workflow, None)
+            .map_err(|_| GraphWorkflowError::CycleDetected)?;
+
+        let mut steps = Vec::new();
+        for node_idx in order.into_iter().filter(|idx| reachable.contains(idx)) {
+            let name = &self.workflow[node_idx].name;
+            let node_input = if node_idx == start_idx {
+                input.clone()
+            } else {
+                self.workflow
+                    .edges_directed(node_idx, Direction::Incoming)
+                    .filter(|edge| reachable.contains(&edge.source()))
+                    .map(|edge| {
+                        let source_name = &self.workflow[edge.source()].name;
+                        let output = dry_run::output_placeholder(source_name);
+                        let output = edge
+                            .weight()
                             .transform
                             .as_ref()
                             .map_or_else(|| output.clone(), |transform| transform(output.clone()));
+                        format!("[From {}] {}\n", source_name, output)
+                    })
+                    .collect()
+            };
+
+            let agent_name = match self.aggregators.get(name).map(|a| &a.strategy) {
+                Some(AggregationStrategy::Agent(agent)) => agent,
+                // Joined without an LLM
+                Some(_) => continue,
+                None => name,
+            };
+            if let Some(agent) = self.agents.get(agent_name) {
+                steps.push(agent.dry_run(node_input));
+            }
+        }
 
-                        // mark this edge as processed
-                        edge_tracker_clone.insert((source_node, target_node), true);
-
-                        // record the input for this node
-                        processed_nodes_clone
-                            .entry(target_node)
-                            .or_default()
-                            .push((source_node, next_input));
-
-                        // check if all incoming edges have been processed
-                        // if yes, then we can execute the target node
-                        let incoming_edges = self
-                            .workflow
-                            .edges_directed(target_node, Direction::Incoming)
-                            .map(|e| (e.source(), target_node))
-                            .collect::<Vec<_>>();
-
-                        let all_processed = incoming_edges
-                            .iter()
-                            .all(|edge| edge_tracker_clone.contains_key(edge));
-
-                        // only execute if all incoming edges have been processed
-                        if all_processed {
-                            let mut aggregated_input = String::new();
-                            if let Some(inputs) = processed_nodes_clone.get(&target_node) {
-                                for (source_idx, input) in inputs.value() {
-                                    let source_name =
-                                        &self.workflow.node_weight(*source_idx).unwrap().name;
-                                    aggregated_input
-                                        .push_str(&format!("[From {}] {}\n", source_name, input));
-                                }
-                            }
-
-                            // execute the target node with the aggregated input
-                            if let Err(e) = self
-                                .execute_node(
-                                    target_node,
-                                    aggregated_input,
-                                    results_clone,
-                                    edge_tracker_clone,
-                                    processed_nodes_clone,
-                                )
-                                .await
-                            {
-                                tracing::error!("Failed to execute node: {:?}", e);
-                            }
-                        }
-                    };
+        Ok(DryRunReport {
+            workflow: self.name.clone(),
+            steps,
+        })
+    }
 
-                    futures.push(future);
-                }
+    /// The structure of the workflow: its nodes with their aggregators and policies, and
+    /// its flows with the names of their functions.
+    ///
+    /// Fails if a flow has a function which isn't named, see [`FlowRegistry`]. Branch
+    /// predictors and node caches are not part of the structure.
+    pub fn spec(&self) -> Result<WorkflowSpec, GraphWorkflowError> {
+        let mut nodes = self
+            .workflow
+            .node_weights()
+            .map(|node| NodeSpec {
+                name: node.name.clone(),
+                aggregator: self.aggregators.get(&node.name).cloned(),
+                policy: self.policies.get(&node.name).cloned(),
+            })
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut edges = Vec::new();
+        for edge in self.workflow.edge_references() {
+            let from = &self.workflow[edge.source()].name;
+            let to = &self.workflow[edge.target()].name;
+            let flow = edge.weight();
+            let unnamed = [
+                ("condition", flow.condition.is_some(), &flow.spec.condition),
+                (
+                    "async condition",
+                    flow.async_condition.is_some(),
+                    &flow.spec.async_condition,
+                ),
+                ("transform", flow.transform.is_some(), &flow.spec.transform),
+            ]
+            .into_iter()
+            .find(|(_, set, name)| *set && name.is_none());
+            if let Some((function, ..)) = unnamed {
+                return Err(GraphWorkflowError::UnregisteredFunction(format!(
+                    "{} of the flow from '{}' to '{}' has no name",
+                    function, from, to
+                )));
+            }
+            edges.push(EdgeSpec {
+                from: from.clone(),
+                to: to.clone(),
+                flow: flow.spec.clone(),
+            });
+        }
+        edges.sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+
+        Ok(WorkflowSpec {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            nodes,
+            edges,
+        })
+    }
 
-                // Execute connected agents concurrently
-                futures::future::join_all(futures).await; // TODO: may use another way which can handle errors
+    /// Build the workflow of a structure, the agents are bound to the nodes by name and the
+    /// flow functions are looked up in the registry.
+    ///
+    /// Fails if the agent of a node or a function is missing. Agents which aren't part of
+    /// the workflow are ignored.
+    pub fn from_spec(
+        spec: WorkflowSpec,
+        agents: Vec<Box<dyn Agent>>,
+        registry: &FlowRegistry,
+    ) -> Result<Self, GraphWorkflowError> {
+        let mut workflow = Self::new(spec.name, spec.description);
+        let mut agents = agents
+            .into_iter()
+            .map(|agent| (agent.name(), agent))
+            .collect::<HashMap<_, _>>();
+        // Agents first, aggregators can refer to them
+        for node in spec.nodes.iter().filter(|node| node.aggregator.is_none()) {
+            let agent = agents.remove(&node.name).ok_or_else(|| {
+                GraphWorkflowError::AgentNotFound(format!("Agent '{}' not found", node.name))
+            })?;
+            workflow.register_agent(agent);
+        }
+        for node in spec.nodes {
+            if let Some(aggregator) = node.aggregator {
+                workflow.add_aggregator(node.name.clone(), aggregator)?;
             }
-            Err(e) => {
-                tracing::error!("Agent '{}' execution failed: {:?}", agent_name, e);
-                // TODO: maybe we need to propagate the error to the caller?
+            if let Some(policy) = node.policy {
+                workflow.set_node_policy(&node.name, policy)?;
             }
         }
+        for edge in spec.edges {
+            let flow = registry.flow(edge.flow)?;
+            workflow.connect_agents(&edge.from, &edge.to, flow)?;
+        }
+        Ok(workflow)
+    }
+
+    /// The structure of the workflow as versioned JSON, see [`DAGWorkflow::spec`].
+    pub fn to_json(&self) -> Result<String, GraphWorkflowError> {
+        persistence::to_versioned_json(&self.spec()?)
+            .map_err(|e| GraphWorkflowError::PersistenceError(Arc::new(e)))
+    }
 
-        result
+    /// Build a workflow saved with [`DAGWorkflow::to_json`], see [`DAGWorkflow::from_spec`].
+    pub fn from_json(
+        json: &str,
+        agents: Vec<Box<dyn Agent>>,
+        registry: &FlowRegistry,
+    ) -> Result<Self, GraphWorkflowError> {
+        let spec = persistence::from_versioned_json(json.as_bytes(), WorkflowSpec::MIGRATIONS)
+            .map_err(|e| GraphWorkflowError::PersistenceError(Arc::new(e)))?;
+        Self::from_spec(spec, agents, registry)
     }
 
     // Get the current workflow as a visualization-friendly format
@@ -398,6 +1030,13 @@ impl DAGWorkflow {
         structure
     }
 
+    /// Live states of the nodes, updated while the workflow executes.
+    ///
+    /// The tracker is shared, clones keep receiving the updates of later executions.
+    pub fn node_states(&self) -> NodeStateTracker {
+        self.node_states.clone()
+    }
+
     // Export the workflow to a format that can be visualized (e.g., DOT format for Graphviz)
     pub fn export_workflow_dot(&self) -> String {
         // TODO: can use petgraph's built-in dot
@@ -408,10 +1047,8 @@ impl DAGWorkflow {
         // Add nodes
         for node_idx in self.workflow.node_indices() {
             if let Some(node) = self.workflow.node_weight(node_idx) {
-                dot.push_str(&format!(
-                    "    \"{}\" [label=\"{}\"];\n",
-                    node.name, node.name
-                ));
+                let id = dot_id(&node.name);
+                dot.push_str(&format!("    {id} [label={id}];\n"));
             }
         }
 
@@ -423,8 +1060,9 @@ impl DAGWorkflow {
                     self.workflow.node_weight(target),
                 ) {
                     dot.push_str(&format!(
-                        "    \"{}\" -> \"{}\";\n",
-                        source_node.name, target_node.name
+                        "    {} -> {};\n",
+                        dot_id(&source_node.name),
+                        dot_id(&target_node.name)
                     ));
                 }
             }
@@ -498,49 +1136,88 @@ impl DAGWorkflow {
     ///
     /// Example: vec![vec!["A", "B", "C"], vec!["X", "Y"]]
     pub fn detect_potential_deadlocks(&self) -> Vec<Vec<String>> {
-        // Build a dependency graph where an edge A→B means B depends on A
-        let mut dependency_graph = petgraph::Graph::<String, ()>::new();
-        let mut node_map = HashMap::new();
-
-        // Create nodes
-        for name in self.name_to_node.keys() {
-            let idx = dependency_graph.add_node(name.clone());
-            node_map.insert(name.clone(), idx);
-        }
-
-        // Add dependencies
-        for node_idx in self.workflow.node_indices() {
-            if let Some(node) = self.workflow.node_weight(node_idx) {
-                let target_dep_idx = *node_map.get(&node.name).unwrap();
-
-                // Add an edge for each incoming connection
-                for source in self
-                    .workflow
-                    .neighbors_directed(node_idx, Direction::Incoming)
-                {
-                    if let Some(source_node) = self.workflow.node_weight(source) {
-                        let source_dep_idx = *node_map.get(&source_node.name).unwrap();
-                        dependency_graph.add_edge(source_dep_idx, target_dep_idx, ());
-                    }
-                }
-            }
-        }
-
-        // Find strongly connected components (cycles in the dependency graph)
-        let sccs = petgraph::algo::kosaraju_scc(&dependency_graph);
-
-        // Return only the non-trivial SCCs (size > 1)
-        sccs.into_iter()
+        // Strongly connected components with more than one node are cycles
+        petgraph::algo::kosaraju_scc(&self.workflow)
+            .into_iter()
             .filter(|scc| scc.len() > 1)
             .map(|scc| {
                 scc.into_iter()
-                    .map(|idx| dependency_graph[idx].clone())
+                    .map(|idx| self.workflow[idx].name.clone())
                     .collect()
             })
             .collect()
     }
 }
 
+// A node name as a quoted DOT ID, names may contain quotes and backslashes
+pub(crate) fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Execution state of a node, nodes which were not reached stay pending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A change of a node's state.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct NodeStateEvent {
+    pub node: String,
+    pub state: NodeState,
+}
+
+/// Current states of a workflow's nodes, with a stream of their changes.
+#[derive(Clone, Debug)]
+pub struct NodeStateTracker {
+    states: Arc<DashMap<String, NodeState>>,
+    tx: broadcast::Sender<NodeStateEvent>,
+}
+
+impl NodeStateTracker {
+    /// Number of changes a slow subscriber can fall behind before it misses some.
+    const CAPACITY: usize = 256;
+
+    fn new() -> Self {
+        Self {
+            states: Arc::new(DashMap::new()),
+            tx: broadcast::channel(Self::CAPACITY).0,
+        }
+    }
+
+    /// The node's state, `Pending` if the workflow has not run yet.
+    pub fn get(&self, node: &str) -> NodeState {
+        self.states
+            .get(node)
+            .map_or(NodeState::Pending, |state| *state)
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, NodeState> {
+        self.states
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    /// Receive every later change of a node's state.
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeStateEvent> {
+        self.tx.subscribe()
+    }
+
+    fn set(&self, node: &str, state: NodeState) {
+        self.states.insert(node.to_owned(), state);
+        // No subscribers is fine
+        let _ = self.tx.send(NodeStateEvent {
+            node: node.to_owned(),
+            state,
+        });
+    }
+}
+
 // Edge weight to represent the flow of data between agents
 #[allow(clippy::type_complexity)]
 #[derive(Clone, Default)]
@@ -549,6 +1226,394 @@ pub struct Flow {
     pub transform: Option<Arc<dyn Fn(String) -> String + Send + Sync>>,
     // Optional condition to determine if this flow should be taken
     pub condition: Option<Arc<dyn Fn(&str) -> bool + Send + Sync>>,
+    // Optional condition which takes a while to evaluate, e.g. asks an LLM. It's evaluated
+    // after `condition`, the flow is taken if both pass
+    pub async_condition: Option<Arc<dyn Fn(String) -> BoxFuture<'static, bool> + Send + Sync>>,
+    // Names of the functions, set by `FlowRegistry::flow`, needed to save the workflow
+    pub spec: FlowSpec,
+}
+
+impl Flow {
+    // The input of the flow's target
+    fn next_input(&self, output: &str) -> String {
+        self.transform.as_ref().map_or_else(
+            || output.to_owned(),
+            |transform| transform(output.to_owned()),
+        )
+    }
+
+    // Whether the output passes both conditions, flows without conditions are always taken
+    async fn is_taken(&self, output: &str) -> bool {
+        if let Some(condition) = &self.condition
+            && !condition(output)
+        {
+            return false;
+        }
+        match &self.async_condition {
+            Some(condition) => condition(output.to_owned()).await,
+            None => true,
+        }
+    }
+}
+
+/// Memoization of an agent node's outputs, keyed by the hash of the node's input, see
+/// [`DAGWorkflow::set_node_cache`].
+///
+/// Outputs are kept in memory for later runs of the same workflow, and in a [`Persistence`]
+/// backend across restarts if one is set.
+#[derive(Clone, Default)]
+pub struct NodeCache {
+    ttl: Option<Duration>,
+    persistence: Option<(Arc<dyn Persistence>, PathBuf)>,
+}
+
+impl NodeCache {
+    /// Outputs never expire and are kept in memory only by default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expire outputs `ttl` after they were cached.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Save the outputs in the directory of the backend, e.g.
+    /// [`FilePersistence`](crate::FilePersistence), so they outlive the process.
+    pub fn persistent(
+        mut self,
+        persistence: impl Persistence + 'static,
+        dir: impl Into<PathBuf>,
+    ) -> Self {
+        self.persistence = Some((Arc::new(persistence), dir.into()));
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedOutput {
+    output: String,
+    created_at: DateTime<Utc>,
+}
+
+impl CachedOutput {
+    /// Upgrades of saved outputs, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+}
+
+// `<dir>/<agent>/<input hash>.json`
+fn cache_path(dir: &std::path::Path, (name, hash): &(String, u64)) -> PathBuf {
+    dir.join(name).join(format!("{hash:016x}.json"))
+}
+
+type Transform = Arc<dyn Fn(String) -> String + Send + Sync>;
+type Condition = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+type AsyncCondition = Arc<dyn Fn(String) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// The names of the registered functions of a flow, see [`FlowRegistry`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlowSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub async_condition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<String>,
+}
+
+impl FlowSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn condition(mut self, name: impl Into<String>) -> Self {
+        self.condition = Some(name.into());
+        self
+    }
+
+    pub fn async_condition(mut self, name: impl Into<String>) -> Self {
+        self.async_condition = Some(name.into());
+        self
+    }
+
+    pub fn transform(mut self, name: impl Into<String>) -> Self {
+        self.transform = Some(name.into());
+        self
+    }
+}
+
+/// Conditions and transformations of flows by name.
+///
+/// Functions can't be saved, a saved workflow refers to them by name and they are looked
+/// up in the registry when the workflow is loaded. Flows built by [`FlowRegistry::flow`]
+/// know the names of their functions, so workflows connected with them can be saved.
+#[derive(Clone, Default)]
+pub struct FlowRegistry {
+    conditions: HashMap<String, Condition>,
+    async_conditions: HashMap<String, AsyncCondition>,
+    transforms: HashMap<String, Transform>,
+}
+
+impl FlowRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn condition(
+        mut self,
+        name: impl Into<String>,
+        condition: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.conditions.insert(name.into(), Arc::new(condition));
+        self
+    }
+
+    pub fn async_condition(
+        mut self,
+        name: impl Into<String>,
+        condition: impl Fn(String) -> BoxFuture<'static, bool> + Send + Sync + 'static,
+    ) -> Self {
+        self.async_conditions
+            .insert(name.into(), Arc::new(condition));
+        self
+    }
+
+    pub fn transform(
+        mut self,
+        name: impl Into<String>,
+        transform: impl Fn(String) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.transforms.insert(name.into(), Arc::new(transform));
+        self
+    }
+
+    /// The flow with the named functions, fails if one of them isn't registered.
+    pub fn flow(&self, spec: FlowSpec) -> Result<Flow, GraphWorkflowError> {
+        fn lookup<T: Clone>(
+            functions: &HashMap<String, T>,
+            name: &Option<String>,
+        ) -> Result<Option<T>, GraphWorkflowError> {
+            name.as_ref()
+                .map(|name| {
+                    functions.get(name).cloned().ok_or_else(|| {
+                        GraphWorkflowError::UnregisteredFunction(format!("'{}'", name))
+                    })
+                })
+                .transpose()
+        }
+
+        Ok(Flow {
+            transform: lookup(&self.transforms, &spec.transform)?,
+            condition: lookup(&self.conditions, &spec.condition)?,
+            async_condition: lookup(&self.async_conditions, &spec.async_condition)?,
+            spec,
+        })
+    }
+}
+
+/// The structure of a [`DAGWorkflow`], see [`DAGWorkflow::spec`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowSpec {
+    pub name: String,
+    pub description: String,
+    /// Sorted by name.
+    pub nodes: Vec<NodeSpec>,
+    /// Sorted by source and target.
+    pub edges: Vec<EdgeSpec>,
+}
+
+impl WorkflowSpec {
+    /// Upgrades of saved workflows, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+}
+
+/// A node of a [`WorkflowSpec`], an agent by name or an aggregator.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeSpec {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregator: Option<Aggregator>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<NodePolicy>,
+}
+
+/// A flow of a [`WorkflowSpec`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EdgeSpec {
+    pub from: String,
+    pub to: String,
+    #[serde(flatten)]
+    pub flow: FlowSpec,
+}
+
+/// Picks the target agent of the flow a node's output likely takes, see
+/// [`DAGWorkflow::set_branch_predictor`].
+pub type BranchPredictor = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Await the conditions while the speculative run goes on. The run is finished if `keep`
+/// accepts the conditions' result, and cancelled otherwise.
+async fn race_speculation<T, R>(
+    conditions: impl Future<Output = T>,
+    run: impl Future<Output = R>,
+    keep: impl FnOnce(&T) -> bool,
+) -> (T, Option<R>) {
+    match future::select(pin!(conditions), pin!(run)).await {
+        Either::Left((taken, run)) => {
+            let result = if keep(&taken) { Some(run.await) } else { None };
+            (taken, result)
+        }
+        Either::Right((result, conditions)) => {
+            let taken = conditions.await;
+            let result = keep(&taken).then_some(result);
+            (taken, result)
+        }
+    }
+}
+
+/// How an aggregation node combines the outputs of its incoming branches.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AggregationStrategy {
+    /// Join the outputs as `[From <agent>] <output>` lines, ordered by agent name.
+    Concat,
+    /// Fill a template, `{<agent>}` is replaced by that agent's output and `{inputs}` by
+    /// the concatenated outputs.
+    Template(String),
+    /// Run the named agent with the concatenated outputs, its answer is the node's output.
+    Agent(String),
+}
+
+/// An aggregation node, which waits for all or `quorum` of its incoming branches.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Aggregator {
+    pub strategy: AggregationStrategy,
+    /// Number of branches to wait for, `None` waits for all of them.
+    /// Outputs of branches arriving after the quorum are ignored.
+    pub quorum: Option<usize>,
+}
+
+impl Aggregator {
+    pub fn new(strategy: AggregationStrategy) -> Self {
+        Self {
+            strategy,
+            quorum: None,
+        }
+    }
+
+    pub fn quorum(mut self, quorum: usize) -> Self {
+        self.quorum = Some(quorum);
+        self
+    }
+}
+
+/// What happens to the rest of the workflow when a node fails after its retries.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailureAction {
+    /// Stop the workflow, no further nodes are started.
+    Halt,
+    /// Skip the nodes depending on the failed node, other branches keep running.
+    #[default]
+    SkipSubtree,
+    /// Send the error and the node's input to the named agent, its output replaces the
+    /// failed node's output. If the handler fails too, the subtree is skipped.
+    ErrorHandler(String),
+}
+
+/// Retry and failure policy of a node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodePolicy {
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled after each retry up to a minute.
+    pub backoff: Duration,
+    pub on_failure: FailureAction,
+}
+
+impl Default for NodePolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff: Duration::from_secs(1),
+            on_failure: FailureAction::default(),
+        }
+    }
+}
+
+impl NodePolicy {
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn on_failure(mut self, on_failure: FailureAction) -> Self {
+        self.on_failure = on_failure;
+        self
+    }
+
+    // Delay before the given retry, doubling from `backoff` up to `MAX_RETRY_BACKOFF`
+    fn retry_backoff(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_RETRY_BACKOFF.max(self.backoff))
+    }
+}
+
+/// A node which failed after all its retries.
+#[derive(Clone, Debug)]
+pub struct NodeFailure {
+    pub agent: String,
+    pub attempts: u32,
+    /// The error of the last attempt.
+    pub error: GraphWorkflowError,
+    pub action: FailureAction,
+    /// Whether the error handler agent recovered from the failure.
+    pub handled: bool,
+    /// Agents which were skipped because they depend on the failed node.
+    pub skipped: Vec<String>,
+}
+
+/// Results and failures of a workflow execution.
+#[derive(Debug)]
+pub struct WorkflowReport {
+    pub results: DashMap<String, Result<String, GraphWorkflowError>>,
+    /// Failed nodes, sorted by agent name.
+    pub failures: Vec<NodeFailure>,
+    /// Whether a node with [`FailureAction::Halt`] stopped the workflow.
+    pub halted: bool,
+    /// Agents whose output was reused from their [`NodeCache`], sorted by name.
+    pub cached: Vec<String>,
+    pub speculation: SpeculationStats,
+}
+
+/// How often the branch predictors were right, see [`DAGWorkflow::set_branch_predictor`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpeculationStats {
+    /// Speculative runs whose flow was taken, their results were kept.
+    pub hits: usize,
+    /// Speculative runs whose flow was not taken, they were cancelled.
+    pub misses: usize,
+}
+
+// Shared tracking state of one workflow execution
+#[derive(Default)]
+struct ExecutionState {
+    results: DashMap<String, Result<String, GraphWorkflowError>>,
+    edge_tracker: DashMap<(NodeIndex, NodeIndex), bool>,
+    processed_nodes: DashMap<NodeIndex, Vec<(NodeIndex, String)>>,
+    // Nodes which were started by an incoming edge
+    triggered: DashSet<NodeIndex>,
+    failures: DashMap<String, NodeFailure>,
+    halted: AtomicBool,
+    // Results of speculative runs whose flow was taken, with their number of attempts
+    speculated: DashMap<NodeIndex, (Result<String, GraphWorkflowError>, u32)>,
+    speculation_hits: AtomicUsize,
+    speculation_misses: AtomicUsize,
+    // Agents whose output was reused from their cache
+    cached: DashSet<String>,
 }
 
 // Node weight for the graph
@@ -562,9 +1627,11 @@ pub struct AgentNode {
 #[derive(Clone, Debug, Error)]
 pub enum GraphWorkflowError {
     #[error("Agent Error: {0}")]
-    AgentError(String),
+    AgentError(#[source] Arc<AgentError>),
     #[error("Agent not found: {0}")]
     AgentNotFound(String),
+    #[error("Node already exists: {0}")]
+    NodeAlreadyExists(String),
     #[error("Cycle detected in workflow")]
     CycleDetected,
     #[error("Timeout executing agent: {0}")]
@@ -573,6 +1640,32 @@ pub enum GraphWorkflowError {
     Deadlock,
     #[error("Workflow execution canceled")]
     Canceled,
+    #[error("Workflow halted, agent '{}' failed: {}", .0.agent, .0.error)]
+    Halted(Box<NodeFailure>),
+    #[error("Tasks or Agents are empty")]
+    EmptyTasksOrAgents,
+    #[error("Unregistered flow function: {0}")]
+    UnregisteredFunction(String),
+    #[error("Persistence error: {0}")]
+    PersistenceError(#[source] Arc<PersistenceError>),
+}
+
+impl CategorizedError for GraphWorkflowError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            GraphWorkflowError::AgentError(e) => e.category(),
+            GraphWorkflowError::AgentNotFound(_)
+            | GraphWorkflowError::NodeAlreadyExists(_)
+            | GraphWorkflowError::CycleDetected
+            | GraphWorkflowError::EmptyTasksOrAgents
+            | GraphWorkflowError::UnregisteredFunction(_) => ErrorCategory::Validation,
+            GraphWorkflowError::Timeout(_) => ErrorCategory::Timeout,
+            GraphWorkflowError::Deadlock => ErrorCategory::Other,
+            GraphWorkflowError::Canceled => ErrorCategory::Cancelled,
+            GraphWorkflowError::Halted(failure) => failure.error.category(),
+            GraphWorkflowError::PersistenceError(e) => e.category(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -582,7 +1675,7 @@ mod tests {
     use futures::future::{self, BoxFuture};
     use mockall::mock;
 
-    use crate::agent::AgentError;
+    use crate::{agent::AgentError, persistence::MemoryPersistence};
 
     mock! {
         pub Agent{}
@@ -763,8 +1856,33 @@ mod tests {
         let result3 = workflow.connect_agents("agent3", "agent1", Flow::default());
         assert!(matches!(result3, Err(GraphWorkflowError::CycleDetected)));
 
-        // edge should not be added
+        // edge should not be added
+        assert_eq!(workflow.workflow.edge_count(), 2);
+
+        // self loops are cycles too
+        let result4 = workflow.connect_agents("agent2", "agent2", Flow::default());
+        assert!(matches!(result4, Err(GraphWorkflowError::CycleDetected)));
+    }
+
+    #[test]
+    fn test_cycle_detection_after_removal() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        for name in ["agent1", "agent2", "agent3", "agent4"] {
+            workflow.register_agent(create_mock_agent(name, name, "", name));
+        }
+        // Removing a node leaves a gap in the indices of the stable graph
+        workflow.remove_agent("agent1").unwrap();
+
+        workflow
+            .connect_agents("agent2", "agent4", Flow::default())
+            .unwrap();
+        workflow
+            .connect_agents("agent4", "agent3", Flow::default())
+            .unwrap();
+        let result = workflow.connect_agents("agent3", "agent2", Flow::default());
+        assert!(matches!(result, Err(GraphWorkflowError::CycleDetected)));
         assert_eq!(workflow.workflow.edge_count(), 2);
+        assert!(workflow.detect_potential_deadlocks().is_empty());
     }
 
     #[test]
@@ -898,6 +2016,27 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_execute_workflow_deep_chain() {
+        // Deep enough to overflow the stack of a test thread when executed recursively
+        const DEPTH: usize = 2000;
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        for i in 0..DEPTH {
+            let name = format!("agent{i}");
+            workflow.register_agent(create_mock_agent(&i.to_string(), &name, "", &name));
+            if i > 0 {
+                workflow
+                    .connect_agents(&format!("agent{}", i - 1), &name, Flow::default())
+                    .unwrap();
+            }
+        }
+
+        let results = workflow.execute_workflow("agent0", "input").await.unwrap();
+        assert_eq!(results.len(), DEPTH);
+        let last = format!("agent{}", DEPTH - 1);
+        assert_eq!(results.get(&last).unwrap().as_ref().unwrap(), &last);
+    }
+
     #[tokio::test]
     async fn test_execute_workflow_branching() {
         let mut workflow = DAGWorkflow::new("test", "Test workflow");
@@ -942,7 +2081,7 @@ mod tests {
         let transform_fn = Arc::new(|input: String| format!("transformed: {}", input));
         let flow = Flow {
             transform: Some(transform_fn),
-            condition: None,
+            ..Default::default()
         };
 
         workflow.connect_agents("agent1", "agent2", flow).unwrap();
@@ -972,6 +2111,7 @@ mod tests {
                 Flow {
                     transform: None,
                     condition: Some(true_condition),
+                    ..Default::default()
                 },
             )
             .unwrap();
@@ -1002,6 +2142,7 @@ mod tests {
                 Flow {
                     transform: None,
                     condition: Some(false_condition),
+                    ..Default::default()
                 },
             )
             .unwrap();
@@ -1046,6 +2187,194 @@ mod tests {
         assert!(agent2_result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_workflow_execution_with_failing_start_agent() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(create_failing_agent("1", "agent1", "fail error"));
+        workflow.register_agent(create_mock_agent(
+            "2",
+            "agent2",
+            "Second agent",
+            "response2",
+        ));
+        workflow
+            .connect_agents("agent1", "agent2", Flow::default())
+            .unwrap();
+
+        let result = workflow.execute_workflow("agent1", "input").await;
+        assert!(matches!(result, Err(GraphWorkflowError::AgentError(_))));
+
+        // The report still has the start agent's failure
+        let report = workflow
+            .execute_workflow_with_report("agent1", "input")
+            .await
+            .unwrap();
+        assert!(report.results.get("agent1").unwrap().is_err());
+        assert!(!report.results.contains_key("agent2"));
+        assert_eq!(report.failures.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_empty_input_or_workflow() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        assert!(matches!(
+            workflow.execute_workflow("agent1", "input").await,
+            Err(GraphWorkflowError::EmptyTasksOrAgents)
+        ));
+
+        workflow.register_agent(create_mock_agent("1", "agent1", "First agent", "response1"));
+        assert!(matches!(
+            workflow.execute_workflow("agent1", " ").await,
+            Err(GraphWorkflowError::EmptyTasksOrAgents)
+        ));
+        assert!(matches!(
+            workflow.dry_run("agent1", ""),
+            Err(GraphWorkflowError::EmptyTasksOrAgents)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_speculative_branch() {
+        let workflow = |guess: &'static str| {
+            let mut workflow = DAGWorkflow::new("test", "Test workflow");
+            workflow.register_agent(create_mock_agent("1", "review", "Reviewer", "approve"));
+            workflow.register_agent(create_mock_agent("2", "publish", "Publisher", "published"));
+            workflow.register_agent(create_mock_agent("3", "revise", "Reviser", "revised"));
+            for (target, approved) in [("publish", true), ("revise", false)] {
+                // The authoritative check is slow
+                let condition = move |output: String| -> BoxFuture<'static, bool> {
+                    Box::pin(async move {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        output.contains("approve") == approved
+                    })
+                };
+                let flow = Flow {
+                    async_condition: Some(Arc::new(condition)),
+                    ..Default::default()
+                };
+                workflow.connect_agents("review", target, flow).unwrap();
+            }
+            workflow
+                .set_branch_predictor("review", move |_| Some(guess.to_owned()))
+                .unwrap();
+            workflow
+        };
+
+        let report = workflow("publish")
+            .execute_workflow_with_report("review", "draft")
+            .await
+            .unwrap();
+        assert_eq!(report.speculation, SpeculationStats { hits: 1, misses: 0 });
+        assert_eq!(
+            report.results.get("publish").unwrap().as_ref().unwrap(),
+            "published"
+        );
+        assert!(!report.results.contains_key("revise"));
+
+        let report = workflow("revise")
+            .execute_workflow_with_report("review", "draft")
+            .await
+            .unwrap();
+        assert_eq!(report.speculation, SpeculationStats { hits: 0, misses: 1 });
+        assert!(report.results.contains_key("publish"));
+        assert!(!report.results.contains_key("revise"));
+    }
+
+    #[tokio::test]
+    async fn test_node_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let persistence = MemoryPersistence::default();
+        let workflow = |cache: NodeCache| {
+            let mut agent = Box::new(MockAgent::new());
+            agent.expect_name().return_const("writer".to_owned());
+            let agent_calls = Arc::clone(&calls);
+            agent.expect_run().returning(move |input| {
+                agent_calls.fetch_add(1, Ordering::SeqCst);
+                Box::pin(future::ready(Ok(format!("draft of {input}"))))
+            });
+            let mut workflow = DAGWorkflow::new("test", "Test workflow");
+            workflow.register_agent(agent);
+            workflow.set_node_cache("writer", cache).unwrap();
+            workflow
+        };
+
+        let mut memoized = workflow(NodeCache::new());
+        for input in ["a", "a", "b"] {
+            memoized.execute_workflow("writer", input).await.unwrap();
+        }
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 2);
+        let report = memoized
+            .execute_workflow_with_report("writer", "a")
+            .await
+            .unwrap();
+        assert_eq!(report.cached, ["writer"]);
+        assert_eq!(
+            report.results.get("writer").unwrap().as_ref().unwrap(),
+            "draft of a"
+        );
+
+        let mut expiring = workflow(NodeCache::new().ttl(Duration::ZERO));
+        for _ in 0..2 {
+            expiring.execute_workflow("writer", "a").await.unwrap();
+        }
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 2);
+
+        // A new workflow, e.g. after a restart, loads the saved outputs
+        let persistent = || NodeCache::new().persistent(persistence.clone(), "cache");
+        workflow(persistent())
+            .execute_workflow("writer", "a")
+            .await
+            .unwrap();
+        let report = workflow(persistent())
+            .execute_workflow_with_report("writer", "a")
+            .await
+            .unwrap();
+        assert_eq!(report.cached, ["writer"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(
+            workflow(NodeCache::new())
+                .set_node_cache("nonexistent", NodeCache::new())
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_node_states() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(create_mock_agent("1", "agent1", "First agent", "response1"));
+        workflow.register_agent(create_failing_agent("2", "agent2", "fail error"));
+        workflow.register_agent(create_mock_agent("3", "agent3", "Third agent", "response3"));
+        workflow
+            .connect_agents("agent1", "agent2", Flow::default())
+            .unwrap();
+        workflow
+            .connect_agents("agent2", "agent3", Flow::default())
+            .unwrap();
+
+        let states = workflow.node_states();
+        assert_eq!(states.get("agent1"), NodeState::Pending);
+        let mut rx = states.subscribe();
+
+        workflow.execute_workflow("agent1", "input").await.unwrap();
+        assert_eq!(states.get("agent1"), NodeState::Done);
+        assert_eq!(states.get("agent2"), NodeState::Failed);
+        assert_eq!(states.get("agent3"), NodeState::Pending);
+
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        let agent1 = events
+            .iter()
+            .filter(|event| event.node == "agent1")
+            .map(|event| event.state)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            agent1,
+            [NodeState::Pending, NodeState::Running, NodeState::Done]
+        );
+    }
+
     #[test]
     fn test_find_execution_paths() {
         let mut workflow = DAGWorkflow::new("test", "Test workflow");
@@ -1140,11 +2469,79 @@ mod tests {
         let deadlocks = workflow.detect_potential_deadlocks();
         assert_eq!(deadlocks.len(), 0);
 
-        // try to add c -> a, which should fail because has_cycle prevents it
+        // try to add c -> a, which should fail because it would close a cycle
         let result = workflow.connect_agents("c", "a", Flow::default());
         assert!(matches!(result, Err(GraphWorkflowError::CycleDetected)));
     }
 
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let registry = FlowRegistry::new()
+            .condition("approved", |output: &str| output.contains("approved"))
+            .transform("shout", |input: String| input.to_uppercase());
+        let agents = || -> Vec<Box<dyn Agent>> {
+            vec![
+                create_mock_agent("1", "writer", "", "approved draft"),
+                create_mock_agent("2", "editor", "", "edited"),
+                create_mock_agent("3", "reviewer", "", "reviewed"),
+            ]
+        };
+        let mut workflow = DAGWorkflow::new("docs", "Write docs");
+        for agent in agents() {
+            workflow.register_agent(agent);
+        }
+        let join = Aggregator::new(AggregationStrategy::Template(
+            "{editor} / {reviewer}".to_owned(),
+        ));
+        workflow.add_aggregator("join", join).unwrap();
+        workflow
+            .set_node_policy("editor", NodePolicy::default().max_retries(2))
+            .unwrap();
+        let flow = registry
+            .flow(FlowSpec::new().condition("approved").transform("shout"))
+            .unwrap();
+        workflow.connect_agents("writer", "editor", flow).unwrap();
+        for (from, to) in [
+            ("writer", "reviewer"),
+            ("editor", "join"),
+            ("reviewer", "join"),
+        ] {
+            workflow.connect_agents(from, to, Flow::default()).unwrap();
+        }
+
+        let json = workflow.to_json().unwrap();
+        let mut all_agents = agents();
+        all_agents.push(create_mock_agent("4", "unused", "", "unused"));
+        let mut loaded = DAGWorkflow::from_json(&json, all_agents, &registry).unwrap();
+        assert_eq!(loaded.spec().unwrap(), workflow.spec().unwrap());
+        assert!(!loaded.name_to_node.contains_key("unused"));
+        let results = loaded.execute_workflow("writer", "input").await.unwrap();
+        assert_eq!(
+            results.get("join").unwrap().as_ref().unwrap(),
+            "edited / reviewed"
+        );
+
+        assert!(matches!(
+            DAGWorkflow::from_json(&json, agents().split_off(1), &registry),
+            Err(GraphWorkflowError::AgentNotFound(_))
+        ));
+        assert!(matches!(
+            DAGWorkflow::from_json(&json, agents(), &FlowRegistry::new()),
+            Err(GraphWorkflowError::UnregisteredFunction(_))
+        ));
+
+        // Anonymous functions can't be saved
+        let flow = Flow {
+            condition: Some(Arc::new(|_: &str| true)),
+            ..Default::default()
+        };
+        workflow.connect_agents("writer", "join", flow).unwrap();
+        assert!(matches!(
+            workflow.to_json(),
+            Err(GraphWorkflowError::UnregisteredFunction(_))
+        ));
+    }
+
     #[test]
     fn test_get_workflow_structure() {
         let mut workflow = DAGWorkflow::new("test", "Test workflow");
@@ -1157,7 +2554,7 @@ mod tests {
         let transform_fn = Arc::new(|input: String| format!("transformed: {}", input));
         let flow = Flow {
             transform: Some(transform_fn),
-            condition: None,
+            ..Default::default()
         };
 
         workflow.connect_agents("b", "c", flow).unwrap();
@@ -1191,6 +2588,15 @@ mod tests {
         assert!(dot.contains("\"b\" [label=\"b\"]"));
         assert!(dot.contains("\"a\" -> \"b\""));
         assert!(dot.contains("}"));
+
+        // Quotes and backslashes in names are escaped
+        workflow.register_agent(create_mock_agent("3", "say \"hi\" \\o/", "Agent C", "c"));
+        workflow
+            .connect_agents("b", "say \"hi\" \\o/", Flow::default())
+            .unwrap();
+        let dot = workflow.export_workflow_dot();
+        assert!(dot.contains(r#""say \"hi\" \\o/" [label="say \"hi\" \\o/"]"#));
+        assert!(dot.contains(r#""b" -> "say \"hi\" \\o/""#));
     }
 
     #[tokio::test]
@@ -1311,35 +2717,21 @@ mod tests {
         let agent1_idx = *workflow.name_to_node.get("agent1").unwrap();
 
         // create shared data structures
-        let results = Arc::new(DashMap::new());
-        let edge_tracker = Arc::new(DashMap::new());
-        let processed_nodes = Arc::new(DashMap::new());
+        let state = ExecutionState::default();
 
         // first execution of agent1
         let result1 = workflow
-            .execute_node(
-                agent1_idx,
-                "input1".to_string(),
-                Arc::clone(&results),
-                Arc::clone(&edge_tracker),
-                Arc::clone(&processed_nodes),
-            )
+            .execute_node(agent1_idx, "input1".to_string(), &state)
             .await
             .unwrap();
 
         assert_eq!(result1, "response for 'input1' (call #1)");
-        assert!(results.contains_key("agent1"));
-        assert!(results.contains_key("agent2")); // agent2 also executed
+        assert!(state.results.contains_key("agent1"));
+        assert!(state.results.contains_key("agent2")); // agent2 also executed
 
         // second execution of agent1 with a different input
         let result2 = workflow
-            .execute_node(
-                agent1_idx,
-                "input2".to_string(),
-                Arc::clone(&results),
-                Arc::clone(&edge_tracker),
-                Arc::clone(&processed_nodes),
-            )
+            .execute_node(agent1_idx, "input2".to_string(), &state)
             .await
             .unwrap();
 
@@ -1347,21 +2739,292 @@ mod tests {
         assert_eq!(result2, "response for 'input1' (call #1)"); // not "response for 'input2' (call #1)"
 
         // clear the results map
-        results.clear();
+        state.results.clear();
 
         // third execution of agent1
         let result3 = workflow
-            .execute_node(
-                agent1_idx,
-                "input3".to_string(),
-                Arc::clone(&results),
-                Arc::clone(&edge_tracker),
-                Arc::clone(&processed_nodes),
-            )
+            .execute_node(agent1_idx, "input3".to_string(), &state)
             .await
             .unwrap();
 
         // the results should contain the new call count, indicating that the agent was re-executed
         assert_eq!(result3, "response for 'input3' (call #2)");
     }
+
+    fn create_flaky_agent(name: &str, failures: usize) -> Box<MockAgent> {
+        let mut agent = Box::new(MockAgent::new());
+        agent.expect_name().return_const(name.to_string());
+        let mut calls = 0;
+        agent.expect_run().returning(move |input| {
+            calls += 1;
+            if calls <= failures {
+                Box::pin(future::ready(Err(AgentError::TestError("flaky".into()))))
+            } else {
+                Box::pin(future::ready(Ok(format!("ok after {calls}: {input}"))))
+            }
+        });
+        agent
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = NodePolicy::default().backoff(Duration::from_millis(500));
+        assert_eq!(policy.retry_backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.retry_backoff(3), Duration::from_secs(2));
+        assert_eq!(policy.retry_backoff(40), MAX_RETRY_BACKOFF);
+        assert_eq!(policy.retry_backoff(u32::MAX), MAX_RETRY_BACKOFF);
+
+        // A huge base doesn't overflow, and a base above the cap is kept
+        let slow = NodePolicy::default().backoff(Duration::MAX);
+        assert_eq!(slow.retry_backoff(u32::MAX), Duration::MAX);
+        let hourly = NodePolicy::default().backoff(Duration::from_secs(3600));
+        assert_eq!(hourly.retry_backoff(5), Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn test_node_policy_retries() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(create_flaky_agent("flaky", 2));
+        workflow
+            .set_node_policy(
+                "flaky",
+                NodePolicy::default()
+                    .max_retries(2)
+                    .backoff(Duration::from_millis(1)),
+            )
+            .unwrap();
+
+        let report = workflow
+            .execute_workflow_with_report("flaky", "input")
+            .await
+            .unwrap();
+        assert!(report.failures.is_empty());
+        assert_eq!(
+            report.results.get("flaky").unwrap().as_ref().unwrap(),
+            "ok after 3: input"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_node_policy_skip_subtree_report() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(create_mock_agent("1", "agent1", "First agent", "response1"));
+        workflow.register_agent(create_failing_agent("2", "agent2", "fail error"));
+        workflow.register_agent(create_mock_agent("3", "agent3", "Third agent", "response3"));
+        workflow
+            .connect_agents("agent1", "agent2", Flow::default())
+            .unwrap();
+        workflow
+            .connect_agents("agent2", "agent3", Flow::default())
+            .unwrap();
+        workflow
+            .set_node_policy(
+                "agent2",
+                NodePolicy::default()
+                    .max_retries(1)
+                    .backoff(Duration::from_millis(1)),
+            )
+            .unwrap();
+
+        let report = workflow
+            .execute_workflow_with_report("agent1", "input")
+            .await
+            .unwrap();
+        assert!(!report.halted);
+        assert_eq!(report.failures.len(), 1);
+        let failure = &report.failures[0];
+        assert_eq!(failure.agent, "agent2");
+        assert_eq!(failure.attempts, 2);
+        assert_eq!(failure.skipped, vec!["agent3".to_owned()]);
+        assert!(!report.results.contains_key("agent3"));
+    }
+
+    #[tokio::test]
+    async fn test_node_policy_halt() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(create_failing_agent("1", "agent1", "fail error"));
+        workflow.register_agent(create_mock_agent(
+            "2",
+            "agent2",
+            "Second agent",
+            "response2",
+        ));
+        workflow
+            .connect_agents("agent1", "agent2", Flow::default())
+            .unwrap();
+        workflow
+            .set_node_policy(
+                "agent1",
+                NodePolicy::default().on_failure(FailureAction::Halt),
+            )
+            .unwrap();
+
+        let err = workflow
+            .execute_workflow("agent1", "input")
+            .await
+            .unwrap_err();
+        let GraphWorkflowError::Halted(failure) = err else {
+            panic!("expected halted error, got {err:?}");
+        };
+        assert_eq!(failure.agent, "agent1");
+        assert_eq!(failure.attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_node_policy_error_handler() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(create_failing_agent("1", "agent1", "fail error"));
+        workflow.register_agent(create_mock_agent(
+            "2",
+            "agent2",
+            "Second agent",
+            "response2",
+        ));
+        workflow.register_agent(create_mock_agent("3", "handler", "Handler", "recovered"));
+        workflow
+            .connect_agents("agent1", "agent2", Flow::default())
+            .unwrap();
+
+        assert!(
+            workflow
+                .set_node_policy(
+                    "agent1",
+                    NodePolicy::default()
+                        .on_failure(FailureAction::ErrorHandler("missing".to_owned())),
+                )
+                .is_err()
+        );
+        workflow
+            .set_node_policy(
+                "agent1",
+                NodePolicy::default().on_failure(FailureAction::ErrorHandler("handler".to_owned())),
+            )
+            .unwrap();
+
+        let report = workflow
+            .execute_workflow_with_report("agent1", "input")
+            .await
+            .unwrap();
+        assert_eq!(
+            report.results.get("agent1").unwrap().as_ref().unwrap(),
+            "recovered"
+        );
+        assert_eq!(
+            report.results.get("agent2").unwrap().as_ref().unwrap(),
+            "response2"
+        );
+        assert!(report.failures[0].handled);
+        assert!(report.failures[0].skipped.is_empty());
+    }
+
+    fn create_fan_out(aggregator: Aggregator) -> DAGWorkflow {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(create_mock_agent("0", "start", "Start", "task"));
+        workflow.register_agent(create_mock_agent("1", "a", "Branch A", "answer a"));
+        workflow.register_agent(create_mock_agent("2", "b", "Branch B", "answer b"));
+        workflow.register_agent(create_flaky_agent("next", 0));
+        workflow.add_aggregator("join", aggregator).unwrap();
+        for (from, to) in [("start", "a"), ("start", "b"), ("a", "join"), ("b", "join")] {
+            workflow.connect_agents(from, to, Flow::default()).unwrap();
+        }
+        workflow
+            .connect_agents("join", "next", Flow::default())
+            .unwrap();
+        workflow
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_concat() {
+        let mut workflow = create_fan_out(Aggregator::new(AggregationStrategy::Concat));
+        assert!(matches!(
+            workflow.add_aggregator("a", Aggregator::new(AggregationStrategy::Concat)),
+            Err(GraphWorkflowError::NodeAlreadyExists(_))
+        ));
+
+        let results = workflow.execute_workflow("start", "input").await.unwrap();
+        assert_eq!(
+            results.get("join").unwrap().as_ref().unwrap(),
+            "[From a] answer a\n[From b] answer b\n"
+        );
+        assert_eq!(
+            results.get("next").unwrap().as_ref().unwrap(),
+            "ok after 1: [From join] [From a] answer a\n[From b] answer b\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_quorum_template() {
+        let mut workflow = create_fan_out(
+            Aggregator::new(AggregationStrategy::Template("first: {a}{b}".to_owned())).quorum(1),
+        );
+
+        let results = workflow.execute_workflow("start", "input").await.unwrap();
+        let joined = results.get("join").unwrap().clone().unwrap();
+        // Only one branch made the quorum, the other placeholder is left as is
+        assert!(joined == "first: answer a{b}" || joined == "first: {a}answer b");
+        assert!(results.contains_key("next"));
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_agent() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        assert!(
+            workflow
+                .add_aggregator(
+                    "join",
+                    Aggregator::new(AggregationStrategy::Agent("judge".to_owned()))
+                )
+                .is_err()
+        );
+
+        workflow.register_agent(create_mock_agent("1", "a", "Branch A", "answer a"));
+        workflow.register_agent(create_flaky_agent("judge", 0));
+        workflow
+            .add_aggregator(
+                "join",
+                Aggregator::new(AggregationStrategy::Agent("judge".to_owned())),
+            )
+            .unwrap();
+        workflow
+            .connect_agents("a", "join", Flow::default())
+            .unwrap();
+
+        let results = workflow.execute_workflow("a", "input").await.unwrap();
+        assert_eq!(
+            results.get("join").unwrap().as_ref().unwrap(),
+            "ok after 1: [From a] answer a\n"
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut workflow = create_fan_out(Aggregator::new(AggregationStrategy::Concat));
+        workflow
+            .connect_agents(
+                "start",
+                "next",
+                Flow {
+                    transform: Some(Arc::new(|output| format!("summary of {output}"))),
+                    condition: Some(Arc::new(|_| false)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let report = workflow.dry_run("start", "input").unwrap();
+        let agents = report
+            .steps
+            .iter()
+            .map(|step| step.agent.as_str())
+            .collect::<Vec<_>>();
+        // The join runs without an agent
+        assert_eq!(agents.len(), 4);
+        assert_eq!(agents[0], "start");
+        assert_eq!(agents[3], "next");
+        assert_eq!(report.steps[0].prompt, "input");
+
+        let next_prompt = &report.steps[3].prompt;
+        assert!(next_prompt.contains("[From join] <output of join>"));
+        assert!(next_prompt.contains("[From start] summary of <output of start>"));
+    }
 }
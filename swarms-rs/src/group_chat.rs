@@ -0,0 +1,510 @@
+//! Agents taking turns in a shared conversation.
+//!
+//! Before every turn a [`SpeakerSelector`] chooses who speaks next, the chosen agent gets
+//! the transcript so far and its answer is added to the conversation. [`RoundRobin`],
+//! [`RandomSpeaker`], [`ModeratorSelector`] and [`PrioritySelector`] are built in, implement
+//! [`SpeakerSelector`] for other scheduling.
+
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use futures::future::BoxFuture;
+use thiserror::Error;
+
+use crate::{
+    agent::{Agent, AgentError},
+    conversation::{AgentConversation, Participant, SwarmConversation, SwarmStats},
+    error::{CategorizedError, ErrorCategory},
+    rng::SwarmRng,
+    swarm::{Swarm, SwarmError, SwarmOutput},
+    utils::is_empty_task,
+};
+
+#[derive(Debug, Error)]
+pub enum GroupChatError {
+    #[error("Tasks or Agents are empty")]
+    EmptyTasksOrAgents,
+    #[error("Speaker selector chose agent {0}, which doesn't exist")]
+    SpeakerOutOfRange(usize),
+    #[error("Moderator chose an unknown speaker: {0}")]
+    UnknownSpeaker(String),
+    #[error("Agent Error: {0}")]
+    AgentError(#[from] AgentError),
+}
+
+impl CategorizedError for GroupChatError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            GroupChatError::EmptyTasksOrAgents | GroupChatError::SpeakerOutOfRange(_) => {
+                ErrorCategory::Validation
+            }
+            // the moderator's reply is unusable
+            GroupChatError::UnknownSpeaker(_) => ErrorCategory::Provider,
+            GroupChatError::AgentError(e) => e.category(),
+        }
+    }
+}
+
+/// The state of the chat the next speaker is chosen from.
+pub struct ChatState<'a> {
+    pub agents: &'a [Box<dyn Agent>],
+    pub conversation: &'a AgentConversation,
+    /// Number of turns taken so far.
+    pub turn: usize,
+    /// Index of the agent who spoke last.
+    pub last_speaker: Option<usize>,
+    /// How many turns each agent took, in the order of the agents.
+    pub turns_taken: &'a [usize],
+    /// Random numbers of the run, seeded by [`GroupChatBuilder::seed`].
+    pub rng: &'a SwarmRng,
+}
+
+/// Chooses the agent who speaks next in a [`GroupChat`].
+pub trait SpeakerSelector: Send + Sync {
+    /// The index of the next speaker in [`ChatState::agents`].
+    fn select<'a>(
+        &'a self,
+        state: &'a ChatState<'a>,
+    ) -> BoxFuture<'a, Result<usize, GroupChatError>>;
+}
+
+/// A shared selector, e.g. to inspect its state after the chat.
+impl<S: SpeakerSelector + ?Sized> SpeakerSelector for Arc<S> {
+    fn select<'a>(
+        &'a self,
+        state: &'a ChatState<'a>,
+    ) -> BoxFuture<'a, Result<usize, GroupChatError>> {
+        (**self).select(state)
+    }
+}
+
+/// The agents speak in turn, in the order they were added.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RoundRobin;
+
+impl SpeakerSelector for RoundRobin {
+    fn select<'a>(
+        &'a self,
+        state: &'a ChatState<'a>,
+    ) -> BoxFuture<'a, Result<usize, GroupChatError>> {
+        let next = state.last_speaker.map_or(0, |last| last + 1) % state.agents.len();
+        Box::pin(async move { Ok(next) })
+    }
+}
+
+/// A random agent speaks, never the same agent twice in a row if there are others.
+///
+/// Draws from [`ChatState::rng`], so a seeded chat is reproducible.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomSpeaker;
+
+impl SpeakerSelector for RandomSpeaker {
+    fn select<'a>(
+        &'a self,
+        state: &'a ChatState<'a>,
+    ) -> BoxFuture<'a, Result<usize, GroupChatError>> {
+        let count = state.agents.len();
+        let next = match state.last_speaker {
+            Some(last) if count > 1 => {
+                // Skip over the last speaker
+                let offset = state.rng.below(count as u64 - 1) as usize;
+                (last + 1 + offset) % count
+            }
+            _ => state.rng.below(count as u64) as usize,
+        };
+        Box::pin(async move { Ok(next) })
+    }
+}
+
+/// A moderator agent reads the transcript and names the next speaker.
+///
+/// The moderator must answer with one of the agents' names, other answers fail the chat
+/// with [`GroupChatError::UnknownSpeaker`].
+pub struct ModeratorSelector {
+    moderator: Box<dyn Agent>,
+}
+
+impl ModeratorSelector {
+    pub fn new(moderator: Box<dyn Agent>) -> Self {
+        Self { moderator }
+    }
+
+    fn prompt(state: &ChatState<'_>) -> String {
+        let agents = state
+            .agents
+            .iter()
+            .map(|agent| format!("- {}: {}\n", agent.name(), agent.description()))
+            .collect::<String>();
+        format!(
+            "You moderate a group chat. Participants:\n{agents}\nConversation so far:\n{}\n\
+            Reply with only the name of the participant who should speak next.",
+            transcript(state.conversation)
+        )
+    }
+}
+
+impl SpeakerSelector for ModeratorSelector {
+    fn select<'a>(
+        &'a self,
+        state: &'a ChatState<'a>,
+    ) -> BoxFuture<'a, Result<usize, GroupChatError>> {
+        Box::pin(async move {
+            let reply = self.moderator.run(Self::prompt(state)).await?;
+            let name = reply
+                .trim()
+                .trim_matches(|c: char| c.is_ascii_punctuation() && c != '_' && c != '-');
+            state
+                .agents
+                .iter()
+                .position(|agent| agent.name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| GroupChatError::UnknownSpeaker(reply.trim().to_owned()))
+        })
+    }
+}
+
+/// Agents speak in proportion to their priority, an agent with priority `2` gets twice the
+/// turns of an agent with priority `1`. Ties go to the agent added first.
+#[derive(Clone, Debug, Default)]
+pub struct PrioritySelector {
+    /// Priorities by agent name, agents without a priority have priority `1`.
+    priorities: HashMap<String, u32>,
+}
+
+impl PrioritySelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the agent's priority, `0` mutes the agent unless all agents are muted.
+    pub fn priority(mut self, agent_name: impl Into<String>, priority: u32) -> Self {
+        self.priorities.insert(agent_name.into(), priority);
+        self
+    }
+
+    fn priority_of(&self, agent: &dyn Agent) -> u32 {
+        self.priorities.get(&agent.name()).copied().unwrap_or(1)
+    }
+}
+
+impl SpeakerSelector for PrioritySelector {
+    fn select<'a>(
+        &'a self,
+        state: &'a ChatState<'a>,
+    ) -> BoxFuture<'a, Result<usize, GroupChatError>> {
+        // The agent furthest behind its share of the turns speaks next
+        let next = state
+            .agents
+            .iter()
+            .enumerate()
+            .map(|(index, agent)| {
+                let priority = self.priority_of(agent.as_ref());
+                let turns = state.turns_taken.get(index).copied().unwrap_or(0);
+                let share = if priority == 0 {
+                    f64::INFINITY
+                } else {
+                    (turns + 1) as f64 / priority as f64
+                };
+                (index, share)
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or(0, |(index, _)| index);
+        Box::pin(async move { Ok(next) })
+    }
+}
+
+pub struct GroupChatBuilder {
+    name: String,
+    description: String,
+    agents: Vec<Box<dyn Agent>>,
+    selector: Box<dyn SpeakerSelector>,
+    max_turns: Option<usize>,
+    seed: Option<u64>,
+}
+
+impl GroupChatBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn add_agent(mut self, agent: Box<dyn Agent>) -> Self {
+        self.agents.push(agent);
+        self
+    }
+
+    pub fn agents(mut self, agents: Vec<Box<dyn Agent>>) -> Self {
+        self.agents = agents;
+        self
+    }
+
+    /// Choose the speakers with this policy instead of [`RoundRobin`].
+    pub fn speaker_selector(mut self, selector: impl SpeakerSelector + 'static) -> Self {
+        self.selector = Box::new(selector);
+        self
+    }
+
+    /// Stop after this many turns, by default every agent speaks once.
+    pub fn max_turns(mut self, max_turns: usize) -> Self {
+        self.max_turns = Some(max_turns);
+        self
+    }
+
+    /// Seed the random numbers of the speaker selection, every run with the same seed
+    /// chooses the same speakers.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn build(self) -> GroupChat {
+        GroupChat {
+            name: self.name,
+            description: self.description,
+            agents: self.agents,
+            selector: self.selector,
+            max_turns: self.max_turns,
+            seed: self.seed,
+        }
+    }
+}
+
+pub struct GroupChat {
+    name: String,
+    description: String,
+    agents: Vec<Box<dyn Agent>>,
+    selector: Box<dyn SpeakerSelector>,
+    max_turns: Option<usize>,
+    seed: Option<u64>,
+}
+
+impl GroupChat {
+    pub fn builder() -> GroupChatBuilder {
+        GroupChatBuilder {
+            name: "GroupChat".to_owned(),
+            description: "Agents taking turns in a shared conversation.".to_owned(),
+            agents: Vec::new(),
+            selector: Box::new(RoundRobin),
+            max_turns: None,
+            seed: None,
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Run the chat on the task, returns the conversation of all turns.
+    pub async fn run(&self, task: impl Into<String>) -> Result<AgentConversation, GroupChatError> {
+        self.run_with_stats(task)
+            .await
+            .map(|(conversation, _)| conversation)
+    }
+
+    /// Run the chat and return statistics of it as well, every turn is a round.
+    pub async fn run_with_stats(
+        &self,
+        task: impl Into<String>,
+    ) -> Result<(AgentConversation, SwarmStats), GroupChatError> {
+        let task = task.into();
+        if self.agents.is_empty() || is_empty_task(&task) {
+            return Err(GroupChatError::EmptyTasksOrAgents);
+        }
+
+        let mut conversation = AgentConversation::new(self.name.clone());
+        conversation.add(Participant::human("User"), task);
+        let mut turns_taken = vec![0; self.agents.len()];
+        let mut last_speaker = None;
+        let mut log = SwarmConversation::new();
+        let rng = SwarmRng::from_seed(self.seed);
+        for turn in 0..self.max_turns.unwrap_or(self.agents.len()) {
+            let state = ChatState {
+                agents: &self.agents,
+                conversation: &conversation,
+                turn,
+                last_speaker,
+                turns_taken: &turns_taken,
+                rng: &rng,
+            };
+            let speaker = self.selector.select(&state).await?;
+            let agent = self
+                .agents
+                .get(speaker)
+                .ok_or(GroupChatError::SpeakerOutOfRange(speaker))?;
+
+            let prompt = format!(
+                "{}\nYou are {} in this group chat, continue the conversation.",
+                transcript(&conversation),
+                agent.name()
+            );
+            tracing::debug!(
+                "| GroupChat: {} | Turn {} | {}",
+                self.name,
+                turn,
+                agent.name()
+            );
+            let start = Instant::now();
+            let response = agent.run(prompt.clone()).await?;
+            log.add_timed_log(agent.name(), prompt, response.clone(), start.elapsed());
+            log.next_round();
+            conversation.add(Participant::agent(agent.name()), response);
+            turns_taken[speaker] += 1;
+            last_speaker = Some(speaker);
+        }
+        Ok((conversation, log.stats()))
+    }
+}
+
+impl Swarm for GroupChat {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, task: String) -> BoxFuture<'_, Result<SwarmOutput, SwarmError>> {
+        Box::pin(async move {
+            self.run(task)
+                .await
+                .map(|output| Box::new(output) as _)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+/// The messages as `name: text` lines, without timestamps.
+fn transcript(conversation: &AgentConversation) -> String {
+    conversation
+        .history
+        .iter()
+        .map(|message| format!("{}: {}\n", message.participant.name, message.body()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::test_support::FnAgent;
+
+    fn agent(name: &'static str) -> Box<dyn Agent> {
+        Box::new(FnAgent::new(name, move |_| format!("{name} spoke")))
+    }
+
+    fn speakers(conversation: &AgentConversation) -> Vec<String> {
+        conversation.history[1..]
+            .iter()
+            .map(|message| message.participant.name.clone())
+            .collect()
+    }
+
+    async fn run_chat(selector: impl SpeakerSelector + 'static, turns: usize) -> Vec<String> {
+        let chat = GroupChat::builder()
+            .agents(vec![agent("a"), agent("b"), agent("c")])
+            .speaker_selector(selector)
+            .max_turns(turns)
+            .build();
+        speakers(&chat.run("plan the launch").await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        assert_eq!(run_chat(RoundRobin, 4).await, ["a", "b", "c", "a"]);
+
+        let chat = GroupChat::builder().add_agent(agent("a")).build();
+        let (conversation, stats) = chat.run_with_stats("task").await.unwrap();
+        assert_eq!(conversation.history.len(), 2);
+        assert!(conversation.history[1].body().ends_with("a spoke"));
+        assert_eq!(stats.messages_per_agent["a"], 1);
+        assert_eq!((stats.rounds, stats.fan_out), (1, 1));
+        assert!(stats.average_latency_ms.is_some());
+        assert!(matches!(
+            GroupChat::builder().build().run("task").await,
+            Err(GroupChatError::EmptyTasksOrAgents)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_random_speaker() {
+        let random = run_chat(RandomSpeaker, 20).await;
+        assert!(random.windows(2).all(|pair| pair[0] != pair[1]));
+
+        let chat = GroupChat::builder()
+            .agents(vec![agent("a"), agent("b"), agent("c")])
+            .speaker_selector(RandomSpeaker)
+            .max_turns(10)
+            .seed(42)
+            .build();
+        let first = speakers(&chat.run("task").await.unwrap());
+        assert_eq!(speakers(&chat.run("task").await.unwrap()), first);
+    }
+
+    #[tokio::test]
+    async fn test_priority_selector() {
+        let selector = PrioritySelector::new().priority("a", 2).priority("c", 0);
+        let speakers = run_chat(selector, 6).await;
+        assert_eq!(speakers, ["a", "a", "b", "a", "a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_moderator_selector() {
+        let moderator = FnAgent::reply("moderator", " C. ");
+        let speakers = run_chat(ModeratorSelector::new(Box::new(moderator)), 2).await;
+        assert_eq!(speakers, ["c", "c"]);
+
+        let moderator = FnAgent::reply("moderator", "nobody");
+        let chat = GroupChat::builder()
+            .add_agent(agent("a"))
+            .speaker_selector(ModeratorSelector::new(Box::new(moderator)))
+            .build();
+        assert!(matches!(
+            chat.run("task").await,
+            Err(GroupChatError::UnknownSpeaker(name)) if name == "nobody"
+        ));
+    }
+
+    /// The turn, last speaker and turns taken of a state.
+    type RecordedState = (usize, Option<usize>, Vec<usize>);
+
+    /// Records the states it was asked to choose from.
+    struct Recorder(Mutex<Vec<RecordedState>>);
+
+    impl SpeakerSelector for Recorder {
+        fn select<'a>(
+            &'a self,
+            state: &'a ChatState<'a>,
+        ) -> BoxFuture<'a, Result<usize, GroupChatError>> {
+            self.0.lock().unwrap().push((
+                state.turn,
+                state.last_speaker,
+                state.turns_taken.to_vec(),
+            ));
+            let next = if state.turn < 2 { 1 } else { 7 };
+            Box::pin(async move { Ok(next) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_selector() {
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let chat = GroupChat::builder()
+            .agents(vec![agent("a"), agent("b")])
+            .speaker_selector(recorder.clone())
+            .max_turns(3)
+            .build();
+        assert!(matches!(
+            chat.run("task").await,
+            Err(GroupChatError::SpeakerOutOfRange(7))
+        ));
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                (0, None, vec![0, 0]),
+                (1, Some(1), vec![0, 1]),
+                (2, Some(1), vec![0, 2])
+            ]
+        );
+    }
+}
@@ -1,19 +1,54 @@
 //! Swarms-rs is a Rust implementation of the Swarms framework for building multi-agent systems.
 //! This crate provides core abstractions and implementations for agents, workflows and swarms.
 pub mod agent;
+pub mod agent_rearrange;
 pub mod auto_swarm;
 pub mod concurrent_workflow;
+pub mod config;
+pub mod conversation;
+pub mod dry_run;
+pub mod error;
+pub mod events;
+pub mod experiment;
+pub mod feedback;
 pub mod graph_workflow;
+pub mod group_chat;
 pub mod llm;
+pub mod locale;
+pub mod meeting_notes;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mixture_of_agents;
 pub mod multi_agent_orchestrator;
+pub mod rng;
+pub mod routing_stats;
+pub mod run_bundle;
+pub mod secrets;
 pub mod sequential_workflow;
+pub mod structured_output;
+pub mod swarm_router;
 pub mod swarming_architectures;
+pub mod task_classifier;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+pub mod tenant;
 pub mod tool;
+pub mod trace_export;
+#[cfg(feature = "visualization")]
+pub mod visualization;
+pub mod webhook;
 pub mod workflow_config;
 
-mod conversation;
+mod concurrency;
 mod persistence;
 mod swarm;
-mod swarm_router;
 mod system_resource_monitor;
+#[cfg(test)]
+mod test_support;
 mod utils;
+
+#[cfg(feature = "object-storage")]
+pub use persistence::ObjectStorePersistence;
+pub use persistence::{
+    FORMAT_VERSION, FilePersistence, MemoryPersistence, Migration, Persistence, PersistenceError,
+};
@@ -2,9 +2,17 @@ use futures::future::BoxFuture;
 use request::{CompletionRequest, CompletionResponse};
 use thiserror::Error;
 
+use crate::{
+    error::{CategorizedError, ErrorCategory},
+    secrets::SecretError,
+};
+
 pub mod completion;
+pub mod embedding;
 pub mod provider;
 pub mod request;
+pub mod router;
+pub mod tokenizer;
 
 pub trait Model {
     type RawCompletionResponse;
@@ -17,6 +25,11 @@ pub trait Model {
     ) -> BoxFuture<Result<CompletionResponse<Self::RawCompletionResponse>, CompletionError>>;
 }
 
+pub trait EmbeddingModel {
+    /// Embed the texts, the returned vectors are in the same order as the texts.
+    fn embed(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>, CompletionError>>;
+}
+
 // Errors
 #[derive(Debug, Error)]
 pub enum CompletionError {
@@ -38,9 +51,188 @@ pub enum CompletionError {
 
     /// Error returned by the completion model provider
     #[error("ProviderError: {0}")]
-    Provider(String),
+    Provider(ProviderError),
+
+    /// Error fetching the provider's credentials
+    #[error("SecretError: {0}")]
+    Secret(#[from] SecretError),
 
     /// Other error
     #[error("OtherError: {0}")]
     Other(String),
 }
+
+impl CompletionError {
+    /// Whether retrying the same request may succeed, e.g. rate limits, network errors and 5xx.
+    ///
+    /// Authentication failures, invalid requests and content filtering are fatal.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            CompletionError::Http(e) => match e.status() {
+                Some(status) => {
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                }
+                None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+            },
+            CompletionError::Provider(e) => e.kind.is_retryable(),
+            // the model may return a usable response next time
+            CompletionError::Response(_) => true,
+            CompletionError::Json(_)
+            | CompletionError::Request(_)
+            | CompletionError::Secret(_)
+            | CompletionError::Other(_) => false,
+        }
+    }
+}
+
+/// An error object returned by the provider's API.
+#[derive(Debug, Clone, Error)]
+#[error("{message}")]
+pub struct ProviderError {
+    pub kind: ProviderErrorKind,
+    pub message: String,
+}
+
+impl ProviderError {
+    /// Classify the error from the provider's error type, code and message.
+    pub fn new(error_type: Option<&str>, code: Option<&str>, message: impl Into<String>) -> Self {
+        let message = message.into();
+        let kind = ProviderErrorKind::classify(
+            &[
+                error_type.unwrap_or_default(),
+                code.unwrap_or_default(),
+                &message,
+            ]
+            .join(" "),
+        );
+        Self { kind, message }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderErrorKind {
+    /// Too many requests, retryable.
+    RateLimit,
+    /// The provider is overloaded or failed internally, retryable.
+    Transient,
+    /// Invalid credentials or missing permissions, fatal.
+    Authentication,
+    /// The account ran out of quota or credits, fatal.
+    QuotaExceeded,
+    /// The request is malformed or exceeds the model's limits, fatal.
+    InvalidRequest,
+    /// The input or output was blocked by the provider's content filter, fatal.
+    ContentFilter,
+    /// Not recognized, retryable.
+    Unknown,
+}
+
+impl ProviderErrorKind {
+    fn classify(text: &str) -> Self {
+        let text = text.to_lowercase();
+        let contains_any = |patterns: &[&str]| patterns.iter().any(|p| text.contains(p));
+        // quota errors are often reported as rate limit errors, so check them first
+        if contains_any(&["insufficient_quota", "quota", "billing", "credit"]) {
+            ProviderErrorKind::QuotaExceeded
+        } else if contains_any(&["rate_limit", "rate limit", "too many requests"]) {
+            ProviderErrorKind::RateLimit
+        } else if contains_any(&[
+            "invalid_api_key",
+            "authentication",
+            "unauthorized",
+            "permission",
+            "api key",
+        ]) {
+            ProviderErrorKind::Authentication
+        } else if contains_any(&["content_filter", "content_policy", "content management"]) {
+            ProviderErrorKind::ContentFilter
+        } else if contains_any(&[
+            "invalid_request",
+            "context_length",
+            "model_not_found",
+            "does not exist",
+            "invalid",
+        ]) {
+            ProviderErrorKind::InvalidRequest
+        } else if contains_any(&[
+            "server_error",
+            "overloaded",
+            "unavailable",
+            "timeout",
+            "internal",
+        ]) {
+            ProviderErrorKind::Transient
+        } else {
+            ProviderErrorKind::Unknown
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ProviderErrorKind::RateLimit
+                | ProviderErrorKind::Transient
+                | ProviderErrorKind::Unknown
+        )
+    }
+}
+
+impl CategorizedError for CompletionError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            CompletionError::Http(e) if e.is_timeout() => ErrorCategory::Timeout,
+            CompletionError::Secret(e) => e.category(),
+            _ => ErrorCategory::Provider,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_provider_error() {
+        let cases = [
+            (
+                Some("requests"),
+                Some("rate_limit_exceeded"),
+                ProviderErrorKind::RateLimit,
+            ),
+            (
+                Some("insufficient_quota"),
+                None,
+                ProviderErrorKind::QuotaExceeded,
+            ),
+            (
+                None,
+                Some("invalid_api_key"),
+                ProviderErrorKind::Authentication,
+            ),
+            (
+                Some("invalid_request_error"),
+                Some("context_length_exceeded"),
+                ProviderErrorKind::InvalidRequest,
+            ),
+            (
+                None,
+                Some("content_filter"),
+                ProviderErrorKind::ContentFilter,
+            ),
+            (Some("server_error"), None, ProviderErrorKind::Transient),
+            (None, None, ProviderErrorKind::Unknown),
+        ];
+        for (error_type, code, kind) in cases {
+            assert_eq!(ProviderError::new(error_type, code, "").kind, kind);
+        }
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let rate_limit = ProviderError::new(None, Some("rate_limit_exceeded"), "slow down");
+        assert!(CompletionError::Provider(rate_limit).is_retryable());
+        let auth = ProviderError::new(None, Some("invalid_api_key"), "bad key");
+        assert!(!CompletionError::Provider(auth).is_retryable());
+        assert!(!CompletionError::Request("bad request".into()).is_retryable());
+    }
+}
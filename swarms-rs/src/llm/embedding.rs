@@ -0,0 +1,55 @@
+//! Helpers to compare and group embedding vectors.
+
+/// Cosine similarity of two vectors, `0.0` if either of them is a zero vector.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm_a = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// Greedily group near-duplicate embeddings.
+///
+/// Each embedding joins the first cluster whose representative (the first member)
+/// has a similarity of at least `threshold`, otherwise it starts a new cluster.
+/// Returns clusters of indices, the first index of each cluster is its representative.
+pub fn cluster_by_similarity(embeddings: &[Vec<f32>], threshold: f32) -> Vec<Vec<usize>> {
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    for (index, embedding) in embeddings.iter().enumerate() {
+        match clusters
+            .iter_mut()
+            .find(|cluster| cosine_similarity(&embeddings[cluster[0]], embedding) >= threshold)
+        {
+            Some(cluster) => cluster.push(index),
+            None => clusters.push(vec![index]),
+        }
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < f32::EPSILON);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 1.0]).abs() < f32::EPSILON);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_cluster_by_similarity() {
+        let embeddings = vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![0.99, 0.01],
+            vec![0.01, 0.99],
+        ];
+        let clusters = cluster_by_similarity(&embeddings, 0.95);
+        assert_eq!(clusters, vec![vec![0, 2], vec![1, 3]]);
+    }
+}
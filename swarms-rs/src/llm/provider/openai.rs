@@ -1,4 +1,4 @@
-use std::{cmp::Ordering, env};
+use std::{cmp::Ordering, env, sync::Arc};
 
 use async_openai::{
     Client,
@@ -12,58 +12,87 @@ use async_openai::{
         ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageContent,
         ChatCompletionRequestToolMessageContentPart, ChatCompletionRequestUserMessageArgs,
         ChatCompletionRequestUserMessageContentPart, ChatCompletionToolArgs,
-        ChatCompletionToolType, CreateChatCompletionRequestArgs, FunctionCall, FunctionObjectArgs,
-        ImageUrl, InputAudio, InputAudioFormat,
+        ChatCompletionToolType, CreateChatCompletionRequestArgs, CreateEmbeddingRequestArgs,
+        EmbeddingInput, FunctionCall, FunctionObjectArgs, ImageUrl, InputAudio, InputAudioFormat,
+        ResponseFormat as OpenAIResponseFormat, ResponseFormatJsonSchema,
     },
 };
 use futures::future::BoxFuture;
 
 use crate::{
     agent::swarms_agent::SwarmsAgentBuilder,
+    config::{ConfigError, SwarmsConfig},
     llm::{
-        self, CompletionError, Model,
-        request::{CompletionRequest, CompletionResponse},
+        self, CompletionError, EmbeddingModel, Model, ProviderError,
+        request::{CompletionRequest, CompletionResponse, ResponseFormat},
     },
+    secrets::SecretProvider,
 };
 
 #[derive(Clone)]
 pub struct OpenAI {
     client: Client<OpenAIConfig>,
+    http_client: reqwest::Client,
+    api_base: String,
+    /// Fetches the API key before every request, overrides the key of `client`.
+    secret_provider: Option<Arc<dyn SecretProvider>>,
     model: String,
+    embedding_model: String,
     system_prompt: Option<String>,
 }
 
 impl OpenAI {
     pub fn new<S: Into<String>>(api_key: S) -> Self {
-        let config = OpenAIConfig::new().with_api_key(api_key);
-        let http_client = reqwest::ClientBuilder::new()
-            .user_agent("swamrs-rs")
-            .build()
-            .expect("TLS backend cannot be initialized");
-        let client = Client::with_config(config).with_http_client(http_client);
-        Self {
-            client,
-            model: "gpt-3.5-turbo".to_owned(),
-            system_prompt: None,
-        }
+        Self::from_url("https://api.openai.com/v1".to_owned(), api_key.into())
     }
 
     pub fn from_url<S: Into<String>>(base_url: S, api_key: S) -> Self {
+        let api_base = base_url.into();
         let config = OpenAIConfig::new()
-            .with_api_base(base_url)
+            .with_api_base(&api_base)
             .with_api_key(api_key);
         let http_client = reqwest::ClientBuilder::new()
             .user_agent("swamrs-rs")
             .build()
             .expect("TLS backend cannot be initialized");
-        let client = Client::with_config(config).with_http_client(http_client);
+        let client = Client::with_config(config).with_http_client(http_client.clone());
         Self {
             client,
+            http_client,
+            api_base,
+            secret_provider: None,
             model: "gpt-3.5-turbo".to_owned(),
+            embedding_model: "text-embedding-3-small".to_owned(),
             system_prompt: None,
         }
     }
 
+    /// Create a client which fetches the API key from the secret provider before every
+    /// request, so the key can be rotated without recreating the client.
+    pub fn from_secret_provider(
+        base_url: impl Into<String>,
+        secret_provider: impl SecretProvider + 'static,
+    ) -> Self {
+        Self::from_url(base_url.into(), String::new()).set_secret_provider(secret_provider)
+    }
+
+    pub fn set_secret_provider(mut self, secret_provider: impl SecretProvider + 'static) -> Self {
+        self.secret_provider = Some(Arc::new(secret_provider));
+        self
+    }
+
+    /// The client with the current API key.
+    async fn client(&self) -> Result<Client<OpenAIConfig>, CompletionError> {
+        let Some(secret_provider) = &self.secret_provider else {
+            return Ok(self.client.clone());
+        };
+        let api_key = secret_provider.get_secret().await?;
+        let config = OpenAIConfig::new()
+            .with_api_base(&self.api_base)
+            .with_api_key(api_key);
+        Ok(Client::with_config(config).with_http_client(self.http_client.clone()))
+    }
+
     pub fn from_env() -> Self {
         let base_url =
             env::var("OPENAI_API_BASE").unwrap_or("https://api.openai.com/v1".to_owned());
@@ -71,6 +100,24 @@ impl OpenAI {
         Self::from_url(base_url, api_key)
     }
 
+    /// Create a client for an OpenAI compatible provider of the global config, the default
+    /// model of the config is used if set.
+    pub fn from_config(config: &SwarmsConfig, provider: &str) -> Result<Self, ConfigError> {
+        let provider_config = config.provider(provider);
+        let api_key = provider_config
+            .and_then(|p| p.api_key.clone())
+            .ok_or_else(|| ConfigError::MissingApiKey(provider.to_owned()))?;
+        let base_url = provider_config
+            .and_then(|p| p.base_url.clone())
+            .unwrap_or("https://api.openai.com/v1".to_owned());
+
+        let openai = Self::from_url(base_url, api_key);
+        Ok(match &config.default_model {
+            Some(model) => openai.set_model(model),
+            None => openai,
+        })
+    }
+
     pub fn from_env_with_model<S: Into<String>>(model: S) -> Self {
         let openai = Self::from_env();
         openai.set_model(model)
@@ -81,6 +128,11 @@ impl OpenAI {
         self
     }
 
+    pub fn set_embedding_model<S: Into<String>>(mut self, model: S) -> Self {
+        self.embedding_model = model.into();
+        self
+    }
+
     pub fn set_system_prompt<S: Into<String>>(&mut self, prompt: S) {
         self.system_prompt = Some(prompt.into());
     }
@@ -137,16 +189,40 @@ impl Model for OpenAI {
             if let Some(temperature) = request.temperature {
                 create_request_builder.temperature(temperature as f32);
             }
+            if let Some(seed) = request.seed {
+                create_request_builder.seed(seed as i64);
+            }
+            if let Some(response_format) = request.response_format {
+                create_request_builder.response_format(match response_format {
+                    ResponseFormat::JsonObject => OpenAIResponseFormat::JsonObject,
+                    ResponseFormat::JsonSchema {
+                        name,
+                        schema,
+                        strict,
+                    } => OpenAIResponseFormat::JsonSchema {
+                        json_schema: ResponseFormatJsonSchema {
+                            description: None,
+                            name,
+                            schema: Some(schema),
+                            strict: Some(strict),
+                        },
+                    },
+                });
+            }
             if !request.tools.is_empty() {
                 create_request_builder.tools(
                     request
                         .tools
                         .into_iter()
                         .map(|tool| {
+                            let mut function = FunctionObjectArgs::default();
+                            if tool.strict {
+                                function.strict(true);
+                            }
                             ChatCompletionToolArgs::default()
                                 .r#type(ChatCompletionToolType::Function)
                                 .function(
-                                    FunctionObjectArgs::default()
+                                    function
                                         .name(tool.name)
                                         .description(tool.description)
                                         .parameters(tool.parameters)
@@ -170,7 +246,12 @@ impl Model for OpenAI {
             );
 
             let response: CompletionResponse<async_openai::types::CreateChatCompletionResponse> =
-                self.client.chat().create(create_request).await?.into();
+                self.client()
+                    .await?
+                    .chat()
+                    .create(create_request)
+                    .await?
+                    .into();
 
             tracing::debug!(
                 "OpenAI response: {}",
@@ -182,12 +263,34 @@ impl Model for OpenAI {
     }
 }
 
+impl EmbeddingModel for OpenAI {
+    fn embed(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>, CompletionError>> {
+        Box::pin(async move {
+            let request = CreateEmbeddingRequestArgs::default()
+                .model(self.embedding_model.clone())
+                .input(EmbeddingInput::StringArray(texts))
+                .build()?;
+            let mut response = self.client().await?.embeddings().create(request).await?;
+            response.data.sort_by_key(|embedding| embedding.index);
+            Ok(response
+                .data
+                .into_iter()
+                .map(|embedding| embedding.embedding)
+                .collect())
+        })
+    }
+}
+
 impl From<async_openai::error::OpenAIError> for CompletionError {
     fn from(error: async_openai::error::OpenAIError) -> Self {
         match error {
             async_openai::error::OpenAIError::Reqwest(e) => e.into(),
             async_openai::error::OpenAIError::ApiError(api_error) => {
-                CompletionError::Provider(api_error.to_string())
+                CompletionError::Provider(ProviderError::new(
+                    api_error.r#type.as_deref(),
+                    api_error.code.as_deref(),
+                    api_error.to_string(),
+                ))
             }
             async_openai::error::OpenAIError::JSONDeserialize(e) => e.into(),
             async_openai::error::OpenAIError::FileSaveError(e) => CompletionError::Other(e),
@@ -1,8 +1,9 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
 
 use super::completion::{AssistantContent, Message};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct CompletionRequest {
     pub prompt: Message,
     pub system_prompt: Option<String>,
@@ -10,6 +11,56 @@ pub struct CompletionRequest {
     pub tools: Vec<ToolDefinition>,
     pub temperature: Option<f64>,
     pub max_tokens: Option<u64>,
+    /// Sample deterministically, if the provider supports it.
+    pub seed: Option<u64>,
+    /// Constrain the answer to JSON, if the provider supports it.
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// Format of the model's answer, enforced by the provider instead of only asked for in the
+/// prompt, e.g. OpenAI's JSON mode and structured outputs.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Any JSON object.
+    JsonObject,
+    /// JSON matching the schema.
+    JsonSchema {
+        name: String,
+        schema: Value,
+        /// Guarantee that the answer matches the schema, the schema must be a strict mode
+        /// schema, see [`strict_schema`].
+        #[serde(default)]
+        strict: bool,
+    },
+}
+
+impl ResponseFormat {
+    /// JSON matching the schema of `T`. Strict mode needs an object at the root, so it's only
+    /// used for structs and the like.
+    pub fn json_schema<T: schemars::JsonSchema>() -> Self {
+        let mut schema = schemars::schema_for!(T).as_value().to_owned();
+        let strict = schema
+            .as_object()
+            .is_some_and(|root| root.get("type") == Some(&json!("object")));
+        if strict {
+            strict_schema(&mut schema);
+        }
+        // Providers only allow letters, digits, underscores and dashes
+        let name = T::schema_name()
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+                true => c,
+                false => '_',
+            })
+            .take(64)
+            .collect();
+        Self::JsonSchema {
+            name,
+            schema,
+            strict,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -17,6 +68,95 @@ pub struct ToolDefinition {
     pub name: String,
     pub description: String,
     pub parameters: serde_json::Value,
+    /// Ask the provider to guarantee that the arguments match the parameters' schema, e.g.
+    /// OpenAI's structured outputs. Set it with [`ToolDefinition::strict`].
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl ToolDefinition {
+    /// Switch to strict mode, the parameters' schema is converted to the subset strict mode
+    /// supports: objects don't allow additional properties and require all their properties,
+    /// optional properties are required but nullable instead.
+    pub fn strict(mut self) -> Self {
+        strict_schema(&mut self.parameters);
+        self.strict = true;
+        self
+    }
+}
+
+/// Convert the schema to a strict mode schema in place, see [`ToolDefinition::strict`].
+pub fn strict_schema(schema: &mut Value) {
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    let required = match schema.get("required") {
+        Some(Value::Array(required)) => required.clone(),
+        _ => Vec::new(),
+    };
+    if let Some(Value::Object(properties)) = schema.get_mut("properties") {
+        for (name, property) in properties.iter_mut() {
+            if !required.contains(&Value::String(name.clone())) {
+                make_nullable(property);
+            }
+        }
+        let names = properties.keys().cloned().map(Value::String).collect();
+        schema.insert("required".to_owned(), Value::Array(names));
+        schema.insert("additionalProperties".to_owned(), Value::Bool(false));
+    } else if is_object(schema) {
+        schema.insert("properties".to_owned(), Value::Object(Map::new()));
+        schema.insert("required".to_owned(), Value::Array(Vec::new()));
+        schema.insert("additionalProperties".to_owned(), Value::Bool(false));
+    }
+
+    for key in ["properties", "$defs", "definitions"] {
+        if let Some(Value::Object(schemas)) = schema.get_mut(key) {
+            schemas.values_mut().for_each(strict_schema);
+        }
+    }
+    for key in ["anyOf", "oneOf", "allOf"] {
+        if let Some(Value::Array(schemas)) = schema.get_mut(key) {
+            schemas.iter_mut().for_each(strict_schema);
+        }
+    }
+    if let Some(items) = schema.get_mut("items") {
+        strict_schema(items);
+    }
+}
+
+// Whether the schema's type is `object`, possibly nullable
+fn is_object(schema: &Map<String, Value>) -> bool {
+    let object = Value::String("object".to_owned());
+    match schema.get("type") {
+        Some(Value::Array(types)) => types.contains(&object),
+        kind => kind == Some(&object),
+    }
+}
+
+// Allow `null` as well, for optional properties of strict schemas
+fn make_nullable(schema: &mut Value) {
+    let null = Value::String("null".to_owned());
+    match schema.get_mut("type") {
+        Some(Value::Array(types)) => {
+            if !types.contains(&null) {
+                types.push(null);
+            }
+        }
+        Some(kind @ Value::String(_)) if *kind != null => {
+            *kind = Value::Array(vec![kind.take(), null]);
+        }
+        Some(_) => {}
+        // e.g. a `$ref` or `anyOf`
+        None => {
+            let nullable = schema
+                .get("anyOf")
+                .and_then(Value::as_array)
+                .is_some_and(|variants| variants.iter().any(|v| v.get("type") == Some(&null)));
+            if !nullable {
+                *schema = json!({ "anyOf": [schema.take(), { "type": "null" }] });
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -24,3 +164,110 @@ pub struct CompletionResponse<T> {
     pub choice: Vec<AssistantContent>,
     pub raw_response: T,
 }
+
+#[cfg(test)]
+mod tests {
+    use schemars::JsonSchema;
+    use swarms_macro::tool;
+
+    use super::*;
+    use crate::{self as swarms_rs, tool::Tool};
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct Address {
+        city: String,
+        zip: Option<String>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    #[schemars(rename = "contact card")]
+    struct Contact {
+        name: String,
+        address: Option<Address>,
+        tags: Vec<String>,
+    }
+
+    #[tool(description = "Look up a word", strict)]
+    fn define(word: String) -> Result<String, std::io::Error> {
+        Ok(word)
+    }
+
+    #[test]
+    fn test_strict() {
+        let mut schema = schemars::schema_for!(Contact).as_value().to_owned();
+        strict_schema(&mut schema);
+        assert_eq!(schema["additionalProperties"], json!(false));
+        assert_eq!(schema["required"], json!(["address", "name", "tags"]));
+        assert_eq!(schema["properties"]["name"]["type"], json!("string"));
+        // Already nullable, not wrapped again
+        let address = &schema["properties"]["address"]["anyOf"];
+        assert_eq!(address.as_array().unwrap().len(), 2);
+        let address = &schema["$defs"]["Address"];
+        assert_eq!(address["additionalProperties"], json!(false));
+        assert_eq!(address["required"], json!(["city", "zip"]));
+        assert_eq!(
+            address["properties"]["zip"]["type"],
+            json!(["string", "null"])
+        );
+
+        let mut optional = json!({
+            "type": "object",
+            "properties": {
+                "limit": { "type": "integer" },
+                "filter": { "$ref": "#/$defs/Filter" },
+                "options": { "type": "object" },
+            },
+        });
+        strict_schema(&mut optional);
+        assert_eq!(optional["required"], json!(["filter", "limit", "options"]));
+        assert_eq!(
+            optional["properties"]["limit"]["type"],
+            json!(["integer", "null"])
+        );
+        assert_eq!(
+            optional["properties"]["filter"],
+            json!({ "anyOf": [{ "$ref": "#/$defs/Filter" }, { "type": "null" }] })
+        );
+        assert_eq!(
+            optional["properties"]["options"],
+            json!({
+                "type": ["object", "null"],
+                "properties": {},
+                "required": [],
+                "additionalProperties": false,
+            })
+        );
+
+        let definition = Tool::definition(&Define);
+        assert!(definition.strict);
+        assert_eq!(definition.parameters["required"], json!(["word"]));
+        assert_eq!(definition.parameters["additionalProperties"], json!(false));
+    }
+
+    #[test]
+    fn test_response_format() {
+        let ResponseFormat::JsonSchema {
+            name,
+            schema,
+            strict,
+        } = ResponseFormat::json_schema::<Contact>()
+        else {
+            panic!("not a JSON schema");
+        };
+        assert_eq!(name, "contact_card");
+        assert!(strict);
+        assert_eq!(schema["additionalProperties"], json!(false));
+
+        let ResponseFormat::JsonSchema { strict, .. } = ResponseFormat::json_schema::<Vec<i32>>()
+        else {
+            panic!("not a JSON schema");
+        };
+        assert!(!strict);
+        assert_eq!(
+            serde_json::to_value(ResponseFormat::JsonObject).unwrap(),
+            json!({ "type": "json_object" })
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Route each completion request to a cheap or an expensive model.
+
+use std::collections::{HashMap, HashSet};
+
+use futures::future::BoxFuture;
+
+use super::{
+    CompletionError, Model,
+    completion::AssistantContent,
+    request::{CompletionRequest, CompletionResponse},
+    tokenizer::{count_message_tokens, count_tokens},
+};
+use crate::task_classifier::TaskClassifier;
+
+const CLASSIFIER_PROMPT: &str = "Classify the complexity of the user's task. \
+Reply with exactly one word: SIMPLE if a small model can answer it reliably, \
+COMPLEX if it needs multi-step reasoning, planning, math, code or expert knowledge.";
+
+/// Which model a request is routed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    Cheap,
+    Expensive,
+}
+
+/// A [`Model`] which sends simple requests to a cheap model and everything else to an
+/// expensive model.
+///
+/// A request is routed to the expensive model if any of the following is true, checked in
+/// order:
+/// - the prompt, including history and system prompt, is longer than `max_cheap_tokens`
+/// - the request offers more than `max_cheap_tools` tools
+/// - the request offers one of the `expensive_tools`
+/// - the task classifier, if set, files the task under a category routed to the expensive
+///   model, tasks of a category routed to the cheap model skip the classifier model
+/// - the classifier model, if set, classifies the task as complex
+#[derive(Clone)]
+pub struct ModelRouter<M> {
+    cheap: M,
+    expensive: M,
+    classifier: Option<M>,
+    max_cheap_tokens: usize,
+    max_cheap_tools: usize,
+    expensive_tools: HashSet<String>,
+    task_classifier: Option<TaskClassifier>,
+    category_routes: HashMap<String, Route>,
+}
+
+impl<M> ModelRouter<M>
+where
+    M: Model + Send + Sync,
+    M::RawCompletionResponse: Send,
+{
+    pub fn new(cheap: M, expensive: M) -> Self {
+        Self {
+            cheap,
+            expensive,
+            classifier: None,
+            max_cheap_tokens: 2000,
+            max_cheap_tools: usize::MAX,
+            expensive_tools: HashSet::new(),
+            task_classifier: None,
+            category_routes: HashMap::new(),
+        }
+    }
+
+    /// Ask a tiny model to classify the task before routing, its answer is only used if the
+    /// other heuristics route to the cheap model.
+    pub fn classifier(mut self, classifier: M) -> Self {
+        self.classifier = Some(classifier);
+        self
+    }
+
+    pub fn max_cheap_tokens(mut self, max_cheap_tokens: usize) -> Self {
+        self.max_cheap_tokens = max_cheap_tokens;
+        self
+    }
+
+    pub fn max_cheap_tools(mut self, max_cheap_tools: usize) -> Self {
+        self.max_cheap_tools = max_cheap_tools;
+        self
+    }
+
+    /// Requests offering this tool always go to the expensive model.
+    pub fn expensive_tool(mut self, tool_name: impl Into<String>) -> Self {
+        self.expensive_tools.insert(tool_name.into());
+        self
+    }
+
+    /// Route the tasks by their category, see [`ModelRouter::category_route`].
+    pub fn task_classifier(mut self, task_classifier: TaskClassifier) -> Self {
+        self.task_classifier = Some(task_classifier);
+        self
+    }
+
+    /// Send the tasks of the category to the model, unless the heuristics already route them
+    /// to the expensive model.
+    pub fn category_route(mut self, category: impl Into<String>, route: Route) -> Self {
+        self.category_routes.insert(category.into(), route);
+        self
+    }
+
+    /// Decide which model the request is routed to.
+    pub async fn route(&self, request: &CompletionRequest) -> Route {
+        let model = self.cheap.name();
+        let tokens = count_message_tokens(&model, &request.chat_history)
+            + count_message_tokens(&model, std::slice::from_ref(&request.prompt))
+            + count_tokens(&model, request.system_prompt.as_deref().unwrap_or_default());
+        if tokens > self.max_cheap_tokens
+            || request.tools.len() > self.max_cheap_tools
+            || request
+                .tools
+                .iter()
+                .any(|tool| self.expensive_tools.contains(&tool.name))
+        {
+            return Route::Expensive;
+        }
+
+        let Some(task) = request.prompt.rag_text() else {
+            return Route::Cheap;
+        };
+        if let Some(task_classifier) = &self.task_classifier {
+            let category = task_classifier.classify(&task).await;
+            if let Some(route) = self.category_routes.get(&category) {
+                return *route;
+            }
+        }
+
+        let Some(classifier) = &self.classifier else {
+            return Route::Cheap;
+        };
+        let classification = CompletionRequest {
+            prompt: task.into(),
+            system_prompt: Some(CLASSIFIER_PROMPT.to_owned()),
+            chat_history: vec![],
+            tools: vec![],
+            temperature: Some(0.0),
+            max_tokens: Some(5),
+            seed: None,
+            response_format: None,
+        };
+        match classifier.completion(classification).await {
+            Ok(response) => match response.choice.first() {
+                Some(AssistantContent::Text(text))
+                    if text.text.to_uppercase().contains("SIMPLE") =>
+                {
+                    Route::Cheap
+                }
+                _ => Route::Expensive,
+            },
+            Err(e) => {
+                tracing::warn!("| model router | Classifier failed, use expensive model: {e}");
+                Route::Expensive
+            }
+        }
+    }
+}
+
+impl<M> Model for ModelRouter<M>
+where
+    M: Model + Send + Sync,
+    M::RawCompletionResponse: Send,
+{
+    type RawCompletionResponse = M::RawCompletionResponse;
+
+    fn name(&self) -> String {
+        format!("{}|{}", self.cheap.name(), self.expensive.name())
+    }
+
+    fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> BoxFuture<'_, Result<CompletionResponse<Self::RawCompletionResponse>, CompletionError>>
+    {
+        Box::pin(async move {
+            let route = self.route(&request).await;
+            tracing::debug!("| model router | Route: {:?}", route);
+            match route {
+                Route::Cheap => self.cheap.completion(request).await,
+                Route::Expensive => self.expensive.completion(request).await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{llm::request::ToolDefinition, task_classifier::Category, test_support::FnModel};
+
+    // Named and answering `name`
+    fn model(name: &str) -> FnModel {
+        FnModel::reply(name, name)
+    }
+
+    fn request(prompt: &str, tools: &[&str]) -> CompletionRequest {
+        CompletionRequest {
+            prompt: prompt.into(),
+            system_prompt: None,
+            chat_history: vec![],
+            tools: tools
+                .iter()
+                .map(|name| ToolDefinition {
+                    name: name.to_string(),
+                    description: String::new(),
+                    parameters: serde_json::Value::Null,
+                    strict: false,
+                })
+                .collect(),
+            temperature: None,
+            max_tokens: None,
+            seed: None,
+            response_format: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_by_heuristics() {
+        let router = ModelRouter::new(model("cheap"), model("expensive"))
+            .max_cheap_tokens(20)
+            .max_cheap_tools(1)
+            .expensive_tool("code_interpreter");
+
+        assert_eq!(router.route(&request("hi", &[])).await, Route::Cheap);
+        assert_eq!(
+            router.route(&request(&"word ".repeat(50), &[])).await,
+            Route::Expensive
+        );
+        assert_eq!(
+            router.route(&request("hi", &["search", "fetch"])).await,
+            Route::Expensive
+        );
+        assert_eq!(
+            router.route(&request("hi", &["code_interpreter"])).await,
+            Route::Expensive
+        );
+    }
+
+    #[tokio::test]
+    async fn test_route_by_classifier() {
+        let simple =
+            ModelRouter::new(model("cheap"), model("expensive")).classifier(model("SIMPLE"));
+        assert_eq!(simple.route(&request("hi", &[])).await, Route::Cheap);
+
+        let complex =
+            ModelRouter::new(model("cheap"), model("expensive")).classifier(model("COMPLEX"));
+        let response = complex.completion(request("hi", &[])).await.unwrap();
+        assert_eq!(response.choice, vec!["expensive".to_owned().into()]);
+    }
+
+    #[tokio::test]
+    async fn test_route_by_category() {
+        let categories = vec![
+            Category::new("code", "Programming tasks"),
+            Category::new("chat", "Small talk"),
+        ];
+        let task_classifier = TaskClassifier::from_fn(categories, |task| {
+            if task.contains("fn ") { "code" } else { "chat" }.to_owned()
+        });
+        let router = ModelRouter::new(model("cheap"), model("expensive"))
+            .classifier(model("COMPLEX"))
+            .task_classifier(task_classifier)
+            .category_route("code", Route::Expensive)
+            .category_route("chat", Route::Cheap);
+
+        assert_eq!(
+            router.route(&request("fn main() {}", &[])).await,
+            Route::Expensive
+        );
+        assert_eq!(router.route(&request("hi there", &[])).await, Route::Cheap);
+    }
+}
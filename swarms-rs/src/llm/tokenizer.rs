@@ -0,0 +1,199 @@
+//! Best-effort token counting for when the provider doesn't report usage.
+//!
+//! The counts are estimates tuned per tokenizer family, they are close enough for
+//! context window management and budgets, but not exact.
+
+use super::completion::{AssistantContent, Message, ToolResultContent, UserContent};
+
+/// Extra tokens each chat message costs for role and formatting markers.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Tokenizer families with different compression characteristics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenizerFamily {
+    /// `cl100k_base`, used by gpt-3.5 and gpt-4.
+    Cl100k,
+    /// `o200k_base`, used by gpt-4o and the o-series.
+    O200k,
+    /// Anthropic Claude models.
+    Claude,
+    /// Everything else, e.g. DeepSeek, Qwen, Llama.
+    Generic,
+}
+
+impl TokenizerFamily {
+    /// Guess the tokenizer family from the model name.
+    pub fn from_model(model: &str) -> Self {
+        let model = model.to_lowercase();
+        if model.starts_with("gpt-4o")
+            || model.starts_with("gpt-4.1")
+            || model.starts_with("o1")
+            || model.starts_with("o3")
+            || model.starts_with("o4")
+        {
+            TokenizerFamily::O200k
+        } else if model.starts_with("gpt-") || model.contains("text-embedding") {
+            TokenizerFamily::Cl100k
+        } else if model.contains("claude") {
+            TokenizerFamily::Claude
+        } else {
+            TokenizerFamily::Generic
+        }
+    }
+
+    /// Average number of ASCII word characters per token.
+    fn chars_per_token(&self) -> f64 {
+        match self {
+            TokenizerFamily::Cl100k => 4.0,
+            TokenizerFamily::O200k => 4.2,
+            TokenizerFamily::Claude => 3.5,
+            TokenizerFamily::Generic => 3.8,
+        }
+    }
+
+    /// Average number of tokens per non-ASCII character (e.g. CJK).
+    fn tokens_per_non_ascii_char(&self) -> f64 {
+        match self {
+            TokenizerFamily::Cl100k => 1.2,
+            TokenizerFamily::O200k => 0.8,
+            TokenizerFamily::Claude => 1.1,
+            TokenizerFamily::Generic => 0.9,
+        }
+    }
+
+    /// Estimate the number of tokens in `text`.
+    pub fn count_tokens(&self, text: &str) -> usize {
+        let mut tokens = 0.0;
+        let mut word_len = 0usize;
+        let mut in_newlines = false;
+
+        let flush_word = |word_len: &mut usize, tokens: &mut f64| {
+            if *word_len > 0 {
+                // short words are usually a single token
+                *tokens += (*word_len as f64 / self.chars_per_token()).round().max(1.0);
+                *word_len = 0;
+            }
+        };
+
+        for c in text.chars() {
+            if c.is_ascii_alphanumeric() {
+                word_len += 1;
+                in_newlines = false;
+                continue;
+            }
+
+            flush_word(&mut word_len, &mut tokens);
+            if c == '\n' {
+                // consecutive newlines are usually merged into one token
+                if !in_newlines {
+                    tokens += 1.0;
+                }
+                in_newlines = true;
+                continue;
+            }
+            in_newlines = false;
+
+            if c.is_whitespace() {
+                // spaces are merged into the following word
+                continue;
+            }
+            if c.is_ascii() {
+                tokens += 1.0;
+            } else {
+                tokens += self.tokens_per_non_ascii_char();
+            }
+        }
+        flush_word(&mut word_len, &mut tokens);
+
+        tokens.ceil() as usize
+    }
+}
+
+/// Estimate the number of tokens in `text` for the given model.
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    TokenizerFamily::from_model(model).count_tokens(text)
+}
+
+/// Estimate the number of tokens a list of chat messages costs for the given model.
+///
+/// Only text content is counted, media content is ignored.
+pub fn count_message_tokens(model: &str, messages: &[Message]) -> usize {
+    let family = TokenizerFamily::from_model(model);
+    messages
+        .iter()
+        .map(|message| {
+            let content_tokens: usize = match message {
+                Message::User { content } => content
+                    .iter()
+                    .map(|content| match content {
+                        UserContent::Text(text) => family.count_tokens(&text.text),
+                        UserContent::ToolResult(result) => result
+                            .content
+                            .iter()
+                            .map(|content| match content {
+                                ToolResultContent::Text(text) => family.count_tokens(&text.text),
+                                ToolResultContent::Image(_) => 0,
+                            })
+                            .sum(),
+                        _ => 0,
+                    })
+                    .sum(),
+                Message::Assistant { content } => content
+                    .iter()
+                    .map(|content| match content {
+                        AssistantContent::Text(text) => family.count_tokens(&text.text),
+                        AssistantContent::ToolCall(tool_call) => {
+                            family.count_tokens(&tool_call.function.name)
+                                + family.count_tokens(&tool_call.function.arguments.to_string())
+                        }
+                    })
+                    .sum(),
+            };
+            content_tokens + MESSAGE_OVERHEAD_TOKENS
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_family_from_model() {
+        assert_eq!(
+            TokenizerFamily::from_model("gpt-4o-mini"),
+            TokenizerFamily::O200k
+        );
+        assert_eq!(
+            TokenizerFamily::from_model("gpt-3.5-turbo"),
+            TokenizerFamily::Cl100k
+        );
+        assert_eq!(
+            TokenizerFamily::from_model("claude-3-5-sonnet"),
+            TokenizerFamily::Claude
+        );
+        assert_eq!(
+            TokenizerFamily::from_model("deepseek-chat"),
+            TokenizerFamily::Generic
+        );
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        assert_eq!(count_tokens("gpt-4", ""), 0);
+        // "hello" + "world" + "!"
+        assert_eq!(count_tokens("gpt-4", "hello world!"), 3);
+        // consecutive newlines are merged
+        assert_eq!(count_tokens("gpt-4", "a\n\n\nb"), 3);
+        assert!(count_tokens("gpt-4", "你好世界") >= 4);
+    }
+
+    #[test]
+    fn test_count_message_tokens() {
+        let messages = vec![Message::user("hello"), Message::assistant("world")];
+        assert_eq!(
+            count_message_tokens("gpt-4", &messages),
+            2 + 2 * MESSAGE_OVERHEAD_TOKENS
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! Locales of the prompt scaffolding the framework adds around tasks, e.g. the section
+//! headings of boss prompts and of recalled memories, and the built-in instructions like the
+//! default planning prompt.
+//!
+//! Each [`Locale`] has a [`Catalog`] with the translated texts, so agents and bosses which
+//! work in another language don't send prompts mixing it with English headings. Texts written
+//! by the user, like system prompts and agent descriptions, are never translated.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "de")]
+    German,
+    #[serde(rename = "fr")]
+    French,
+    #[serde(rename = "es")]
+    Spanish,
+    #[serde(rename = "zh")]
+    Chinese,
+}
+
+/// The scaffolding texts of a locale.
+#[derive(Debug)]
+pub struct Catalog {
+    /// Asks for a plan of the task which follows it, used if the agent plans without a
+    /// planning prompt of its own.
+    pub planning_prompt: &'static str,
+    pub current_date_and_time: &'static str,
+    pub environment: &'static str,
+    pub timezone: &'static str,
+    pub context: &'static str,
+    pub available_agents: &'static str,
+    /// There are no agents.
+    pub no_agents: &'static str,
+    pub model: &'static str,
+    pub tools: &'static str,
+    pub tool_examples: &'static str,
+    /// An agent has no tools.
+    pub no_tools: &'static str,
+    pub known_entities: &'static str,
+    pub relevant_past_tasks: &'static str,
+    pub task: &'static str,
+    pub output: &'static str,
+    pub feedback: &'static str,
+    /// Instructions of the boss of a [`MultiAgentOrchestrator`].
+    ///
+    /// [`MultiAgentOrchestrator`]: crate::multi_agent_orchestrator::MultiAgentOrchestrator
+    pub boss_instructions: &'static str,
+    /// Asks the boss to route a task again after the verifier rejected its output.
+    pub reroute_prompt: &'static str,
+    pub triage_prompt: &'static str,
+    /// Asks for `PASS`, or `FAIL:` with the reason, these keywords are never translated.
+    pub verifier_prompt: &'static str,
+    /// Asks for a clarifying question in `<QUESTION>` tags if the task is unclear, used by
+    /// interactive agents. The tags are never translated.
+    pub interactive_prompt: &'static str,
+    /// Asks to summarize the older turns of a conversation which no longer fits the context
+    /// window.
+    pub context_summarizer_prompt: &'static str,
+    /// The heading of the summary which replaces the older turns.
+    pub conversation_summary: &'static str,
+    /// Asks for the facts a persona learned, one per line starting with `- `, or `NONE`. The
+    /// keyword is never translated.
+    pub reflection_prompt: &'static str,
+    /// Asks for the category of a task, followed by the categories.
+    pub classifier_prompt: &'static str,
+}
+
+const ENGLISH: Catalog = Catalog {
+    planning_prompt: "Before you start, make a step-by-step plan for the following task:",
+    current_date_and_time: "Current Date and Time",
+    environment: "Environment",
+    timezone: "Timezone",
+    context: "Context",
+    available_agents: "Available Agents",
+    no_agents: "None",
+    model: "Model",
+    tools: "Tools",
+    tool_examples: "Tool Examples",
+    no_tools: "none",
+    known_entities: "Known Entities",
+    relevant_past_tasks: "Relevant Past Tasks",
+    task: "Task",
+    output: "Output",
+    feedback: "Feedback",
+    boss_instructions: "You are a boss agent responsible for routing tasks to the most appropriate specialized agent.
+
+Your job is to:
+1. Analyze the incoming task
+2. Select the most appropriate agent based on their descriptions, models and tools
+3. Provide clear reasoning for your selection
+4. Optionally modify the task to better suit the selected agent's capabilities
+
+Always select exactly one agent that best matches the task requirements.",
+    reroute_prompt: "Select the agent which can fix it, this may be the same agent with a modified task.",
+    triage_prompt: "Rewrite the task below into a clear, self-contained task: fix \
+        typos, resolve ambiguous references and drop irrelevant details, but keep every requirement. \
+        Reply with the rewritten task only.",
+    verifier_prompt: "Check whether the output below fully and correctly solves the \
+        task. Reply with PASS if it does, otherwise with FAIL: followed by what is wrong or missing.",
+    interactive_prompt: "If the task is ambiguous or misses information you need, ask \
+        the user exactly one clarifying question by replying only with <QUESTION>your \
+        question</QUESTION>. Otherwise answer normally.",
+    context_summarizer_prompt: "Summarize the following conversation concisely. Keep all \
+        facts, decisions, tool results and open questions which are needed to continue the task.",
+    conversation_summary: "Summary of earlier conversation",
+    reflection_prompt: "You maintain the long-term memory of a character. Given the \
+        character's profile and their latest exchange, list the new facts about themselves, other \
+        people or the world which the character should remember. Skip facts the profile already \
+        contains. Reply with one fact per line, each starting with \"- \", or with NONE.",
+    classifier_prompt: "Classify the user's task into exactly one of the categories \
+        below. Reply with the name of the category only.",
+};
+
+const GERMAN: Catalog = Catalog {
+    planning_prompt: "Erstelle vor dem Beginn einen schrittweisen Plan für die folgende Aufgabe:",
+    current_date_and_time: "Aktuelles Datum und Uhrzeit",
+    environment: "Umgebung",
+    timezone: "Zeitzone",
+    context: "Kontext",
+    available_agents: "Verfügbare Agenten",
+    no_agents: "Keine",
+    model: "Modell",
+    tools: "Werkzeuge",
+    tool_examples: "Werkzeugbeispiele",
+    no_tools: "keine",
+    known_entities: "Bekannte Entitäten",
+    relevant_past_tasks: "Relevante frühere Aufgaben",
+    task: "Aufgabe",
+    output: "Ausgabe",
+    feedback: "Feedback",
+    boss_instructions: "Du bist ein Boss-Agent, der Aufgaben an den am besten geeigneten spezialisierten Agenten weiterleitet.
+
+Deine Aufgabe ist es:
+1. Die eingehende Aufgabe zu analysieren
+2. Den am besten geeigneten Agenten anhand seiner Beschreibung, seines Modells und seiner Werkzeuge auszuwählen
+3. Deine Auswahl klar zu begründen
+4. Die Aufgabe optional an die Fähigkeiten des ausgewählten Agenten anzupassen
+
+Wähle immer genau einen Agenten, der die Anforderungen der Aufgabe am besten erfüllt.",
+    reroute_prompt: "Wähle den Agenten, der das beheben kann, das kann derselbe Agent mit einer geänderten \
+        Aufgabe sein.",
+    triage_prompt: "Formuliere die folgende Aufgabe in eine klare, eigenständige Aufgabe um: \
+        korrigiere Tippfehler, löse mehrdeutige Verweise auf und lass irrelevante Details weg, aber \
+        behalte jede Anforderung bei. Antworte nur mit der umformulierten Aufgabe.",
+    verifier_prompt: "Prüfe, ob die folgende Ausgabe die Aufgabe vollständig und korrekt löst. \
+        Antworte mit PASS, wenn ja, andernfalls mit FAIL: gefolgt von dem, was falsch ist oder fehlt.",
+    interactive_prompt: "Wenn die Aufgabe mehrdeutig ist oder Informationen fehlen, \
+        die du brauchst, stelle dem Benutzer genau eine Rückfrage, indem du nur mit \
+        <QUESTION>deine Frage</QUESTION> antwortest. Antworte andernfalls normal.",
+    context_summarizer_prompt: "Fasse das folgende Gespräch knapp zusammen. Behalte alle \
+        Fakten, Entscheidungen, Werkzeugergebnisse und offenen Fragen bei, die zum Fortsetzen der \
+        Aufgabe nötig sind.",
+    conversation_summary: "Zusammenfassung des bisherigen Gesprächs",
+    reflection_prompt: "Du verwaltest das Langzeitgedächtnis einer Figur. Liste \
+        anhand des Profils der Figur und ihres letzten Austauschs die neuen Fakten über sie selbst, \
+        andere Personen oder die Welt auf, die sich die Figur merken sollte. Lass Fakten weg, die \
+        das Profil bereits enthält. Antworte mit einem Fakt pro Zeile, jeweils beginnend mit \"- \", \
+        oder mit NONE.",
+    classifier_prompt: "Ordne die Aufgabe des Benutzers genau einer der folgenden \
+        Kategorien zu. Antworte nur mit dem Namen der Kategorie.",
+};
+
+const FRENCH: Catalog = Catalog {
+    planning_prompt: "Avant de commencer, établis un plan étape par étape pour la tâche suivante :",
+    current_date_and_time: "Date et heure actuelles",
+    environment: "Environnement",
+    timezone: "Fuseau horaire",
+    context: "Contexte",
+    available_agents: "Agents disponibles",
+    no_agents: "Aucun",
+    model: "Modèle",
+    tools: "Outils",
+    tool_examples: "Exemples d'outils",
+    no_tools: "aucun",
+    known_entities: "Entités connues",
+    relevant_past_tasks: "Tâches passées pertinentes",
+    task: "Tâche",
+    output: "Résultat",
+    feedback: "Retour",
+    boss_instructions:
+        "Tu es un agent chef chargé d'attribuer les tâches à l'agent spécialisé le plus approprié.
+
+Ton rôle est de :
+1. Analyser la tâche reçue
+2. Choisir l'agent le plus approprié selon sa description, son modèle et ses outils
+3. Justifier clairement ton choix
+4. Adapter éventuellement la tâche aux capacités de l'agent choisi
+
+Choisis toujours exactement un agent qui correspond le mieux aux exigences de la tâche.",
+    reroute_prompt: "Choisis l'agent qui peut corriger cela, ce peut être le même agent avec une tâche \
+        modifiée.",
+    triage_prompt: "Reformule la tâche ci-dessous en une tâche claire et autonome : corrige les fautes \
+        de frappe, résous les références ambiguës et supprime les détails inutiles, mais conserve \
+        chaque exigence. Réponds uniquement avec la tâche reformulée.",
+    verifier_prompt: "Vérifie si le résultat ci-dessous résout entièrement et correctement la tâche. \
+        Réponds PASS si c'est le cas, sinon FAIL: suivi de ce qui est faux ou manquant.",
+    interactive_prompt: "Si la tâche est ambiguë ou s'il manque des informations dont \
+        tu as besoin, pose exactement une question de clarification à l'utilisateur en répondant \
+        uniquement par <QUESTION>ta question</QUESTION>. Sinon, réponds normalement.",
+    context_summarizer_prompt: "Résume la conversation suivante de manière concise. Conserve \
+        tous les faits, décisions, résultats d'outils et questions ouvertes nécessaires pour \
+        poursuivre la tâche.",
+    conversation_summary: "Résumé de la conversation précédente",
+    reflection_prompt: "Tu gères la mémoire à long terme d'un personnage. À partir du \
+        profil du personnage et de son dernier échange, liste les nouveaux faits sur lui-même, sur \
+        d'autres personnes ou sur le monde dont le personnage doit se souvenir. Ignore les faits que \
+        le profil contient déjà. Réponds avec un fait par ligne, chacun commençant par \"- \", ou \
+        avec NONE.",
+    classifier_prompt: "Classe la tâche de l'utilisateur dans exactement une des \
+        catégories ci-dessous. Réponds uniquement avec le nom de la catégorie.",
+};
+
+const SPANISH: Catalog = Catalog {
+    planning_prompt: "Antes de empezar, haz un plan paso a paso para la siguiente tarea:",
+    current_date_and_time: "Fecha y hora actuales",
+    environment: "Entorno",
+    timezone: "Zona horaria",
+    context: "Contexto",
+    available_agents: "Agentes disponibles",
+    no_agents: "Ninguno",
+    model: "Modelo",
+    tools: "Herramientas",
+    tool_examples: "Ejemplos de herramientas",
+    no_tools: "ninguna",
+    known_entities: "Entidades conocidas",
+    relevant_past_tasks: "Tareas anteriores relevantes",
+    task: "Tarea",
+    output: "Resultado",
+    feedback: "Comentarios",
+    boss_instructions:
+        "Eres un agente jefe responsable de asignar las tareas al agente especializado más adecuado.
+
+Tu trabajo es:
+1. Analizar la tarea recibida
+2. Seleccionar el agente más adecuado según su descripción, su modelo y sus herramientas
+3. Explicar claramente el motivo de tu selección
+4. Opcionalmente, adaptar la tarea a las capacidades del agente seleccionado
+
+Selecciona siempre exactamente un agente que se ajuste mejor a los requisitos de la tarea.",
+    reroute_prompt: "Selecciona el agente que pueda corregirlo, puede ser el mismo agente con una tarea \
+        modificada.",
+    triage_prompt: "Reescribe la siguiente tarea como una tarea clara e independiente: corrige las \
+        erratas, resuelve las referencias ambiguas y elimina los detalles irrelevantes, pero \
+        conserva todos los requisitos. Responde solo con la tarea reescrita.",
+    verifier_prompt: "Comprueba si el resultado siguiente resuelve la tarea de forma completa y \
+        correcta. Responde PASS si es así; de lo contrario, FAIL: seguido de lo que está mal o falta.",
+    interactive_prompt: "Si la tarea es ambigua o le falta información que necesitas, \
+        haz al usuario exactamente una pregunta aclaratoria respondiendo solo con \
+        <QUESTION>tu pregunta</QUESTION>. De lo contrario, responde con normalidad.",
+    context_summarizer_prompt: "Resume la siguiente conversación de forma concisa. Conserva \
+        todos los hechos, decisiones, resultados de herramientas y preguntas abiertas necesarios \
+        para continuar la tarea.",
+    conversation_summary: "Resumen de la conversación anterior",
+    reflection_prompt: "Mantienes la memoria a largo plazo de un personaje. A partir \
+        del perfil del personaje y de su último intercambio, enumera los nuevos hechos sobre sí \
+        mismo, otras personas o el mundo que el personaje debería recordar. Omite los hechos que el \
+        perfil ya contiene. Responde con un hecho por línea, cada uno empezando por \"- \", o con \
+        NONE.",
+    classifier_prompt: "Clasifica la tarea del usuario en exactamente una de las \
+        categorías siguientes. Responde solo con el nombre de la categoría.",
+};
+
+const CHINESE: Catalog = Catalog {
+    planning_prompt: "开始之前，请为以下任务制定一个分步计划：",
+    current_date_and_time: "当前日期和时间",
+    environment: "环境",
+    timezone: "时区",
+    context: "上下文",
+    available_agents: "可用的代理",
+    no_agents: "无",
+    model: "模型",
+    tools: "工具",
+    tool_examples: "工具示例",
+    no_tools: "无",
+    known_entities: "已知实体",
+    relevant_past_tasks: "相关的历史任务",
+    task: "任务",
+    output: "输出",
+    feedback: "反馈",
+    boss_instructions: "你是一个负责将任务分配给最合适的专业代理的主管代理。
+
+你的工作是：
+1. 分析收到的任务
+2. 根据代理的描述、模型和工具选择最合适的代理
+3. 清楚地说明你的选择理由
+4. 可选地修改任务，使其更适合所选代理的能力
+
+始终只选择一个最符合任务要求的代理。",
+    reroute_prompt: "选择能够修正它的代理，也可以是同一个代理配合修改后的任务。",
+    triage_prompt: "将下面的任务改写为一个清晰、独立的任务：修正错别字，消除含糊的指代，删去无关的细节，\
+        但保留每一项要求。只回复改写后的任务。",
+    verifier_prompt: "检查下面的输出是否完整且正确地完成了任务。如果是，回复 PASS，否则回复 FAIL: \
+        并说明哪里有错误或遗漏。",
+    interactive_prompt: "如果任务含糊不清或缺少你需要的信息，请只回复 <QUESTION>你的问题</QUESTION>，\
+        向用户提出一个澄清问题。否则正常回答。",
+    context_summarizer_prompt: "简明地总结以下对话。保留继续任务所需的所有事实、决定、工具结果和未解决的问题。",
+    conversation_summary: "先前对话的摘要",
+    reflection_prompt: "你负责维护一个角色的长期记忆。根据角色的资料和其最近一次对话，列出角色应当记住的关于\
+        自己、其他人或世界的新事实。跳过资料中已有的事实。每行回复一个事实，每行以 \"- \" 开头，\
+        或者回复 NONE。",
+    classifier_prompt: "将用户的任务归入以下类别中恰好一个类别。只回复类别名称。",
+};
+
+impl Locale {
+    /// The locale of a BCP 47 language tag, e.g. `de-DE`, by its language.
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.split(['-', '_']).next()?.to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::English),
+            "de" => Some(Locale::German),
+            "fr" => Some(Locale::French),
+            "es" => Some(Locale::Spanish),
+            "zh" => Some(Locale::Chinese),
+            _ => None,
+        }
+    }
+
+    /// The language tag of the locale, e.g. `de`.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Locale::English => "en",
+            Locale::German => "de",
+            Locale::French => "fr",
+            Locale::Spanish => "es",
+            Locale::Chinese => "zh",
+        }
+    }
+
+    pub const fn catalog(&self) -> &'static Catalog {
+        match self {
+            Locale::English => &ENGLISH,
+            Locale::German => &GERMAN,
+            Locale::French => &FRENCH,
+            Locale::Spanish => &SPANISH,
+            Locale::Chinese => &CHINESE,
+        }
+    }
+}
+
+/// A section heading of a prompt, e.g. `### Context:`.
+pub(crate) fn heading(title: &str) -> String {
+    format!("### {title}:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locales() {
+        assert_eq!(Locale::from_tag("de-DE"), Some(Locale::German));
+        assert_eq!(Locale::from_tag("zh_Hans"), Some(Locale::Chinese));
+        assert_eq!(Locale::from_tag("EN"), Some(Locale::English));
+        assert_eq!(Locale::from_tag("ja-JP"), None);
+        assert_eq!(
+            serde_json::to_string(&Locale::French).unwrap(),
+            format!("\"{}\"", Locale::French.tag())
+        );
+        assert_eq!(heading(Locale::Spanish.catalog().context), "### Contexto:");
+    }
+}
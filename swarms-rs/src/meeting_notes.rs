@@ -0,0 +1,268 @@
+//! Structured "meeting notes" of a swarm run, written by a summarizer agent.
+//!
+//! [`MeetingNotesSummarizer::summarize`] turns a [`SwarmConversation`] into [`MeetingNotes`]
+//! with the decisions, action items and the contribution of every agent. Attached to a
+//! workflow with its builder's `meeting_notes`, the notes of every run are stored next to the
+//! run's metadata as `<task hash>.notes.json`.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    agent::{Agent, AgentError, RunOptions},
+    conversation::SwarmConversation,
+    error::{CategorizedError, ErrorCategory},
+    persistence::{self, Persistence, PersistenceError},
+    swarm::MetadataSchema,
+};
+
+const SUMMARIZER_PROMPT: &str = "You take the meeting notes of a team of AI agents. \
+Read the transcript and reply only with JSON: {\"summary\": <string>, \
+\"decisions\": [<string>, ...], \
+\"action_items\": [{\"owner\": <agent name or null>, \"description\": <string>}, ...], \
+\"contributions\": [{\"agent_name\": <string>, \"summary\": <string>}, ...]}. \
+List one contribution for every agent of the transcript.";
+
+#[derive(Debug, Error)]
+pub enum MeetingNotesError {
+    #[error("Agent error: {0}")]
+    AgentError(#[from] AgentError),
+    #[error("Invalid meeting notes: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Persistence error: {0}")]
+    PersistenceError(#[from] PersistenceError),
+}
+
+impl CategorizedError for MeetingNotesError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            MeetingNotesError::AgentError(e) => e.category(),
+            MeetingNotesError::JsonError(_) => ErrorCategory::Provider,
+            MeetingNotesError::PersistenceError(e) => e.category(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MeetingNotes {
+    pub summary: String,
+    #[serde(default)]
+    pub decisions: Vec<String>,
+    #[serde(default)]
+    pub action_items: Vec<ActionItem>,
+    #[serde(default)]
+    pub contributions: Vec<Contribution>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionItem {
+    /// The agent responsible for the item, if any.
+    #[serde(default)]
+    pub owner: Option<String>,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contribution {
+    pub agent_name: String,
+    pub summary: String,
+}
+
+impl MeetingNotes {
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("# Meeting notes\n\n{}\n", self.summary);
+        if !self.decisions.is_empty() {
+            markdown.push_str("\n## Decisions\n\n");
+            for decision in &self.decisions {
+                markdown.push_str(&format!("- {decision}\n"));
+            }
+        }
+        if !self.action_items.is_empty() {
+            markdown.push_str("\n## Action items\n\n");
+            for item in &self.action_items {
+                match &item.owner {
+                    Some(owner) => {
+                        markdown.push_str(&format!("- [ ] {owner}: {}\n", item.description))
+                    }
+                    None => markdown.push_str(&format!("- [ ] {}\n", item.description)),
+                }
+            }
+        }
+        if !self.contributions.is_empty() {
+            markdown.push_str("\n## Contributions\n\n");
+            for contribution in &self.contributions {
+                markdown.push_str(&format!(
+                    "- **{}**: {}\n",
+                    contribution.agent_name, contribution.summary
+                ));
+            }
+        }
+        markdown
+    }
+
+    /// Save the notes as pretty JSON.
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), MeetingNotesError> {
+        let data = serde_json::to_string_pretty(self)?;
+        persistence::save_to_file(data, path).await?;
+        Ok(())
+    }
+}
+
+/// Writes [`MeetingNotes`] with an agent, the agent's system prompt is replaced with the
+/// summarizer's instructions if it supports [`RunOptions`].
+pub struct MeetingNotesSummarizer {
+    agent: Box<dyn Agent>,
+}
+
+impl Clone for MeetingNotesSummarizer {
+    fn clone(&self) -> Self {
+        Self {
+            agent: self.agent.clone_box(),
+        }
+    }
+}
+
+impl MeetingNotesSummarizer {
+    pub fn new(agent: Box<dyn Agent>) -> Self {
+        Self { agent }
+    }
+
+    pub async fn summarize(
+        &self,
+        conversation: &SwarmConversation,
+    ) -> Result<MeetingNotes, MeetingNotesError> {
+        let options = RunOptions {
+            system_prompt_override: Some(SUMMARIZER_PROMPT.to_owned()),
+            ..Default::default()
+        };
+        let reply = self
+            .agent
+            .run_with_options(transcript(conversation), options)
+            .await?;
+        let json = match (reply.find('{'), reply.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &reply[start..=end],
+            _ => &reply,
+        };
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Summarize a workflow run and save the notes, a failure is logged and skipped so it
+    /// doesn't fail the run.
+    pub(crate) async fn write_notes(
+        &self,
+        metadata: &MetadataSchema,
+        persistence: &dyn Persistence,
+        path: &Path,
+    ) {
+        let result = match self.summarize(&SwarmConversation::from(metadata)).await {
+            Ok(notes) => match serde_json::to_vec_pretty(&notes) {
+                Ok(data) => persistence.save(path, data).await.map_err(Into::into),
+                Err(e) => Err(e.into()),
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!(
+                "| meeting notes | Task: {} | Failed to write meeting notes: {}",
+                metadata.task,
+                e
+            );
+        }
+    }
+}
+
+fn transcript(conversation: &SwarmConversation) -> String {
+    let mut transcript = String::from("Transcript:\n");
+    for log in &conversation.logs {
+        transcript.push_str(&format!(
+            "\n[{}] on task: {}\n{}\n",
+            log.agent_name, log.task, log.response
+        ));
+    }
+    for failure in &conversation.failures {
+        transcript.push_str(&format!(
+            "\n[{}] failed task: {}\nError: {}\n",
+            failure.agent_name, failure.task, failure.error
+        ));
+    }
+    transcript
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        concurrent_workflow::ConcurrentWorkflow,
+        test_support::{FnAgent, TestAgent, temp_dir},
+    };
+
+    /// Replies with fixed notes, the summary is the transcript it got.
+    fn note_taker() -> Box<dyn Agent> {
+        Box::new(FnAgent::new("note-taker", |task| {
+            let notes = serde_json::json!({
+                "summary": task,
+                "decisions": ["Use the 2024 data"],
+                "action_items": [{"owner": "writer", "description": "Retry the report"}],
+                "contributions": [{"agent_name": "researcher", "summary": "Found the data"}],
+            });
+            format!("Notes: {notes}")
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_summarize() {
+        let mut conversation = SwarmConversation::new();
+        conversation.add_log("researcher".into(), "find".into(), "2024 data".into());
+        conversation.add_failure("writer".into(), "write".into(), "timeout");
+
+        let notes = MeetingNotesSummarizer::new(note_taker())
+            .summarize(&conversation)
+            .await
+            .unwrap();
+        assert!(
+            notes
+                .summary
+                .contains("[researcher] on task: find\n2024 data")
+        );
+        assert!(
+            notes
+                .summary
+                .contains("[writer] failed task: write\nError: timeout")
+        );
+        assert_eq!(notes.decisions, ["Use the 2024 data"]);
+        assert_eq!(notes.action_items[0].owner.as_deref(), Some("writer"));
+        assert_eq!(notes.contributions[0].agent_name, "researcher");
+
+        let markdown = notes.to_markdown();
+        assert!(markdown.contains("## Decisions\n\n- Use the 2024 data\n"));
+        assert!(markdown.contains("- [ ] writer: Retry the report\n"));
+
+        let dir = temp_dir();
+        let path = dir.join("notes.json");
+        notes.save(&path).await.unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(serde_json::from_str::<MeetingNotes>(&saved).unwrap(), notes);
+    }
+
+    #[tokio::test]
+    async fn test_workflow_meeting_notes() {
+        let dir = temp_dir();
+        let workflow = ConcurrentWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .meeting_notes(MeetingNotesSummarizer::new(note_taker()))
+            .build();
+        workflow.run("task").await.unwrap();
+
+        let notes_path = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_string_lossy().ends_with(".notes.json"))
+            .unwrap();
+        let notes: MeetingNotes =
+            serde_json::from_slice(&std::fs::read(notes_path).unwrap()).unwrap();
+        assert!(notes.summary.contains("[test] on task: task\ndone: task"));
+    }
+}
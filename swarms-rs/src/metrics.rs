@@ -0,0 +1,213 @@
+//! Prometheus metrics, enabled with the `metrics` feature.
+//!
+//! Use [`gather`] to embed the metrics into an existing exporter, or [`serve`] to expose
+//! them on a `/metrics` endpoint.
+
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use dashmap::DashMap;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+pub const AGENT_RUNS_STARTED: &str = "swarms_agent_runs_started_total";
+pub const AGENT_RUNS_COMPLETED: &str = "swarms_agent_runs_completed_total";
+pub const AGENT_RUNS_FAILED: &str = "swarms_agent_runs_failed_total";
+pub const AGENT_TOKENS: &str = "swarms_agent_tokens_total";
+pub const AGENT_TOOL_CALLS: &str = "swarms_agent_tool_calls_total";
+pub const AGENT_RUN_DURATION: &str = "swarms_agent_run_duration_seconds";
+pub const EXPERIMENT_RUNS: &str = "swarms_experiment_runs_total";
+pub const EXPERIMENT_RUN_DURATION: &str = "swarms_experiment_run_duration_seconds";
+pub const TASKS_CLASSIFIED: &str = "swarms_tasks_classified_total";
+
+const HELP: &[(&str, &str)] = &[
+    (AGENT_RUNS_STARTED, "Number of agent runs started."),
+    (AGENT_RUNS_COMPLETED, "Number of agent runs completed."),
+    (AGENT_RUNS_FAILED, "Number of agent runs failed."),
+    (
+        AGENT_TOKENS,
+        "Number of tokens used, estimated if the provider doesn't report usage.",
+    ),
+    (AGENT_TOOL_CALLS, "Number of tool calls."),
+    (AGENT_RUN_DURATION, "Latency of agent runs in seconds."),
+    (EXPERIMENT_RUNS, "Number of experiment runs per variant."),
+    (
+        EXPERIMENT_RUN_DURATION,
+        "Latency of experiment runs per variant in seconds.",
+    ),
+    (TASKS_CLASSIFIED, "Number of classified tasks per category."),
+];
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+type Key = (&'static str, String);
+
+#[derive(Default)]
+struct Registry {
+    counters: DashMap<Key, AtomicU64>,
+    histograms: DashMap<Key, Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// Render labels as `key="value",...`, values are escaped.
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{key}=\"{value}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Increase a counter by `value`.
+pub fn inc_counter(name: &'static str, labels: &[(&str, &str)], value: u64) {
+    REGISTRY
+        .counters
+        .entry((name, render_labels(labels)))
+        .or_default()
+        .fetch_add(value, Ordering::Relaxed);
+}
+
+/// Record an observation in a histogram.
+pub fn observe(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let mut histogram = REGISTRY
+        .histograms
+        .entry((name, render_labels(labels)))
+        .or_default();
+    for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+        if value <= *bound {
+            *bucket += 1;
+        }
+    }
+    histogram.sum += value;
+    histogram.count += 1;
+}
+
+/// Render all metrics in the Prometheus text exposition format.
+pub fn gather() -> String {
+    let mut output = String::new();
+    for (name, help) in HELP {
+        let counters = REGISTRY
+            .counters
+            .iter()
+            .filter(|entry| entry.key().0 == *name)
+            .map(|entry| (entry.key().1.clone(), entry.value().load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+        let histograms = REGISTRY
+            .histograms
+            .iter()
+            .filter(|entry| entry.key().0 == *name)
+            .map(|entry| {
+                let histogram = entry.value();
+                (
+                    entry.key().1.clone(),
+                    histogram.buckets,
+                    histogram.sum,
+                    histogram.count,
+                )
+            })
+            .collect::<Vec<_>>();
+        if counters.is_empty() && histograms.is_empty() {
+            continue;
+        }
+
+        let _ = writeln!(output, "# HELP {name} {help}");
+        if !counters.is_empty() {
+            let _ = writeln!(output, "# TYPE {name} counter");
+            for (labels, value) in counters {
+                let _ = writeln!(output, "{name}{{{labels}}} {value}");
+            }
+        } else {
+            let _ = writeln!(output, "# TYPE {name} histogram");
+            for (labels, buckets, sum, count) in histograms {
+                let separator = if labels.is_empty() { "" } else { "," };
+                for (bound, bucket) in BUCKETS.iter().zip(buckets) {
+                    let _ = writeln!(
+                        output,
+                        "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {bucket}"
+                    );
+                }
+                let _ = writeln!(
+                    output,
+                    "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {count}"
+                );
+                let _ = writeln!(output, "{name}_sum{{{labels}}} {sum}");
+                let _ = writeln!(output, "{name}_count{{{labels}}} {count}");
+            }
+        }
+    }
+    output
+}
+
+/// Serve the metrics on `http://{addr}/metrics` until the task is dropped.
+pub async fn serve(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            let n = match stream.read(&mut buf).await {
+                Ok(n) => n,
+                Err(_) => return,
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let response = if request.starts_with("GET /metrics") {
+                let body = gather();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_owned()
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gather() {
+        inc_counter(AGENT_RUNS_STARTED, &[("agent", "test\"agent")], 2);
+        observe(AGENT_RUN_DURATION, &[("agent", "test")], 0.3);
+
+        let output = gather();
+        assert!(output.contains("# TYPE swarms_agent_runs_started_total counter"));
+        assert!(output.contains("swarms_agent_runs_started_total{agent=\"test\\\"agent\"} 2"));
+        assert!(
+            output
+                .contains("swarms_agent_run_duration_seconds_bucket{agent=\"test\",le=\"0.1\"} 0")
+        );
+        assert!(
+            output
+                .contains("swarms_agent_run_duration_seconds_bucket{agent=\"test\",le=\"0.5\"} 1")
+        );
+        assert!(output.contains("swarms_agent_run_duration_seconds_count{agent=\"test\"} 1"));
+    }
+}
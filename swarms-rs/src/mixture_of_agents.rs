@@ -0,0 +1,216 @@
+//! Experts answering independently, an aggregator combining their answers.
+//!
+//! Every layer runs all experts concurrently, from the second layer on they also get the
+//! answers of the previous layer to refine. The aggregator then gets the task with the answers
+//! of the last layer and writes the final answer.
+
+use futures::future::{self, BoxFuture};
+use thiserror::Error;
+
+use crate::{
+    agent::{Agent, AgentError},
+    conversation::{AgentConversation, Participant},
+    error::{CategorizedError, ErrorCategory},
+    swarm::{Swarm, SwarmError, SwarmOutput},
+    utils::is_empty_task,
+};
+
+#[derive(Debug, Error)]
+pub enum MixtureOfAgentsError {
+    #[error("Tasks or Agents are empty")]
+    EmptyTasksOrAgents,
+    #[error("No aggregator agent is set")]
+    MissingAggregator,
+    #[error("Agent Error: {0}")]
+    AgentError(#[from] AgentError),
+}
+
+impl CategorizedError for MixtureOfAgentsError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            MixtureOfAgentsError::EmptyTasksOrAgents | MixtureOfAgentsError::MissingAggregator => {
+                ErrorCategory::Validation
+            }
+            MixtureOfAgentsError::AgentError(e) => e.category(),
+        }
+    }
+}
+
+pub struct MixtureOfAgentsBuilder {
+    name: String,
+    description: String,
+    agents: Vec<Box<dyn Agent>>,
+    aggregator: Option<Box<dyn Agent>>,
+    layers: usize,
+}
+
+impl MixtureOfAgentsBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Add an expert.
+    pub fn add_agent(mut self, agent: Box<dyn Agent>) -> Self {
+        self.agents.push(agent);
+        self
+    }
+
+    /// Replace the experts.
+    pub fn agents(mut self, agents: Vec<Box<dyn Agent>>) -> Self {
+        self.agents = agents;
+        self
+    }
+
+    /// The agent which combines the experts' answers into the final answer.
+    pub fn aggregator(mut self, aggregator: Box<dyn Agent>) -> Self {
+        self.aggregator = Some(aggregator);
+        self
+    }
+
+    /// Let the experts refine their answers this many times, 1 by default.
+    pub fn layers(mut self, layers: usize) -> Self {
+        self.layers = layers.max(1);
+        self
+    }
+
+    pub fn build(self) -> MixtureOfAgents {
+        MixtureOfAgents {
+            name: self.name,
+            description: self.description,
+            agents: self.agents,
+            aggregator: self.aggregator,
+            layers: self.layers,
+        }
+    }
+}
+
+pub struct MixtureOfAgents {
+    name: String,
+    description: String,
+    agents: Vec<Box<dyn Agent>>,
+    aggregator: Option<Box<dyn Agent>>,
+    layers: usize,
+}
+
+impl MixtureOfAgents {
+    pub fn builder() -> MixtureOfAgentsBuilder {
+        MixtureOfAgentsBuilder {
+            name: "MixtureOfAgents".to_owned(),
+            description: "Experts answering independently, an aggregator combining their answers."
+                .to_owned(),
+            agents: Vec::new(),
+            aggregator: None,
+            layers: 1,
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Run the experts and the aggregator on the task, returns the conversation of all
+    /// answers, the aggregator's answer last.
+    pub async fn run(
+        &self,
+        task: impl Into<String>,
+    ) -> Result<AgentConversation, MixtureOfAgentsError> {
+        let task = task.into();
+        if self.agents.is_empty() || is_empty_task(&task) {
+            return Err(MixtureOfAgentsError::EmptyTasksOrAgents);
+        }
+        let aggregator = self
+            .aggregator
+            .as_ref()
+            .ok_or(MixtureOfAgentsError::MissingAggregator)?;
+
+        let mut conversation = AgentConversation::new(self.name.clone());
+        conversation.add(Participant::human("User"), task.clone());
+        let mut prompt = task.clone();
+        for _ in 0..self.layers {
+            let answers =
+                future::try_join_all(self.agents.iter().map(|agent| agent.run(prompt.clone())))
+                    .await?;
+            for (agent, answer) in self.agents.iter().zip(&answers) {
+                conversation.add(Participant::agent(agent.name()), answer.clone());
+            }
+            prompt = format!(
+                "{task}\n\n### Answers of the experts:\n{}",
+                self.agents
+                    .iter()
+                    .zip(answers)
+                    .map(|(agent, answer)| format!("- {}: {answer}", agent.name()))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        let answer = aggregator.run(prompt).await?;
+        conversation.add(Participant::agent(aggregator.name()), answer);
+        Ok(conversation)
+    }
+}
+
+impl Swarm for MixtureOfAgents {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, task: String) -> BoxFuture<'_, Result<SwarmOutput, SwarmError>> {
+        Box::pin(async move {
+            self.run(task)
+                .await
+                .map(|output| Box::new(output) as _)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FnAgent;
+
+    #[tokio::test]
+    async fn test_aggregate() {
+        let expert = |name: &'static str| -> Box<dyn Agent> {
+            Box::new(FnAgent::new(name, move |task| {
+                format!("{name} x{}", task.matches("- ").count() + 1)
+            }))
+        };
+        let swarm = MixtureOfAgents::builder()
+            .agents(vec![expert("a"), expert("b")])
+            .aggregator(Box::new(FnAgent::new("judge", |task| task)))
+            .layers(2)
+            .build();
+        let conversation = swarm.run("task").await.unwrap();
+        let answers = conversation.history[1..]
+            .iter()
+            .map(|message| message.body().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            answers,
+            [
+                "a x1",
+                "b x1",
+                "a x3",
+                "b x3",
+                "task\n\n### Answers of the experts:\n- a: a x3\n- b: b x3"
+            ]
+        );
+
+        let missing = MixtureOfAgents::builder()
+            .add_agent(expert("a"))
+            .build()
+            .run("task")
+            .await;
+        assert!(matches!(
+            missing,
+            Err(MixtureOfAgentsError::MissingAggregator)
+        ));
+    }
+}
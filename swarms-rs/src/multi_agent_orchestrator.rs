@@ -1,20 +1,26 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Instant};
 
 use chrono::Local;
 use dashmap::DashMap;
-use futures::{StreamExt, stream};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use swarms_macro::tool;
 use thiserror::Error;
-use tokio::sync::mpsc;
 use uuid::Uuid;
 
 use crate::agent::swarms_agent::SwarmsAgent;
 use crate::{self as swarms_rs, llm};
 use crate::{
-    agent::{Agent, AgentError},
-    conversation::{AgentShortMemory, Role},
+    agent::{Agent, AgentError, RunOptions, boss_prompt::BossPrompt},
+    concurrency,
+    conversation::{AgentShortMemory, Participant},
+    error::{CategorizedError, ErrorCategory},
+    locale::{self, Locale},
+    persistence::PersistenceError,
+    routing_stats::RoutingStats,
+    structured_output::{self, StructuredOutputError},
+    task_classifier::{DEFAULT_CATEGORY, TaskClassifier},
+    utils::{has_empty_tasks, is_empty_task},
 };
 
 #[derive(Debug, Error)]
@@ -31,6 +37,32 @@ pub enum MultiAgentOrchestratorError {
     JsonError(#[from] serde_json::Error),
     #[error("Can not find the agent returned from boss")]
     AgentNotFound,
+    #[error("Tasks or Agents are empty")]
+    EmptyTasksOrAgents,
+    #[error("Persistence error: {0}")]
+    PersistenceError(#[from] PersistenceError),
+    #[error("Structured output error: {0}")]
+    StructuredOutputError(#[from] StructuredOutputError),
+    #[error("Task was not executed, enable_execute_task is false")]
+    NotExecuted,
+}
+
+impl CategorizedError for MultiAgentOrchestratorError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            MultiAgentOrchestratorError::NameOrDescriptionNotFound
+            | MultiAgentOrchestratorError::DuplicateAgentName(_)
+            | MultiAgentOrchestratorError::EmptyTasksOrAgents
+            | MultiAgentOrchestratorError::NotExecuted => ErrorCategory::Validation,
+            // the boss agent's reply is unusable
+            MultiAgentOrchestratorError::WrongBossResponse(_)
+            | MultiAgentOrchestratorError::JsonError(_)
+            | MultiAgentOrchestratorError::AgentNotFound => ErrorCategory::Provider,
+            MultiAgentOrchestratorError::AgentError(e) => e.category(),
+            MultiAgentOrchestratorError::PersistenceError(e) => e.category(),
+            MultiAgentOrchestratorError::StructuredOutputError(e) => e.category(),
+        }
+    }
 }
 
 pub struct MultiAgentOrchestrator<M>
@@ -39,9 +71,16 @@ where
     M::RawCompletionResponse: Clone + Send + Sync,
 {
     boss: SwarmsAgent<M>,
+    boss_prompt: BossPrompt,
     agents: Vec<Box<dyn Agent>>,
     router_conversation: AgentShortMemory,
     enable_execute_task: bool,
+    routing_stats: Option<RoutingStats>,
+    task_classifier: Option<TaskClassifier>,
+    triage: Option<Box<dyn Agent>>,
+    verifier: Option<Box<dyn Agent>>,
+    max_concurrency: Option<usize>,
+    locale: Locale,
 }
 
 impl<M> MultiAgentOrchestrator<M>
@@ -54,69 +93,258 @@ where
         agents: Vec<Box<dyn Agent>>,
         enable_execute_task: bool,
     ) -> Result<Self, MultiAgentOrchestratorError> {
+        if agents.is_empty() {
+            return Err(MultiAgentOrchestratorError::EmptyTasksOrAgents);
+        }
+        validate_agents(&agents)?;
         let router_conversation = AgentShortMemory::new();
+        let boss_prompt = BossPrompt::new(Locale::default().catalog().boss_instructions)
+            .agents(agents.iter().map(|agent| &**agent));
 
         Ok(Self {
-            boss: boss
-                .tool(SelectAgent)
-                .system_prompt(create_boss_system_prompt(&agents)?),
+            boss: boss.tool(SelectAgent),
+            boss_prompt,
             agents,
             router_conversation,
             enable_execute_task,
+            routing_stats: None,
+            task_classifier: None,
+            triage: None,
+            verifier: None,
+            max_concurrency: None,
+            locale: Locale::default(),
         })
     }
 
+    /// Learn from the executed tasks which agents do well, see [`RoutingStats`].
+    pub fn routing_stats(mut self, routing_stats: RoutingStats) -> Self {
+        self.routing_stats = Some(routing_stats);
+        self
+    }
+
+    /// File every task under a category, routing statistics are kept per category. Without
+    /// a classifier all tasks share one category.
+    pub fn task_classifier(mut self, task_classifier: TaskClassifier) -> Self {
+        self.task_classifier = Some(task_classifier);
+        self
+    }
+
+    pub fn get_routing_stats(&self) -> Option<&RoutingStats> {
+        self.routing_stats.as_ref()
+    }
+
+    /// A context variable the boss is told about, e.g. the user's locale.
+    pub fn context(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.boss_prompt = self.boss_prompt.context(key, value);
+        self
+    }
+
+    /// Whether to tell the boss the current date and time, `true` by default.
+    pub fn current_time(mut self, current_time: bool) -> Self {
+        self.boss_prompt = self.boss_prompt.current_time(current_time);
+        self
+    }
+
+    /// Normalize every task with the triage agent before it's routed, e.g. to fix typos,
+    /// resolve references or split off irrelevant context.
+    pub fn triage(mut self, triage: Box<dyn Agent>) -> Self {
+        self.triage = Some(triage);
+        self
+    }
+
+    /// Check the worker's output against the task with the verifier agent. If the output is
+    /// rejected the task is routed once more, with the verifier's feedback.
+    pub fn verifier(mut self, verifier: Box<dyn Agent>) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// Language of the boss's instructions and of the prompts of the triage and verifier
+    /// agents, English by default.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self.boss_prompt = self
+            .boss_prompt
+            .instructions(locale.catalog().boss_instructions)
+            .locale(locale);
+        self
+    }
+
+    /// Max number of tasks [`run_batch`](Self::run_batch) runs at once, unlimited by default.
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = Some(max_concurrency);
+        self
+    }
+
+    /// Triage the task, route it to an agent, execute and verify it. Triage and verification
+    /// only run if there is a triage and verifier agent, and verification only if the task is
+    /// executed.
     pub async fn run(
         &self,
         task: impl Into<String>,
+    ) -> Result<MultiAgentOrchestratorResult, MultiAgentOrchestratorError> {
+        self.run_stages(task.into(), None).await
+    }
+
+    /// Like [`MultiAgentOrchestrator::run`], but the selected agent is asked to answer with
+    /// JSON matching the schema of `T`, which is deserialized. An answer which doesn't match
+    /// is sent back to the agent once to be fixed, see [`structured_output::repair`]. The schema
+    /// is also passed to the agent's provider, see [`structured_output::options`].
+    pub async fn run_typed<T>(
+        &self,
+        task: impl Into<String>,
+    ) -> Result<(MultiAgentOrchestratorResult, T), MultiAgentOrchestratorError>
+    where
+        T: DeserializeOwned + JsonSchema,
+    {
+        let instructions = structured_output::instructions::<T>();
+        let options = structured_output::options::<T>();
+        let result = self
+            .run_stages(task.into(), Some((&instructions, &options)))
+            .await?;
+        let Some(response) = result.execution.response.clone() else {
+            return Err(MultiAgentOrchestratorError::NotExecuted);
+        };
+        // Safety: the agent was found by name before it ran
+        let agent = self
+            .find_agent_by_name(&result.execution.agent_name)
+            .unwrap();
+        let output = structured_output::repair(agent, response, 1).await?;
+        Ok((result, output))
+    }
+
+    // `output` instructions are appended to the task of the selected agent, which runs with
+    // the options
+    async fn run_stages(
+        &self,
+        task: String,
+        output: Option<(&str, &RunOptions)>,
     ) -> Result<MultiAgentOrchestratorResult, MultiAgentOrchestratorError> {
         let total_start = Local::now();
 
-        let task = task.into();
+        if is_empty_task(&task) {
+            return Err(MultiAgentOrchestratorError::EmptyTasksOrAgents);
+        }
         self.router_conversation.add(
             task.clone(),
             self.boss.name(),
-            Role::User("User".to_owned()),
+            Participant::human("User"),
             task.clone(),
         );
 
-        let boss_response_str = self.boss.run(task.clone()).await?;
-        let boss_response = serde_json::from_str::<SelectAgentResponse>(boss_response_str.trim())?;
-
-        self.router_conversation.add(
-            task.clone(),
-            self.boss.name(),
-            Role::Assistant(self.boss.name()),
-            boss_response_str,
-        );
+        let triaged = match &self.triage {
+            Some(triage) => {
+                let catalog = self.locale.catalog();
+                let prompt = format!(
+                    "{}\n\n{}\n{task}",
+                    catalog.triage_prompt,
+                    locale::heading(catalog.task)
+                );
+                let triaged = triage.run(prompt).await?.trim().to_owned();
+                self.router_conversation.add(
+                    task.clone(),
+                    self.boss.name(),
+                    Participant::agent(triage.name()),
+                    triaged.clone(),
+                );
+                Some(triaged).filter(|triaged| !is_empty_task(triaged) && *triaged != task)
+            }
+            None => None,
+        };
+        let routed_task = triaged.clone().unwrap_or_else(|| task.clone());
 
-        let selected_agent = match self.find_agent_by_name(&boss_response.selected_agent) {
-            Some(agent) => agent,
-            None => return Err(MultiAgentOrchestratorError::AgentNotFound),
+        let category = match &self.task_classifier {
+            Some(classifier) => classifier.classify(&routed_task).await,
+            None => DEFAULT_CATEGORY.to_owned(),
         };
 
-        let selected_agent_name = selected_agent.name();
-        let selected_agent_id = selected_agent.id();
-
-        let final_task = boss_response.modified_task.unwrap_or(task.clone());
-        let mut agent_response = None;
-
-        let execution_start = Local::now();
-        let mut execution_time = 0;
-        if !self.enable_execute_task {
-            tracing::info!("Task execution skipped (enable_execute_task=false)")
-        } else {
-            agent_response = Some(selected_agent.run(final_task.clone()).await?);
-            execution_time = Local::now()
-                .signed_duration_since(execution_start)
-                .num_seconds();
+        let mut verifications = Vec::new();
+        let mut feedback = None;
+        let (boss_response, selected_agent, final_task, agent_response, execution_time) = loop {
+            let boss_response = self
+                .route(&task, &routed_task, &category, feedback.as_deref())
+                .await?;
+            let selected_agent = self
+                .find_agent_by_name(&boss_response.selected_agent)
+                .ok_or(MultiAgentOrchestratorError::AgentNotFound)?;
+            let final_task = boss_response
+                .modified_task
+                .clone()
+                .unwrap_or(routed_task.clone());
+
+            if !self.enable_execute_task {
+                tracing::info!("Task execution skipped (enable_execute_task=false)");
+                break (boss_response, selected_agent, final_task, None, None);
+            }
+
+            let start = Instant::now();
+            let response = match output {
+                Some((instructions, options)) => {
+                    selected_agent
+                        .run_with_options(
+                            format!("{final_task}\n\n{instructions}"),
+                            options.clone(),
+                        )
+                        .await
+                }
+                None => selected_agent.run(final_task.clone()).await,
+            };
+            let verification = match (&response, &self.verifier) {
+                (Ok(response), Some(verifier)) => Some(
+                    self.verify(verifier.as_ref(), &task, selected_agent, response)
+                        .await?,
+                ),
+                _ => None,
+            };
+            let passed = verification.as_ref().is_none_or(|v| v.passed);
+            if let Some(stats) = &self.routing_stats
+                && let Err(e) = stats
+                    .record(
+                        &category,
+                        &selected_agent.name(),
+                        response.is_ok() && passed,
+                        start.elapsed(),
+                    )
+                    .await
+            {
+                tracing::warn!("Failed to save routing stats: {e}");
+            }
+            let response = response?;
+            let execution_time = start.elapsed().as_secs() as i64;
             self.router_conversation.add(
                 task.clone(),
                 self.boss.name(),
-                Role::Assistant(selected_agent_name.clone()),
-                agent_response.clone().unwrap(), // Safety: we just make it Some
+                Participant::agent(selected_agent.name()),
+                response.clone(),
             );
-        }
+
+            let Some(verification) = verification else {
+                break (
+                    boss_response,
+                    selected_agent,
+                    final_task,
+                    Some(response),
+                    Some(execution_time),
+                );
+            };
+            let reroute = !verification.passed && verifications.is_empty();
+            if reroute {
+                feedback = Some(format!(
+                    "The output of the agent {} was rejected: {}",
+                    verification.agent_name, verification.feedback
+                ));
+            }
+            verifications.push(verification);
+            if !reroute {
+                break (
+                    boss_response,
+                    selected_agent,
+                    final_task,
+                    Some(response),
+                    Some(execution_time),
+                );
+            }
+        };
 
         let total_time = Local::now()
             .signed_duration_since(total_start)
@@ -125,65 +353,143 @@ where
         Ok(MultiAgentOrchestratorResult {
             id: Uuid::new_v4(),
             timestamp: Local::now().timestamp(),
+            category,
             task: Task {
-                original: task.clone(),
-                modified: if task != final_task {
-                    Some(final_task)
-                } else {
-                    None
-                },
+                modified: Some(final_task).filter(|final_task| *final_task != routed_task),
+                original: task,
+                triaged,
             },
             boss_decision: BossDecision {
-                selected_agent: selected_agent_name.clone(),
+                selected_agent: selected_agent.name(),
                 reasoning: boss_response.reasoning,
             },
             execution: Execution {
-                agent_id: selected_agent_id,
-                agent_name: selected_agent_name,
+                agent_id: selected_agent.id(),
+                agent_name: selected_agent.name(),
                 was_executed: self.enable_execute_task,
                 response: agent_response,
-                execution_time: if self.enable_execute_task {
-                    Some(execution_time)
-                } else {
-                    None
-                },
+                execution_time,
             },
+            verifications,
             total_time,
         })
     }
 
+    // Ask the boss which agent should execute the task, `feedback` explains why the previous
+    // agent's output was rejected
+    async fn route(
+        &self,
+        task: &str,
+        routed_task: &str,
+        category: &str,
+        feedback: Option<&str>,
+    ) -> Result<SelectAgentResponse, MultiAgentOrchestratorError> {
+        // Rebuilt on every run, so the boss always knows the current time and routing history
+        let mut system_prompt = self.boss_prompt.build();
+        if let Some(stats) = &self.routing_stats {
+            stats.load().await?;
+            let agents = self
+                .agents
+                .iter()
+                .map(|agent| agent.name())
+                .collect::<Vec<_>>();
+            let history = stats.prompt(category, agents.iter().map(String::as_str));
+            system_prompt = format!("{system_prompt}\n\n{history}");
+        }
+        let options = RunOptions {
+            system_prompt_override: Some(system_prompt),
+            ..Default::default()
+        };
+        let catalog = self.locale.catalog();
+        let prompt = match feedback {
+            Some(feedback) => format!(
+                "{routed_task}\n\n{}\n{feedback}\n{}",
+                locale::heading(catalog.feedback),
+                catalog.reroute_prompt
+            ),
+            None => routed_task.to_owned(),
+        };
+
+        let boss_response_str = self.boss.run_with_options(prompt, options).await?;
+        let boss_response = serde_json::from_str::<SelectAgentResponse>(boss_response_str.trim())?;
+        self.router_conversation.add(
+            task.to_owned(),
+            self.boss.name(),
+            Participant::agent(self.boss.name()),
+            boss_response_str,
+        );
+        Ok(boss_response)
+    }
+
+    async fn verify(
+        &self,
+        verifier: &dyn Agent,
+        task: &str,
+        agent: &dyn Agent,
+        response: &str,
+    ) -> Result<Verification, MultiAgentOrchestratorError> {
+        let catalog = self.locale.catalog();
+        let prompt = format!(
+            "{}\n\n{}\n{task}\n\n{}\n{response}",
+            catalog.verifier_prompt,
+            locale::heading(catalog.task),
+            locale::heading(catalog.output)
+        );
+        let verdict = verifier.run(prompt).await?;
+        self.router_conversation.add(
+            task.to_owned(),
+            self.boss.name(),
+            Participant::agent(verifier.name()),
+            verdict.clone(),
+        );
+
+        let verdict = verdict.trim();
+        let passed = verdict.to_uppercase().starts_with("PASS");
+        let feedback = verdict
+            .split_once(':')
+            .map_or(verdict, |(_, feedback)| feedback)
+            .trim()
+            .to_owned();
+        Ok(Verification {
+            agent_name: agent.name(),
+            passed,
+            feedback,
+        })
+    }
+
     pub async fn run_batch(
         &self,
         tasks: Vec<String>,
     ) -> Result<DashMap<String, MultiAgentOrchestratorResult>, MultiAgentOrchestratorError> {
+        if has_empty_tasks(&tasks) {
+            return Err(MultiAgentOrchestratorError::EmptyTasksOrAgents);
+        }
         let results = DashMap::with_capacity(tasks.len());
 
-        let (tx, mut rx) = mpsc::channel(tasks.len());
-        stream::iter(tasks)
-            .for_each_concurrent(None, |task| {
-                let tx = tx.clone();
-                let orchestrator = self;
-                async move {
-                    let result = orchestrator.run(task.clone()).await;
-                    match result {
-                        Ok(result) => {
-                            tx.send((task, result)).await.unwrap();
-                        }
-                        Err(e) => {
-                            tracing::error!(
-                                "| multi agent orchestrator  | Task:  {} | Error: {}",
-                                task,
-                                e
-                            );
-                        }
-                    }
+        let outcomes =
+            concurrency::run_bounded(&tasks, self.max_concurrency, |task| self.run(task.clone()))
+                .await;
+        for (index, outcome) in outcomes {
+            let task = &tasks[index];
+            match outcome {
+                Ok(Ok(result)) => {
+                    results.insert(task.clone(), result);
                 }
-            })
-            .await;
-        drop(tx);
-
-        while let Some((task, result)) = rx.recv().await {
-            results.insert(task, result);
+                Ok(Err(e)) => {
+                    tracing::error!(
+                        "| multi agent orchestrator  | Task:  {} | Error: {}",
+                        task,
+                        e
+                    );
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "| multi agent orchestrator  | Task:  {} | Error: {}",
+                        task,
+                        e
+                    );
+                }
+            }
         }
 
         Ok(results)
@@ -197,9 +503,7 @@ where
     }
 }
 
-fn create_boss_system_prompt(
-    agents: &Vec<Box<dyn Agent>>,
-) -> Result<String, MultiAgentOrchestratorError> {
+fn validate_agents(agents: &[Box<dyn Agent>]) -> Result<(), MultiAgentOrchestratorError> {
     // because we need to route, the description of each agent must be set.
     if agents
         .iter()
@@ -218,26 +522,7 @@ fn create_boss_system_prompt(
         }
     }
 
-    let agent_descriptions = agents
-        .iter()
-        .map(|agent| format!("- {}: {}\n", agent.name(), agent.description()))
-        .collect::<Vec<String>>()
-        .concat();
-
-    Ok(format!(
-        "You are a boss agent responsible for routing tasks to the most appropriate specialized agent.
-    Available agents:
-    {agent_descriptions}
-
-    Your job is to:
-    1. Analyze the incoming task
-    2. Select the most appropriate agent based on their descriptions
-    3. Provide clear reasoning for your selection
-    4. Optionally modify the task to better suit the selected agent's capabilities
-
-    Always select exactly one agent that best matches the task requirements.
-    "
-    ))
+    Ok(())
 }
 
 #[tool(description = "Select the most appropriate agent to execute the task.")]
@@ -261,15 +546,22 @@ pub struct SelectAgentResponse {
 pub struct MultiAgentOrchestratorResult {
     id: Uuid,
     timestamp: i64,
+    category: String,
     task: Task,
     boss_decision: BossDecision,
     execution: Execution,
+    /// Verdicts of the verifier, a rejected output is followed by the verdict of the rerouted
+    /// task.
+    verifications: Vec<Verification>,
     total_time: i64,
 }
 
 #[derive(Serialize)]
 pub struct Task {
     original: String,
+    /// The task as rewritten by the triage agent, if it changed it.
+    triaged: Option<String>,
+    /// The task as modified by the boss, if it changed it.
     modified: Option<String>,
 }
 
@@ -287,3 +579,200 @@ pub struct Execution {
     response: Option<String>,
     execution_time: Option<i64>,
 }
+
+#[derive(Serialize)]
+pub struct Verification {
+    /// The agent whose output was checked.
+    agent_name: String,
+    passed: bool,
+    feedback: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::swarms_agent::SwarmsAgentBuilder,
+        llm::completion::{AssistantContent, ToolCall, ToolFunction},
+        task_classifier::Category,
+        test_support::FnModel,
+    };
+
+    const BOSS_INSTRUCTIONS: &str = Locale::English.catalog().boss_instructions;
+    const TRIAGE_PROMPT: &str = Locale::English.catalog().triage_prompt;
+    const VERIFIER_PROMPT: &str = Locale::English.catalog().verifier_prompt;
+
+    /// Selects the agent the system prompt scores best, or answers with the task.
+    fn routing_model() -> FnModel {
+        FnModel::new("router", |request| {
+            let system_prompt = request.system_prompt.unwrap_or_default();
+            match system_prompt.split_once("### Routing History") {
+                Some((_, history)) => {
+                    // the first listed agent has the best score
+                    let best = history.split("\n- ").nth(1).unwrap();
+                    select(best.split(':').next().unwrap())
+                }
+                None => "done".to_owned().into(),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_routing_stats() {
+        let agent = |name: &str| {
+            Box::new(
+                SwarmsAgentBuilder::new_with_model(routing_model())
+                    .agent_name(name)
+                    .description(format!("The {name}"))
+                    .build(),
+            ) as Box<dyn Agent>
+        };
+        let boss = SwarmsAgentBuilder::new_with_model(routing_model())
+            .agent_name("boss")
+            .build();
+        let orchestrator =
+            MultiAgentOrchestrator::new(boss, vec![agent("coder"), agent("writer")], true)
+                .unwrap()
+                .routing_stats(RoutingStats::new())
+                .task_classifier(TaskClassifier::from_fn(
+                    vec![Category::new("poetry", "Writing poems")],
+                    |_| "poetry".to_owned(),
+                ));
+
+        // Each untried agent is explored once
+        for _ in 0..2 {
+            orchestrator.run("write a poem").await.unwrap();
+        }
+        let stats = orchestrator.get_routing_stats().unwrap();
+        assert_eq!(stats.stats("poetry", "coder").successes, 1);
+        assert_eq!(stats.stats("poetry", "writer").successes, 1);
+    }
+
+    /// Answers by a function of the system prompt and the prompt.
+    fn script_model(script: fn(&str, &str) -> AssistantContent) -> FnModel {
+        FnModel::new("script", move |request| {
+            let system_prompt = request.system_prompt.unwrap_or_default();
+            let prompt = request.prompt.rag_text().unwrap_or_default();
+            script(&system_prompt, &prompt)
+        })
+    }
+
+    fn select(agent: &str) -> AssistantContent {
+        AssistantContent::ToolCall(ToolCall {
+            id: "call-1".to_owned(),
+            function: ToolFunction {
+                name: "select_agent".to_owned(),
+                arguments: serde_json::json!({
+                    "selected": {"selected_agent": agent, "reasoning": "fits"},
+                }),
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_triage_route_verify() {
+        let model = script_model(|system_prompt, prompt| {
+            if prompt.starts_with(TRIAGE_PROMPT) {
+                "Write a haiku about rust".to_owned().into()
+            } else if prompt.starts_with(VERIFIER_PROMPT) {
+                let verdict = if prompt.contains("haiku by poet") {
+                    "PASS"
+                } else {
+                    "FAIL: not a haiku"
+                };
+                verdict.to_owned().into()
+            } else if system_prompt.starts_with(BOSS_INSTRUCTIONS) {
+                // The boss tries the coder first, and the poet after the feedback
+                select(if prompt.contains("### Feedback") {
+                    "poet"
+                } else {
+                    "coder"
+                })
+            } else if system_prompt == "poet" {
+                "haiku by poet".to_owned().into()
+            } else {
+                "fn main() {}".to_owned().into()
+            }
+        });
+        let agent = |name: &str| {
+            Box::new(
+                SwarmsAgentBuilder::new_with_model(model.clone())
+                    .agent_name(name)
+                    .description(format!("The {name}"))
+                    .system_prompt(name)
+                    .build(),
+            ) as Box<dyn Agent>
+        };
+        let boss = SwarmsAgentBuilder::new_with_model(model.clone())
+            .agent_name("boss")
+            .build();
+        let orchestrator =
+            MultiAgentOrchestrator::new(boss, vec![agent("coder"), agent("poet")], true)
+                .unwrap()
+                .triage(agent("triage"))
+                .verifier(agent("verifier"))
+                .routing_stats(RoutingStats::new());
+
+        let result = orchestrator.run("haiku abt rust pls").await.unwrap();
+        assert_eq!(
+            result.task.triaged.as_deref(),
+            Some("Write a haiku about rust")
+        );
+        assert_eq!(result.boss_decision.selected_agent, "poet");
+        assert_eq!(result.execution.response.as_deref(), Some("haiku by poet"));
+        let verdicts = result
+            .verifications
+            .iter()
+            .map(|v| (v.agent_name.as_str(), v.passed, v.feedback.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            verdicts,
+            [("coder", false, "not a haiku"), ("poet", true, "PASS")]
+        );
+        let stats = orchestrator.get_routing_stats().unwrap();
+        assert_eq!(stats.stats(DEFAULT_CATEGORY, "coder").successes, 0);
+        assert_eq!(stats.stats(DEFAULT_CATEGORY, "poet").successes, 1);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct Forecast {
+        city: String,
+        celsius: i32,
+    }
+
+    #[tokio::test]
+    async fn test_run_typed() {
+        let model = script_model(|system_prompt, prompt| {
+            if system_prompt.starts_with(BOSS_INSTRUCTIONS) {
+                select("forecaster")
+            } else if prompt.starts_with("Your answer doesn't match") {
+                r#"{"city": "Oslo", "celsius": -3}"#.to_owned().into()
+            } else {
+                // The first answer misses a field
+                r#"Sure! {"city": "Oslo"}"#.to_owned().into()
+            }
+        });
+        let forecaster = SwarmsAgentBuilder::new_with_model(model.clone())
+            .agent_name("forecaster")
+            .description("Forecasts the weather")
+            .build();
+        let boss = SwarmsAgentBuilder::new_with_model(model)
+            .agent_name("boss")
+            .build();
+        let orchestrator =
+            MultiAgentOrchestrator::new(boss, vec![Box::new(forecaster)], true).unwrap();
+
+        let (result, forecast) = orchestrator
+            .run_typed::<Forecast>("Weather in Oslo?")
+            .await
+            .unwrap();
+        assert_eq!(
+            forecast,
+            Forecast {
+                city: "Oslo".to_owned(),
+                celsius: -3
+            }
+        );
+        assert_eq!(result.execution.agent_name, "forecaster");
+    }
+}
@@ -1,8 +1,34 @@
-use std::path::Path;
+//! Where agents and workflows store their states, metadata and results.
+//!
+//! Everything is written through a [`Persistence`] backend. [`FilePersistence`], the default,
+//! writes files atomically with checksums and backups. [`MemoryPersistence`] keeps the data
+//! in memory, for tests and ephemeral runs which shouldn't touch the file system. With the
+//! `object-storage` feature, `ObjectStorePersistence` stores it in S3 or another object store.
 
-use chrono::Local;
+use std::{
+    ffi::OsString,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
-use tokio::{fs, io::AsyncWriteExt};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+use twox_hash::XxHash3_64;
+use uuid::Uuid;
+
+use crate::error::{CategorizedError, ErrorCategory};
+
+#[cfg(feature = "object-storage")]
+mod object_storage;
+#[cfg(feature = "object-storage")]
+pub use object_storage::ObjectStorePersistence;
 
 #[derive(Debug, Error)]
 pub enum PersistenceError {
@@ -12,22 +38,360 @@ pub enum PersistenceError {
     JsonError(#[from] serde_json::Error),
     #[error("Missing directory: {0}")]
     MissingParent(String),
+    #[error("Unsupported format version {0}, the latest known version is {FORMAT_VERSION}")]
+    UnsupportedFormatVersion(u64),
+    #[error("Checksum mismatch, the file is corrupted: {0}")]
+    ChecksumMismatch(String),
+}
+
+/// Storage backend of agents and workflows, paths identify the stored data.
+pub trait Persistence: Send + Sync {
+    /// Store the data, replacing the data stored at the path.
+    fn save<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), PersistenceError>>;
+
+    /// The data stored at the path, fails with a `NotFound` [`PersistenceError::IoError`] if
+    /// there is none.
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>, PersistenceError>>;
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<(), PersistenceError>>;
+}
+
+/// Stores the data in files, see [`save_to_file`] and [`load_verified`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FilePersistence;
+
+impl Persistence for FilePersistence {
+    fn save<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), PersistenceError>> {
+        Box::pin(save_to_file(data, path))
+    }
+
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>, PersistenceError>> {
+        Box::pin(load_verified(path))
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<(), PersistenceError>> {
+        Box::pin(remove_file(path))
+    }
+}
+
+/// Stores the data in memory, clones share the data.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryPersistence {
+    entries: Arc<DashMap<PathBuf, Vec<u8>>>,
+}
+
+impl MemoryPersistence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The data stored at the path.
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.entries.get(path.as_ref()).map(|data| data.clone())
+    }
+
+    /// The paths of the stored data, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = self
+            .entries
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Persistence for MemoryPersistence {
+    fn save<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), PersistenceError>> {
+        Box::pin(async move {
+            self.entries.insert(path.to_path_buf(), data);
+            Ok(())
+        })
+    }
+
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>, PersistenceError>> {
+        Box::pin(async move { self.get(path).ok_or_else(|| not_found(path)) })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<(), PersistenceError>> {
+        Box::pin(async move {
+            self.entries
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| not_found(path))
+        })
+    }
+}
+
+fn not_found(path: &Path) -> PersistenceError {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} is not stored", path.display()),
+    )
+    .into()
+}
+
+/// Version of the format of persisted agent states and workflow metadata.
+///
+/// Bump it when the format changes, and add a [`Migration`] from the previous version to
+/// the migrations of every changed type.
+pub const FORMAT_VERSION: u64 = 1;
+const FORMAT_VERSION_FIELD: &str = "format_version";
+
+/// Upgrades a persisted value from the version of its index in the migrations to the next
+/// version. Values saved before versioning have version `0`.
+pub type Migration = fn(Value) -> Result<Value, PersistenceError>;
+
+/// Serialize the value as pretty JSON with the current `format_version` next to its fields.
+pub fn to_versioned_json<T: Serialize>(value: &T) -> Result<String, PersistenceError> {
+    let mut json = serde_json::to_value(value)?;
+    if let Value::Object(fields) = &mut json {
+        fields.insert(FORMAT_VERSION_FIELD.to_owned(), FORMAT_VERSION.into());
+    }
+    Ok(serde_json::to_string_pretty(&json)?)
+}
+
+/// Deserialize a value saved by [`to_versioned_json`] or before versioning, after
+/// upgrading it to the current version with the migrations.
+pub fn from_versioned_json<T: DeserializeOwned>(
+    data: &[u8],
+    migrations: &[Migration],
+) -> Result<T, PersistenceError> {
+    debug_assert_eq!(migrations.len() as u64, FORMAT_VERSION);
+    let mut json = serde_json::from_slice::<Value>(data)?;
+    let version = match &mut json {
+        Value::Object(fields) => fields
+            .remove(FORMAT_VERSION_FIELD)
+            .and_then(|version| version.as_u64())
+            .unwrap_or(0),
+        _ => 0,
+    };
+    if version > FORMAT_VERSION {
+        return Err(PersistenceError::UnsupportedFormatVersion(version));
+    }
+    for migration in &migrations[version as usize..] {
+        json = migration(json)?;
+    }
+    Ok(serde_json::from_value(json)?)
+}
+
+/// A migration for a version which didn't change the type's fields.
+pub fn unchanged(value: Value) -> Result<Value, PersistenceError> {
+    Ok(value)
+}
+
+/// The path in a form every platform can open.
+///
+/// On Windows, paths longer than `MAX_PATH` (260 characters) are made absolute and get the
+/// verbatim `\\?\` prefix, which lifts the limit. Other paths are returned as they are.
+pub fn normalize_path(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    #[cfg(windows)]
+    {
+        const MAX_PATH: usize = 260;
+        if path.as_os_str().len() >= MAX_PATH
+            && let Ok(absolute) = std::path::absolute(path)
+        {
+            // `absolute` resolves `.` and `..` and uses `\` separators on Windows, verbatim
+            // paths support neither
+            let absolute = absolute.to_string_lossy();
+            return PathBuf::from(if absolute.starts_with(r"\\?\") {
+                absolute.into_owned()
+            } else if let Some(share) = absolute.strip_prefix(r"\\") {
+                format!(r"\\?\UNC\{share}")
+            } else {
+                format!(r"\\?\{absolute}")
+            });
+        }
+    }
+    path.to_path_buf()
+}
+
+/// The name with every character which isn't allowed in a file name on some platform
+/// replaced by `_`, so it can be used as part of a file name.
+pub fn sanitize_file_name(name: &str) -> String {
+    let sanitized = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // Windows drops trailing dots and spaces from file names
+    let sanitized = sanitized.trim_end_matches(['.', ' ']);
+    if sanitized.is_empty() {
+        "_".to_owned()
+    } else {
+        sanitized.to_owned()
+    }
+}
+
+impl CategorizedError for PersistenceError {
+    fn category(&self) -> ErrorCategory {
+        ErrorCategory::Persistence
+    }
 }
 
 /// Save the data to a file, if the file exists, it will be overwritten
+///
+/// The data is written to a temporary file which replaces the file, so a failed write leaves
+/// the old file intact. The old file is kept as `<file>.bak` and the SHA-256 checksum of each
+/// is stored in `<file>.sha256`, [`load_verified`] uses both to detect and recover from
+/// corruption. Saves of the same file are serialized, concurrent saves don't interfere.
 pub async fn save_to_file(
     data: impl AsRef<[u8]>,
     path: impl AsRef<Path>,
 ) -> Result<(), PersistenceError> {
-    match path.as_ref().parent() {
+    let path = &normalize_path(path);
+    match path.parent() {
         Some(parent) => fs::create_dir_all(parent).await?,
         None => {
             return Err(PersistenceError::MissingParent(
-                path.as_ref().to_string_lossy().to_string(),
+                path.to_string_lossy().to_string(),
             ));
         }
     };
-    fs::write(path, data).await.map_err(|e| e.into())
+
+    let lock = file_lock(path);
+    let _guard = lock.lock().await;
+    let temp_path = unique_temp_path(path);
+    let mut file = fs::File::create(&temp_path).await?;
+    file.write_all(data.as_ref()).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    let backup_path = sibling(path, BACKUP_EXTENSION);
+    if fs::try_exists(path).await? {
+        fs::rename(path, &backup_path).await?;
+        match fs::rename(checksum_path(path), checksum_path(&backup_path)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                remove_if_exists(&checksum_path(&backup_path)).await?;
+            }
+            result => result?,
+        }
+    }
+    fs::rename(&temp_path, path).await?;
+    fs::write(checksum_path(path), checksum(data.as_ref())).await?;
+    Ok(())
+}
+
+/// Load a file written by [`save_to_file`] and verify its checksum, a missing or corrupted
+/// file is recovered from its backup. Files without a checksum, e.g. those which are
+/// appended to, are not verified.
+pub async fn load_verified(path: impl AsRef<Path>) -> Result<Vec<u8>, PersistenceError> {
+    let path = &normalize_path(path);
+    let error = match read_verified(path).await {
+        Ok(data) => return Ok(data),
+        Err(e) => e,
+    };
+    let backup_path = sibling(path, BACKUP_EXTENSION);
+    match read_verified(&backup_path).await {
+        Ok(data) => {
+            tracing::warn!(
+                "| persistence | Recovered {} from its backup: {}",
+                path.display(),
+                error
+            );
+            Ok(data)
+        }
+        Err(_) => Err(error),
+    }
+}
+
+/// Remove a file written by [`save_to_file`] together with its backup and checksums.
+pub async fn remove_file(path: impl AsRef<Path>) -> Result<(), PersistenceError> {
+    let path = &normalize_path(path);
+    let lock = file_lock(path);
+    let _guard = lock.lock().await;
+    fs::remove_file(path).await?;
+    let backup_path = sibling(path, BACKUP_EXTENSION);
+    for sidecar in [
+        checksum_path(path),
+        checksum_path(&backup_path),
+        backup_path,
+    ] {
+        remove_if_exists(&sidecar).await?;
+    }
+    Ok(())
+}
+
+const BACKUP_EXTENSION: &str = ".bak";
+
+// Locks held while a file, its backup and checksums are replaced. A path always maps to the
+// same lock, the number of locks is fixed so it doesn't grow with the number of files.
+const FILE_LOCK_STRIPES: usize = 64;
+static FILE_LOCKS: [Mutex<()>; FILE_LOCK_STRIPES] =
+    [const { Mutex::const_new(()) }; FILE_LOCK_STRIPES];
+
+fn file_lock(path: &Path) -> &'static Mutex<()> {
+    let mut hasher = XxHash3_64::default();
+    path.hash(&mut hasher);
+    &FILE_LOCKS[(hasher.finish() % FILE_LOCK_STRIPES as u64) as usize]
+}
+
+/// A temporary file next to the file, unique so concurrent writes never share it.
+fn unique_temp_path(path: &Path) -> PathBuf {
+    sibling(path, &format!(".{}.tmp", Uuid::new_v4().simple()))
+}
+
+async fn read_verified(path: &Path) -> Result<Vec<u8>, PersistenceError> {
+    let data = fs::read(path).await?;
+    match fs::read_to_string(checksum_path(path)).await {
+        Ok(expected) if expected.trim() != checksum(&data) => Err(
+            PersistenceError::ChecksumMismatch(path.to_string_lossy().into_owned()),
+        ),
+        Ok(_) => Ok(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(data),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), PersistenceError> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Hex SHA-256 of the data.
+fn checksum(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    sibling(path, ".sha256")
+}
+
+/// The path with `suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling = OsString::from(path.as_os_str());
+    sibling.push(suffix);
+    PathBuf::from(sibling)
 }
 
 /// Append the data to a file, if the file doesn't exist, it will be created
@@ -35,24 +399,26 @@ pub async fn append_to_file(
     data: impl AsRef<[u8]>,
     path: impl AsRef<Path>,
 ) -> Result<(), PersistenceError> {
+    let path = normalize_path(path);
     // create the parent directory if it doesn't exist
-    if path.as_ref().parent().is_none() {
-        fs::create_dir_all(&path).await?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
     }
 
-    fs::OpenOptions::new()
+    let mut file = fs::OpenOptions::new()
         .append(true)
         .create(true)
         .open(path)
-        .await?
-        .write_all(data.as_ref())
         .await?;
+    file.write_all(data.as_ref()).await?;
+    // tokio writes in the background, flush so the data is written when this returns
+    file.flush().await?;
     Ok(())
 }
 
 /// Load the data from a file
 pub async fn load_from_file(path: impl AsRef<Path>) -> Result<Vec<u8>, PersistenceError> {
-    fs::read(path).await.map_err(|e| e.into())
+    fs::read(normalize_path(path)).await.map_err(|e| e.into())
 }
 
 /// Compress data, defaults to zstd
@@ -68,12 +434,166 @@ pub fn decompress(data: impl AsRef<[u8]>) -> Result<Vec<u8>, PersistenceError> {
     decode_all(data.as_ref()).map_err(|e| e.into())
 }
 
-pub async fn log_to_file(
-    message: impl AsRef<str>,
-    path: impl AsRef<Path>,
-) -> Result<(), PersistenceError> {
-    let message = message.as_ref();
-    let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-    let log_message = format!("{timestamp} - {message}");
-    append_to_file(log_message.as_bytes(), path).await
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct State {
+        name: String,
+    }
+
+    fn rename_title(mut value: Value) -> Result<Value, PersistenceError> {
+        if let Some(title) = value.as_object_mut().and_then(|v| v.remove("title")) {
+            value["name"] = title;
+        }
+        Ok(value)
+    }
+
+    #[test]
+    fn test_versioned_json() {
+        let state = State {
+            name: "a".to_owned(),
+        };
+        let json = to_versioned_json(&state).unwrap();
+        assert!(json.contains("\"format_version\": 1"));
+        assert_eq!(
+            from_versioned_json::<State>(json.as_bytes(), &[rename_title]).unwrap(),
+            state
+        );
+
+        // Unversioned values are migrated, current values are not
+        let legacy = json!({ "title": "a" }).to_string();
+        assert_eq!(
+            from_versioned_json::<State>(legacy.as_bytes(), &[rename_title]).unwrap(),
+            state
+        );
+
+        let future = json!({ "name": "a", "format_version": FORMAT_VERSION + 1 }).to_string();
+        assert!(matches!(
+            from_versioned_json::<State>(future.as_bytes(), &[unchanged]),
+            Err(PersistenceError::UnsupportedFormatVersion(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_save_keeps_backup_and_checksum() {
+        let dir = temp_dir();
+        let path = dir.join("state.json");
+        save_to_file("first", &path).await.unwrap();
+        save_to_file("second", &path).await.unwrap();
+
+        assert_eq!(load_verified(&path).await.unwrap(), b"second");
+        assert_eq!(std::fs::read(sibling(&path, ".bak")).unwrap(), b"first");
+
+        remove_file(&path).await.unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_saves() {
+        let dir = temp_dir();
+        let path = dir.join("state.json");
+        let saves = (0..16).map(|i| {
+            let path = path.clone();
+            tokio::spawn(async move { save_to_file(format!("save {i}"), &path).await })
+        });
+        for save in futures::future::join_all(saves).await {
+            save.unwrap().unwrap();
+        }
+        // Saves of the same path share a lock
+        assert!(std::ptr::eq(file_lock(&path), file_lock(&path.clone())));
+        // The last save wins and matches its checksum, no temporary file is left behind
+        let data = load_verified(&path).await.unwrap();
+        assert!(String::from_utf8(data).unwrap().starts_with("save "));
+        remove_file(&path).await.unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_load_recovers_from_backup() {
+        let dir = temp_dir();
+        let path = dir.join("state.json");
+        save_to_file("first", &path).await.unwrap();
+        save_to_file("second", &path).await.unwrap();
+
+        std::fs::write(&path, "sec").unwrap();
+        assert_eq!(load_verified(&path).await.unwrap(), b"first");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(load_verified(&path).await.unwrap(), b"first");
+
+        std::fs::write(sibling(&path, ".bak"), "fir").unwrap();
+        assert!(matches!(
+            load_verified(&path).await,
+            Err(PersistenceError::IoError(_))
+        ));
+        std::fs::write(&path, "sec").unwrap();
+        assert!(matches!(
+            load_verified(&path).await,
+            Err(PersistenceError::ChecksumMismatch(_))
+        ));
+
+        // Files without a checksum aren't verified
+        let unverified = path.with_file_name("log.jsonl");
+        append_to_file("line", &unverified).await.unwrap();
+        assert_eq!(load_verified(&unverified).await.unwrap(), b"line");
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("support agent"), "support agent");
+        assert_eq!(sanitize_file_name("a/b\\c:d*e?"), "a_b_c_d_e_");
+        assert_eq!(sanitize_file_name("agent.v2.. "), "agent.v2");
+        assert_eq!(sanitize_file_name(".."), "_");
+        assert_eq!(sanitize_file_name(""), "_");
+    }
+
+    #[tokio::test]
+    async fn test_missing_parents() {
+        let dir = temp_dir();
+        let nested = dir.join("nested").join("state.json");
+        save_to_file("data", &nested).await.unwrap();
+        assert_eq!(load_verified(&nested).await.unwrap(), b"data");
+
+        assert!(matches!(
+            save_to_file("data", "").await,
+            Err(PersistenceError::MissingParent(_))
+        ));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_normalize_long_windows_path() {
+        let long = format!(r"C:\{}\state.json", "a".repeat(300));
+        let normalized = normalize_path(&long);
+        assert_eq!(normalized.to_string_lossy(), format!(r"\\?\{long}"));
+        assert_eq!(normalize_path(&normalized), normalized);
+        assert_eq!(
+            normalize_path(r"C:\short.json"),
+            Path::new(r"C:\short.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_persistence() {
+        let persistence = MemoryPersistence::new();
+        let shared = persistence.clone();
+        let path = Path::new("states/a.json");
+        persistence.save(path, b"a".to_vec()).await.unwrap();
+        persistence.save(path, b"b".to_vec()).await.unwrap();
+
+        assert_eq!(shared.load(path).await.unwrap(), b"b");
+        assert_eq!(shared.paths(), [path]);
+        assert!(!path.exists());
+        shared.remove(path).await.unwrap();
+        assert!(persistence.is_empty());
+        assert!(matches!(
+            persistence.load(path).await,
+            Err(PersistenceError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+    }
 }
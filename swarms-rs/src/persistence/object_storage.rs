@@ -0,0 +1,136 @@
+use std::{
+    path::{Component, Path},
+    sync::Arc,
+};
+
+use futures::future::BoxFuture;
+use object_store::{ObjectStore, PutPayload, aws::AmazonS3Builder, path::Path as ObjectPath};
+
+use super::{Persistence, PersistenceError};
+
+/// Stores the data in an object store, e.g. S3, MinIO or GCS, so swarms in containers don't
+/// need a persistent disk.
+///
+/// The paths configured on the agent and workflow builders become object keys below the
+/// prefix: `./temp/states/a.json` is stored as `<prefix>/temp/states/a.json`.
+#[derive(Clone, Debug)]
+pub struct ObjectStorePersistence {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+}
+
+impl ObjectStorePersistence {
+    pub fn new(store: impl ObjectStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            prefix: ObjectPath::default(),
+        }
+    }
+
+    /// An S3 bucket, configured with the `AWS_*` environment variables, e.g. `AWS_REGION`,
+    /// `AWS_ENDPOINT` for S3-compatible stores and `AWS_VIRTUAL_HOSTED_STYLE_REQUEST`.
+    pub fn s3(bucket: impl Into<String>) -> Result<Self, PersistenceError> {
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(std::io::Error::from)?;
+        Ok(Self::new(store))
+    }
+
+    /// Store every object below this prefix, e.g. `swarms/production`.
+    pub fn prefix(mut self, prefix: impl AsRef<str>) -> Self {
+        self.prefix = ObjectPath::from(prefix.as_ref());
+        self
+    }
+
+    /// The key of the object stored at the path, `.` and the root are dropped.
+    fn key(&self, path: &Path) -> Result<ObjectPath, PersistenceError> {
+        let mut parts = self
+            .prefix
+            .parts()
+            .map(|part| part.as_ref().to_owned())
+            .collect::<Vec<_>>();
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+                Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+                Component::ParentDir => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Object keys can't contain '..': {}", path.display()),
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(ObjectPath::from_iter(parts))
+    }
+}
+
+impl Persistence for ObjectStorePersistence {
+    fn save<'a>(
+        &'a self,
+        path: &'a Path,
+        data: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), PersistenceError>> {
+        Box::pin(async move {
+            let key = self.key(path)?;
+            self.store
+                .put(&key, PutPayload::from(data))
+                .await
+                .map_err(std::io::Error::from)?;
+            Ok(())
+        })
+    }
+
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>, PersistenceError>> {
+        Box::pin(async move {
+            let key = self.key(path)?;
+            let object = self.store.get(&key).await.map_err(std::io::Error::from)?;
+            let data = object.bytes().await.map_err(std::io::Error::from)?;
+            Ok(data.to_vec())
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<(), PersistenceError>> {
+        Box::pin(async move {
+            let key = self.key(path)?;
+            self.store
+                .delete(&key)
+                .await
+                .map_err(std::io::Error::from)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::memory::InMemory;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_object_store_persistence() {
+        let store = Arc::new(InMemory::new());
+        let persistence = ObjectStorePersistence {
+            store: store.clone(),
+            prefix: ObjectPath::default(),
+        }
+        .prefix("swarms/test");
+        let path = Path::new("./temp/states/agent_1.json");
+        persistence.save(path, b"state".to_vec()).await.unwrap();
+
+        let key = ObjectPath::from("swarms/test/temp/states/agent_1.json");
+        let stored = store.get(&key).await.unwrap().bytes().await.unwrap();
+        assert_eq!(stored.as_ref(), b"state");
+        assert_eq!(persistence.load(path).await.unwrap(), b"state");
+
+        persistence.remove(path).await.unwrap();
+        assert!(matches!(
+            persistence.load(path).await,
+            Err(PersistenceError::IoError(e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+        assert!(persistence.key(Path::new("../escape.json")).is_err());
+    }
+}
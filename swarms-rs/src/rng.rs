@@ -0,0 +1,89 @@
+//! Random numbers of scheduling decisions, e.g. the next speaker of a group chat or the
+//! variant of an A/B experiment.
+//!
+//! Unseeded generators draw from the same cryptographically secure source as uuid v4. Seeded
+//! generators return the same sequence for the same seed, so simulations and tests can be
+//! reproduced exactly as long as the decisions are made in the same order.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use uuid::Uuid;
+
+/// Increment of the SplitMix64 state, the golden ratio.
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(Debug, Default)]
+pub struct SwarmRng {
+    seed: Option<u64>,
+    state: AtomicU64,
+}
+
+impl SwarmRng {
+    /// An unseeded generator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    /// A seeded generator if there is a seed, otherwise an unseeded one.
+    pub fn from_seed(seed: Option<u64>) -> Self {
+        seed.map_or_else(Self::new, Self::seeded)
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        if self.seed.is_none() {
+            return Uuid::new_v4().as_u128() as u64;
+        }
+        // SplitMix64, the shared state keeps the generator usable through `&self`
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`, `bound` must not be `0`.
+    pub fn below(&self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+impl Clone for SwarmRng {
+    /// The clone continues the sequence where the original is now, independently of it.
+    fn clone(&self) -> Self {
+        Self {
+            seed: self.seed,
+            state: AtomicU64::new(self.state.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_sequence() {
+        let sequence = |rng: &SwarmRng| (0..5).map(|_| rng.below(1000)).collect::<Vec<_>>();
+        let rng = SwarmRng::seeded(42);
+        let first = sequence(&rng);
+        assert_eq!(first, sequence(&SwarmRng::seeded(42)));
+        assert_ne!(first, sequence(&SwarmRng::seeded(43)));
+        assert_eq!(sequence(&rng.clone()), sequence(&rng));
+
+        assert_eq!(SwarmRng::from_seed(None).seed(), None);
+        assert!((0..100).all(|_| SwarmRng::new().below(3) < 3));
+    }
+}
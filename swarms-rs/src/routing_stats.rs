@@ -0,0 +1,268 @@
+//! Routing statistics of a [`MultiAgentOrchestrator`](crate::multi_agent_orchestrator::MultiAgentOrchestrator).
+//!
+//! Every executed task is filed under a category, see
+//! [`TaskClassifier`](crate::task_classifier::TaskClassifier), and the outcome and latency of
+//! the selected agent are recorded. Before the boss routes the next task of the same category it is told
+//! each agent's score, so routing is biased towards the agents which did well before while
+//! agents without runs are still tried, like a multi-armed bandit. The score is computed by
+//! the [`Scorer`], UCB1 on the success rate by default.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::persistence::{self, Persistence, PersistenceError};
+
+/// Scores an agent from its stats and the number of runs of all agents in the category.
+pub type Scorer = Arc<dyn Fn(&AgentStats, u64) -> f64 + Send + Sync>;
+
+/// Category -> agent name -> stats
+type StatsTable = BTreeMap<String, BTreeMap<String, AgentStats>>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentStats {
+    pub runs: u64,
+    pub successes: u64,
+    pub total_latency_ms: u64,
+}
+
+impl AgentStats {
+    /// Share of the runs which succeeded, `0` without runs.
+    pub fn success_rate(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        self.successes as f64 / self.runs as f64
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        Duration::from_millis(
+            self.total_latency_ms
+                .checked_div(self.runs)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+/// UCB1, the success rate plus a bonus which shrinks the more often the agent ran. Agents
+/// without runs score infinitely high, so each agent is tried at least once.
+pub fn ucb1(stats: &AgentStats, total_runs: u64) -> f64 {
+    if stats.runs == 0 {
+        return f64::INFINITY;
+    }
+    let exploration = (2.0 * (total_runs.max(1) as f64).ln() / stats.runs as f64).sqrt();
+    stats.success_rate() + exploration
+}
+
+pub struct RoutingStats {
+    table: RwLock<StatsTable>,
+    scorer: Scorer,
+    persistence: Option<(Arc<dyn Persistence>, PathBuf)>,
+    loaded: OnceCell<()>,
+    // Serializes the saves, so the last save holds the latest stats
+    save_lock: Mutex<()>,
+}
+
+impl Default for RoutingStats {
+    fn default() -> Self {
+        Self {
+            table: RwLock::default(),
+            scorer: Arc::new(ucb1),
+            persistence: None,
+            loaded: OnceCell::new(),
+            save_lock: Mutex::new(()),
+        }
+    }
+}
+
+impl RoutingStats {
+    /// Upgrades of saved stats, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    /// Agents are scored with [`ucb1`] and the stats are kept in memory only by default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn scorer(
+        mut self,
+        scorer: impl Fn(&AgentStats, u64) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        self.scorer = Arc::new(scorer);
+        self
+    }
+
+    /// Save the stats to `path` of the backend after every run, and load them before the first
+    /// run, so the learned routing outlives the process.
+    pub fn persistent(
+        mut self,
+        persistence: impl Persistence + 'static,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        self.persistence = Some((Arc::new(persistence), path.into()));
+        self
+    }
+
+    pub fn stats(&self, category: &str, agent: &str) -> AgentStats {
+        self.table
+            .read()
+            .unwrap() // Safety: the lock is never held across a panic
+            .get(category)
+            .and_then(|agents| agents.get(agent))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Score of each agent in the category, best first.
+    pub fn scores<'a>(
+        &self,
+        category: &str,
+        agents: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<(String, f64)> {
+        let table = self.table.read().unwrap();
+        let category_stats = table.get(category);
+        let total_runs = category_stats
+            .map(|agents| agents.values().map(|stats| stats.runs).sum())
+            .unwrap_or_default();
+
+        let mut scores = agents
+            .into_iter()
+            .map(|agent| {
+                let stats = category_stats
+                    .and_then(|agents| agents.get(agent))
+                    .copied()
+                    .unwrap_or_default();
+                (agent.to_owned(), (self.scorer)(&stats, total_runs))
+            })
+            .collect::<Vec<_>>();
+        scores.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        scores
+    }
+
+    /// The scores as a section of the boss's system prompt.
+    pub fn prompt<'a>(&self, category: &str, agents: impl IntoIterator<Item = &'a str>) -> String {
+        let mut prompt = format!(
+            "### Routing History (category: {category}):\n\
+             Prefer agents with a higher score when several of them fit the task."
+        );
+        for (agent, score) in self.scores(category, agents) {
+            let stats = self.stats(category, &agent);
+            if stats.runs == 0 {
+                prompt.push_str(&format!("\n- {agent}: not tried yet"));
+            } else {
+                prompt.push_str(&format!(
+                    "\n- {agent}: score {score:.2}, {}/{} runs succeeded, mean latency {:.1}s",
+                    stats.successes,
+                    stats.runs,
+                    stats.mean_latency().as_secs_f64()
+                ));
+            }
+        }
+        prompt
+    }
+
+    /// Replace the stats with the saved ones, once. A missing file keeps the stats.
+    pub async fn load(&self) -> Result<(), PersistenceError> {
+        let Some((persistence, path)) = &self.persistence else {
+            return Ok(());
+        };
+        self.loaded
+            .get_or_try_init(|| async {
+                match persistence.load(path).await {
+                    Ok(data) => {
+                        let table = persistence::from_versioned_json(&data, Self::MIGRATIONS)?;
+                        *self.table.write().unwrap() = table;
+                        Ok(())
+                    }
+                    Err(PersistenceError::IoError(e))
+                        if e.kind() == std::io::ErrorKind::NotFound =>
+                    {
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await
+            .map(|_| ())
+    }
+
+    /// Record a run of the agent and save the stats if they are persistent.
+    pub async fn record(
+        &self,
+        category: &str,
+        agent: &str,
+        success: bool,
+        latency: Duration,
+    ) -> Result<(), PersistenceError> {
+        {
+            let mut table = self.table.write().unwrap();
+            let stats = table
+                .entry(category.to_owned())
+                .or_default()
+                .entry(agent.to_owned())
+                .or_default();
+            stats.runs += 1;
+            stats.successes += success as u64;
+            stats.total_latency_ms += latency.as_millis() as u64;
+        }
+
+        if let Some((persistence, path)) = &self.persistence {
+            let _guard = self.save_lock.lock().await;
+            let json = persistence::to_versioned_json(&*self.table.read().unwrap())?;
+            persistence.save(path, json.into_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::MemoryPersistence;
+
+    #[tokio::test]
+    async fn test_record_score_and_persist() {
+        let persistence = MemoryPersistence::default();
+        let stats = RoutingStats::new().persistent(persistence.clone(), "routing.json");
+        stats.load().await.unwrap();
+
+        let category = "coding";
+        for success in [true, true, false] {
+            stats
+                .record(category, "coder", success, Duration::from_secs(2))
+                .await
+                .unwrap();
+        }
+        for _ in 0..3 {
+            stats
+                .record(category, "writer", false, Duration::from_secs(1))
+                .await
+                .unwrap();
+        }
+
+        let coder = stats.stats("coding", "coder");
+        assert_eq!(coder.successes, 2);
+        assert_eq!(coder.mean_latency(), Duration::from_secs(2));
+        let scores = stats.scores("coding", ["writer", "coder", "tester"]);
+        let order = scores
+            .iter()
+            .map(|(agent, _)| agent.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["tester", "coder", "writer"]);
+
+        let prompt = stats.prompt("coding", ["coder", "tester"]);
+        assert!(prompt.contains("- tester: not tried yet"));
+        assert!(prompt.contains("2/3 runs succeeded, mean latency 2.0s"));
+
+        let reloaded = RoutingStats::new().persistent(persistence, "routing.json");
+        reloaded.load().await.unwrap();
+        assert_eq!(reloaded.stats("coding", "coder"), coder);
+        assert_eq!(reloaded.stats("writing", "coder"), AgentStats::default());
+    }
+}
@@ -0,0 +1,376 @@
+//! Self-contained exports of workflow runs, to replay and inspect them offline.
+//!
+//! A [`RunBundle`] holds everything about a run in one JSON file: the [`MetadataSchema`] with
+//! the outputs, timings and tool calls of every agent, the conversations, and the artifacts
+//! the run produced. Artifacts are listed in a manifest with their size and SHA-256 checksum,
+//! their content is embedded as base64 unless the bundle only references them.
+//!
+//! Bundles are versioned like saved states and can be compressed with zstd,
+//! [`RunBundle::load`] reads either. [`RunBundle::timeline`] orders the run's events by time
+//! for a viewer to step through.
+
+use std::path::Path;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    agent::{StopReason, ToolCallRecord},
+    conversation::AgentConversation,
+    error::{CategorizedError, ErrorCategory},
+    persistence::{self, PersistenceError},
+    swarm::MetadataSchema,
+};
+
+// First bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+#[derive(Debug, Error)]
+pub enum RunBundleError {
+    #[error("Persistence error: {0}")]
+    Persistence(#[from] PersistenceError),
+    #[error("Artifact {0} is not embedded in the bundle")]
+    NotEmbedded(String),
+    #[error("Artifact {0} is corrupted: {1}")]
+    CorruptedArtifact(String, String),
+}
+
+impl CategorizedError for RunBundleError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            RunBundleError::Persistence(e) => e.category(),
+            RunBundleError::NotEmbedded(_) => ErrorCategory::Validation,
+            RunBundleError::CorruptedArtifact(..) => ErrorCategory::Persistence,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunBundle {
+    /// Usually the workflow's name.
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Version of swarms-rs which created the bundle.
+    pub swarms_version: String,
+    pub metadata: MetadataSchema,
+    #[serde(default)]
+    pub conversations: Vec<AgentConversation>,
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+}
+
+impl RunBundle {
+    /// Upgrades of saved bundles, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    pub fn new(metadata: MetadataSchema) -> Self {
+        Self {
+            name: "Workflow".to_owned(),
+            created_at: Utc::now(),
+            swarms_version: env!("CARGO_PKG_VERSION").to_owned(),
+            metadata,
+            conversations: Vec::new(),
+            artifacts: Vec::new(),
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn conversation(mut self, conversation: AgentConversation) -> Self {
+        self.conversations.push(conversation);
+        self
+    }
+
+    pub fn artifact(mut self, artifact: Artifact) -> Self {
+        self.artifacts.push(artifact);
+        self
+    }
+
+    /// The artifact with the name.
+    pub fn get_artifact(&self, name: &str) -> Option<&Artifact> {
+        self.artifacts.iter().find(|artifact| artifact.name == name)
+    }
+
+    /// Save the bundle as versioned JSON, compressed with zstd if `compressed`.
+    pub async fn save(
+        &self,
+        path: impl AsRef<Path>,
+        compressed: bool,
+    ) -> Result<(), RunBundleError> {
+        let json = persistence::to_versioned_json(self)?.into_bytes();
+        let data = if compressed {
+            persistence::compress(json)?
+        } else {
+            json
+        };
+        persistence::save_to_file(data, path).await?;
+        Ok(())
+    }
+
+    /// Load a bundle saved by [`RunBundle::save`], compressed or not.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, RunBundleError> {
+        let mut data = persistence::load_verified(path).await?;
+        if data.starts_with(&ZSTD_MAGIC) {
+            data = persistence::decompress(data)?;
+        }
+        Ok(persistence::from_versioned_json(&data, Self::MIGRATIONS)?)
+    }
+
+    /// The events of the run ordered by time, agents which started at the same time in the
+    /// order of the metadata. Tool calls only record their duration, they are laid out one
+    /// after the other from the start of their agent's run.
+    pub fn timeline(&self) -> Vec<ReplayEvent> {
+        let mut events = Vec::new();
+        for output in &self.metadata.agents_output_schema {
+            events.push(ReplayEvent::AgentStarted {
+                at: output.start,
+                agent: output.agent_name.clone(),
+                task: output.task.clone(),
+            });
+            let mut at = output.start;
+            for call in &output.tool_calls {
+                events.push(ReplayEvent::ToolCalled {
+                    at,
+                    agent: output.agent_name.clone(),
+                    call: call.clone(),
+                });
+                at += TimeDelta::milliseconds(call.duration_ms as i64);
+            }
+            events.push(ReplayEvent::AgentFinished {
+                at: output.end,
+                agent: output.agent_name.clone(),
+                output: output.output.clone(),
+                stop_reason: output.stop_reason,
+            });
+        }
+        // Stable, so every agent's events stay in order if its tool calls overrun its end
+        events.sort_by_key(ReplayEvent::at);
+        events
+    }
+}
+
+/// An event of a replayed run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ReplayEvent {
+    AgentStarted {
+        at: DateTime<Local>,
+        agent: String,
+        task: String,
+    },
+    ToolCalled {
+        at: DateTime<Local>,
+        agent: String,
+        call: ToolCallRecord,
+    },
+    AgentFinished {
+        at: DateTime<Local>,
+        agent: String,
+        output: String,
+        stop_reason: StopReason,
+    },
+}
+
+impl ReplayEvent {
+    pub fn at(&self) -> DateTime<Local> {
+        match self {
+            ReplayEvent::AgentStarted { at, .. }
+            | ReplayEvent::ToolCalled { at, .. }
+            | ReplayEvent::AgentFinished { at, .. } => *at,
+        }
+    }
+}
+
+/// A file the run produced, e.g. a report, in the manifest of a bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Artifact {
+    /// Name of the artifact, usually its path relative to the run's output directory.
+    pub name: String,
+    /// e.g. `text/markdown`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    pub size: u64,
+    /// Hex encoded SHA-256 checksum of the content.
+    pub sha256: String,
+    /// The base64 encoded content, `None` if the bundle only references the artifact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+impl Artifact {
+    /// An artifact embedding the data.
+    pub fn new(name: impl Into<String>, data: impl AsRef<[u8]>) -> Self {
+        let data = data.as_ref();
+        Self {
+            name: name.into(),
+            media_type: None,
+            size: data.len() as u64,
+            sha256: checksum(data),
+            content: Some(STANDARD.encode(data)),
+        }
+    }
+
+    /// An artifact embedding the file's content, named after the file.
+    pub async fn from_file(path: impl AsRef<Path>) -> Result<Self, RunBundleError> {
+        let path = path.as_ref();
+        let data = persistence::load_from_file(path).await?;
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        Ok(Self::new(name.to_string_lossy(), data))
+    }
+
+    pub fn media_type(mut self, media_type: impl Into<String>) -> Self {
+        self.media_type = Some(media_type.into());
+        self
+    }
+
+    /// Only reference the artifact, e.g. if it's too large to embed. Its size and checksum
+    /// are kept.
+    pub fn without_content(mut self) -> Self {
+        self.content = None;
+        self
+    }
+
+    /// The embedded content, verified against the checksum.
+    pub fn data(&self) -> Result<Vec<u8>, RunBundleError> {
+        let content = self
+            .content
+            .as_ref()
+            .ok_or_else(|| RunBundleError::NotEmbedded(self.name.clone()))?;
+        let data = STANDARD
+            .decode(content)
+            .map_err(|e| RunBundleError::CorruptedArtifact(self.name.clone(), e.to_string()))?;
+        if checksum(&data) != self.sha256 {
+            return Err(RunBundleError::CorruptedArtifact(
+                self.name.clone(),
+                "checksum mismatch".to_owned(),
+            ));
+        }
+        Ok(data)
+    }
+}
+
+fn checksum(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{conversation::Participant, swarm::AgentOutputSchema, test_support::temp_dir};
+
+    fn output(
+        agent: &str,
+        start: DateTime<Local>,
+        tool_calls: Vec<ToolCallRecord>,
+    ) -> AgentOutputSchema {
+        AgentOutputSchema {
+            run_id: Uuid::new_v4(),
+            agent_id: agent.to_owned(),
+            agent_name: agent.to_owned(),
+            task: "Write a report".to_owned(),
+            output: format!("{agent} done"),
+            start,
+            end: start + TimeDelta::seconds(2),
+            duration: 2,
+            stop_reason: StopReason::MaxLoops,
+            tool_calls,
+            injections: Vec::new(),
+            grounding: None,
+            metadata: Default::default(),
+            dedup: Default::default(),
+            tool_usage: Default::default(),
+            prompt: None,
+            provenance: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_load_and_timeline() {
+        let start = Local::now();
+        let call = ToolCallRecord {
+            agent: "researcher".to_owned(),
+            name: "search".to_owned(),
+            arguments: serde_json::json!({ "query": "rust" }),
+            output: Ok("results".to_owned()),
+            duration_ms: 500,
+        };
+        let metadata = MetadataSchema {
+            task: "Write a report".to_owned(),
+            agents_output_schema: vec![
+                output("researcher", start, vec![call.clone(), call.clone()]),
+                output("writer", start + TimeDelta::milliseconds(700), Vec::new()),
+            ],
+            ..Default::default()
+        };
+        let mut conversation = AgentConversation::new("researcher".to_owned());
+        conversation.add(Participant::human("user"), "Write a report".to_owned());
+        let bundle = RunBundle::new(metadata)
+            .name("Research")
+            .conversation(conversation)
+            .artifact(Artifact::new("report.md", "# Report").media_type("text/markdown"))
+            .artifact(Artifact::new("data.bin", [0u8, 159, 146, 150]).without_content());
+
+        let timeline = bundle.timeline();
+        let events = timeline
+            .iter()
+            .map(|event| match event {
+                ReplayEvent::AgentStarted { agent, .. } => format!("{agent} started"),
+                ReplayEvent::ToolCalled { agent, call, .. } => {
+                    format!("{agent} called {}", call.name)
+                }
+                ReplayEvent::AgentFinished { agent, .. } => format!("{agent} finished"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            [
+                "researcher started",
+                "researcher called search",
+                "researcher called search",
+                "writer started",
+                "researcher finished",
+                "writer finished",
+            ]
+        );
+        assert_eq!(timeline[2].at(), start + TimeDelta::milliseconds(500));
+
+        for compressed in [false, true] {
+            let dir = temp_dir();
+            let path = dir.join("bundle.json");
+            bundle.save(&path, compressed).await.unwrap();
+            let loaded = RunBundle::load(&path).await.unwrap();
+            assert_eq!(loaded.name, "Research");
+            assert_eq!(
+                loaded.conversations[0].history,
+                bundle.conversations[0].history
+            );
+            assert_eq!(loaded.timeline(), timeline);
+            assert_eq!(loaded.artifacts, bundle.artifacts);
+        }
+
+        let report = bundle.get_artifact("report.md").unwrap();
+        assert_eq!(report.size, 8);
+        assert_eq!(report.data().unwrap(), b"# Report");
+        assert!(matches!(
+            bundle.get_artifact("data.bin").unwrap().data(),
+            Err(RunBundleError::NotEmbedded(_))
+        ));
+        let mut tampered = report.clone();
+        tampered.content = Some(STANDARD.encode("# Forged"));
+        assert!(matches!(
+            tampered.data(),
+            Err(RunBundleError::CorruptedArtifact(..))
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! Sources of provider credentials which are fetched when they are needed.
+//!
+//! LLM providers built with a [`SecretProvider`] ask it for the API key before every request,
+//! so a key can be rotated at runtime without recreating the client or the agents using it.
+//! Secret stores like Vault can be plugged in with [`FnSecret`].
+
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use futures::future::BoxFuture;
+use thiserror::Error;
+
+use crate::error::{CategorizedError, ErrorCategory};
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("Secret not found: {0}")]
+    NotFound(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Secret error: {0}")]
+    Other(String),
+}
+
+impl CategorizedError for SecretError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            SecretError::NotFound(_) => ErrorCategory::Validation,
+            SecretError::IoError(_) => ErrorCategory::Persistence,
+            SecretError::Other(_) => ErrorCategory::Other,
+        }
+    }
+}
+
+pub trait SecretProvider: Send + Sync {
+    /// Fetch the current value of the secret, called before every request,
+    /// implementations may cache it.
+    fn get_secret(&self) -> BoxFuture<'_, Result<String, SecretError>>;
+}
+
+/// Read the secret from an environment variable on every request.
+#[derive(Clone, Debug)]
+pub struct EnvSecret {
+    name: String,
+}
+
+impl EnvSecret {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl SecretProvider for EnvSecret {
+    fn get_secret(&self) -> BoxFuture<'_, Result<String, SecretError>> {
+        Box::pin(async move {
+            std::env::var(&self.name).map_err(|_| SecretError::NotFound(self.name.clone()))
+        })
+    }
+}
+
+/// Read the secret from a file on every request, surrounding whitespace is trimmed.
+///
+/// Works with secrets mounted as files, e.g. by Kubernetes, which are updated in place.
+#[derive(Clone, Debug)]
+pub struct FileSecret {
+    path: PathBuf,
+}
+
+impl FileSecret {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl SecretProvider for FileSecret {
+    fn get_secret(&self) -> BoxFuture<'_, Result<String, SecretError>> {
+        Box::pin(async move {
+            let secret = tokio::fs::read_to_string(&self.path).await?;
+            Ok(secret.trim().to_owned())
+        })
+    }
+}
+
+/// A secret held in memory, all clones share the value, so rotating it through one clone
+/// updates every client using it.
+#[derive(Clone, Debug)]
+pub struct SharedSecret(Arc<RwLock<String>>);
+
+impl SharedSecret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(Arc::new(RwLock::new(secret.into())))
+    }
+
+    /// Replace the secret, the next request uses the new value.
+    pub fn rotate(&self, secret: impl Into<String>) {
+        // Safety: the lock is never held across a panic
+        *self.0.write().unwrap() = secret.into();
+    }
+}
+
+impl SecretProvider for SharedSecret {
+    fn get_secret(&self) -> BoxFuture<'_, Result<String, SecretError>> {
+        // Safety: the lock is never held across a panic
+        let secret = self.0.read().unwrap().clone();
+        Box::pin(async move { Ok(secret) })
+    }
+}
+
+/// Fetch the secret with a custom async closure, e.g. from Vault or a cloud secret manager.
+pub struct FnSecret<F>(F);
+
+impl<F, Fut> FnSecret<F>
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String, SecretError>> + Send + 'static,
+{
+    pub fn new(f: F) -> Self {
+        Self(f)
+    }
+}
+
+impl<F, Fut> SecretProvider for FnSecret<F>
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String, SecretError>> + Send + 'static,
+{
+    fn get_secret(&self) -> BoxFuture<'_, Result<String, SecretError>> {
+        Box::pin((self.0)())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[tokio::test]
+    async fn test_shared_secret_rotation() {
+        let secret = SharedSecret::new("key-1");
+        let provider: Arc<dyn SecretProvider> = Arc::new(secret.clone());
+        assert_eq!(provider.get_secret().await.unwrap(), "key-1");
+
+        secret.rotate("key-2");
+        assert_eq!(provider.get_secret().await.unwrap(), "key-2");
+    }
+
+    #[tokio::test]
+    async fn test_file_and_fn_secret() {
+        let dir = temp_dir();
+        let path = dir.join("secret");
+        std::fs::write(&path, "file-key\n").unwrap();
+        assert_eq!(
+            FileSecret::new(&path).get_secret().await.unwrap(),
+            "file-key"
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(FileSecret::new(&path).get_secret().await.is_err());
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let provider = FnSecret::new(move || {
+            let call = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Ok(format!("key-{call}")) }
+        });
+        assert_eq!(provider.get_secret().await.unwrap(), "key-1");
+        assert_eq!(provider.get_secret().await.unwrap(), "key-2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        assert!(matches!(
+            EnvSecret::new("SWARMS_TEST_MISSING_SECRET")
+                .get_secret()
+                .await,
+            Err(SecretError::NotFound(_))
+        ));
+    }
+}
@@ -1,20 +1,31 @@
 use std::{
     hash::{Hash, Hasher},
     ops::Deref,
-    path::Path,
+    sync::Arc,
+    time::Instant,
 };
 
 use chrono::Local;
+use futures::future::{self, BoxFuture};
 use thiserror::Error;
+use tokio::sync::mpsc;
 use twox_hash::XxHash3_64;
 use uuid::Uuid;
 
 use crate::{
-    agent::{Agent, AgentError},
-    conversation::{AgentConversation, Role},
-    persistence,
-    swarm::MetadataSchema,
-    utils::run_agent_with_output_schema,
+    agent::{Agent, AgentError, RunOptions},
+    config::SwarmsConfig,
+    conversation::{AgentConversation, Participant},
+    dry_run::{self, DryRunReport},
+    error::{CategorizedError, ErrorCategory},
+    events::{self, Phase},
+    meeting_notes::MeetingNotesSummarizer,
+    persistence::{self, FilePersistence, Persistence},
+    swarm::{AgentOutputSchema, MetadataSchema, Swarm, SwarmError, SwarmOutput},
+    tenant::{self, TenantId},
+    tool::ToolPermissions,
+    utils::{is_empty_task, run_agent_with_output_schema},
+    webhook::{WebhookConfig, WebhookEvent, WebhookNotifier},
 };
 
 pub struct SequentialWorkflowBuilder {
@@ -22,6 +33,12 @@ pub struct SequentialWorkflowBuilder {
     description: String,
     metadata_output_dir: String,
     agents: Vec<Box<dyn Agent>>,
+    webhook: Option<WebhookConfig>,
+    tenant_id: Option<TenantId>,
+    tool_permissions: Option<ToolPermissions>,
+    meeting_notes: Option<MeetingNotesSummarizer>,
+    persistence: Option<Arc<dyn Persistence>>,
+    streaming: Option<StreamingOptions>,
 }
 
 impl SequentialWorkflowBuilder {
@@ -50,12 +67,74 @@ impl SequentialWorkflowBuilder {
         self
     }
 
+    /// Post the result of each run to a webhook when the run completes or fails.
+    pub fn webhook(mut self, config: WebhookConfig) -> Self {
+        self.webhook = Some(config);
+        self
+    }
+
+    /// Write metadata in the tenant's directory, and record the tenant in it.
+    pub fn tenant(mut self, tenant_id: TenantId) -> Self {
+        self.tenant_id = Some(tenant_id);
+        self
+    }
+
+    /// Restrict the capabilities of the tools of all agents in the workflow, in addition to
+    /// the agents' own tool permissions.
+    pub fn tool_permissions(mut self, tool_permissions: ToolPermissions) -> Self {
+        self.tool_permissions = Some(tool_permissions);
+        self
+    }
+
+    /// Write meeting notes of every run next to its metadata, as `<task hash>.notes.json`.
+    pub fn meeting_notes(mut self, summarizer: MeetingNotesSummarizer) -> Self {
+        self.meeting_notes = Some(summarizer);
+        self
+    }
+
+    /// Store the metadata, meeting notes and completed results in this backend instead of
+    /// files, e.g. [`MemoryPersistence`](crate::MemoryPersistence) for tests.
+    pub fn persistence(mut self, persistence: impl Persistence + 'static) -> Self {
+        self.persistence = Some(Arc::new(persistence));
+        self
+    }
+
+    /// Run the agents at the same time, each agent processes the output of the previous one
+    /// chunk by chunk as soon as the chunks are ready, see [`StreamingOptions`].
+    pub fn streaming(mut self, options: StreamingOptions) -> Self {
+        self.streaming = Some(options);
+        self
+    }
+
+    /// Apply the global defaults, currently the metadata directory.
+    pub fn swarms_config(mut self, config: &SwarmsConfig) -> Self {
+        if let Some(dir) = &config.metadata_dir {
+            self.metadata_output_dir = dir.clone();
+        }
+        self
+    }
+
+    /// Build the workflow, fails with [`SequentialWorkflowError::EmptyTasksOrAgents`] if no
+    /// agent was added.
+    pub fn try_build(self) -> Result<SequentialWorkflow, SequentialWorkflowError> {
+        if self.agents.is_empty() {
+            return Err(SequentialWorkflowError::EmptyTasksOrAgents);
+        }
+        Ok(self.build())
+    }
+
     pub fn build(self) -> SequentialWorkflow {
         SequentialWorkflow {
             name: self.name,
             description: self.description,
             metadata_output_dir: self.metadata_output_dir,
             agents: self.agents,
+            webhook: self.webhook.map(WebhookNotifier::new),
+            tenant_id: self.tenant_id,
+            tool_permissions: self.tool_permissions,
+            meeting_notes: self.meeting_notes,
+            persistence: self.persistence,
+            streaming: self.streaming,
         }
     }
 }
@@ -65,6 +144,12 @@ pub struct SequentialWorkflow {
     description: String,
     metadata_output_dir: String,
     agents: Vec<Box<dyn Agent>>,
+    webhook: Option<WebhookNotifier>,
+    tenant_id: Option<TenantId>,
+    tool_permissions: Option<ToolPermissions>,
+    meeting_notes: Option<MeetingNotesSummarizer>,
+    persistence: Option<Arc<dyn Persistence>>,
+    streaming: Option<StreamingOptions>,
 }
 
 impl SequentialWorkflow {
@@ -74,36 +159,142 @@ impl SequentialWorkflow {
             description: "A Workflow to solve a problem with sequential agents.".to_string(),
             metadata_output_dir: "./temp/sequential_workflow/metadata".to_string(),
             agents: Vec::new(),
+            webhook: None,
+            tenant_id: None,
+            tool_permissions: None,
+            meeting_notes: None,
+            persistence: None,
+            streaming: None,
         }
     }
 
+    fn persistence(&self) -> &dyn Persistence {
+        self.persistence.as_deref().unwrap_or(&FilePersistence)
+    }
+
     pub async fn run(
         &self,
         task: impl Into<String>,
     ) -> Result<AgentConversation, SequentialWorkflowError> {
         let task = task.into();
+        let task_id = Uuid::new_v4();
+        let start = Instant::now();
+        tracing::info!(
+            target: events::TARGET,
+            event = events::WORKFLOW_RUN,
+            phase = Phase::Started.as_str(),
+            %task_id,
+            workflow = %self.name,
+        );
 
-        if self.agents.is_empty() {
-            return Err(SequentialWorkflowError::NoAgents);
+        let result = self.run_inner(task.clone()).await;
+
+        let duration_ms = start.elapsed().as_millis() as u64;
+        match &result {
+            Ok(_) => tracing::info!(
+                target: events::TARGET,
+                event = events::WORKFLOW_RUN,
+                phase = Phase::Completed.as_str(),
+                %task_id,
+                workflow = %self.name,
+                duration_ms,
+            ),
+            Err(e) => tracing::error!(
+                target: events::TARGET,
+                event = events::WORKFLOW_RUN,
+                phase = Phase::Failed.as_str(),
+                %task_id,
+                workflow = %self.name,
+                duration_ms,
+                error = %e,
+            ),
         }
 
-        if task.is_empty() {
-            return Err(SequentialWorkflowError::NoTasks);
+        match (&self.webhook, result) {
+            (Some(webhook), Ok((conversation, metadata))) => {
+                webhook.notify_in_background(WebhookEvent::completed(
+                    &self.name,
+                    &task,
+                    Some(metadata),
+                ));
+                Ok(conversation)
+            }
+            (Some(webhook), Err(e)) => {
+                webhook.notify_in_background(WebhookEvent::failed(&self.name, &task, &e));
+                Err(e)
+            }
+            (None, result) => result.map(|(conversation, _)| conversation),
+        }
+    }
+
+    /// Report the agents the workflow would run for the task, without calling any LLM.
+    pub fn dry_run(
+        &self,
+        task: impl Into<String>,
+    ) -> Result<DryRunReport, SequentialWorkflowError> {
+        let task = task.into();
+        if self.agents.is_empty() || is_empty_task(&task) {
+            return Err(SequentialWorkflowError::EmptyTasksOrAgents);
+        }
+
+        let mut next_input = task;
+        let mut steps = Vec::with_capacity(self.agents.len());
+        for agent in &self.agents {
+            steps.push(agent.dry_run(next_input));
+            next_input = dry_run::output_placeholder(&agent.name());
+        }
+        Ok(DryRunReport {
+            workflow: self.name.clone(),
+            steps,
+        })
+    }
+
+    async fn run_inner(
+        &self,
+        task: String,
+    ) -> Result<(AgentConversation, MetadataSchema), SequentialWorkflowError> {
+        if self.agents.is_empty() || is_empty_task(&task) {
+            return Err(SequentialWorkflowError::EmptyTasksOrAgents);
         }
 
         let mut conversation = AgentConversation::new(self.name.clone());
-        conversation.add(Role::User("User".to_owned()), task.clone());
+        conversation.set_tenant_id(self.tenant_id.clone());
+        conversation.add(Participant::human("User"), task.clone());
+
+        let (stages, separator) = match self.streaming {
+            Some(streaming) => (
+                self.run_streaming(task.clone(), streaming).await?,
+                streaming.chunking.separator(),
+            ),
+            None => {
+                let mut stages = Vec::with_capacity(self.agents.len());
+                let mut next_input = task.clone();
+                for agent in &self.agents {
+                    let output =
+                        run_agent_with_output_schema(agent.deref(), next_input, self.run_options())
+                            .await?;
+                    next_input = output.output.clone();
+                    stages.push(vec![output]);
+                }
+                (stages, "")
+            }
+        };
 
-        let mut next_input = task.clone();
         let mut agents_output_schema = Vec::with_capacity(self.agents.len());
-        for agent in &self.agents {
-            let output = run_agent_with_output_schema(agent.deref(), next_input.clone()).await?;
-            conversation.add(
-                Role::Assistant(agent.name().to_owned()),
-                output.output.clone(),
-            );
-            next_input = output.output.clone();
-            agents_output_schema.push(output);
+        for (agent, runs) in self.agents.iter().zip(stages) {
+            if runs.is_empty() {
+                continue;
+            }
+            let output = runs
+                .iter()
+                .map(|run| run.output.as_str())
+                .collect::<Vec<_>>()
+                .join(separator);
+            conversation.add(Participant::agent(agent.name()), output);
+            for tool_call in runs.iter().flat_map(|run| &run.tool_calls) {
+                conversation.add_tool_call(tool_call.clone());
+            }
+            agents_output_schema.extend(runs);
         }
 
         let metadata = MetadataSchema {
@@ -112,28 +303,231 @@ impl SequentialWorkflow {
             description: self.description.clone(),
             agents_output_schema,
             timestamp: Local::now(),
+            tenant_id: self.tenant_id.clone(),
         };
 
         let mut hasher = XxHash3_64::default();
         task.hash(&mut hasher);
         let task_hash = hasher.finish();
-        let metadata_path_dir = Path::new(&self.metadata_output_dir);
+        let metadata_path_dir =
+            tenant::scoped_dir(&self.metadata_output_dir, self.tenant_id.as_ref());
         let metadata_output_dir = metadata_path_dir
             .join(format!("{:x}", task_hash & 0xFFFFFFFF)) // Lower 32 bits of the hash
             .with_extension("json");
-        let metadata_data = serde_json::to_string_pretty(&metadata)?;
-        persistence::save_to_file(metadata_data, &metadata_output_dir).await?;
+        metadata
+            .save(self.persistence(), &metadata_output_dir)
+            .await?;
+
+        if let Some(summarizer) = &self.meeting_notes {
+            summarizer
+                .write_notes(
+                    &metadata,
+                    self.persistence(),
+                    &metadata_output_dir.with_extension("notes.json"),
+                )
+                .await;
+        }
+
+        Ok((conversation, metadata))
+    }
+
+    fn run_options(&self) -> RunOptions {
+        RunOptions {
+            tool_permissions: self.tool_permissions.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Run all agents at the same time, connected by bounded channels. Returns the runs of
+    /// every agent, one per chunk.
+    async fn run_streaming(
+        &self,
+        task: String,
+        streaming: StreamingOptions,
+    ) -> Result<Vec<Vec<AgentOutputSchema>>, SequentialWorkflowError> {
+        let mut inputs = vec![StageInput::Task(Some(task))];
+        let mut outputs = Vec::with_capacity(self.agents.len());
+        for _ in 1..self.agents.len() {
+            let (tx, rx) = mpsc::channel(streaming.buffer.max(1));
+            outputs.push(Some(tx));
+            inputs.push(StageInput::Upstream(rx, Chunker::new(streaming.chunking)));
+        }
+        outputs.push(None);
+
+        let stages = self
+            .agents
+            .iter()
+            .zip(inputs)
+            .zip(outputs)
+            .map(|((agent, input), output)| self.run_stage(agent.deref(), input, output));
+        // A failed agent drops its channels, which stops the agents before and after it
+        future::try_join_all(stages).await
+    }
+
+    async fn run_stage(
+        &self,
+        agent: &dyn Agent,
+        mut input: StageInput,
+        output: Option<mpsc::Sender<String>>,
+    ) -> Result<Vec<AgentOutputSchema>, SequentialWorkflowError> {
+        let mut runs = Vec::new();
+        while let Some(chunk) = input.next_chunk().await {
+            tracing::debug!(
+                "| SequentialWorkflow: {} | Agent: {} | Chunk {}",
+                self.name,
+                agent.name(),
+                runs.len()
+            );
+            let run = run_agent_with_output_schema(agent, chunk, self.run_options()).await?;
+            // Waits while the next agent is behind
+            if let Some(output) = &output
+                && output.send(run.output.clone()).await.is_err()
+            {
+                // The next agent failed, its error is returned
+                break;
+            }
+            runs.push(run);
+        }
+        Ok(runs)
+    }
+}
+
+/// Settings of a streaming [`SequentialWorkflow`].
+///
+/// Every agent's output is split into chunks, and the next agent runs once per chunk as soon
+/// as the chunk is complete, so the agents work at the same time instead of one after another.
+/// The output of an agent is its chunk outputs joined by the chunking's separator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamingOptions {
+    pub chunking: Chunking,
+    /// Max number of chunks waiting for the next agent, an agent pauses when the next one
+    /// is this far behind.
+    pub buffer: usize,
+}
+
+impl Default for StreamingOptions {
+    fn default() -> Self {
+        Self {
+            chunking: Chunking::Paragraphs(1),
+            buffer: 4,
+        }
+    }
+}
+
+/// How the output of an agent is split into the chunks the next agent processes. Blank
+/// lines and paragraphs are skipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chunking {
+    /// This many lines per chunk.
+    Lines(usize),
+    /// This many paragraphs per chunk, paragraphs are separated by blank lines.
+    Paragraphs(usize),
+    /// At least this many characters per chunk, the chunk ends at the next whitespace.
+    Chars(usize),
+}
+
+impl Chunking {
+    /// The separator between chunk outputs.
+    pub fn separator(&self) -> &'static str {
+        match self {
+            Chunking::Lines(_) => "\n",
+            Chunking::Paragraphs(_) => "\n\n",
+            Chunking::Chars(_) => " ",
+        }
+    }
+}
+
+/// Splits the outputs of an agent into chunks.
+struct Chunker {
+    chunking: Chunking,
+    buffer: String,
+}
 
-        Ok(conversation)
+impl Chunker {
+    fn new(chunking: Chunking) -> Self {
+        Self {
+            chunking,
+            buffer: String::new(),
+        }
+    }
+
+    fn push(&mut self, output: &str) {
+        self.buffer.push_str(output);
+        self.buffer.push_str(self.chunking.separator());
+    }
+
+    /// The next complete chunk, if there is one.
+    fn next_chunk(&mut self) -> Option<String> {
+        loop {
+            let end = match self.chunking {
+                Chunking::Lines(count) => self.end_of_segments(count, "\n"),
+                Chunking::Paragraphs(count) => self.end_of_segments(count, "\n\n"),
+                Chunking::Chars(count) => self
+                    .buffer
+                    .char_indices()
+                    .enumerate()
+                    .find(|(n, (_, c))| *n >= count && c.is_whitespace())
+                    .map(|(_, (i, c))| i + c.len_utf8()),
+            }?;
+            let chunk = self.buffer.drain(..end).collect::<String>();
+            if !is_empty_task(&chunk) {
+                return Some(chunk.trim().to_owned());
+            }
+        }
+    }
+
+    /// The rest of the buffer, once the previous agent is done.
+    fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        (!is_empty_task(&rest)).then(|| rest.trim().to_owned())
+    }
+
+    /// The end of the `count`th non-blank segment which is followed by the separator.
+    fn end_of_segments(&self, count: usize, separator: &str) -> Option<usize> {
+        let mut start = 0;
+        let mut segments = 0;
+        for (i, _) in self.buffer.match_indices(separator) {
+            if !is_empty_task(&self.buffer[start..i]) {
+                segments += 1;
+            }
+            start = i + separator.len();
+            if segments >= count.max(1) {
+                return Some(start);
+            }
+        }
+        None
+    }
+}
+
+/// The chunks an agent of a streaming workflow processes.
+enum StageInput {
+    /// The first agent processes the task as a whole.
+    Task(Option<String>),
+    /// The other agents process the output of the previous agent.
+    Upstream(mpsc::Receiver<String>, Chunker),
+}
+
+impl StageInput {
+    async fn next_chunk(&mut self) -> Option<String> {
+        match self {
+            StageInput::Task(task) => task.take(),
+            StageInput::Upstream(receiver, chunker) => loop {
+                if let Some(chunk) = chunker.next_chunk() {
+                    return Some(chunk);
+                }
+                match receiver.recv().await {
+                    Some(output) => chunker.push(&output),
+                    None => return chunker.finish(),
+                }
+            },
+        }
     }
 }
 
 #[derive(Debug, Error)]
 pub enum SequentialWorkflowError {
-    #[error("No agents provided.")]
-    NoAgents,
-    #[error("No tasks provided.")]
-    NoTasks,
+    #[error("Tasks or Agents are empty")]
+    EmptyTasksOrAgents,
     #[error("Agent error: {0}")]
     AgentError(#[from] AgentError),
     #[error("Persistence error: {0}")]
@@ -141,3 +535,175 @@ pub enum SequentialWorkflowError {
     #[error("Json error: {0}")]
     JsonError(#[from] serde_json::Error),
 }
+
+impl CategorizedError for SequentialWorkflowError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            SequentialWorkflowError::EmptyTasksOrAgents => ErrorCategory::Validation,
+            SequentialWorkflowError::AgentError(e) => e.category(),
+            SequentialWorkflowError::PersistenceError(e) => e.category(),
+            SequentialWorkflowError::JsonError(_) => ErrorCategory::Persistence,
+        }
+    }
+}
+
+impl Swarm for SequentialWorkflow {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, task: String) -> BoxFuture<'_, Result<SwarmOutput, SwarmError>> {
+        Box::pin(async move {
+            self.run(task)
+                .await
+                .map(|output| Box::new(output) as _)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        persistence::MemoryPersistence,
+        test_support::{TestAgent, temp_dir},
+    };
+
+    #[tokio::test]
+    async fn test_run_as_swarm() {
+        let dir = temp_dir();
+        let workflow = SequentialWorkflow::builder()
+            .name("pipeline")
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .build();
+        let swarm: &dyn Swarm = &workflow;
+        assert_eq!(swarm.name(), "pipeline");
+
+        let output = swarm.run("task".to_owned()).await.unwrap();
+        let output = serde_json::to_string(&output).unwrap();
+        // The second agent gets the output of the first one
+        assert!(output.contains("done: done: task"));
+
+        let failing = SequentialWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: true }))
+            .build();
+        let err = Swarm::run(&failing, "task".to_owned()).await.err().unwrap();
+        assert!(matches!(err, SwarmError::SequentialWorkflowError(_)));
+    }
+
+    #[tokio::test]
+    async fn test_empty_tasks_or_agents() {
+        assert!(matches!(
+            SequentialWorkflow::builder().try_build(),
+            Err(SequentialWorkflowError::EmptyTasksOrAgents)
+        ));
+
+        let workflow = SequentialWorkflow::builder()
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .try_build()
+            .unwrap();
+        for task in ["", "  \n"] {
+            assert!(matches!(
+                workflow.run(task).await,
+                Err(SequentialWorkflowError::EmptyTasksOrAgents)
+            ));
+            assert!(workflow.dry_run(task).is_err());
+        }
+    }
+
+    #[test]
+    fn test_chunker() {
+        let chunks = |chunking, outputs: &[&str]| {
+            let mut chunker = Chunker::new(chunking);
+            let mut chunks = Vec::new();
+            for output in outputs {
+                chunker.push(output);
+                chunks.extend(std::iter::from_fn(|| chunker.next_chunk()));
+            }
+            chunks.extend(chunker.finish());
+            chunks
+        };
+        assert_eq!(
+            chunks(Chunking::Lines(2), &["a\nb\n\nc", "d"]),
+            ["a\nb", "c\nd"]
+        );
+        assert_eq!(
+            chunks(Chunking::Paragraphs(1), &["a\n\n\n\nb\nc"]),
+            ["a", "b\nc"]
+        );
+        assert_eq!(
+            chunks(Chunking::Chars(3), &["ab cdef gh", "ij"]),
+            ["ab cdef", "gh ij"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streaming() {
+        let workflow = |fail| {
+            SequentialWorkflow::builder()
+                .persistence(MemoryPersistence::default())
+                .add_agent(Box::new(TestAgent { fail: false }))
+                .add_agent(Box::new(TestAgent { fail: false }))
+                .add_agent(Box::new(TestAgent { fail }))
+                .streaming(StreamingOptions {
+                    chunking: Chunking::Paragraphs(1),
+                    buffer: 1,
+                })
+                .build()
+        };
+
+        let (conversation, metadata) = workflow(false)
+            .run_inner("a\n\nb\n\nc".to_owned())
+            .await
+            .unwrap();
+        let outputs = conversation
+            .history
+            .iter()
+            .map(|message| message.body())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outputs[1..],
+            [
+                "done: a\n\nb\n\nc",
+                "done: done: a\n\ndone: b\n\ndone: c",
+                "done: done: done: a\n\ndone: done: b\n\ndone: done: c",
+            ]
+        );
+        // One run of the first agent and three of the others
+        assert_eq!(metadata.agents_output_schema.len(), 7);
+        assert_eq!(metadata.agents_output_schema[4].task, "done: done: a");
+
+        assert!(matches!(
+            workflow(true).run("a\n\nb").await,
+            Err(SequentialWorkflowError::AgentError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_versioned_metadata() {
+        let dir = temp_dir();
+        let workflow = SequentialWorkflow::builder()
+            .metadata_output_dir(dir.to_string_lossy())
+            .add_agent(Box::new(TestAgent { fail: false }))
+            .build();
+        workflow.run("task").await.unwrap();
+
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(json.contains(&format!(
+            "\"format_version\": {}",
+            persistence::FORMAT_VERSION
+        )));
+        let metadata = MetadataSchema::load(&path).await.unwrap();
+        assert_eq!(metadata.task, "task");
+        assert_eq!(metadata.agents_output_schema[0].output, "done: task");
+    }
+}
@@ -0,0 +1,194 @@
+//! Typed answers from agents.
+//!
+//! The agent is asked to answer with JSON matching the JSON schema of the output type. Answers
+//! which don't deserialize are sent back to the agent with the error, up to a number of
+//! repairs, before the run fails. JSON wrapped in a code fence or surrounded by prose is
+//! accepted too. The runs also pass the schema as [`ResponseFormat`] to the provider, which
+//! enforces it if it supports JSON mode or structured outputs.
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::{
+    agent::{Agent, AgentError, RunOptions},
+    error::{CategorizedError, ErrorCategory},
+    llm::request::ResponseFormat,
+};
+
+#[derive(Debug, Error)]
+pub enum StructuredOutputError {
+    #[error("Agent error: {0}")]
+    AgentError(#[from] AgentError),
+    #[error("Answer doesn't match the output schema: {0}")]
+    InvalidOutput(#[from] serde_json::Error),
+}
+
+impl CategorizedError for StructuredOutputError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            StructuredOutputError::AgentError(e) => e.category(),
+            // the model didn't follow the schema
+            StructuredOutputError::InvalidOutput(_) => ErrorCategory::Provider,
+        }
+    }
+}
+
+/// Instructions to answer with JSON matching the schema of `T`, to be appended to the task.
+pub fn instructions<T: JsonSchema>() -> String {
+    format!(
+        "Reply with only a JSON value matching this JSON schema, without any other text:\n{}",
+        schema::<T>()
+    )
+}
+
+/// Deserialize the answer, or the JSON in its code fence or between its outermost brackets.
+pub fn parse<T: DeserializeOwned>(answer: &str) -> Result<T, serde_json::Error> {
+    let answer = answer.trim();
+    serde_json::from_str(answer).or_else(|e| match extract_json(answer) {
+        Some(json) => serde_json::from_str(json),
+        None => Err(e),
+    })
+}
+
+/// Run the task with the [`instructions`] and deserialize the answer, see [`repair`].
+pub async fn run_structured<T>(
+    agent: &dyn Agent,
+    task: String,
+    max_repairs: usize,
+) -> Result<T, StructuredOutputError>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    let answer = agent
+        .run_with_options(format!("{task}\n\n{}", instructions::<T>()), options::<T>())
+        .await?;
+    repair(agent, answer, max_repairs).await
+}
+
+/// Deserialize the agent's answer, if that fails the agent is asked to fix it, at most
+/// `max_repairs` times.
+pub async fn repair<T>(
+    agent: &dyn Agent,
+    mut answer: String,
+    max_repairs: usize,
+) -> Result<T, StructuredOutputError>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    let mut repairs = 0;
+    loop {
+        let error = match parse(&answer) {
+            Ok(output) => return Ok(output),
+            Err(e) if repairs == max_repairs => return Err(e.into()),
+            Err(e) => e,
+        };
+        repairs += 1;
+        tracing::debug!(
+            "| structured output | Agent: {} | Invalid answer, repair {repairs}: {error}",
+            agent.name()
+        );
+        answer = agent
+            .run_with_options(
+                format!(
+                    "Your answer doesn't match the JSON schema: {error}\n\
+                     Reply again with only a JSON value matching this JSON schema:\n{}\n\n\
+                     ### Your answer:\n{answer}",
+                    schema::<T>()
+                ),
+                options::<T>(),
+            )
+            .await?;
+    }
+}
+
+/// Run options which ask the provider to enforce the JSON schema of `T`.
+pub fn options<T: JsonSchema>() -> RunOptions {
+    RunOptions {
+        response_format: Some(ResponseFormat::json_schema::<T>()),
+        ..Default::default()
+    }
+}
+
+fn schema<T: JsonSchema>() -> String {
+    let schema = schemars::schema_for!(T);
+    // Safety: a schema is always serializable
+    serde_json::to_string_pretty(&schema).unwrap()
+}
+
+fn extract_json(answer: &str) -> Option<&str> {
+    if let Some((_, fenced)) = answer.split_once("```") {
+        // Skip the language tag
+        let fenced = fenced.split_once('\n').map_or(fenced, |(_, code)| code);
+        if let Some((code, _)) = fenced.split_once("```") {
+            return Some(code.trim());
+        }
+    }
+    let start = answer.find(['{', '['])?;
+    let end = answer.rfind(['}', ']'])?;
+    (start < end).then(|| &answer[start..=end])
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{agent::swarms_agent::SwarmsAgentBuilder, test_support::FnModel};
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct Weather {
+        city: String,
+        celsius: i32,
+    }
+
+    #[test]
+    fn test_parse() {
+        let weather = Weather {
+            city: "Oslo".to_owned(),
+            celsius: -3,
+        };
+        let json = r#"{"city": "Oslo", "celsius": -3}"#;
+        assert_eq!(parse::<Weather>(json).unwrap(), weather);
+        assert_eq!(
+            parse::<Weather>(&format!("Here you go:\n```json\n{json}\n```")).unwrap(),
+            weather
+        );
+        assert_eq!(
+            parse::<Weather>(&format!("The weather is {json}.")).unwrap(),
+            weather
+        );
+        assert!(parse::<Weather>("It's cold in Oslo").is_err());
+        assert!(instructions::<Weather>().contains("\"celsius\""));
+    }
+
+    /// Answers with JSON only if the provider is asked to, the city is the schema's name.
+    fn json_mode_model() -> FnModel {
+        FnModel::new("json-mode", |request| match request.response_format {
+            Some(ResponseFormat::JsonSchema { name, strict, .. }) => {
+                serde_json::json!({ "city": name, "celsius": strict as i32 }).to_string()
+            }
+            _ => "It's cold".to_owned(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_run_structured() {
+        let agent = SwarmsAgentBuilder::new_with_model(json_mode_model())
+            .max_loops(1)
+            .build();
+        let weather = run_structured::<Weather>(&agent, "Weather in Oslo?".to_owned(), 0)
+            .await
+            .unwrap();
+        assert_eq!(
+            weather,
+            Weather {
+                city: "Weather".to_owned(),
+                celsius: 1,
+            }
+        );
+        // Plain runs don't constrain the answer
+        let answer = agent.run("Weather in Oslo?".to_owned()).await.unwrap();
+        assert_eq!(answer, "It's cold");
+    }
+}
@@ -1,40 +1,155 @@
+use std::{collections::BTreeMap, path::Path};
+
 use chrono::{DateTime, Local};
 use erased_serde::Serialize as ErasedSerialize;
 use futures::future::BoxFuture;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 
-use crate::concurrent_workflow::ConcurrentWorkflowError;
+use crate::{
+    agent::{
+        StopReason, ToolCallRecord, grounding::GroundingReport, prompt_guard::InjectionDetection,
+        prompt_store::PromptRef, tool_analytics::ToolStats, watermark::Provenance,
+    },
+    agent_rearrange::AgentRearrangeError,
+    concurrent_workflow::ConcurrentWorkflowError,
+    conversation::DedupStats,
+    error::{CategorizedError, ErrorCategory},
+    group_chat::GroupChatError,
+    mixture_of_agents::MixtureOfAgentsError,
+    persistence::{self, Persistence, PersistenceError},
+    sequential_workflow::SequentialWorkflowError,
+    tenant::TenantId,
+};
+
+/// The result of a swarm run, serializable without knowing the swarm's output type.
+pub type SwarmOutput = Box<dyn ErasedSerialize + Send + Sync>;
 
-pub trait Swarm {
+pub trait Swarm: Send + Sync {
     fn name(&self) -> &str;
 
-    fn run(&self, task: String) -> BoxFuture<Result<Box<dyn ErasedSerialize>, SwarmError>>;
+    fn run(&self, task: String) -> BoxFuture<'_, Result<SwarmOutput, SwarmError>>;
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum SwarmError {
     #[error("ConcurrentWorkflowError: {0}")]
     ConcurrentWorkflowError(#[from] ConcurrentWorkflowError),
+    #[error("SequentialWorkflowError: {0}")]
+    SequentialWorkflowError(#[from] SequentialWorkflowError),
+    #[error("GroupChatError: {0}")]
+    GroupChatError(#[from] GroupChatError),
+    #[error("AgentRearrangeError: {0}")]
+    AgentRearrangeError(#[from] AgentRearrangeError),
+    #[error("MixtureOfAgentsError: {0}")]
+    MixtureOfAgentsError(#[from] MixtureOfAgentsError),
+}
+
+impl CategorizedError for SwarmError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            SwarmError::ConcurrentWorkflowError(e) => e.category(),
+            SwarmError::SequentialWorkflowError(e) => e.category(),
+            SwarmError::GroupChatError(e) => e.category(),
+            SwarmError::AgentRearrangeError(e) => e.category(),
+            SwarmError::MixtureOfAgentsError(e) => e.category(),
+        }
+    }
 }
 
-#[derive(Clone, Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct MetadataSchema {
     pub swarm_id: Uuid,
     pub task: String,
     pub description: String,
     pub agents_output_schema: Vec<AgentOutputSchema>,
     pub timestamp: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<TenantId>,
+}
+
+impl MetadataSchema {
+    /// Upgrades of saved metadata, see [`persistence::Migration`].
+    const MIGRATIONS: &[persistence::Migration] = &[persistence::unchanged];
+
+    /// Load metadata saved by a workflow run.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, PersistenceError> {
+        let data = persistence::load_verified(path).await?;
+        persistence::from_versioned_json(&data, Self::MIGRATIONS)
+    }
+
+    /// Save the metadata as versioned JSON.
+    pub(crate) async fn save(
+        &self,
+        persistence: &dyn Persistence,
+        path: &Path,
+    ) -> Result<(), PersistenceError> {
+        let json = persistence::to_versioned_json(self)?;
+        persistence.save(path, json.into_bytes()).await
+    }
+
+    /// The tool calls of all agents in the run.
+    pub fn tool_calls(&self) -> impl Iterator<Item = &ToolCallRecord> {
+        self.agents_output_schema
+            .iter()
+            .flat_map(|output| &output.tool_calls)
+    }
+
+    /// The duplicate messages all agents dropped from their prompts.
+    pub fn dedup(&self) -> DedupStats {
+        let mut stats = DedupStats::default();
+        for output in &self.agents_output_schema {
+            stats += output.dedup;
+        }
+        stats
+    }
+
+    /// The prompt injections found by all agents.
+    pub fn injections(&self) -> impl Iterator<Item = &InjectionDetection> {
+        self.agents_output_schema
+            .iter()
+            .flat_map(|output| &output.injections)
+    }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AgentOutputSchema {
     pub run_id: Uuid,
+    /// The agent's [`Agent::id`](crate::agent::Agent::id).
+    #[serde(default)]
+    pub agent_id: String,
     pub agent_name: String,
     pub task: String,
     pub output: String,
     pub start: DateTime<Local>,
     pub end: DateTime<Local>,
     pub duration: i64,
+    /// Why the agent stopped, check [`StopReason::is_completed`] to tell whether it gave up.
+    pub stop_reason: StopReason,
+    /// The tool calls the agent made during the run.
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCallRecord>,
+    /// Prompt injections found in tool outputs and retrieved documents during the run.
+    #[serde(default)]
+    pub injections: Vec<InjectionDetection>,
+    /// How well the retrieved context supports the output, if the agent checks it.
+    #[serde(default)]
+    pub grounding: Option<GroundingReport>,
+    /// The agent's tags, see [`AgentConfig::metadata`](crate::agent::AgentConfig::metadata).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    /// Duplicate messages the agent dropped from its prompts.
+    #[serde(default, skip_serializing_if = "DedupStats::is_empty")]
+    pub dedup: DedupStats,
+    /// Calls, failures and latency of each tool in the run.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tool_usage: BTreeMap<String, ToolStats>,
+    /// The version of the system prompt, if the agent takes it from a prompt store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<PromptRef>,
+    /// Who produced the output, if the agent tags its outputs with a watermark.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
 }
@@ -1,23 +1,56 @@
+use futures::{StreamExt, stream};
 use thiserror::Error;
 
 use crate::{
     agent::Agent,
-    concurrent_workflow::ConcurrentWorkflow,
+    agent_rearrange::AgentRearrange,
+    concurrent_workflow::{ConcurrentWorkflow, ConcurrentWorkflowBuilder},
+    config::SwarmsConfig,
+    error::{CategorizedError, ErrorCategory},
+    group_chat::GroupChat,
+    mixture_of_agents::MixtureOfAgents,
+    sequential_workflow::{SequentialWorkflow, SequentialWorkflowBuilder, StreamingOptions},
     swarm::{Swarm, SwarmError},
+    tenant::TenantId,
+    tool::ToolPermissions,
+    webhook::WebhookConfig,
 };
 
+pub use crate::swarm::SwarmOutput;
+
 #[derive(Debug, Error)]
 pub enum SwarmRouterError {
     #[error("Swarm Error: {0}")]
     SwarmError(#[from] SwarmError),
+    #[error("Swarm type {0:?} is not supported by the router")]
+    UnsupportedSwarmType(SwarmType),
+    #[error("Aggregator {0} is not one of the router's agents")]
+    UnknownAggregator(String),
+}
+
+impl CategorizedError for SwarmRouterError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            SwarmRouterError::SwarmError(e) => e.category(),
+            SwarmRouterError::UnsupportedSwarmType(_) | SwarmRouterError::UnknownAggregator(_) => {
+                ErrorCategory::Validation
+            }
+        }
+    }
 }
 
+/// Runs tasks in a swarm of the chosen type.
+///
+/// The router keeps the agents and the config, every run builds a fresh swarm from them. Runs
+/// may run concurrently and repeat tasks, and no per-run state of the swarms piles up in a
+/// long-running router.
 pub struct SwarmRouter {
     name: String,
     description: String,
-    swarm: Option<Box<dyn Swarm>>,
     swarm_type: SwarmType,
     agents: Vec<Box<dyn Agent>>,
+    config: Option<SwarmsConfig>,
+    swarm_config: Option<SwarmConfig>,
 }
 
 impl SwarmRouter {
@@ -30,38 +63,225 @@ impl SwarmRouter {
         Self {
             name: name.into(),
             description: description.into(),
-            swarm: None,
             swarm_type,
             agents,
+            config: None,
+            swarm_config: None,
         }
     }
 
-    pub async fn run(
-        &mut self,
-        task: impl Into<String>,
-    ) -> Result<Box<dyn erased_serde::Serialize>, SwarmRouterError> {
-        let task = task.into();
-        self.swarm = Some(self.create_swarm(&task));
+    /// Apply the global defaults to the swarm, see the `swarms_config` of its builder.
+    pub fn swarms_config(mut self, config: &SwarmsConfig) -> Self {
+        self.config = Some(config.clone());
+        self
+    }
+
+    /// Route to the swarm type of the config and tune the swarm with it, the config's
+    /// settings take precedence over [`SwarmRouter::swarms_config`].
+    pub fn swarm_config(mut self, config: SwarmConfig) -> Self {
+        self.swarm_type = config.swarm_type();
+        self.swarm_config = Some(config);
+        self
+    }
 
-        let result = self.swarm.as_ref().unwrap().run(task).await?;
+    pub async fn run(&self, task: impl Into<String>) -> Result<SwarmOutput, SwarmRouterError> {
+        let result = self.create_swarm()?.run(task.into()).await?;
         Ok(result)
     }
 
-    fn create_swarm(&self, task: &str) -> Box<dyn Swarm> {
-        match self.swarm_type {
-            SwarmType::ConcurrentWorkflow => Box::new(
-                ConcurrentWorkflow::builder()
+    /// Run the tasks in the swarm, at most `max_concurrency` at the same time.
+    ///
+    /// The results are in the order of the tasks, a failed task doesn't stop the others.
+    pub async fn run_batch(
+        &self,
+        tasks: Vec<String>,
+        max_concurrency: usize,
+    ) -> Vec<Result<SwarmOutput, SwarmRouterError>> {
+        stream::iter(tasks)
+            .map(|task| async move {
+                let swarm = self.create_swarm()?;
+                swarm.run(task).await.map_err(SwarmRouterError::from)
+            })
+            .buffered(max_concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    fn create_swarm(&self) -> Result<Box<dyn Swarm>, SwarmRouterError> {
+        let config = self.config.clone().unwrap_or_default();
+        let swarm: Box<dyn Swarm> = match self.swarm_type {
+            SwarmType::ConcurrentWorkflow => {
+                let mut builder = ConcurrentWorkflow::builder()
+                    .name(&self.name)
+                    .description(&self.description)
+                    .agents(self.agents.clone())
+                    .swarms_config(&config);
+                if let Some(SwarmConfig::Concurrent(swarm_config)) = self.swarm_config.clone() {
+                    builder = swarm_config.apply(builder);
+                }
+                Box::new(builder.build())
+            }
+            SwarmType::SequentialWorkflow => {
+                let mut builder = SequentialWorkflow::builder()
                     .name(&self.name)
                     .description(&self.description)
                     .agents(self.agents.clone())
-                    .build(),
-            ),
-            // TODO: Add more swarm types
-            _ => unimplemented!(),
+                    .swarms_config(&config);
+                if let Some(SwarmConfig::Sequential(swarm_config)) = self.swarm_config.clone() {
+                    builder = swarm_config.apply(builder);
+                }
+                Box::new(builder.build())
+            }
+            SwarmType::GroupChat => {
+                let mut builder = GroupChat::builder()
+                    .name(&self.name)
+                    .description(&self.description)
+                    .agents(self.agents.clone());
+                if let Some(seed) = config.seed {
+                    builder = builder.seed(seed);
+                }
+                Box::new(builder.build())
+            }
+            SwarmType::AgentRearrange => {
+                let mut builder = AgentRearrange::builder()
+                    .name(&self.name)
+                    .description(&self.description)
+                    .agents(self.agents.clone());
+                if let Some(SwarmConfig::Rearrange(RearrangeConfig { flow: Some(flow) })) =
+                    self.swarm_config.clone()
+                {
+                    builder = builder.flow(flow);
+                }
+                Box::new(builder.build())
+            }
+            SwarmType::MixtureOfAgents => {
+                let swarm_config = match self.swarm_config.clone() {
+                    Some(SwarmConfig::Mixture(swarm_config)) => swarm_config,
+                    _ => MixtureConfig::default(),
+                };
+                let mut experts = self.agents.clone();
+                let aggregator = match &swarm_config.aggregator {
+                    Some(name) => experts
+                        .iter()
+                        .position(|agent| agent.name() == *name)
+                        .ok_or_else(|| SwarmRouterError::UnknownAggregator(name.clone()))?,
+                    None => experts.len().saturating_sub(1),
+                };
+                let mut builder = MixtureOfAgents::builder()
+                    .name(&self.name)
+                    .description(&self.description);
+                if aggregator < experts.len() {
+                    builder = builder.aggregator(experts.remove(aggregator));
+                }
+                if let Some(layers) = swarm_config.layers {
+                    builder = builder.layers(layers);
+                }
+                Box::new(builder.agents(experts).build())
+            }
+            swarm_type => return Err(SwarmRouterError::UnsupportedSwarmType(swarm_type)),
+        };
+        Ok(swarm)
+    }
+}
+
+/// Settings of a routed swarm, the variant selects the swarm type.
+///
+/// Unset fields keep the defaults of the swarm's builder.
+#[derive(Clone, Debug)]
+pub enum SwarmConfig {
+    Concurrent(ConcurrentConfig),
+    Sequential(SequentialConfig),
+    Rearrange(RearrangeConfig),
+    Mixture(MixtureConfig),
+}
+
+impl SwarmConfig {
+    pub fn swarm_type(&self) -> SwarmType {
+        match self {
+            SwarmConfig::Concurrent(_) => SwarmType::ConcurrentWorkflow,
+            SwarmConfig::Sequential(_) => SwarmType::SequentialWorkflow,
+            SwarmConfig::Rearrange(_) => SwarmType::AgentRearrange,
+            SwarmConfig::Mixture(_) => SwarmType::MixtureOfAgents,
         }
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct ConcurrentConfig {
+    pub metadata_dir: Option<String>,
+    /// Max number of agents running the same task at the same time.
+    pub max_concurrency: Option<usize>,
+    pub tenant_id: Option<TenantId>,
+    pub tool_permissions: Option<ToolPermissions>,
+    pub webhook: Option<WebhookConfig>,
+}
+
+impl ConcurrentConfig {
+    fn apply(self, mut builder: ConcurrentWorkflowBuilder) -> ConcurrentWorkflowBuilder {
+        if let Some(dir) = self.metadata_dir {
+            builder = builder.metadata_output_dir(dir);
+        }
+        if let Some(max_concurrency) = self.max_concurrency {
+            builder = builder.max_concurrency(max_concurrency);
+        }
+        if let Some(tenant_id) = self.tenant_id {
+            builder = builder.tenant(tenant_id);
+        }
+        if let Some(tool_permissions) = self.tool_permissions {
+            builder = builder.tool_permissions(tool_permissions);
+        }
+        if let Some(webhook) = self.webhook {
+            builder = builder.webhook(webhook);
+        }
+        builder
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SequentialConfig {
+    pub metadata_dir: Option<String>,
+    pub tenant_id: Option<TenantId>,
+    pub tool_permissions: Option<ToolPermissions>,
+    pub webhook: Option<WebhookConfig>,
+    /// Run the agents as a streaming pipeline, see [`StreamingOptions`].
+    pub streaming: Option<StreamingOptions>,
+}
+
+impl SequentialConfig {
+    fn apply(self, mut builder: SequentialWorkflowBuilder) -> SequentialWorkflowBuilder {
+        if let Some(dir) = self.metadata_dir {
+            builder = builder.metadata_output_dir(dir);
+        }
+        if let Some(tenant_id) = self.tenant_id {
+            builder = builder.tenant(tenant_id);
+        }
+        if let Some(tool_permissions) = self.tool_permissions {
+            builder = builder.tool_permissions(tool_permissions);
+        }
+        if let Some(webhook) = self.webhook {
+            builder = builder.webhook(webhook);
+        }
+        if let Some(streaming) = self.streaming {
+            builder = builder.streaming(streaming);
+        }
+        builder
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RearrangeConfig {
+    /// The flow of the agents, e.g. `"a -> b, c -> d"`, they run in order without one.
+    pub flow: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MixtureConfig {
+    /// Name of the agent aggregating the others' answers, the last agent without one.
+    pub aggregator: Option<String>,
+    pub layers: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SwarmType {
     Auto,
     AgentRearrange,
@@ -80,9 +300,160 @@ impl Default for SwarmRouter {
         Self {
             name: "SwarmRouter".to_string(),
             description: "Routes your task to the desired swarm.".to_string(),
-            swarm: None,
             swarm_type: SwarmType::Auto,
             agents: Vec::new(),
+            config: None,
+            swarm_config: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FnAgent, TestAgent, temp_dir};
+
+    #[tokio::test]
+    async fn test_run_batch() {
+        let dir = temp_dir();
+        let config = SwarmsConfig {
+            metadata_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let router = SwarmRouter::new(
+            "router",
+            "test",
+            SwarmType::SequentialWorkflow,
+            vec![Box::new(TestAgent { fail: false })],
+        )
+        .swarms_config(&config);
+
+        let output = router.run("first").await.unwrap();
+        assert!(
+            serde_json::to_string(&output)
+                .unwrap()
+                .contains("done: first")
+        );
+
+        let results = router
+            .run_batch(vec!["a".to_owned(), String::new(), "c".to_owned()], 2)
+            .await;
+        assert_eq!(results.len(), 3);
+        assert!(
+            serde_json::to_string(results[0].as_ref().unwrap())
+                .unwrap()
+                .contains("done: a")
+        );
+        // The empty task fails on its own
+        assert!(results[1].is_err());
+        assert!(
+            serde_json::to_string(results[2].as_ref().unwrap())
+                .unwrap()
+                .contains("done: c")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repeat_task_on_concurrent_workflow() {
+        let dir = temp_dir();
+        let config = SwarmsConfig {
+            metadata_dir: Some(dir.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let router = SwarmRouter::new(
+            "router",
+            "test",
+            SwarmType::ConcurrentWorkflow,
+            vec![Box::new(TestAgent { fail: false })],
+        )
+        .swarms_config(&config);
+
+        router.run("task").await.unwrap();
+        router.run("task").await.unwrap();
+        let results = router
+            .run_batch(vec!["task".to_owned(), "task".to_owned()], 2)
+            .await;
+        assert!(results.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn test_swarm_config() {
+        let dir = temp_dir();
+        let tenant_id = TenantId::new("acme").unwrap();
+        let router = SwarmRouter::new(
+            "router",
+            "test",
+            SwarmType::Auto,
+            vec![Box::new(TestAgent { fail: false })],
+        )
+        .swarm_config(SwarmConfig::Concurrent(ConcurrentConfig {
+            metadata_dir: Some(dir.to_string_lossy().into_owned()),
+            tenant_id: Some(tenant_id.clone()),
+            ..Default::default()
+        }));
+        assert_eq!(router.swarm_type, SwarmType::ConcurrentWorkflow);
+
+        router.run("task").await.unwrap();
+        // The metadata is written in the tenant's directory of the configured dir
+        assert!(tenant_id.scope_dir(&dir).is_dir());
+    }
+
+    fn agents() -> Vec<Box<dyn Agent>> {
+        ["a", "b", "c"]
+            .into_iter()
+            .map(|name| -> Box<dyn Agent> {
+                Box::new(FnAgent::new(name, move |task| format!("{name}({task})")))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_rearrange_and_mixture() {
+        let router = SwarmRouter::new("router", "test", SwarmType::Auto, agents()).swarm_config(
+            SwarmConfig::Rearrange(RearrangeConfig {
+                flow: Some("c -> a".to_owned()),
+            }),
+        );
+        let output = serde_json::to_string(&router.run("task").await.unwrap()).unwrap();
+        assert!(output.contains("a(c(task))"));
+
+        let router = SwarmRouter::new("router", "test", SwarmType::Auto, agents()).swarm_config(
+            SwarmConfig::Mixture(MixtureConfig {
+                aggregator: Some("a".to_owned()),
+                ..Default::default()
+            }),
+        );
+        let output = serde_json::to_string(&router.run("task").await.unwrap()).unwrap();
+        assert!(
+            output
+                .contains("a(task\\n\\n### Answers of the experts:\\n- b: b(task)\\n- c: c(task))")
+        );
+
+        let router = SwarmRouter::new("router", "test", SwarmType::Auto, agents()).swarm_config(
+            SwarmConfig::Mixture(MixtureConfig {
+                aggregator: Some("x".to_owned()),
+                ..Default::default()
+            }),
+        );
+        assert!(matches!(
+            router.run("task").await,
+            Err(SwarmRouterError::UnknownAggregator(name)) if name == "x"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_swarm_type() {
+        let router = SwarmRouter::new("router", "test", SwarmType::MajorityVoting, agents());
+        assert!(matches!(
+            router.run("task").await,
+            Err(SwarmRouterError::UnsupportedSwarmType(
+                SwarmType::MajorityVoting
+            ))
+        ));
+        let results = router.run_batch(vec!["task".to_owned()], 1).await;
+        assert!(matches!(
+            results[..],
+            [Err(SwarmRouterError::UnsupportedSwarmType(_))]
+        ));
+    }
+}
@@ -1,10 +1,17 @@
-use futures::{StreamExt, stream};
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures::future;
 use thiserror::Error;
-use tokio::sync::mpsc;
 
 use crate::{
     agent::{Agent, AgentError},
-    conversation::SwarmConversation,
+    concurrency,
+    error::{CategorizedError, ErrorCategory},
+    utils::{has_empty_tasks, is_empty_task},
 };
 
 #[derive(Debug, Error)]
@@ -17,190 +24,415 @@ pub enum SwarmingArchsError {
     CanNotFormAPerfectSquareGrid,
 }
 
+impl CategorizedError for SwarmingArchsError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            SwarmingArchsError::EmptyTasksOrAgents
+            | SwarmingArchsError::CanNotFormAPerfectSquareGrid => ErrorCategory::Validation,
+            SwarmingArchsError::AgentError(e) => e.category(),
+        }
+    }
+}
+
+pub use crate::conversation::{AgentFailure, AgentLog, SwarmConversation, SwarmStats};
+
 pub enum SwarmResult {
-    Responses(Vec<String>),
+    /// The responses of the agents which succeeded, and the failures of the others.
+    Responses {
+        responses: Vec<String>,
+        failures: Vec<AgentFailure>,
+        stats: SwarmStats,
+    },
     FullHistory(SwarmConversation),
 }
 
+impl SwarmResult {
+    /// The agent-task pairs which failed.
+    pub fn failures(&self) -> &[AgentFailure] {
+        match self {
+            SwarmResult::Responses { failures, .. } => failures,
+            SwarmResult::FullHistory(conversation) => &conversation.failures,
+        }
+    }
+
+    /// Statistics of the run, see [`SwarmConversation::stats`].
+    pub fn stats(&self) -> SwarmStats {
+        match self {
+            SwarmResult::Responses { stats, .. } => stats.clone(),
+            SwarmResult::FullHistory(conversation) => conversation.stats(),
+        }
+    }
+}
+
+/// Run the task and measure how long the agent took.
+async fn timed_run(
+    agent: &(impl Agent + ?Sized),
+    task: String,
+) -> (Result<String, AgentError>, Duration) {
+    let start = Instant::now();
+    let result = agent.run(task).await;
+    (result, start.elapsed())
+}
+
 /// All agents process each task in a circular manner, each agent process each task.
 pub async fn circular_swarm(
     mut agents: Vec<Box<dyn Agent>>,
     tasks: Vec<String>,
     return_full_history: bool,
 ) -> Result<SwarmResult, SwarmingArchsError> {
-    if agents.is_empty() || tasks.is_empty() || tasks.iter().all(|task| task.is_empty()) {
+    if agents.is_empty() || has_empty_tasks(&tasks) {
         return Err(SwarmingArchsError::EmptyTasksOrAgents);
     }
 
     // TODO: maybe need concurrency? Now it's sequential, because the `run` method needs a mutable reference to the agent
     let mut conversation = SwarmConversation::new();
     let mut responses = Vec::new();
+    // Every task is a round
     for task in &tasks {
         for agent in &mut agents {
-            let response = match agent.run(task.to_owned()).await {
+            let (result, latency) = timed_run(agent.as_ref(), task.to_owned()).await;
+            let response = match result {
                 Ok(response) => response,
                 Err(e) => {
-                    tracing::error!(
-                        "| circular swarm | Agent {} | Task {} | Error: {}",
-                        agent.name(),
-                        task,
-                        e
-                    );
+                    conversation.add_failure(agent.name(), task.to_owned(), e);
                     continue;
                 }
             };
-            conversation.add_log(agent.name(), task.to_owned(), response.clone());
+            conversation.add_timed_log(agent.name(), task.to_owned(), response.clone(), latency);
             responses.push(response);
         }
+        conversation.next_round();
     }
 
     if return_full_history {
         Ok(SwarmResult::FullHistory(conversation))
     } else {
-        Ok(SwarmResult::Responses(responses))
+        Ok(SwarmResult::Responses {
+            responses,
+            stats: conversation.stats(),
+            failures: conversation.failures,
+        })
+    }
+}
+
+/// How [`grid_swarm`] assigns the tasks to the agents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GridOptions {
+    /// Fail with [`SwarmingArchsError::CanNotFormAPerfectSquareGrid`] unless the number of
+    /// agents is a perfect square.
+    pub require_square: bool,
+    /// Agents take the next task from a shared queue once they are done, so every task runs
+    /// even if there are more tasks than agents. Otherwise agent `i` runs task `i` only.
+    pub work_queue: bool,
+}
+
+impl Default for GridOptions {
+    fn default() -> Self {
+        Self {
+            require_square: true,
+            work_queue: false,
+        }
     }
 }
 
+pub struct GridResult {
+    pub conversation: SwarmConversation,
+    /// Tasks no agent was assigned, only without the work queue when there are more tasks
+    /// than agents.
+    pub unassigned: Vec<String>,
+}
+
 /// Grid Swarm: (Concurrently) Agents are arranged in a grid and process tasks in a grid-like manner, a agent process a task, then the next agent process the next task, and so on.
 pub async fn grid_swarm(
     agents: Vec<Box<dyn Agent>>,
     tasks: Vec<String>,
-) -> Result<SwarmConversation, SwarmingArchsError> {
-    if agents.is_empty() || tasks.is_empty() || tasks.iter().all(|task| task.is_empty()) {
+    options: GridOptions,
+) -> Result<GridResult, SwarmingArchsError> {
+    if agents.is_empty() || has_empty_tasks(&tasks) {
         return Err(SwarmingArchsError::EmptyTasksOrAgents);
     }
 
-    let mut conversation = SwarmConversation::new();
-    let (tx, mut rx) = mpsc::channel(agents.len());
-
     let grid_size = (agents.len() as f64).sqrt() as usize;
-    if grid_size * grid_size != agents.len() {
+    if options.require_square && grid_size * grid_size != agents.len() {
         return Err(SwarmingArchsError::CanNotFormAPerfectSquareGrid);
     }
 
-    stream::iter(agents.into_iter().enumerate())
-        .for_each_concurrent(None, |(index, agent)| {
-            let tx = tx.clone();
-            let task = tasks.get(index).cloned();
-            async move {
-                if let Some(task) = task {
-                    let result = agent
-                        .run(task.clone())
-                        .await
-                        .map(|response| (agent.name(), task, response));
-                    tx.send(result).await.unwrap(); // Safe: we know the rx is alive
-                }
+    let mut queue = VecDeque::from(tasks);
+    let mut unassigned = Vec::new();
+    // Tasks of each agent, the queue hands out the rest on demand
+    let mut assigned = vec![VecDeque::new(); agents.len()];
+    if !options.work_queue {
+        for slot in &mut assigned {
+            slot.extend(queue.pop_front());
+        }
+        unassigned.extend(queue.drain(..));
+        if !unassigned.is_empty() {
+            tracing::warn!(
+                "| grid swarm | {} tasks are not assigned, more tasks than agents",
+                unassigned.len()
+            );
+        }
+    }
+    let queue = Mutex::new(queue);
+
+    let results = future::join_all(agents.iter().zip(assigned).map(|(agent, mut own)| {
+        let queue = &queue;
+        async move {
+            let mut results = Vec::new();
+            // Safety: the lock is never held across an await or by a panicking thread
+            while let Some(task) = own
+                .pop_front()
+                .or_else(|| queue.lock().unwrap().pop_front())
+            {
+                let (result, latency) = timed_run(agent.as_ref(), task.clone()).await;
+                results.push((agent.name(), task, result, latency));
             }
-        })
-        .await;
+            results
+        }
+    }))
+    .await;
 
-    while let Some(result) = rx.recv().await {
+    // All agents work in the same round
+    let mut conversation = SwarmConversation::new();
+    for (agent_name, task, result, latency) in results.into_iter().flatten() {
         match result {
-            Ok((agent_name, task, response)) => {
-                conversation.add_log(agent_name, task, response);
-            }
-            Err(e) => {
-                tracing::error!("Agent failed in grid swarm: {}", e);
-            }
+            Ok(response) => conversation.add_timed_log(agent_name, task, response, latency),
+            Err(e) => conversation.add_failure(agent_name, task, e),
         }
     }
 
-    Ok(conversation)
+    Ok(GridResult {
+        conversation,
+        unassigned,
+    })
 }
 
 /// Linear Swarm: Agents process tasks in a sequential linear manner, a agent process a task, then the next agent process the next task, and so on.
+///
+/// A failed agent is reported in the failures, the next agents still run.
 pub async fn linear_swarm(
     agents: Vec<Box<dyn Agent>>,
     mut tasks: Vec<String>,
     return_full_history: bool,
 ) -> Result<SwarmResult, SwarmingArchsError> {
-    if agents.is_empty() || tasks.is_empty() {
+    if agents.is_empty() || has_empty_tasks(&tasks) {
         return Err(SwarmingArchsError::EmptyTasksOrAgents);
     }
 
     let mut conversation = SwarmConversation::new();
     let mut responses = Vec::new();
 
+    // Every agent is a round
     for agent in agents {
         if let Some(task) = tasks.pop() {
-            let response = agent.run(task.clone()).await?;
-            conversation.add_log(agent.name(), task, response.clone());
-            responses.push(response);
+            let (result, latency) = timed_run(agent.as_ref(), task.clone()).await;
+            match result {
+                Ok(response) => {
+                    conversation.add_timed_log(agent.name(), task, response.clone(), latency);
+                    responses.push(response);
+                }
+                Err(e) => conversation.add_failure(agent.name(), task, e),
+            }
+            conversation.next_round();
         };
     }
 
     if return_full_history {
         Ok(SwarmResult::FullHistory(conversation))
     } else {
-        Ok(SwarmResult::Responses(responses))
+        Ok(SwarmResult::Responses {
+            responses,
+            stats: conversation.stats(),
+            failures: conversation.failures,
+        })
     }
 }
 
 /// Facilitates one-to-one communication between two agents. The sender and receiver agents exchange messages for a specified number of loops.
+///
+/// The sender starts with the task, afterwards each agent gets the task and the conversation so
+/// far and replies to the other's latest message. If an agent fails, the failure is reported in
+/// the conversation and the exchange ends. See [`one_to_one_until_complete`] to end the
+/// exchange early.
 pub async fn one_to_one(
     sender: impl Agent,
     receiver: impl Agent,
     task: impl Into<String>,
     max_loops: u32,
 ) -> Result<SwarmConversation, SwarmingArchsError> {
-    let task = task.into();
-    if task.is_empty() {
+    exchange(sender, receiver, task.into(), max_loops, false).await
+}
+
+/// Like [`one_to_one`], but the exchange ends as soon as a response is complete by
+/// [`Agent::is_response_complete`], e.g. it contains one of the agent's stop words, and the
+/// conversation is marked as converged.
+pub async fn one_to_one_until_complete(
+    sender: impl Agent,
+    receiver: impl Agent,
+    task: impl Into<String>,
+    max_loops: u32,
+) -> Result<SwarmConversation, SwarmingArchsError> {
+    exchange(sender, receiver, task.into(), max_loops, true).await
+}
+
+async fn exchange(
+    sender: impl Agent,
+    receiver: impl Agent,
+    task: String,
+    max_loops: u32,
+    stop_on_stop_word: bool,
+) -> Result<SwarmConversation, SwarmingArchsError> {
+    if is_empty_task(&task) {
         return Err(SwarmingArchsError::EmptyTasksOrAgents);
     }
 
     let mut conversation = SwarmConversation::new();
-    let mut responses = Vec::new();
-
-    for _ in 0..max_loops {
-        let task = task.clone();
-        let sender_response = sender.run(task.clone()).await?;
-        conversation.add_log(sender.name(), task.clone(), sender_response.clone());
-        responses.push(sender_response.clone());
+    // (agent name, message) of every turn
+    let mut history = Vec::new();
+
+    // Every loop is a round
+    'exchange: for _ in 0..max_loops {
+        for (agent, other) in [
+            (&sender as &dyn Agent, &receiver as &dyn Agent),
+            (&receiver, &sender),
+        ] {
+            let prompt = if history.is_empty() {
+                task.clone()
+            } else {
+                exchange_prompt(&task, &history, &other.name())
+            };
+            let (result, latency) = timed_run(agent, prompt).await;
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    conversation.add_failure(agent.name(), task.clone(), e);
+                    break 'exchange;
+                }
+            };
+            conversation.add_timed_log(agent.name(), task.clone(), response.clone(), latency);
+            history.push((agent.name(), response.clone()));
 
-        let receiver_response = receiver.run(sender_response).await?;
-        conversation.add_log(receiver.name(), task, receiver_response.clone());
-        responses.push(receiver_response);
+            if stop_on_stop_word && agent.is_response_complete(response) {
+                conversation.converged = true;
+                break 'exchange;
+            }
+        }
+        conversation.next_round();
     }
 
     Ok(conversation)
 }
 
+fn exchange_prompt(task: &str, history: &[(String, String)], other: &str) -> String {
+    let transcript = history
+        .iter()
+        .map(|(name, message)| format!("[{name}] {message}"))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    format!(
+        "Task: {task}\n\nConversation so far:\n{transcript}\n\nReply to the latest message of {other}."
+    )
+}
+
 /// (Concurrently) Sender agent processes the task and then sends the result to all receivers agent.
 pub async fn one_to_three(
     sender: impl Agent,
     receivers: [Box<dyn Agent>; 3],
     task: impl Into<String>,
+) -> Result<SwarmConversation, SwarmingArchsError> {
+    one_to_many(
+        sender,
+        receivers.into(),
+        task,
+        FanoutStrategy::Broadcast,
+        false,
+    )
+    .await
+}
+
+/// How [`one_to_many`] hands the sender's message to the receivers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FanoutStrategy {
+    /// Every receiver gets the whole message.
+    #[default]
+    Broadcast,
+    /// The non-empty lines of the message are dealt round robin to the receivers, e.g. one
+    /// subtask per line. Receivers which get no line don't run.
+    SplitLines,
+}
+
+/// (Concurrently) Sender agent processes the task and then fans its message out to any number
+/// of receiver agents.
+///
+/// If `aggregate` is set, the sender gets the receivers' responses for a second pass and its
+/// answer is the last log of the conversation. Failed receivers are logged and skipped.
+pub async fn one_to_many(
+    sender: impl Agent,
+    receivers: Vec<Box<dyn Agent>>,
+    task: impl Into<String>,
+    fanout_strategy: FanoutStrategy,
+    aggregate: bool,
 ) -> Result<SwarmConversation, SwarmingArchsError> {
     let task = task.into();
-    if task.is_empty() {
+    if receivers.is_empty() || is_empty_task(&task) {
         return Err(SwarmingArchsError::EmptyTasksOrAgents);
     }
 
+    // The sender, the receivers and the aggregation are a round each
     let mut conversation = SwarmConversation::new();
-    let sender_message = sender.run(task.clone()).await?;
-    conversation.add_log(sender.name(), task, sender_message.clone());
-
-    let (tx, mut rx) = mpsc::channel(3);
-    stream::iter(receivers)
-        .for_each_concurrent(None, |receiver| {
-            let task = sender_message.clone();
-            let tx = tx.clone();
-            async move {
-                let result = receiver
-                    .run(task.clone())
-                    .await
-                    .map(|response| (receiver.name(), task, response));
-                tx.send(result).await.unwrap(); // Safe: we know the receiver is alive
+    let (result, latency) = timed_run(&sender, task.clone()).await;
+    let sender_message = result?;
+    conversation.add_timed_log(sender.name(), task.clone(), sender_message.clone(), latency);
+    conversation.next_round();
+
+    let messages = match fanout_strategy {
+        FanoutStrategy::Broadcast => vec![sender_message; receivers.len()],
+        FanoutStrategy::SplitLines => {
+            let mut messages = vec![Vec::new(); receivers.len()];
+            for (index, line) in sender_message
+                .lines()
+                .filter(|line| !is_empty_task(line))
+                .enumerate()
+            {
+                messages[index % receivers.len()].push(line);
             }
-        })
-        .await;
-
-    while let Some(result) = rx.recv().await {
+            messages.into_iter().map(|lines| lines.join("\n")).collect()
+        }
+    };
+
+    // join_all keeps the receivers' order
+    let results = future::join_all(
+        receivers
+            .iter()
+            .zip(messages)
+            .filter(|(_, message)| !message.is_empty())
+            .map(|(receiver, message)| async move {
+                let (result, latency) = timed_run(receiver.as_ref(), message.clone()).await;
+                (receiver.name(), message, result, latency)
+            }),
+    )
+    .await;
+
+    let mut responses = Vec::with_capacity(results.len());
+    for (agent_name, message, result, latency) in results {
         match result {
-            Ok((agent_name, task, response)) => conversation.add_log(agent_name, task, response),
-            Err(e) => tracing::error!("Receiver agent failed in one to three swarm: {}", e),
+            Ok(response) => {
+                responses.push(format!("[From {agent_name}] {response}"));
+                conversation.add_timed_log(agent_name, message, response, latency);
+            }
+            Err(e) => conversation.add_failure(agent_name, message, e),
         }
     }
+    conversation.next_round();
+
+    if aggregate {
+        let aggregation_task = format!(
+            "Task: {task}\n\nResponses of the receivers:\n{}\n\nCombine the responses into the final answer.",
+            responses.join("\n")
+        );
+        let (result, latency) = timed_run(&sender, aggregation_task.clone()).await;
+        conversation.add_timed_log(sender.name(), aggregation_task, result?, latency);
+    }
 
     Ok(conversation)
 }
@@ -210,44 +442,283 @@ pub async fn broadcast(
     sender: impl Agent,
     receivers: Vec<Box<dyn Agent>>,
     task: impl Into<String>,
+) -> Result<SwarmConversation, SwarmingArchsError> {
+    broadcast_bounded(sender, receivers, task, None).await
+}
+
+/// Like [`broadcast`], with at most `max_concurrency` receivers running at the same time,
+/// unlimited if `None`.
+pub async fn broadcast_bounded(
+    sender: impl Agent,
+    receivers: Vec<Box<dyn Agent>>,
+    task: impl Into<String>,
+    max_concurrency: Option<usize>,
 ) -> Result<SwarmConversation, SwarmingArchsError> {
     let task = task.into();
-    if receivers.is_empty() || task.is_empty() {
+    if receivers.is_empty() || is_empty_task(&task) {
         return Err(SwarmingArchsError::EmptyTasksOrAgents);
     }
 
     let mut conversation = SwarmConversation::new();
 
     // First get the sender's boardcast response
-    let broadcast_response = sender.run(task.clone()).await?;
-    conversation.add_log(sender.name(), task.clone(), broadcast_response);
+    let (result, latency) = timed_run(&sender, task.clone()).await;
+    conversation.add_timed_log(sender.name(), task.clone(), result?, latency);
+    conversation.next_round();
 
     // Then have all agents process it
-    let (tx, mut rx) = mpsc::channel(receivers.len());
-
-    // TODO: tokio::spawn is needed ?
-    // tokio::spawn(async move {
-    stream::iter(receivers)
-        .for_each_concurrent(None, |receiver| {
-            let task = task.clone();
-            let tx = tx.clone();
-            async move {
-                let result = receiver
-                    .run(task.clone())
-                    .await
-                    .map(|response| (receiver.name(), task, response));
-                tx.send(result).await.unwrap(); // Safe: we know the receiver is alive
+    let names = receivers
+        .iter()
+        .map(|receiver| receiver.name())
+        .collect::<Vec<_>>();
+    let outcomes = concurrency::spawn_bounded(receivers, max_concurrency, |receiver| {
+        let task = task.clone();
+        async move { timed_run(receiver.as_ref(), task).await }
+    })
+    .await;
+
+    for (index, outcome) in outcomes {
+        let agent_name = names[index].clone();
+        match outcome {
+            Ok((Ok(response), latency)) => {
+                conversation.add_timed_log(agent_name, task.clone(), response, latency)
             }
-        })
-        .await;
-    // });
-
-    while let Some(result) = rx.recv().await {
-        match result {
-            Ok((agent_name, task, response)) => conversation.add_log(agent_name, task, response),
-            Err(e) => tracing::error!("Receiver agent failed in boardcast swarm: {}", e),
+            Ok((Err(e), _)) => conversation.add_failure(agent_name, task.clone(), e),
+            Err(e) => conversation.add_failure(agent_name, task.clone(), e),
         }
     }
 
     Ok(conversation)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestAgent;
+
+    #[tokio::test]
+    async fn test_failures_are_reported() {
+        let agents = || -> Vec<Box<dyn Agent>> {
+            vec![
+                Box::new(TestAgent { fail: false }),
+                Box::new(TestAgent { fail: true }),
+            ]
+        };
+
+        let result = circular_swarm(agents(), vec!["a".to_owned(), "b".to_owned()], false)
+            .await
+            .unwrap();
+        let SwarmResult::Responses {
+            responses,
+            failures,
+            ..
+        } = &result
+        else {
+            panic!("expected responses");
+        };
+        assert_eq!(responses, &["done: a", "done: b"]);
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[1].task, "b");
+        assert!(failures[1].error.contains('b'));
+
+        let conversation = broadcast(TestAgent { fail: false }, agents(), "task")
+            .await
+            .unwrap();
+        assert_eq!(conversation.logs.len(), 2);
+        assert_eq!(conversation.failures.len(), 1);
+        assert_eq!(conversation.failures[0].agent_name, "test");
+        let json = serde_json::to_value(&conversation).unwrap();
+        assert_eq!(json["failures"][0]["task"], "task");
+
+        // The tasks are taken from the back, the failed agent doesn't stop the next one
+        let agents = vec![
+            Box::new(TestAgent { fail: true }) as Box<dyn Agent>,
+            Box::new(TestAgent { fail: false }),
+        ];
+        let result = linear_swarm(agents, vec!["a".to_owned(), "b".to_owned()], false)
+            .await
+            .unwrap();
+        let SwarmResult::Responses {
+            responses,
+            failures,
+            ..
+        } = &result
+        else {
+            panic!("expected responses");
+        };
+        assert_eq!(responses, &["done: a"]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].task, "b");
+
+        let conversation = one_to_one(
+            TestAgent { fail: false },
+            TestAgent { fail: true },
+            "task",
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(conversation.logs.len(), 1);
+        assert_eq!(conversation.failures.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_grid_swarm_assignment() {
+        let tasks = || (0..5).map(|i| format!("task {i}")).collect::<Vec<_>>();
+
+        assert!(matches!(
+            grid_swarm(receivers(3), tasks(), GridOptions::default()).await,
+            Err(SwarmingArchsError::CanNotFormAPerfectSquareGrid)
+        ));
+
+        let result = grid_swarm(receivers(4), tasks(), GridOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(result.conversation.logs.len(), 4);
+        assert_eq!(result.unassigned, ["task 4"]);
+
+        let options = GridOptions {
+            require_square: false,
+            work_queue: true,
+        };
+        let result = grid_swarm(receivers(3), tasks(), options).await.unwrap();
+        assert!(result.unassigned.is_empty());
+        let mut done = result
+            .conversation
+            .logs
+            .iter()
+            .map(|log| log.task.clone())
+            .collect::<Vec<_>>();
+        done.sort();
+        assert_eq!(done, tasks());
+    }
+
+    #[tokio::test]
+    async fn test_one_to_one_exchanges_context() {
+        let conversation = one_to_one(
+            TestAgent { fail: false },
+            TestAgent { fail: false },
+            "task",
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(conversation.logs.len(), 4);
+        assert_eq!(conversation.logs[0].response, "done: task");
+        // The receiver sees the sender's message, the sender then sees the receiver's reply
+        assert!(conversation.logs[1].response.contains("[test] done: task"));
+        assert!(
+            conversation.logs[2]
+                .response
+                .contains(&format!("[test] {}", conversation.logs[1].response))
+        );
+
+        // TestAgent reports every response as complete
+        let conversation = one_to_one_until_complete(
+            TestAgent { fail: false },
+            TestAgent { fail: false },
+            "task",
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(conversation.logs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let conversation = broadcast(TestAgent { fail: false }, receivers(3), "task")
+            .await
+            .unwrap();
+        let stats = conversation.stats();
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.messages_per_agent["test"], 4);
+        assert_eq!((stats.rounds, stats.fan_out), (2, 3));
+        assert_eq!(stats.rounds_to_convergence, None);
+        assert!(stats.average_latency_ms.is_some());
+        assert!(stats.total_tokens > 0);
+
+        let bounded = broadcast_bounded(TestAgent { fail: false }, receivers(3), "task", Some(1))
+            .await
+            .unwrap();
+        assert_eq!(bounded.stats().messages, 4);
+
+        let result = circular_swarm(receivers(2), vec!["a".to_owned(), "b".to_owned()], false)
+            .await
+            .unwrap();
+        assert_eq!(result.stats().rounds, 2);
+
+        let conversation = one_to_one_until_complete(
+            TestAgent { fail: false },
+            TestAgent { fail: false },
+            "task",
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(conversation.stats().rounds_to_convergence, Some(1));
+        assert!(
+            SwarmConversation::new()
+                .stats()
+                .average_latency_ms
+                .is_none()
+        );
+    }
+
+    fn receivers(count: usize) -> Vec<Box<dyn Agent>> {
+        (0..count)
+            .map(|_| Box::new(TestAgent { fail: false }) as _)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_one_to_many_broadcast_and_aggregate() {
+        let conversation = one_to_many(
+            TestAgent { fail: false },
+            receivers(5),
+            "task",
+            FanoutStrategy::Broadcast,
+            true,
+        )
+        .await
+        .unwrap();
+        // sender, 5 receivers, sender again
+        assert_eq!(conversation.logs.len(), 7);
+        assert_eq!(conversation.logs[1].task, "done: task");
+        let aggregation = conversation.logs.back().unwrap();
+        assert!(aggregation.task.contains("[From test] done: done: task"));
+    }
+
+    #[tokio::test]
+    async fn test_one_to_many_split_lines() {
+        // TestAgent answers "done: <task>", which is the first line
+        let conversation = one_to_many(
+            TestAgent { fail: false },
+            receivers(3),
+            "a\n\nb",
+            FanoutStrategy::SplitLines,
+            false,
+        )
+        .await
+        .unwrap();
+        let tasks = conversation
+            .logs
+            .iter()
+            .skip(1)
+            .map(|log| log.task.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(tasks, ["done: a", "b"]);
+
+        assert!(matches!(
+            one_to_many(
+                TestAgent { fail: false },
+                Vec::new(),
+                "task",
+                FanoutStrategy::Broadcast,
+                false
+            )
+            .await,
+            Err(SwarmingArchsError::EmptyTasksOrAgents)
+        ));
+    }
+}
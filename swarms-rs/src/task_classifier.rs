@@ -0,0 +1,372 @@
+//! Map tasks to a user-defined taxonomy of categories.
+//!
+//! A [`TaskClassifier`] files each task under one of its [`Category`]s, either by asking a
+//! model, by the similarity of the task's embedding to the categories, or by a function.
+//! Classifications are cached by task, so classifying the same task again is free. The
+//! category can then configure behavior per category, e.g. the routing statistics of a
+//! [`MultiAgentOrchestrator`](crate::multi_agent_orchestrator::MultiAgentOrchestrator), the
+//! route of a [`ModelRouter`](crate::llm::router::ModelRouter) or the labels of the metrics.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use futures::future::BoxFuture;
+use tokio::sync::OnceCell;
+
+use crate::{
+    llm::{
+        CompletionError, EmbeddingModel, Model, completion::AssistantContent,
+        embedding::cosine_similarity, request::CompletionRequest,
+    },
+    locale::Locale,
+};
+
+/// Category of the tasks which fit no category of the taxonomy.
+pub const DEFAULT_CATEGORY: &str = "general";
+
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Category {
+    pub name: String,
+    pub description: String,
+    /// Tasks of the category, they help the model and the embeddings tell the categories apart.
+    pub examples: Vec<String>,
+}
+
+impl Category {
+    pub fn new(name: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            examples: Vec::new(),
+        }
+    }
+
+    pub fn example(mut self, example: impl Into<String>) -> Self {
+        self.examples.push(example.into());
+        self
+    }
+
+    // How the category is shown to the model and embedded
+    fn render(&self) -> String {
+        let mut text = format!("{}: {}", self.name, self.description);
+        for example in &self.examples {
+            text.push_str(&format!("\n  e.g. {example}"));
+        }
+        text
+    }
+}
+
+type Complete = Arc<
+    dyn Fn(CompletionRequest) -> BoxFuture<'static, Result<String, CompletionError>> + Send + Sync,
+>;
+type Rule = Arc<dyn Fn(&str) -> String + Send + Sync>;
+/// Task -> category, and the tasks in insertion order
+type Cache = (HashMap<String, String>, VecDeque<String>);
+
+#[derive(Clone)]
+enum Backend {
+    Llm(Complete),
+    Embedding {
+        embedder: Arc<dyn EmbeddingModel + Send + Sync>,
+        // Embeddings of the categories, in the order of the taxonomy
+        categories: Arc<OnceCell<Vec<Vec<f32>>>>,
+        min_similarity: f32,
+    },
+    Fn(Rule),
+}
+
+/// Clones share the cached classifications.
+#[derive(Clone)]
+pub struct TaskClassifier {
+    categories: Vec<Category>,
+    backend: Backend,
+    fallback: String,
+    max_entries: usize,
+    locale: Locale,
+    // The oldest classification is evicted first
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl TaskClassifier {
+    fn new(categories: Vec<Category>, backend: Backend) -> Self {
+        Self {
+            categories,
+            backend,
+            fallback: DEFAULT_CATEGORY.to_owned(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+            locale: Locale::default(),
+            cache: Arc::default(),
+        }
+    }
+
+    /// Ask the model which category the task belongs to.
+    pub fn llm<M>(model: M, categories: Vec<Category>) -> Self
+    where
+        M: Model + Clone + Send + Sync + 'static,
+        M::RawCompletionResponse: Send,
+    {
+        let complete: Complete = Arc::new(move |request| {
+            let model = model.clone();
+            Box::pin(async move {
+                let response = model.completion(request).await?;
+                match response.choice.into_iter().next() {
+                    Some(AssistantContent::Text(text)) => Ok(text.text),
+                    _ => Err(CompletionError::Other("Classifier returned no text".into())),
+                }
+            })
+        });
+        Self::new(categories, Backend::Llm(complete))
+    }
+
+    /// File the task under the category whose embedding is the most similar to the task's,
+    /// tasks less similar than `min_similarity` to every category get the fallback category.
+    pub fn embedding(
+        embedder: impl EmbeddingModel + Send + Sync + 'static,
+        categories: Vec<Category>,
+        min_similarity: f32,
+    ) -> Self {
+        let backend = Backend::Embedding {
+            embedder: Arc::new(embedder),
+            categories: Arc::new(OnceCell::new()),
+            min_similarity,
+        };
+        Self::new(categories, backend)
+    }
+
+    /// Classify by a function, e.g. by keywords. Answers which aren't the name of a category
+    /// get the fallback category.
+    pub fn from_fn(
+        categories: Vec<Category>,
+        classify: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        Self::new(categories, Backend::Fn(Arc::new(classify)))
+    }
+
+    /// Category of the tasks which fit no category, [`DEFAULT_CATEGORY`] by default.
+    pub fn fallback(mut self, fallback: impl Into<String>) -> Self {
+        self.fallback = fallback.into();
+        self
+    }
+
+    /// Max number of cached classifications, 1024 by default.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// The language of the prompt asking the model for the category, see
+    /// [`locale`](crate::locale). Category names and descriptions are never translated.
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn categories(&self) -> &[Category] {
+        &self.categories
+    }
+
+    /// The name of the task's category. If the model or embedder fails the task gets the
+    /// fallback category, which isn't cached.
+    pub async fn classify(&self, task: &str) -> String {
+        let cached = self.lock().0.get(task).cloned();
+        let category = match cached {
+            Some(category) => category,
+            None => match self.classify_uncached(task).await {
+                Ok(category) => {
+                    let category = category.unwrap_or_else(|| self.fallback.clone());
+                    self.insert(task, category.clone());
+                    category
+                }
+                Err(e) => {
+                    tracing::warn!("| task classifier | Classification failed, use fallback: {e}");
+                    self.fallback.clone()
+                }
+            },
+        };
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::inc_counter(
+            crate::metrics::TASKS_CLASSIFIED,
+            &[("category", &category)],
+            1,
+        );
+
+        category
+    }
+
+    async fn classify_uncached(&self, task: &str) -> Result<Option<String>, CompletionError> {
+        match &self.backend {
+            Backend::Llm(complete) => {
+                let taxonomy = self
+                    .categories
+                    .iter()
+                    .map(|category| format!("- {}", category.render()))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let request = CompletionRequest {
+                    prompt: task.into(),
+                    system_prompt: Some(format!(
+                        "{}\n\n{taxonomy}",
+                        self.locale.catalog().classifier_prompt
+                    )),
+                    chat_history: vec![],
+                    tools: vec![],
+                    temperature: Some(0.0),
+                    max_tokens: Some(20),
+                    seed: None,
+                    response_format: None,
+                };
+                let answer = complete(request).await?;
+                Ok(self.find(&answer))
+            }
+            Backend::Embedding {
+                embedder,
+                categories,
+                min_similarity,
+            } => {
+                let category_embeddings = categories
+                    .get_or_try_init(|| {
+                        embedder.embed(self.categories.iter().map(Category::render).collect())
+                    })
+                    .await?;
+                let task_embedding = embedder
+                    .embed(vec![task.to_owned()])
+                    .await?
+                    .pop()
+                    .ok_or_else(|| {
+                        CompletionError::Other("Embedding model returned no embedding".into())
+                    })?;
+                Ok(self
+                    .categories
+                    .iter()
+                    .zip(category_embeddings)
+                    .map(|(category, embedding)| {
+                        (cosine_similarity(&task_embedding, embedding), category)
+                    })
+                    .filter(|(similarity, _)| similarity >= min_similarity)
+                    .max_by(|(a, _), (b, _)| a.total_cmp(b))
+                    .map(|(_, category)| category.name.clone()))
+            }
+            Backend::Fn(classify) => Ok(self.find(&classify(task))),
+        }
+    }
+
+    /// The category named in the answer, an exact match wins over a mention.
+    fn find(&self, answer: &str) -> Option<String> {
+        let answer = answer.trim().trim_matches(|c: char| c == '"' || c == '.');
+        self.categories
+            .iter()
+            .find(|category| category.name.eq_ignore_ascii_case(answer))
+            .or_else(|| {
+                let answer = answer.to_lowercase();
+                self.categories
+                    .iter()
+                    .find(|category| answer.contains(&category.name.to_lowercase()))
+            })
+            .map(|category| category.name.clone())
+    }
+
+    fn insert(&self, task: &str, category: String) {
+        let mut cache = self.lock();
+        let (entries, order) = &mut *cache;
+        if entries.insert(task.to_owned(), category).is_none() {
+            order.push_back(task.to_owned());
+        }
+        while order.len() > self.max_entries {
+            if let Some(oldest) = order.pop_front() {
+                entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        // Safety: the lock is never held across a panic
+        self.cache.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::test_support::{FnModel, KeywordEmbedder};
+
+    fn taxonomy() -> Vec<Category> {
+        vec![
+            Category::new("billing", "Refunds, invoices and payments").example("refund my order"),
+            Category::new("account", "Logins and passwords").example("reset my password"),
+        ]
+    }
+
+    /// Answers with the category of the first keyword in the prompt, counting its calls.
+    fn keyword_model(calls: Arc<AtomicUsize>) -> FnModel {
+        FnModel::new("keyword", move |request| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            let task = request.prompt.rag_text().unwrap_or_default();
+            match task.contains("refund") {
+                true => "Category: Billing.",
+                false => "none",
+            }
+            .to_owned()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_llm_classifier_is_cached() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let classifier =
+            TaskClassifier::llm(keyword_model(calls.clone()), taxonomy()).fallback("other");
+        assert_eq!(classifier.classify("refund please").await, "billing");
+        assert_eq!(
+            classifier.clone().classify("refund please").await,
+            "billing"
+        );
+        assert_eq!(classifier.classify("hello").await, "other");
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_classifier_locale() {
+        let system_prompt = Arc::new(Mutex::new(None));
+        let model = FnModel::new("capture", {
+            let system_prompt = system_prompt.clone();
+            move |request| {
+                *system_prompt.lock().unwrap() = request.system_prompt;
+                "billing".to_owned()
+            }
+        });
+        let classifier = TaskClassifier::llm(model, taxonomy()).locale(Locale::French);
+        assert_eq!(classifier.classify("rembourser").await, "billing");
+        let system_prompt = system_prompt.lock().unwrap().clone().unwrap();
+        assert!(system_prompt.starts_with(Locale::French.catalog().classifier_prompt));
+        assert!(system_prompt.contains("- billing: Refunds, invoices and payments"));
+    }
+
+    #[tokio::test]
+    async fn test_embedding_and_fn_classifiers() {
+        let classifier = TaskClassifier::embedding(KeywordEmbedder, taxonomy(), 0.5).max_entries(1);
+        assert_eq!(classifier.classify("I want a refund").await, "billing");
+        assert_eq!(classifier.classify("Forgot my password").await, "account");
+        assert_eq!(
+            classifier.classify("Where is my parcel?").await,
+            DEFAULT_CATEGORY
+        );
+        assert_eq!(classifier.lock().0.len(), 1);
+
+        let classifier = TaskClassifier::from_fn(taxonomy(), |task| {
+            if task.contains("login") {
+                "ACCOUNT"
+            } else {
+                "unknown"
+            }
+            .to_owned()
+        });
+        assert_eq!(classifier.classify("login fails").await, "account");
+        assert_eq!(classifier.classify("hi").await, DEFAULT_CATEGORY);
+    }
+}
@@ -0,0 +1,338 @@
+//! One-line logging setup for applications, examples and tests.
+//!
+//! [`init`] installs a global tracing subscriber configured by environment variables:
+//!
+//! | variable                      | setting                                             |
+//! |-------------------------------|-----------------------------------------------------|
+//! | `SWARMS_LOG_LEVEL`            | log filter, e.g. `info` or `swarms_rs=debug`, falls back to `RUST_LOG`, then `info` |
+//! | `SWARMS_LOG_FORMAT`           | `full` (default), `compact`, `pretty` or `json`     |
+//! | `SWARMS_JSON_LOGS`            | `true` is the same as `SWARMS_LOG_FORMAT=json`      |
+//! | `SWARMS_LOG_FILE`             | append the logs to this file instead of stdout      |
+//! | `OTEL_EXPORTER_OTLP_ENDPOINT` | export spans with OTLP over HTTP, needs the `otlp` feature |
+//! | `OTEL_SERVICE_NAME`           | service name of the exported spans, `swarms-rs` by default |
+//!
+//! Only the first call installs a subscriber, later calls and calls after another subscriber
+//! was installed do nothing, so every test can call [`init`].
+
+use std::{
+    fs::OpenOptions,
+    path::PathBuf,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use thiserror::Error;
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry,
+    layer::{Layered, SubscriberExt},
+    util::SubscriberInitExt,
+};
+
+use crate::error::{CategorizedError, ErrorCategory};
+
+const DEFAULT_LEVEL: &str = "info";
+const DEFAULT_SERVICE_NAME: &str = "swarms-rs";
+
+/// The subscriber the formatting and export layers are added to.
+type Subscriber = Layered<EnvFilter, Registry>;
+type BoxedLayer = Box<dyn Layer<Subscriber> + Send + Sync>;
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "otlp")]
+static TRACER_PROVIDER: std::sync::OnceLock<opentelemetry_sdk::trace::SdkTracerProvider> =
+    std::sync::OnceLock::new();
+
+#[derive(Debug, Error)]
+pub enum TelemetryError {
+    #[error("Invalid value of {name}: {value}")]
+    InvalidEnv { name: String, value: String },
+    #[error("Invalid log filter: {0}")]
+    InvalidFilter(#[from] tracing_subscriber::filter::ParseError),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("OTLP error: {0}")]
+    OtlpError(String),
+}
+
+impl CategorizedError for TelemetryError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            TelemetryError::InvalidEnv { .. } | TelemetryError::InvalidFilter(_) => {
+                ErrorCategory::Validation
+            }
+            TelemetryError::IoError(_) => ErrorCategory::Persistence,
+            TelemetryError::OtlpError(_) => ErrorCategory::Other,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Pretty,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TelemetryConfig {
+    /// Log filter, e.g. `info` or `swarms_rs=debug`.
+    pub level: String,
+    pub format: LogFormat,
+    /// Append the logs to this file instead of writing them to stdout.
+    pub file: Option<PathBuf>,
+    /// OTLP/HTTP endpoint the spans are exported to, needs the `otlp` feature.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_LEVEL.to_owned(),
+            format: LogFormat::default(),
+            file: None,
+            otlp_endpoint: None,
+            service_name: DEFAULT_SERVICE_NAME.to_owned(),
+        }
+    }
+}
+
+/// Install the global subscriber configured by the environment variables, see the
+/// [module docs](self).
+pub fn init() -> Result<(), TelemetryError> {
+    TelemetryConfig::from_env()?.init()
+}
+
+/// Flush and stop the OTLP export, call it before the application exits so the last spans
+/// are exported. Does nothing without OTLP export.
+pub fn shutdown() -> Result<(), TelemetryError> {
+    #[cfg(feature = "otlp")]
+    if let Some(provider) = TRACER_PROVIDER.get() {
+        provider
+            .shutdown()
+            .map_err(|e| TelemetryError::OtlpError(e.to_string()))?;
+    }
+    Ok(())
+}
+
+impl TelemetryConfig {
+    pub fn from_env() -> Result<Self, TelemetryError> {
+        Self::from_env_with(|name| std::env::var(name).ok())
+    }
+
+    fn from_env_with(var: impl Fn(&str) -> Option<String>) -> Result<Self, TelemetryError> {
+        let mut config = Self::default();
+        if let Some(level) = var("SWARMS_LOG_LEVEL").or_else(|| var("RUST_LOG")) {
+            config.level = level;
+        }
+        if let Some(value) = var("SWARMS_JSON_LOGS")
+            && matches!(value.to_lowercase().as_str(), "1" | "true" | "yes")
+        {
+            config.format = LogFormat::Json;
+        }
+        if let Some(value) = var("SWARMS_LOG_FORMAT") {
+            config.format = match value.to_lowercase().as_str() {
+                "full" => LogFormat::Full,
+                "compact" => LogFormat::Compact,
+                "pretty" => LogFormat::Pretty,
+                "json" => LogFormat::Json,
+                _ => {
+                    return Err(TelemetryError::InvalidEnv {
+                        name: "SWARMS_LOG_FORMAT".to_owned(),
+                        value,
+                    });
+                }
+            };
+        }
+        config.file = var("SWARMS_LOG_FILE").map(PathBuf::from);
+        config.otlp_endpoint = var("OTEL_EXPORTER_OTLP_ENDPOINT");
+        if let Some(service_name) = var("OTEL_SERVICE_NAME") {
+            config.service_name = service_name;
+        }
+        Ok(config)
+    }
+
+    pub fn level(mut self, level: impl Into<String>) -> Self {
+        self.level = level.into();
+        self
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    pub fn otlp_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.otlp_endpoint = Some(endpoint.into());
+        self
+    }
+
+    pub fn service_name(mut self, service_name: impl Into<String>) -> Self {
+        self.service_name = service_name.into();
+        self
+    }
+
+    /// Install the global subscriber, does nothing if one is already installed.
+    ///
+    /// Fails if the configuration is invalid, or if an OTLP endpoint is set without the
+    /// `otlp` feature.
+    pub fn init(self) -> Result<(), TelemetryError> {
+        if INITIALIZED.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let result = self.try_init();
+        if result.is_err() {
+            INITIALIZED.store(false, Ordering::SeqCst);
+        }
+        result
+    }
+
+    fn try_init(self) -> Result<(), TelemetryError> {
+        let filter = EnvFilter::try_new(&self.level)?;
+        let mut layers = vec![self.fmt_layer()?];
+        if let Some(endpoint) = &self.otlp_endpoint {
+            layers.push(self.otlp_layer(endpoint)?);
+        }
+        // Another subscriber is already installed, e.g. by the application
+        let _ = tracing_subscriber::registry()
+            .with(filter)
+            .with(layers)
+            .try_init();
+        Ok(())
+    }
+
+    fn fmt_layer(&self) -> Result<BoxedLayer, TelemetryError> {
+        let Some(path) = &self.file else {
+            return Ok(format_layer(std::io::stdout, true, self.format));
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(format_layer(Mutex::new(file), false, self.format))
+    }
+
+    #[cfg(feature = "otlp")]
+    fn otlp_layer(&self, endpoint: &str) -> Result<BoxedLayer, TelemetryError> {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry_otlp::WithExportConfig;
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| TelemetryError::OtlpError(e.to_string()))?;
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(self.service_name.clone())
+                    .build(),
+            )
+            .build();
+        let tracer = provider.tracer(DEFAULT_SERVICE_NAME);
+        let _ = TRACER_PROVIDER.set(provider);
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer).boxed())
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    fn otlp_layer(&self, _endpoint: &str) -> Result<BoxedLayer, TelemetryError> {
+        Err(TelemetryError::OtlpError(
+            "OTLP export requires the `otlp` feature".to_owned(),
+        ))
+    }
+}
+
+fn format_layer<W>(writer: W, ansi: bool, format: LogFormat) -> BoxedLayer
+where
+    W: for<'writer> tracing_subscriber::fmt::MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<TelemetryConfig, TelemetryError> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        TelemetryConfig::from_env_with(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_from_env() {
+        assert_eq!(from_vars(&[]).unwrap(), TelemetryConfig::default());
+
+        let config = from_vars(&[
+            ("RUST_LOG", "warn"),
+            ("SWARMS_LOG_LEVEL", "swarms_rs=debug"),
+            ("SWARMS_JSON_LOGS", "true"),
+            ("SWARMS_LOG_FILE", "logs/swarms.log"),
+            ("OTEL_SERVICE_NAME", "support-bot"),
+        ])
+        .unwrap();
+        assert_eq!(config.level, "swarms_rs=debug");
+        assert_eq!(config.format, LogFormat::Json);
+        assert_eq!(config.file, Some(PathBuf::from("logs/swarms.log")));
+        assert_eq!(config.service_name, "support-bot");
+
+        let config = from_vars(&[("RUST_LOG", "warn"), ("SWARMS_LOG_FORMAT", "Compact")]);
+        assert_eq!(config.unwrap().format, LogFormat::Compact);
+        assert!(matches!(
+            from_vars(&[("SWARMS_LOG_FORMAT", "xml")]),
+            Err(TelemetryError::InvalidEnv { .. })
+        ));
+    }
+
+    #[test]
+    fn test_repeated_init() {
+        let dir = temp_dir();
+        let path = dir.join("swarms.log");
+        assert!(
+            TelemetryConfig::default()
+                .level("not a [filter")
+                .init()
+                .is_err()
+        );
+        // Other tests log as well, only this test's target is written
+        TelemetryConfig::default()
+            .level("swarms_rs::telemetry=info")
+            .format(LogFormat::Json)
+            .file(&path)
+            .init()
+            .unwrap();
+        init().unwrap();
+        TelemetryConfig::default().level("debug").init().unwrap();
+
+        tracing::info!(target: "swarms_rs::telemetry", "logged once");
+        let logs = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(logs.lines().count(), 1);
+        assert!(logs.contains("\"message\":\"logged once\""));
+        // Without OTLP export there is nothing to shut down
+        shutdown().unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! Tenant ids, which isolate the persisted states of agents and workflows of different users
+//! hosted by the same service.
+//!
+//! Agents and workflows built with a tenant write their states, metadata and completed
+//! results under `<dir>/tenants/<tenant id>/` instead of `<dir>/`, and record the tenant in
+//! the metadata and conversations they produce. In-memory state is isolated by creating
+//! separate agent and workflow instances per tenant.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::{CategorizedError, ErrorCategory};
+
+#[derive(Debug, Error)]
+pub enum TenantError {
+    #[error("Invalid tenant id: {0:?}, only ASCII letters, digits, '-' and '_' are allowed")]
+    InvalidId(String),
+}
+
+impl CategorizedError for TenantError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            TenantError::InvalidId(_) => ErrorCategory::Validation,
+        }
+    }
+}
+
+/// Id of a tenant, it's safe to use as a path component.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TenantId(String);
+
+impl TenantId {
+    pub fn new(id: impl Into<String>) -> Result<Self, TenantError> {
+        let id = id.into();
+        let valid = !id.is_empty()
+            && id.len() <= 128
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if valid {
+            Ok(Self(id))
+        } else {
+            Err(TenantError::InvalidId(id))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The tenant's directory inside `dir`.
+    pub fn scope_dir(&self, dir: impl AsRef<Path>) -> PathBuf {
+        dir.as_ref().join("tenants").join(&self.0)
+    }
+}
+
+impl TryFrom<String> for TenantId {
+    type Error = TenantError;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Self::new(id)
+    }
+}
+
+impl From<TenantId> for String {
+    fn from(id: TenantId) -> Self {
+        id.0
+    }
+}
+
+impl Display for TenantId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// `dir` scoped to the tenant, or `dir` itself without a tenant.
+pub(crate) fn scoped_dir(dir: impl AsRef<Path>, tenant_id: Option<&TenantId>) -> PathBuf {
+    match tenant_id {
+        Some(tenant_id) => tenant_id.scope_dir(dir),
+        None => dir.as_ref().to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_id() {
+        let tenant_id = TenantId::new("acme-1").unwrap();
+        assert_eq!(
+            scoped_dir("./temp", Some(&tenant_id)),
+            Path::new("./temp/tenants/acme-1")
+        );
+        assert_eq!(scoped_dir("./temp", None), Path::new("./temp"));
+
+        for invalid in ["", "..", "a/b", "a\\b", "späce"] {
+            assert!(TenantId::new(invalid).is_err(), "{invalid}");
+        }
+        assert!(serde_json::from_str::<TenantId>("\"../etc\"").is_err());
+        assert_eq!(serde_json::to_string(&tenant_id).unwrap(), "\"acme-1\"");
+    }
+}
@@ -0,0 +1,261 @@
+//! Test doubles shared by the unit tests of several modules.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::future::{self, BoxFuture};
+use uuid::Uuid;
+
+use crate::{
+    agent::{Agent, AgentError, AgentRunResult, StopReason, TokenUsage, ToolCallRecord},
+    conversation::DedupStats,
+    llm::{
+        self, CompletionError, EmbeddingModel,
+        completion::AssistantContent,
+        request::{CompletionRequest, CompletionResponse},
+    },
+};
+
+/// Answers with its name, or fails if `fail` is set, reports one `lookup` tool call.
+#[derive(Clone)]
+pub(crate) struct TestAgent {
+    pub(crate) fail: bool,
+}
+
+impl Agent for TestAgent {
+    fn run(&self, task: String) -> BoxFuture<'_, Result<String, AgentError>> {
+        Box::pin(async move {
+            if self.fail {
+                return Err(AgentError::TestError(task));
+            }
+            Ok(format!("done: {task}"))
+        })
+    }
+    fn run_detailed(&self, task: String) -> BoxFuture<'_, Result<AgentRunResult, AgentError>> {
+        Box::pin(async move {
+            let answer = self.run(task.clone()).await?;
+            Ok(AgentRunResult {
+                responses: vec![answer.clone()],
+                answer,
+                tool_calls: vec![ToolCallRecord {
+                    agent: self.name(),
+                    name: "lookup".to_owned(),
+                    arguments: serde_json::json!({ "query": task }),
+                    output: Ok("found".to_owned()),
+                    duration_ms: 1,
+                }],
+                usage: TokenUsage::default(),
+                duration: Duration::ZERO,
+                stop_reason: StopReason::MaxLoops,
+                injections: vec![],
+                grounding: None,
+                dedup: DedupStats::default(),
+                prompt: None,
+                provenance: None,
+            })
+        })
+    }
+    fn run_multiple_tasks(
+        &mut self,
+        tasks: Vec<String>,
+    ) -> BoxFuture<'_, Result<Vec<String>, AgentError>> {
+        Box::pin(async move {
+            let mut answers = Vec::with_capacity(tasks.len());
+            for task in tasks {
+                answers.push(self.run(task).await?);
+            }
+            Ok(answers)
+        })
+    }
+    fn plan(&self, _task: String) -> BoxFuture<'_, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+    fn query_long_term_memory(&self, _task: String) -> BoxFuture<'_, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+    fn save_task_state(&self, _task: String) -> BoxFuture<'_, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+    fn is_response_complete(&self, _response: String) -> bool {
+        true
+    }
+    fn id(&self) -> String {
+        "test".to_owned()
+    }
+    fn name(&self) -> String {
+        "test".to_owned()
+    }
+    fn description(&self) -> String {
+        String::new()
+    }
+    fn clone_box(&self) -> Box<dyn Agent> {
+        Box::new(self.clone())
+    }
+}
+
+/// Answers with what its function makes of the task.
+#[derive(Clone)]
+pub(crate) struct FnAgent {
+    name: String,
+    answer: Arc<dyn Fn(String) -> String + Send + Sync>,
+}
+
+impl FnAgent {
+    pub(crate) fn new(
+        name: impl Into<String>,
+        answer: impl Fn(String) -> String + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            answer: Arc::new(answer),
+        }
+    }
+
+    /// Always answers with `reply`.
+    pub(crate) fn reply(name: impl Into<String>, reply: impl Into<String>) -> Self {
+        let reply = reply.into();
+        Self::new(name, move |_| reply.clone())
+    }
+}
+
+impl Agent for FnAgent {
+    fn run(&self, task: String) -> BoxFuture<'_, Result<String, AgentError>> {
+        Box::pin(future::ready(Ok((self.answer)(task))))
+    }
+    fn run_multiple_tasks(
+        &mut self,
+        tasks: Vec<String>,
+    ) -> BoxFuture<'_, Result<Vec<String>, AgentError>> {
+        let answers = tasks.into_iter().map(|task| (self.answer)(task)).collect();
+        Box::pin(future::ready(Ok(answers)))
+    }
+    fn plan(&self, _task: String) -> BoxFuture<'_, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+    fn query_long_term_memory(&self, _task: String) -> BoxFuture<'_, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+    fn save_task_state(&self, _task: String) -> BoxFuture<'_, Result<(), AgentError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+    fn is_response_complete(&self, _response: String) -> bool {
+        true
+    }
+    fn id(&self) -> String {
+        self.name.clone()
+    }
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn description(&self) -> String {
+        String::new()
+    }
+    fn clone_box(&self) -> Box<dyn Agent> {
+        Box::new(self.clone())
+    }
+}
+
+/// A model answering with what its function makes of the request.
+#[derive(Clone)]
+pub(crate) struct FnModel {
+    name: String,
+    answer: Arc<dyn Fn(CompletionRequest) -> AssistantContent + Send + Sync>,
+}
+
+impl FnModel {
+    pub(crate) fn new<C: Into<AssistantContent>>(
+        name: impl Into<String>,
+        answer: impl Fn(CompletionRequest) -> C + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            answer: Arc::new(move |request| answer(request).into()),
+        }
+    }
+
+    /// Always answers with `reply`.
+    pub(crate) fn reply(name: impl Into<String>, reply: impl Into<String>) -> Self {
+        let reply = reply.into();
+        Self::new(name, move |_| reply.clone())
+    }
+
+    /// Answers with the system prompt and the prompt, `<system prompt> | <prompt>`.
+    pub(crate) fn echo() -> Self {
+        Self::new("echo", |request| {
+            let prompt = request.prompt.rag_text().unwrap_or_default();
+            let system_prompt = request.system_prompt.unwrap_or_default();
+            format!("{system_prompt} | {prompt}")
+        })
+    }
+}
+
+impl llm::Model for FnModel {
+    type RawCompletionResponse = ();
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> BoxFuture<'_, Result<CompletionResponse<()>, CompletionError>> {
+        let choice = (self.answer)(request);
+        Box::pin(future::ready(Ok(CompletionResponse {
+            choice: vec![choice],
+            raw_response: (),
+        })))
+    }
+}
+
+/// Embeds texts by the keywords they contain.
+pub(crate) struct KeywordEmbedder;
+
+impl EmbeddingModel for KeywordEmbedder {
+    fn embed(&self, texts: Vec<String>) -> BoxFuture<'_, Result<Vec<Vec<f32>>, CompletionError>> {
+        Box::pin(async move {
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    let text = text.to_lowercase();
+                    ["refund", "shipping", "password"]
+                        .map(|keyword| text.contains(keyword) as u8 as f32)
+                        .to_vec()
+                })
+                .collect())
+        })
+    }
+}
+
+/// A new, empty directory under the system temp dir, removed with its contents on drop.
+pub(crate) struct TempDir(PathBuf);
+
+pub(crate) fn temp_dir() -> TempDir {
+    let path = std::env::temp_dir().join(format!("swarms-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,7 +1,16 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
 use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 
-use crate::llm::request::ToolDefinition;
+use crate::{
+    error::{CategorizedError, ErrorCategory},
+    llm::request::ToolDefinition,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ToolError {
@@ -11,6 +20,274 @@ pub enum ToolError {
 
     #[error("JsonError: {0}")]
     JsonError(#[from] serde_json::Error),
+
+    #[error("PermissionDenied: tool {tool} requires the {capability} capability")]
+    PermissionDenied {
+        tool: String,
+        capability: ToolCapability,
+    },
+
+    #[error("LimitExceeded: tool {tool} exceeded the run's limit of {limit}")]
+    LimitExceeded { tool: String, limit: ResourceLimit },
+}
+
+impl CategorizedError for ToolError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            ToolError::PermissionDenied { .. } => ErrorCategory::Validation,
+            ToolError::LimitExceeded {
+                limit: ResourceLimit::WallTime(_),
+                ..
+            } => ErrorCategory::Timeout,
+            ToolError::LimitExceeded { .. } => ErrorCategory::Tool,
+            ToolError::ToolCallError(_) | ToolError::JsonError(_) => ErrorCategory::Tool,
+        }
+    }
+}
+
+/// What a tool can do outside of the process, used to allow or deny tools per agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolCapability {
+    /// Sends requests over the network.
+    Network,
+    /// Reads or writes files.
+    Filesystem,
+    /// Executes code or commands.
+    CodeExec,
+    /// The tool doesn't declare its capabilities, it may do anything.
+    Unknown,
+}
+
+impl ToolCapability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ToolCapability::Network => "network",
+            ToolCapability::Filesystem => "filesystem",
+            ToolCapability::CodeExec => "code_exec",
+            ToolCapability::Unknown => "unknown",
+        }
+    }
+
+    // Tools which declare no capabilities have the `Unknown` capability
+    fn declared_or_unknown(capabilities: &[ToolCapability]) -> &[ToolCapability] {
+        if capabilities.is_empty() {
+            &[ToolCapability::Unknown]
+        } else {
+            capabilities
+        }
+    }
+}
+
+impl Display for ToolCapability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Allow and deny lists of tool capabilities, checked before every tool call.
+///
+/// A tool is allowed if none of its capabilities is denied and, if there is an allow list,
+/// all of its capabilities are allowed. Tools which declare no capabilities have the
+/// [`ToolCapability::Unknown`] capability, so deny rules and allow lists apply to them too.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolPermissions {
+    allow: Option<HashSet<ToolCapability>>,
+    deny: HashSet<ToolCapability>,
+}
+
+impl ToolPermissions {
+    /// Add the capability to the allow list, once there is an allow list, capabilities which
+    /// are not in it are denied.
+    pub fn allow(mut self, capability: ToolCapability) -> Self {
+        self.allow
+            .get_or_insert_with(HashSet::new)
+            .insert(capability);
+        self
+    }
+
+    pub fn deny(mut self, capability: ToolCapability) -> Self {
+        self.deny.insert(capability);
+        self
+    }
+
+    /// Deny every capability, including [`ToolCapability::Unknown`].
+    pub fn deny_all() -> Self {
+        Self {
+            allow: Some(HashSet::new()),
+            deny: HashSet::new(),
+        }
+    }
+
+    pub fn is_allowed(&self, capability: ToolCapability) -> bool {
+        !self.deny.contains(&capability)
+            && self
+                .allow
+                .as_ref()
+                .is_none_or(|allow| allow.contains(&capability))
+    }
+
+    /// Check whether the tool may be called, returns the first capability which is not allowed.
+    pub fn check(&self, tool: &str, capabilities: &[ToolCapability]) -> Result<(), ToolError> {
+        match ToolCapability::declared_or_unknown(capabilities)
+            .iter()
+            .find(|capability| !self.is_allowed(**capability))
+        {
+            Some(capability) => Err(ToolError::PermissionDenied {
+                tool: tool.to_owned(),
+                capability: *capability,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Permissions which allow only what both `self` and `other` allow.
+    pub fn restrict(&self, other: &ToolPermissions) -> ToolPermissions {
+        let allow = match (&self.allow, &other.allow) {
+            (Some(a), Some(b)) => Some(a.intersection(b).copied().collect()),
+            (Some(allow), None) | (None, Some(allow)) => Some(allow.clone()),
+            (None, None) => None,
+        };
+        ToolPermissions {
+            allow,
+            deny: self.deny.union(&other.deny).copied().collect(),
+        }
+    }
+}
+
+/// Per-run limits of the sandboxed tools, i.e. the tools which execute code or access files.
+/// Checked before and enforced around every call of a sandboxed tool, a call which exceeds a
+/// limit fails with [`ToolError::LimitExceeded`]. Nothing is limited by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// Total time the sandboxed tools may run, a call is cancelled once it's used up.
+    pub max_wall_time: Option<Duration>,
+    /// Total size of the outputs of the sandboxed tools.
+    pub max_output_bytes: Option<u64>,
+    /// Max number of calls of tools which execute code, each of which may start a subprocess.
+    pub max_subprocesses: Option<u32>,
+}
+
+/// A limit of [`ResourceLimits`] which a tool call exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceLimit {
+    WallTime(Duration),
+    OutputBytes(u64),
+    Subprocesses(u32),
+}
+
+impl Display for ResourceLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceLimit::WallTime(max) => write!(f, "{max:?} wall time"),
+            ResourceLimit::OutputBytes(max) => write!(f, "{max} output bytes"),
+            ResourceLimit::Subprocesses(max) => write!(f, "{max} subprocesses"),
+        }
+    }
+}
+
+/// Resources the sandboxed tools used so far in a run.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ResourceUsage {
+    wall_time: Duration,
+    output_bytes: u64,
+    subprocesses: u32,
+}
+
+impl ResourceLimits {
+    pub fn max_wall_time(mut self, max_wall_time: Duration) -> Self {
+        self.max_wall_time = Some(max_wall_time);
+        self
+    }
+
+    pub fn max_output_bytes(mut self, max_output_bytes: u64) -> Self {
+        self.max_output_bytes = Some(max_output_bytes);
+        self
+    }
+
+    pub fn max_subprocesses(mut self, max_subprocesses: u32) -> Self {
+        self.max_subprocesses = Some(max_subprocesses);
+        self
+    }
+
+    /// Whether calls of a tool with these capabilities are limited, tools which may execute
+    /// code or access files are, including the tools which declare no capabilities.
+    pub fn applies_to(capabilities: &[ToolCapability]) -> bool {
+        ToolCapability::declared_or_unknown(capabilities)
+            .iter()
+            .any(|capability| {
+                matches!(
+                    capability,
+                    ToolCapability::CodeExec | ToolCapability::Filesystem | ToolCapability::Unknown
+                )
+            })
+    }
+
+    /// Check that the run has resources left for a call of the tool, and count the call.
+    pub(crate) fn admit(
+        &self,
+        tool: &str,
+        capabilities: &[ToolCapability],
+        usage: &mut ResourceUsage,
+    ) -> Result<(), ToolError> {
+        if !Self::applies_to(capabilities) {
+            return Ok(());
+        }
+        let exceeded = |limit| ToolError::LimitExceeded {
+            tool: tool.to_owned(),
+            limit,
+        };
+        if let Some(max) = self.max_wall_time
+            && usage.wall_time >= max
+        {
+            return Err(exceeded(ResourceLimit::WallTime(max)));
+        }
+        if capabilities.contains(&ToolCapability::CodeExec) {
+            if let Some(max) = self.max_subprocesses
+                && usage.subprocesses >= max
+            {
+                return Err(exceeded(ResourceLimit::Subprocesses(max)));
+            }
+            usage.subprocesses += 1;
+        }
+        Ok(())
+    }
+
+    /// Run an admitted call of the tool within the wall time left in the run, and count the
+    /// time and output it used.
+    pub(crate) async fn enforce(
+        &self,
+        tool: &str,
+        capabilities: &[ToolCapability],
+        usage: &mut ResourceUsage,
+        call: impl Future<Output = Result<String, ToolError>>,
+    ) -> Result<String, ToolError> {
+        if !Self::applies_to(capabilities) {
+            return call.await;
+        }
+        let exceeded = |limit| ToolError::LimitExceeded {
+            tool: tool.to_owned(),
+            limit,
+        };
+        let start = Instant::now();
+        let result = match self.max_wall_time {
+            Some(max) => tokio::time::timeout(max.saturating_sub(usage.wall_time), call)
+                .await
+                .unwrap_or_else(|_| Err(exceeded(ResourceLimit::WallTime(max)))),
+            None => call.await,
+        };
+        usage.wall_time += start.elapsed();
+        let output = result?;
+
+        usage.output_bytes += output.len() as u64;
+        match self.max_output_bytes {
+            Some(max) if usage.output_bytes > max => Err(exceeded(ResourceLimit::OutputBytes(max))),
+            _ => Ok(output),
+        }
+    }
 }
 
 pub trait Tool: Sized + Send + Sync {
@@ -19,6 +296,9 @@ pub trait Tool: Sized + Send + Sync {
     type Output: Serialize;
 
     const NAME: &'static str;
+    /// What the tool can do outside of the process, checked against the agent's
+    /// [`ToolPermissions`] before every call.
+    const CAPABILITIES: &'static [ToolCapability] = &[];
 
     // Required methods
     fn definition(&self) -> ToolDefinition;
@@ -38,6 +318,10 @@ pub trait ToolDyn: Send + Sync {
 
     fn definition(&self) -> ToolDefinition;
 
+    fn capabilities(&self) -> &'static [ToolCapability] {
+        &[]
+    }
+
     fn call(&self, args: String) -> BoxFuture<Result<String, ToolError>>;
 }
 
@@ -50,6 +334,10 @@ impl<T: Tool> ToolDyn for T {
         <Self as Tool>::definition(self)
     }
 
+    fn capabilities(&self) -> &'static [ToolCapability] {
+        Self::CAPABILITIES
+    }
+
     fn call(&self, args: String) -> BoxFuture<Result<String, ToolError>> {
         Box::pin(async move {
             match serde_json::from_str(&args) {
@@ -64,3 +352,138 @@ impl<T: Tool> ToolDyn for T {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resource_limits() {
+        let limits = ResourceLimits::default()
+            .max_wall_time(Duration::from_millis(50))
+            .max_output_bytes(8)
+            .max_subprocesses(1);
+        let shell = &[ToolCapability::CodeExec];
+        let mut usage = ResourceUsage::default();
+        let output = |output: &str| {
+            let output = output.to_owned();
+            async move { Ok(output) }
+        };
+
+        assert!(limits.admit("shell", shell, &mut usage).is_ok());
+        let result = limits
+            .enforce("shell", shell, &mut usage, output("ok"))
+            .await;
+        assert_eq!(result.unwrap(), "ok");
+        assert!(matches!(
+            limits.admit("shell", shell, &mut usage),
+            Err(ToolError::LimitExceeded {
+                limit: ResourceLimit::Subprocesses(1),
+                ..
+            })
+        ));
+
+        let read_file = &[ToolCapability::Filesystem];
+        let result = limits
+            .enforce("read_file", read_file, &mut usage, output("too long"))
+            .await;
+        assert!(matches!(
+            result,
+            Err(ToolError::LimitExceeded {
+                limit: ResourceLimit::OutputBytes(8),
+                ..
+            })
+        ));
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(String::new())
+        };
+        let result = limits
+            .enforce("read_file", read_file, &mut usage, slow)
+            .await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "LimitExceeded: tool read_file exceeded the run's limit of 50ms wall time"
+        );
+        assert!(limits.admit("read_file", read_file, &mut usage).is_err());
+
+        // Tools which neither execute code nor access files aren't limited
+        let search = &[ToolCapability::Network];
+        assert!(limits.admit("search", search, &mut usage).is_ok());
+        let result = limits
+            .enforce("search", search, &mut usage, output("long results"))
+            .await;
+        assert!(result.is_ok());
+
+        // Tools which declare no capabilities might, so they are limited
+        assert!(ResourceLimits::applies_to(&[]));
+        let result = limits
+            .enforce("custom", &[], &mut usage, output("long results"))
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_tool_permissions() {
+        let permissions = ToolPermissions::default().deny(ToolCapability::CodeExec);
+        assert!(
+            permissions
+                .check("search", &[ToolCapability::Network])
+                .is_ok()
+        );
+        assert!(matches!(
+            permissions.check(
+                "shell",
+                &[ToolCapability::Filesystem, ToolCapability::CodeExec]
+            ),
+            Err(ToolError::PermissionDenied {
+                capability: ToolCapability::CodeExec,
+                ..
+            })
+        ));
+
+        let allow_network = ToolPermissions::default().allow(ToolCapability::Network);
+        assert!(
+            allow_network
+                .check("search", &[ToolCapability::Network])
+                .is_ok()
+        );
+        assert!(
+            allow_network
+                .check("read_file", &[ToolCapability::Filesystem])
+                .is_err()
+        );
+        // Tools which declare no capabilities are matched by `Unknown`
+        assert!(matches!(
+            ToolPermissions::deny_all().check("custom", &[]),
+            Err(ToolError::PermissionDenied {
+                capability: ToolCapability::Unknown,
+                ..
+            })
+        ));
+        assert!(
+            ToolPermissions::default()
+                .deny(ToolCapability::Unknown)
+                .check("custom", &[])
+                .is_err()
+        );
+        assert!(allow_network.check("custom", &[]).is_err());
+        assert!(ToolPermissions::default().check("custom", &[]).is_ok());
+        assert!(
+            ToolPermissions::deny_all()
+                .allow(ToolCapability::Unknown)
+                .check("custom", &[])
+                .is_ok()
+        );
+
+        let restricted = allow_network.restrict(&permissions);
+        assert!(restricted.is_allowed(ToolCapability::Network));
+        assert!(!restricted.is_allowed(ToolCapability::CodeExec));
+        assert!(!restricted.is_allowed(ToolCapability::Filesystem));
+        assert!(
+            !allow_network
+                .restrict(&ToolPermissions::deny_all())
+                .is_allowed(ToolCapability::Network)
+        );
+    }
+}
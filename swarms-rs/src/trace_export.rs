@@ -0,0 +1,602 @@
+//! Export run metadata to LLM observability platforms.
+//!
+//! A [`TraceExport`] turns the [`MetadataSchema`] of a workflow run, and optionally its
+//! conversation, into one trace: a root `chain` for the workflow, a child for every agent run
+//! and a grandchild for every tool call. It's available in two formats:
+//!
+//! - [`TraceExport::to_langsmith`], the body of the LangSmith `POST /runs/batch` API, send it
+//!   with [`LangSmithExporter`].
+//! - [`TraceExport::to_openinference`], OTLP/JSON spans with OpenInference attributes, accepted
+//!   by Arize Phoenix and other OpenTelemetry collectors, send it with [`OtlpExporter`].
+//!
+//! The ids are derived from the run ids, exporting the same run twice updates the trace
+//! instead of duplicating it. Tool calls only record their duration, they are laid out one
+//! after the other from the start of their agent's run.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{Value, json};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    agent::ToolCallRecord,
+    conversation::{AgentConversation, Message, ParticipantKind},
+    error::{CategorizedError, ErrorCategory},
+    swarm::{AgentOutputSchema, MetadataSchema},
+};
+
+pub const LANGSMITH_ENDPOINT: &str = "https://api.smith.langchain.com";
+
+#[derive(Debug, Error)]
+pub enum TraceExportError {
+    #[error("Http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Trace backend returned status {0}")]
+    Status(u16),
+}
+
+impl CategorizedError for TraceExportError {
+    fn category(&self) -> ErrorCategory {
+        match self {
+            TraceExportError::Http(e) if e.is_timeout() => ErrorCategory::Timeout,
+            TraceExportError::Http(_) | TraceExportError::Status(_) => ErrorCategory::Other,
+        }
+    }
+}
+
+/// One workflow run, ready to be exported.
+pub struct TraceExport<'a> {
+    metadata: &'a MetadataSchema,
+    name: String,
+    conversation: Option<&'a AgentConversation>,
+}
+
+impl<'a> TraceExport<'a> {
+    pub fn new(metadata: &'a MetadataSchema) -> Self {
+        Self {
+            metadata,
+            name: "Workflow".to_owned(),
+            conversation: None,
+        }
+    }
+
+    /// Name of the root run, usually the workflow's name.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Export the conversation's messages as the output of the root run.
+    pub fn conversation(mut self, conversation: &'a AgentConversation) -> Self {
+        self.conversation = Some(conversation);
+        self
+    }
+
+    /// The runs of the trace, in the format of the LangSmith batch API.
+    pub fn to_langsmith(&self, project: &str) -> LangSmithBatch {
+        let root = self.root_span();
+        let mut post = Vec::new();
+        self.push_langsmith(&root, None, project, &mut post);
+        LangSmithBatch { post }
+    }
+
+    fn push_langsmith(
+        &self,
+        span: &Span,
+        parent: Option<(&Uuid, &str)>,
+        project: &str,
+        runs: &mut Vec<LangSmithRun>,
+    ) {
+        let start_time = span.start.with_timezone(&Utc);
+        // Ordered by start time, then id, the ancestors' parts come first
+        let part = format!("{}{}", start_time.format("%Y%m%dT%H%M%S%6fZ"), span.id);
+        let dotted_order = match parent {
+            Some((_, parent_order)) => format!("{parent_order}.{part}"),
+            None => part,
+        };
+        runs.push(LangSmithRun {
+            id: span.id,
+            trace_id: self.metadata.swarm_id,
+            dotted_order: dotted_order.clone(),
+            parent_run_id: parent.map(|(id, _)| *id),
+            name: span.name.clone(),
+            run_type: span.kind.langsmith_run_type(),
+            start_time: start_time.to_rfc3339_opts(SecondsFormat::Micros, true),
+            end_time: span
+                .end
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            inputs: span.input.clone(),
+            outputs: span.output.clone(),
+            error: span.error.clone(),
+            session_name: project.to_owned(),
+            extra: json!({ "metadata": span.metadata }),
+        });
+        for child in &span.children {
+            self.push_langsmith(child, Some((&span.id, &dotted_order)), project, runs);
+        }
+    }
+
+    /// The spans of the trace as an OTLP/JSON `ExportTraceServiceRequest`, with the
+    /// OpenInference semantic conventions.
+    pub fn to_openinference(&self) -> Value {
+        let trace_id = self.metadata.swarm_id.simple().to_string();
+        let mut spans = Vec::new();
+        push_otlp(&self.root_span(), None, &trace_id, &mut spans);
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [string_attribute("service.name", "swarms-rs")],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "swarms-rs", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+
+    fn root_span(&self) -> Span {
+        let metadata = self.metadata;
+        let outputs = &metadata.agents_output_schema;
+        let start = outputs
+            .iter()
+            .map(|output| output.start)
+            .min()
+            .unwrap_or(metadata.timestamp);
+        let output = match self.conversation {
+            Some(conversation) => json!({
+                "messages": conversation
+                    .history
+                    .iter()
+                    .map(message_json)
+                    .collect::<Vec<_>>(),
+            }),
+            None => json!({
+                "outputs": outputs
+                    .iter()
+                    .map(|output| json!({ "agent": output.agent_name, "output": output.output }))
+                    .collect::<Vec<_>>(),
+            }),
+        };
+        let mut span_metadata = json!({ "description": metadata.description });
+        if let Some(tenant_id) = &metadata.tenant_id {
+            span_metadata["tenant_id"] = json!(tenant_id);
+        }
+        Span {
+            id: metadata.swarm_id,
+            name: self.name.clone(),
+            kind: SpanKind::Chain,
+            start,
+            end: metadata.timestamp.max(start),
+            input: json!({ "task": metadata.task }),
+            output,
+            error: None,
+            metadata: span_metadata,
+            children: outputs.iter().map(agent_span).collect(),
+        }
+    }
+}
+
+fn message_json(message: &Message) -> Value {
+    let role = match message.participant.kind {
+        ParticipantKind::Human => "user",
+        ParticipantKind::Agent => "assistant",
+        ParticipantKind::Tool | ParticipantKind::Retrieval => "tool",
+        ParticipantKind::System => "system",
+    };
+    json!({
+        "role": role,
+        "name": message.participant.name,
+        "content": message.content.to_string(),
+    })
+}
+
+fn agent_span(output: &AgentOutputSchema) -> Span {
+    let mut tool_start = output.start;
+    let children = output
+        .tool_calls
+        .iter()
+        .enumerate()
+        .map(|(i, tool_call)| {
+            let span = tool_span(output.run_id, i, tool_call, tool_start);
+            tool_start = span.end;
+            span
+        })
+        .collect();
+    Span {
+        id: output.run_id,
+        name: output.agent_name.clone(),
+        kind: SpanKind::Agent,
+        start: output.start,
+        end: output.end,
+        input: json!({ "task": output.task }),
+        output: json!({ "output": output.output }),
+        error: (!output.stop_reason.is_completed())
+            .then(|| format!("Agent stopped: {}", output.stop_reason.as_str())),
+        metadata: json!({ "stop_reason": output.stop_reason }),
+        children,
+    }
+}
+
+fn tool_span(
+    run_id: Uuid,
+    index: usize,
+    tool_call: &ToolCallRecord,
+    start: DateTime<Local>,
+) -> Span {
+    let (output, error) = match &tool_call.output {
+        Ok(output) => (json!({ "output": output }), None),
+        Err(e) => (Value::Null, Some(e.clone())),
+    };
+    Span {
+        // Stable for the same run, unique within it
+        id: Uuid::from_u128(run_id.as_u128() ^ (index as u128 + 1)),
+        name: tool_call.name.clone(),
+        kind: SpanKind::Tool,
+        start,
+        end: start + Duration::from_millis(tool_call.duration_ms),
+        input: json!({ "arguments": tool_call.arguments }),
+        output,
+        error,
+        metadata: json!({ "agent": tool_call.agent }),
+        children: Vec::new(),
+    }
+}
+
+/// A run of the trace, independent of the export format.
+struct Span {
+    id: Uuid,
+    name: String,
+    kind: SpanKind,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    input: Value,
+    output: Value,
+    error: Option<String>,
+    metadata: Value,
+    children: Vec<Span>,
+}
+
+#[derive(Clone, Copy)]
+enum SpanKind {
+    Chain,
+    Agent,
+    Tool,
+}
+
+impl SpanKind {
+    fn langsmith_run_type(self) -> &'static str {
+        match self {
+            // LangSmith has no agent run type, agents are chains of LLM and tool calls
+            SpanKind::Chain | SpanKind::Agent => "chain",
+            SpanKind::Tool => "tool",
+        }
+    }
+
+    fn openinference_kind(self) -> &'static str {
+        match self {
+            SpanKind::Chain => "CHAIN",
+            SpanKind::Agent => "AGENT",
+            SpanKind::Tool => "TOOL",
+        }
+    }
+}
+
+fn push_otlp(span: &Span, parent_id: Option<&str>, trace_id: &str, spans: &mut Vec<Value>) {
+    let span_id = format!("{:016x}", span.id.as_u128() as u64);
+    let mut attributes = vec![
+        string_attribute("openinference.span.kind", span.kind.openinference_kind()),
+        string_attribute("input.value", &span.input.to_string()),
+        string_attribute("input.mime_type", "application/json"),
+        string_attribute("metadata", &span.metadata.to_string()),
+    ];
+    if !span.output.is_null() {
+        attributes.push(string_attribute("output.value", &span.output.to_string()));
+        attributes.push(string_attribute("output.mime_type", "application/json"));
+    }
+    match span.kind {
+        SpanKind::Agent => attributes.push(string_attribute("agent.name", &span.name)),
+        SpanKind::Tool => {
+            attributes.push(string_attribute("tool.name", &span.name));
+            attributes.push(string_attribute(
+                "tool.parameters",
+                &span.input["arguments"].to_string(),
+            ));
+        }
+        SpanKind::Chain => {}
+    }
+    let status = match &span.error {
+        Some(message) => json!({ "code": 2, "message": message }),
+        None => json!({ "code": 1 }),
+    };
+
+    let mut otlp_span = json!({
+        "traceId": trace_id,
+        "spanId": span_id,
+        "name": span.name,
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": attributes,
+        "status": status,
+    });
+    if let Some(parent_id) = parent_id {
+        otlp_span["parentSpanId"] = json!(parent_id);
+    }
+    spans.push(otlp_span);
+    for child in &span.children {
+        push_otlp(child, Some(&span_id), trace_id, spans);
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// OTLP/JSON encodes 64 bit integers as strings.
+fn unix_nanos(time: DateTime<Local>) -> String {
+    time.timestamp_nanos_opt().unwrap_or_default().to_string()
+}
+
+/// The body of the LangSmith `POST /runs/batch` API.
+#[derive(Clone, Debug, Serialize)]
+pub struct LangSmithBatch {
+    pub post: Vec<LangSmithRun>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LangSmithRun {
+    pub id: Uuid,
+    pub trace_id: Uuid,
+    pub dotted_order: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_run_id: Option<Uuid>,
+    pub name: String,
+    pub run_type: &'static str,
+    pub start_time: String,
+    pub end_time: String,
+    pub inputs: Value,
+    pub outputs: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The LangSmith project.
+    pub session_name: String,
+    pub extra: Value,
+}
+
+/// Sends traces to LangSmith.
+#[derive(Clone)]
+pub struct LangSmithExporter {
+    client: reqwest::Client,
+    api_key: String,
+    endpoint: String,
+    project: String,
+    timeout: Duration,
+}
+
+impl LangSmithExporter {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_key: api_key.into(),
+            endpoint: LANGSMITH_ENDPOINT.to_owned(),
+            project: "default".to_owned(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// The API url, for self hosted or regional LangSmith deployments.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    pub fn project(mut self, project: impl Into<String>) -> Self {
+        self.project = project.into();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn export(&self, trace: &TraceExport<'_>) -> Result<(), TraceExportError> {
+        let request = self
+            .client
+            .post(format!(
+                "{}/runs/batch",
+                self.endpoint.trim_end_matches('/')
+            ))
+            .header("x-api-key", &self.api_key)
+            .json(&trace.to_langsmith(&self.project));
+        send(request, self.timeout).await
+    }
+}
+
+/// Sends traces to an OTLP/HTTP endpoint which accepts JSON, such as
+/// `http://localhost:6006/v1/traces` for a local Phoenix.
+#[derive(Clone)]
+pub struct OtlpExporter {
+    client: reqwest::Client,
+    endpoint: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.into(),
+            headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Send a header with every export, e.g. the `api_key` of Phoenix Cloud.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn export(&self, trace: &TraceExport<'_>) -> Result<(), TraceExportError> {
+        let mut request = self
+            .client
+            .post(&self.endpoint)
+            .json(&trace.to_openinference());
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        send(request, self.timeout).await
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder, timeout: Duration) -> Result<(), TraceExportError> {
+    let response = request.timeout(timeout).send().await?;
+    if !response.status().is_success() {
+        return Err(TraceExportError::Status(response.status().as_u16()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{
+        agent::StopReason,
+        conversation::{DedupStats, Participant},
+    };
+
+    fn metadata() -> MetadataSchema {
+        let start = Local::now();
+        MetadataSchema {
+            swarm_id: Uuid::new_v4(),
+            task: "task".to_owned(),
+            description: "test".to_owned(),
+            agents_output_schema: vec![AgentOutputSchema {
+                run_id: Uuid::new_v4(),
+                agent_id: "agent-1".to_owned(),
+                agent_name: "agent".to_owned(),
+                task: "task".to_owned(),
+                output: "answer".to_owned(),
+                start,
+                end: start + TimeDelta::seconds(1),
+                duration: 1,
+                stop_reason: StopReason::StopWord,
+                tool_calls: vec![
+                    ToolCallRecord {
+                        agent: "agent".to_owned(),
+                        name: "lookup".to_owned(),
+                        arguments: json!({ "query": "x" }),
+                        output: Ok("found".to_owned()),
+                        duration_ms: 10,
+                    },
+                    ToolCallRecord {
+                        agent: "agent".to_owned(),
+                        name: "exec".to_owned(),
+                        arguments: json!({}),
+                        output: Err("denied".to_owned()),
+                        duration_ms: 0,
+                    },
+                ],
+                injections: vec![],
+                grounding: None,
+                metadata: BTreeMap::new(),
+                dedup: DedupStats::default(),
+                tool_usage: BTreeMap::new(),
+                prompt: None,
+                provenance: None,
+            }],
+            timestamp: start + TimeDelta::seconds(1),
+            tenant_id: None,
+        }
+    }
+
+    #[test]
+    fn test_langsmith_runs() {
+        let metadata = metadata();
+        let batch = TraceExport::new(&metadata)
+            .name("workflow")
+            .to_langsmith("project");
+        let [root, agent, lookup, exec] = batch.post.as_slice() else {
+            panic!("expected 4 runs, got {}", batch.post.len());
+        };
+
+        assert_eq!(root.id, metadata.swarm_id);
+        assert_eq!(root.parent_run_id, None);
+        assert_eq!(root.name, "workflow");
+        assert_eq!(root.outputs["outputs"][0]["output"], "answer");
+        assert_eq!(agent.parent_run_id, Some(root.id));
+        assert_eq!(agent.run_type, "chain");
+        assert_eq!(lookup.parent_run_id, Some(agent.id));
+        assert_eq!(lookup.run_type, "tool");
+        assert_eq!(lookup.inputs["arguments"]["query"], "x");
+        assert_eq!(exec.error.as_deref(), Some("denied"));
+        assert!(batch.post.iter().all(|run| run.trace_id == root.id));
+        assert!(batch.post.iter().all(|run| run.session_name == "project"));
+        assert!(exec.dotted_order.starts_with(&agent.dotted_order));
+        assert!(agent.dotted_order.starts_with(&root.dotted_order));
+        assert_ne!(lookup.id, exec.id);
+
+        // Exporting again gives the same ids
+        let again = TraceExport::new(&metadata).to_langsmith("project");
+        assert_eq!(again.post[2].id, lookup.id);
+    }
+
+    #[test]
+    fn test_openinference_spans() {
+        let metadata = metadata();
+        let mut conversation = AgentConversation::new("workflow".to_owned());
+        conversation.add(Participant::human("User"), "task".to_owned());
+        let otlp = TraceExport::new(&metadata)
+            .conversation(&conversation)
+            .to_openinference();
+        let spans = otlp["resourceSpans"][0]["scopeSpans"][0]["spans"]
+            .as_array()
+            .unwrap();
+        assert_eq!(spans.len(), 4);
+
+        let attribute = |span: &Value, key: &str| {
+            span["attributes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|attribute| attribute["key"] == key)
+                .map(|attribute| attribute["value"]["stringValue"].clone())
+        };
+        assert_eq!(spans[0]["traceId"], metadata.swarm_id.simple().to_string());
+        assert!(spans[0].get("parentSpanId").is_none());
+        assert_eq!(
+            attribute(&spans[0], "openinference.span.kind").unwrap(),
+            "CHAIN"
+        );
+        assert!(
+            attribute(&spans[0], "output.value")
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .contains("messages")
+        );
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(attribute(&spans[1], "agent.name").unwrap(), "agent");
+        assert_eq!(spans[2]["parentSpanId"], spans[1]["spanId"]);
+        assert_eq!(attribute(&spans[2], "tool.name").unwrap(), "lookup");
+        assert_eq!(spans[2]["status"]["code"], 1);
+        assert_eq!(spans[3]["status"]["code"], 2);
+        assert!(attribute(&spans[3], "output.value").is_none());
+    }
+}
@@ -2,28 +2,54 @@ use chrono::Local;
 use uuid::Uuid;
 
 use crate::{
-    agent::{Agent, AgentError},
+    agent::{Agent, AgentError, RunOptions, tool_analytics::ToolStats},
     swarm::AgentOutputSchema,
 };
 
+/// Whether the task has no content, a blank task is rejected like a missing one.
+pub(crate) fn is_empty_task(task: &str) -> bool {
+    task.trim().is_empty()
+}
+
+/// Whether there are no tasks, or any of them is blank.
+pub(crate) fn has_empty_tasks(tasks: &[impl AsRef<str>]) -> bool {
+    tasks.is_empty() || tasks.iter().any(|task| is_empty_task(task.as_ref()))
+}
+
 pub async fn run_agent_with_output_schema(
     agent: &dyn Agent,
     task: String,
+    options: RunOptions,
 ) -> Result<AgentOutputSchema, AgentError> {
     let start = Local::now();
-    let output = agent.run(task.clone()).await?;
+    let result = agent
+        .run_detailed_with_options(task.clone(), options)
+        .await?;
 
     let end = Local::now();
     let duration = end.signed_duration_since(start).num_seconds();
 
     let agent_output = AgentOutputSchema {
-        run_id: Uuid::new_v4(),
+        run_id: result
+            .provenance
+            .as_ref()
+            .map_or_else(Uuid::new_v4, |provenance| provenance.run_id),
+        agent_id: agent.id(),
         agent_name: agent.name(),
         task,
-        output,
+        output: result.answer,
         start,
         end,
         duration,
+        stop_reason: result.stop_reason,
+        tool_usage: ToolStats::from_calls(&result.tool_calls),
+        tool_calls: result.tool_calls,
+        injections: result.injections,
+        grounding: result.grounding,
+        metadata: agent.metadata(),
+        dedup: result.dedup,
+        prompt: result.prompt,
+        provenance: result.provenance,
     };
 
     Ok(agent_output)
@@ -0,0 +1,319 @@
+//! Live view of a [`DAGWorkflow`] in the browser, enabled with the `visualization` feature.
+//!
+//! [`GraphVisualizer::serve`] serves a small page which renders the workflow's DOT export
+//! and colors every node by its [`NodeState`], updated through server-sent events while the
+//! workflow executes:
+//!
+//! | path         | content                                                        |
+//! |--------------|----------------------------------------------------------------|
+//! | `/`          | The page, it renders the graph with Graphviz compiled to WASM. |
+//! | `/graph.dot` | The DOT graph with the current node states.                    |
+//! | `/events`    | `graph` events with the DOT graph, `state` events with a JSON [`NodeStateEvent`]. |
+//! | `/viz.js`    | The copy of Graphviz set with [`GraphVisualizer::viz_script`], if any. |
+//!
+//! By default the page loads Graphviz (viz.js) from the jsDelivr CDN, so the browser needs
+//! internet access. Offline and air-gapped deployments vendor `viz-standalone.js` and serve it
+//! with [`GraphVisualizer::viz_script`]. `/graph.dot` never needs the network.
+//!
+//! ```no_run
+//! # use swarms_rs::{graph_workflow::DAGWorkflow, visualization::GraphVisualizer};
+//! # async fn run(mut workflow: DAGWorkflow) {
+//! let visualizer = GraphVisualizer::new(&workflow);
+//! tokio::spawn(visualizer.serve("127.0.0.1:8080".parse().unwrap()));
+//! workflow.execute_workflow("start", "task").await.unwrap();
+//! # }
+//! ```
+
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::error::RecvError,
+};
+
+use crate::graph_workflow::{DAGWorkflow, NodeState, NodeStateEvent, NodeStateTracker, dot_id};
+
+const VIZ_CDN_URL: &str = "https://cdn.jsdelivr.net/npm/@viz-js/viz@3/lib/viz-standalone.js";
+
+// The page, `{viz_script_src}` is replaced with the URL of viz.js
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>swarms-rs workflow</title>
+<style>body { font-family: sans-serif; margin: 2em; } svg { max-width: 100%; height: auto; }</style>
+</head>
+<body>
+<p id="status">Connecting...</p>
+<div id="graph"></div>
+<script src="{viz_script_src}"></script>
+<script>
+const status = document.getElementById("status");
+Viz.instance().then(viz => {
+  const events = new EventSource("/events");
+  events.onopen = () => { status.textContent = "Live"; };
+  events.onerror = () => { status.textContent = "Disconnected, retrying..."; };
+  events.addEventListener("graph", event => {
+    document.getElementById("graph").replaceChildren(viz.renderSVGElement(event.data));
+  });
+});
+</script>
+</body>
+</html>
+"#;
+
+/// Serves the live graph of a workflow.
+///
+/// The graph's structure is captured when the visualizer is created, the node states are
+/// read live from the workflow's [`NodeStateTracker`].
+#[derive(Clone, Debug)]
+pub struct GraphVisualizer {
+    dot: String,
+    states: NodeStateTracker,
+    viz_script: Option<Arc<str>>,
+}
+
+impl GraphVisualizer {
+    pub fn new(workflow: &DAGWorkflow) -> Self {
+        Self {
+            dot: workflow.export_workflow_dot(),
+            states: workflow.node_states(),
+            viz_script: None,
+        }
+    }
+
+    /// Serve this copy of `viz-standalone.js` from `/viz.js` instead of loading it from the
+    /// jsDelivr CDN, e.g. `include_str!` of a vendored file, so the page works offline.
+    pub fn viz_script(mut self, script: impl Into<String>) -> Self {
+        self.viz_script = Some(script.into().into());
+        self
+    }
+
+    /// The DOT graph with every node filled with the color of its current state.
+    pub fn render_dot(&self) -> String {
+        let states = self.states.snapshot();
+        let body = self.dot.trim_end().trim_end_matches('}');
+        let mut dot = body.to_owned();
+        let mut nodes = states.into_iter().collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.0.cmp(&b.0));
+        for (node, state) in nodes {
+            dot.push_str(&format!(
+                "    {} [style=filled, fillcolor=\"{}\", tooltip=\"{}\"];\n",
+                dot_id(&node),
+                state_color(state),
+                state_name(state)
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Serve the page on `addr` until an error occurs.
+    pub async fn serve(self, addr: SocketAddr) -> std::io::Result<()> {
+        self.serve_listener(TcpListener::bind(addr).await?).await
+    }
+
+    async fn serve_listener(self, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let visualizer = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = visualizer.handle(stream).await {
+                    tracing::debug!("| Visualization | Connection closed: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..n]);
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        match path {
+            "/" | "/index.html" => {
+                let src = match self.viz_script {
+                    Some(_) => "/viz.js",
+                    None => VIZ_CDN_URL,
+                };
+                let page = INDEX_HTML.replace("{viz_script_src}", src);
+                write_response(&mut stream, "text/html; charset=utf-8", &page).await
+            }
+            "/viz.js" if self.viz_script.is_some() => {
+                let script = self.viz_script.as_deref().unwrap_or_default();
+                write_response(&mut stream, "text/javascript; charset=utf-8", script).await
+            }
+            "/graph.dot" => {
+                write_response(&mut stream, "text/vnd.graphviz", &self.render_dot()).await
+            }
+            "/events" => self.stream_events(stream).await,
+            _ => {
+                stream
+                    .write_all(
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .await
+            }
+        }
+    }
+
+    /// Send the graph now and after every state change, until the client disconnects.
+    async fn stream_events(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        // Subscribe first, so no change between the first graph and the loop is missed
+        let mut rx = self.states.subscribe();
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+            )
+            .await?;
+        stream
+            .write_all(sse_event("graph", &self.render_dot()).as_bytes())
+            .await?;
+
+        loop {
+            let mut message = match rx.recv().await {
+                Ok(event) => state_event(&event),
+                // The graph below contains the missed changes
+                Err(RecvError::Lagged(_)) => String::new(),
+                Err(RecvError::Closed) => return Ok(()),
+            };
+            message.push_str(&sse_event("graph", &self.render_dot()));
+            stream.write_all(message.as_bytes()).await?;
+        }
+    }
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+fn state_event(event: &NodeStateEvent) -> String {
+    // Safety: the event only contains strings and a unit enum
+    sse_event("state", &serde_json::to_string(event).unwrap())
+}
+
+/// A server-sent event, every line of the data is sent as its own `data:` field.
+fn sse_event(event: &str, data: &str) -> String {
+    let mut message = format!("event: {event}\n");
+    for line in data.lines() {
+        message.push_str("data: ");
+        message.push_str(line);
+        message.push('\n');
+    }
+    message.push('\n');
+    message
+}
+
+fn state_color(state: NodeState) -> &'static str {
+    match state {
+        NodeState::Pending => "#e0e0e0",
+        NodeState::Running => "#fff176",
+        NodeState::Done => "#a5d6a7",
+        NodeState::Failed => "#ef9a9a",
+    }
+}
+
+fn state_name(state: NodeState) -> &'static str {
+    match state {
+        NodeState::Pending => "pending",
+        NodeState::Running => "running",
+        NodeState::Done => "done",
+        NodeState::Failed => "failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FnAgent;
+
+    #[test]
+    fn test_sse_event() {
+        assert_eq!(
+            sse_event("graph", "digraph {\n}\n"),
+            "event: graph\ndata: digraph {\ndata: }\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_graph() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(Box::new(FnAgent::reply("a", "answer a")));
+        let visualizer = GraphVisualizer::new(&workflow);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(visualizer.serve_listener(listener));
+
+        workflow.execute_workflow("a", "input").await.unwrap();
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /graph.dot HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("\"a\" [style=filled, fillcolor=\"#a5d6a7\", tooltip=\"done\"]"));
+        assert!(response.trim_end().ends_with('}'));
+    }
+
+    #[tokio::test]
+    async fn test_render_dot_escapes_names() {
+        let mut workflow = DAGWorkflow::new("test", "Test workflow");
+        workflow.register_agent(Box::new(FnAgent::reply("say \"hi\"", "hi")));
+        let visualizer = GraphVisualizer::new(&workflow);
+        workflow
+            .execute_workflow("say \"hi\"", "input")
+            .await
+            .unwrap();
+        let dot = visualizer.render_dot();
+        assert!(dot.contains(r#""say \"hi\"" [label="say \"hi\""]"#));
+        assert!(
+            dot.contains(r##""say \"hi\"" [style=filled, fillcolor="#a5d6a7", tooltip="done"]"##)
+        );
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_vendored_viz_script() {
+        let workflow = DAGWorkflow::new("test", "Test workflow");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(GraphVisualizer::new(&workflow).serve_listener(listener));
+        assert!(get(addr, "/").await.contains(VIZ_CDN_URL));
+        assert!(get(addr, "/viz.js").await.starts_with("HTTP/1.1 404"));
+
+        let visualizer = GraphVisualizer::new(&workflow).viz_script("var Viz = {};");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(visualizer.serve_listener(listener));
+        let page = get(addr, "/").await;
+        assert!(page.contains("<script src=\"/viz.js\"></script>"));
+        assert!(!page.contains(VIZ_CDN_URL));
+        assert!(
+            get(addr, "/viz.js")
+                .await
+                .ends_with("\r\n\r\nvar Viz = {};")
+        );
+    }
+}
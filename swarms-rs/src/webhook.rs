@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::swarm::MetadataSchema;
+
+/// Header which carries the HMAC-SHA256 signature of the body, `sha256=<hex>`.
+pub const SIGNATURE_HEADER: &str = "X-Swarms-Signature";
+
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum WebhookError {
+    #[error("Http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Webhook returned status {0}")]
+    Status(u16),
+}
+
+/// Where and how to deliver run results.
+#[derive(Clone, Debug)]
+pub struct WebhookConfig {
+    url: String,
+    secret: Option<String>,
+    max_retries: u32,
+    timeout: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            secret: None,
+            max_retries: 3,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sign the body with HMAC-SHA256, the signature is sent in [`SIGNATURE_HEADER`].
+    pub fn secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Completed,
+    Failed,
+}
+
+/// The body posted to the webhook.
+#[derive(Clone, Serialize)]
+pub struct WebhookEvent {
+    pub workflow: String,
+    pub task: String,
+    pub status: RunStatus,
+    pub metadata: Option<MetadataSchema>,
+    pub error: Option<String>,
+    pub timestamp: DateTime<Local>,
+}
+
+impl WebhookEvent {
+    pub fn completed(
+        workflow: impl Into<String>,
+        task: impl Into<String>,
+        metadata: Option<MetadataSchema>,
+    ) -> Self {
+        Self {
+            workflow: workflow.into(),
+            task: task.into(),
+            status: RunStatus::Completed,
+            metadata,
+            error: None,
+            timestamp: Local::now(),
+        }
+    }
+
+    pub fn failed(
+        workflow: impl Into<String>,
+        task: impl Into<String>,
+        error: impl ToString,
+    ) -> Self {
+        Self {
+            workflow: workflow.into(),
+            task: task.into(),
+            status: RunStatus::Failed,
+            metadata: None,
+            error: Some(error.to_string()),
+            timestamp: Local::now(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    config: WebhookConfig,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    /// Post the event, retries with exponential backoff if the delivery fails.
+    pub async fn notify(&self, event: &WebhookEvent) -> Result<(), WebhookError> {
+        let body = serde_json::to_vec(event)?;
+        let signature = self
+            .config
+            .secret
+            .as_ref()
+            .map(|secret| sign(secret, &body));
+
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .client
+                .post(&self.config.url)
+                .timeout(self.config.timeout)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            let result = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => Err(WebhookError::Status(response.status().as_u16())),
+                Err(e) => Err(e.into()),
+            };
+
+            if attempt >= self.config.max_retries {
+                return result;
+            }
+            attempt += 1;
+            tokio::time::sleep(backoff(attempt)).await;
+        }
+    }
+
+    /// Post the event in the background, failures are logged.
+    pub fn notify_in_background(&self, event: WebhookEvent) {
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = notifier.notify(&event).await {
+                tracing::error!(
+                    "| webhook | Workflow: {} | Task: {} | Error: {}",
+                    event.workflow,
+                    event.task,
+                    e
+                );
+            }
+        });
+    }
+}
+
+// Delay before the given retry, doubling from `BASE_BACKOFF` up to `MAX_BACKOFF`
+fn backoff(attempt: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// HMAC-SHA256 signature of `body`, formatted as `sha256=<hex>`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    // Safety: HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    let signature = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("sha256={signature}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(3), Duration::from_secs(2));
+        assert_eq!(backoff(7), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+}
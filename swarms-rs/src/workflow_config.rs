@@ -1,11 +1,23 @@
+//! Declarative graph workflows, e.g. loaded from a config file.
+//!
+//! Connections refer to conditions and transformations by name, the functions are registered
+//! once in a [`FlowRegistry`] and looked up when the workflow is built.
+
 use serde::Deserialize;
 
-use crate::agent::AgentConfig;
+use crate::{
+    agent::{Agent, AgentConfig, swarms_agent::SwarmsAgentBuilder},
+    graph_workflow::{
+        DAGWorkflow, EdgeSpec, FlowRegistry, FlowSpec, GraphWorkflowError, NodeSpec, WorkflowSpec,
+    },
+    llm,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct GraphWorkflowConfig {
     pub name: String,
     pub description: String,
+    /// Model name of the agents which don't name one.
     pub default_model: Option<String>,
     pub agents: Vec<AgentConfig>,
     pub connections: Vec<ConnectionConfig>,
@@ -15,6 +27,137 @@ pub struct GraphWorkflowConfig {
 pub struct ConnectionConfig {
     pub from: String,
     pub to: String,
+    /// Names of functions registered in the [`FlowRegistry`].
     pub condition: Option<String>,
+    #[serde(default)]
+    pub async_condition: Option<String>,
     pub transform: Option<String>,
 }
+
+impl GraphWorkflowConfig {
+    /// Build the workflow, every agent runs on `model` and the functions of the connections
+    /// are looked up in the registry.
+    ///
+    /// Fails if a connection refers to an unknown agent or function, or would close a cycle.
+    pub fn build<M>(
+        self,
+        model: M,
+        registry: &FlowRegistry,
+    ) -> Result<DAGWorkflow, GraphWorkflowError>
+    where
+        M: llm::Model + Clone + Send + Sync + 'static,
+        M::RawCompletionResponse: Clone + Send + Sync,
+    {
+        let spec = WorkflowSpec {
+            name: self.name,
+            description: self.description,
+            nodes: self
+                .agents
+                .iter()
+                .map(|agent| NodeSpec {
+                    name: agent.name.clone(),
+                    aggregator: None,
+                    policy: None,
+                })
+                .collect(),
+            edges: self.connections.into_iter().map(EdgeSpec::from).collect(),
+        };
+        let agents = self
+            .agents
+            .into_iter()
+            .map(|mut config| {
+                if config.model_name.is_empty()
+                    && let Some(default_model) = &self.default_model
+                {
+                    config.model_name = default_model.clone();
+                }
+                let system_prompt = config.system_prompt.clone();
+                let mut builder = SwarmsAgentBuilder::new_with_model(model.clone()).config(config);
+                if !system_prompt.is_empty() {
+                    builder = builder.system_prompt(system_prompt);
+                }
+                Box::new(builder.build()) as Box<dyn Agent>
+            })
+            .collect();
+        DAGWorkflow::from_spec(spec, agents, registry)
+    }
+}
+
+impl From<ConnectionConfig> for EdgeSpec {
+    fn from(connection: ConnectionConfig) -> Self {
+        EdgeSpec {
+            from: connection.from,
+            to: connection.to,
+            flow: FlowSpec {
+                condition: connection.condition,
+                async_condition: connection.async_condition,
+                transform: connection.transform,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FnModel;
+
+    /// Answers with the agent's system prompt.
+    fn system_prompt_model() -> FnModel {
+        FnModel::new("system-prompt", |request| {
+            request.system_prompt.unwrap_or_default()
+        })
+    }
+
+    fn config() -> GraphWorkflowConfig {
+        let agent = |name: &str, answer: &str| {
+            let mut config = AgentConfig::builder()
+                .agent_name(name)
+                .model_name("")
+                .build();
+            config.system_prompt = answer.to_owned();
+            config
+        };
+        GraphWorkflowConfig {
+            name: "review".to_owned(),
+            description: "Review a draft".to_owned(),
+            default_model: Some("gpt-4o".to_owned()),
+            agents: vec![
+                agent("writer", "approved draft"),
+                agent("editor", "edited"),
+                agent("critic", "rejected"),
+            ],
+            connections: serde_json::from_str(
+                r#"[
+                    {"from": "writer", "to": "editor", "condition": "approved", "transform": "shout"},
+                    {"from": "writer", "to": "critic", "condition": "rejected"}
+                ]"#,
+            )
+            .unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_with_registry() {
+        let registry = FlowRegistry::new()
+            .condition("approved", |output: &str| output.contains("approved"))
+            .condition("rejected", |output: &str| !output.contains("approved"))
+            .transform("shout", |input: String| input.to_uppercase());
+        let mut workflow = config().build(system_prompt_model(), &registry).unwrap();
+        let spec = workflow.spec().unwrap();
+        assert_eq!(
+            spec.edges[1].flow,
+            FlowSpec::new().condition("approved").transform("shout")
+        );
+
+        let results = workflow.execute_workflow("writer", "draft").await.unwrap();
+        assert_eq!(results.get("editor").unwrap().as_ref().unwrap(), "edited");
+        assert!(!results.contains_key("critic"));
+
+        let registry = FlowRegistry::new().condition("approved", |_: &str| true);
+        assert!(matches!(
+            config().build(system_prompt_model(), &registry),
+            Err(GraphWorkflowError::UnregisteredFunction(_))
+        ));
+    }
+}